@@ -1,7 +1,7 @@
 ;; String formatting for Lust in Lust
 
-(let #t (eq 1 1))
-(let #f (eq 0 1))
+;; Formatting works on lists of characters.
+(let chars string->list)
 
 (let list (fn (& args) args))
 (let last (fn (list)
@@ -55,15 +55,15 @@
 		    (cons start (split rest wedge)))
 		   ())))
 
-(let append (fn (lst item)
+(let append-item (fn (lst item)
 		(if (null? lst)
 		    (list item)
-		    (cons (car lst) (append (cdr lst) item)))))
+		    (cons (car lst) (append-item (cdr lst) item)))))
 
 (let concat (fn (lst tail)
 		(if (null? tail)
 		    lst
-		    (concat (append lst (car tail)) (cdr tail)))))
+		    (concat (append-item lst (car tail)) (cdr tail)))))
 
 (let list-kinda-eq (fn (a b)
 		 (if (not (eq (len a) (len b))) #f
@@ -79,7 +79,7 @@
 (let printf (fn (lst & args)
 		 (if (not (null? lst))
 		   (do
-		    (let sections (split lst "{}"))
+		    (let sections (split lst (chars "{}")))
 		    (let helper (fn (split args)
 				    (if (not (null? split))
 					(do
@@ -105,7 +105,7 @@
 		 (if (not (null? lst))
 		   (do
 		    (let res ())
-		    (let sections (split lst "{}"))
+		    (let sections (split lst (chars "{}")))
 		    (let helper (fn (split args)
 				    (if (not (null? split))
 					(do
@@ -127,8 +127,9 @@
 		    res)
 		   ())))
 
-(println (sprintf "hello {} {}!" "zeke" "medley"))
-(printf "hello {}!" "zeke")
+(println (sprintf (chars "hello {} {}!") (chars "zeke") (chars "medley")))
+(printf (chars "hello {}!") (chars "zeke"))
 
 ;; This line just exists so that we can test these functions.
-(list-kinda-eq (sprintf "hello there {}! your lucky number is {} :)" "zeke" "10") "hello there zeke! your lucky number is 10 :)")
+(list-kinda-eq (sprintf (chars "hello there {}! your lucky number is {} :)") (chars "zeke") (chars "10"))
+	       (chars "hello there zeke! your lucky number is 10 :)"))
@@ -1,3 +1,3 @@
 (print "hello world!\n")
 
-(println (car "hello world"))
+(println (car (string->list "hello world")))
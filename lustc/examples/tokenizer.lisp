@@ -1,7 +1,7 @@
 ;; Tokenizer for Lust in Lust
 
-(let #t (eq 1 1))
-(let #f (eq 0 1))
+;; The tokenizer works on lists of characters.
+(let chars string->list)
 
 (let len (fn (list)
 	     (if (eq list ())
@@ -57,15 +57,15 @@
 			 (cons start (split rest wedge))))
 		   ())))
 
-(let append (fn (lst item)
+(let append-item (fn (lst item)
 		(if (null? lst)
 		    (list item)
-		    (cons (car lst) (append (cdr lst) item)))))
+		    (cons (car lst) (append-item (cdr lst) item)))))
 
 (let concat (fn (lst tail)
 		(if (null? tail)
 		    lst
-		    (concat (append lst (car tail)) (cdr tail)))))
+		    (concat (append-item lst (car tail)) (cdr tail)))))
 
 (let replace (fn (str target replacement)
 		 (if (null? str)
@@ -77,10 +77,10 @@
 (let tokenize (fn (str)
 		  (do
 		   (let prep (fn (str)
-				 (replace (replace str "(" " ( ") ")" " ) ")))
-		   (split (prep str) " "))))
+				 (replace (replace str (chars "(") (chars " ( ")) (chars ")") (chars " ) "))))
+		   (split (prep str) (chars " ")))))
 
-(println (tokenize "(add 1 a)"))
+(println (tokenize (chars "(add 1 a)")))
 
 ;; TESTING CODE:
 
@@ -101,4 +101,5 @@
 					      #f))))
 		      (helper a b)))))
 
-(list-kinda-eq (tokenize "(add foo bar)") (list "(" "add" "foo" "bar" ")"))
+(list-kinda-eq (tokenize (chars "(add foo bar)"))
+	       (list (chars "(") (chars "add") (chars "foo") (chars "bar") (chars ")")))
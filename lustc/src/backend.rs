@@ -0,0 +1,262 @@
+//! Where the code goes once it has been compiled. Either straight into
+//! memory to be run right away or into an object file to be linked
+//! into something else later.
+
+use std::path::Path;
+
+use cranelift::codegen::binemit::TrapSink;
+use cranelift::codegen::{self, ir, isa};
+use cranelift::prelude::*;
+use cranelift_jit::JITModule;
+use cranelift_module::{
+    DataContext, DataId, FuncId, Linkage, Module, ModuleCompiledFunction, ModuleDeclarations,
+    ModuleResult, RelocRecord,
+};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+use target_lexicon::Triple;
+
+use crate::errors::LustError;
+
+/// The modules that lustc knows how to compile into. Everything that
+/// emits code goes through the Module trait so it doesn't need to care
+/// which one it is using.
+pub enum LustModule {
+    /// Compiles code into memory so that it can be run right away.
+    JIT(JITModule),
+    /// Compiles code into an object file.
+    Object(ObjectModule),
+}
+
+impl LustModule {
+    /// Makes a new object module. NAME is the name of the object
+    /// file's file symbol. The code is for TARGET if it is set and the
+    /// host machine otherwise.
+    pub(crate) fn object(name: &str, target: Option<Triple>) -> Result<Self, LustError> {
+        let mut flag_builder = settings::builder();
+        // Object files are going to be linked into who knows what so
+        // the code needs to be position independent.
+        flag_builder
+            .set("is_pic", "true")
+            .map_err(|e| LustError::CraneliftError(e.to_string()))?;
+        let isa_builder = match target {
+            Some(triple) => isa::lookup(triple.clone()).map_err(|e| {
+                LustError::CraneliftError(format!("can not compile for {}: {}", triple, e))
+            }),
+            None => {
+                cranelift_native::builder().map_err(|e| LustError::CraneliftError(e.to_string()))
+            }
+        }?;
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder));
+
+        let builder = ObjectBuilder::new(isa, name, cranelift_module::default_libcall_names())
+            .map_err(LustError::from)?;
+        Ok(LustModule::Object(ObjectModule::new(builder)))
+    }
+
+    /// Finalizes the definitions made so far so that they can be
+    /// used. Object files are finalized by the linker so this does
+    /// nothing for them.
+    pub(crate) fn finalize_definitions(&mut self) {
+        if let LustModule::JIT(m) = self {
+            m.finalize_definitions()
+        }
+    }
+
+    /// Gets a pointer to the finalized function ID. Only a JIT
+    /// module can do this as object files don't know where their
+    /// code will end up.
+    pub(crate) fn get_finalized_function(&self, id: FuncId) -> Result<*const u8, LustError> {
+        match self {
+            LustModule::JIT(m) => Ok(m.get_finalized_function(id)),
+            LustModule::Object(_) => Err(LustError::Compile(
+                "internal error: can not run code that was compiled to an object file".to_string(),
+            )),
+        }
+    }
+
+    /// Unmaps the code and data that a JIT module has defined. Object
+    /// modules don't own any.
+    ///
+    /// # Safety
+    ///
+    /// Nothing that the module defined can be running or be used
+    /// afterwards.
+    pub(crate) unsafe fn free_memory(&mut self) {
+        if let LustModule::JIT(m) = self {
+            m.free_memory()
+        }
+    }
+
+    /// Writes the object file that this module has been compiling to
+    /// PATH.
+    pub(crate) fn write_object(self, path: &Path) -> Result<(), LustError> {
+        match self {
+            LustModule::Object(m) => {
+                let bytes = m
+                    .finish()
+                    .emit()
+                    .map_err(|e| LustError::Compile(e.to_string()))?;
+                std::fs::write(path, bytes).map_err(|e| LustError::Compile(e.to_string()))
+            }
+            LustModule::JIT(_) => Err(LustError::Compile(
+                "internal error: can not write an object file for code compiled into memory"
+                    .to_string(),
+            )),
+        }
+    }
+}
+
+impl Module for LustModule {
+    fn isa(&self) -> &dyn isa::TargetIsa {
+        match self {
+            LustModule::JIT(m) => m.isa(),
+            LustModule::Object(m) => m.isa(),
+        }
+    }
+
+    fn declarations(&self) -> &ModuleDeclarations {
+        match self {
+            LustModule::JIT(m) => m.declarations(),
+            LustModule::Object(m) => m.declarations(),
+        }
+    }
+
+    fn declare_function(
+        &mut self,
+        name: &str,
+        linkage: Linkage,
+        signature: &ir::Signature,
+    ) -> ModuleResult<FuncId> {
+        match self {
+            LustModule::JIT(m) => m.declare_function(name, linkage, signature),
+            LustModule::Object(m) => m.declare_function(name, linkage, signature),
+        }
+    }
+
+    fn declare_data(
+        &mut self,
+        name: &str,
+        linkage: Linkage,
+        writable: bool,
+        tls: bool,
+    ) -> ModuleResult<DataId> {
+        match self {
+            LustModule::JIT(m) => m.declare_data(name, linkage, writable, tls),
+            LustModule::Object(m) => m.declare_data(name, linkage, writable, tls),
+        }
+    }
+
+    fn define_function<TS>(
+        &mut self,
+        func: FuncId,
+        ctx: &mut codegen::Context,
+        trap_sink: &mut TS,
+    ) -> ModuleResult<ModuleCompiledFunction>
+    where
+        TS: TrapSink,
+    {
+        match self {
+            LustModule::JIT(m) => m.define_function(func, ctx, trap_sink),
+            LustModule::Object(m) => m.define_function(func, ctx, trap_sink),
+        }
+    }
+
+    fn define_function_bytes(
+        &mut self,
+        func: FuncId,
+        bytes: &[u8],
+        relocs: &[RelocRecord],
+    ) -> ModuleResult<ModuleCompiledFunction> {
+        match self {
+            LustModule::JIT(m) => m.define_function_bytes(func, bytes, relocs),
+            LustModule::Object(m) => m.define_function_bytes(func, bytes, relocs),
+        }
+    }
+
+    fn define_data(&mut self, data: DataId, data_ctx: &DataContext) -> ModuleResult<()> {
+        match self {
+            LustModule::JIT(m) => m.define_data(data, data_ctx),
+            LustModule::Object(m) => m.define_data(data, data_ctx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use object::{Object, ObjectSymbol};
+
+    use crate::compiler::{
+        compile_to_object, compile_to_object_for, compile_to_object_for_with_options,
+    };
+    use crate::options::CompileOptions;
+    use crate::parse_string;
+
+    #[test]
+    fn object_file() {
+        let program = parse_string(
+            r#"
+(let words (quote ("hello" 1.5 (2 3))))
+(let f (fn (n) (add1 n)))
+(display (time (apply f (list 41))))
+"#,
+        )
+        .unwrap();
+        let path = std::env::temp_dir().join(format!("lustc-test-{}.o", std::process::id()));
+        compile_to_object(&program, &path).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let file = object::File::parse(&bytes).unwrap();
+
+        let defined = |name: &str| {
+            file.symbols()
+                .any(|s| s.name() == Ok(name) && !s.is_undefined())
+        };
+        let imported = |name: &str| {
+            file.symbols()
+                .any(|s| s.name() == Ok(name) && s.is_undefined())
+        };
+        assert!(defined("lust_entry"));
+        assert!(defined("__anon_fn_0"));
+        assert!(defined("alloc"));
+        assert!(imported("malloc"));
+        assert!(imported("print_lustc_word"));
+        assert!(imported("clock_lustc_word"));
+        assert!(imported("print_elapsed_lustc_word"));
+    }
+
+    #[test]
+    fn cross_compiled_object() {
+        let program = parse_string("(add1 1)").unwrap();
+        let path = std::env::temp_dir().join(format!("lustc-cross-test-{}.o", std::process::id()));
+        let target = "x86_64-pc-windows-gnu".parse().unwrap();
+        compile_to_object_for(&program, &path, target).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let file = object::File::parse(&bytes).unwrap();
+        assert_eq!(file.architecture(), object::Architecture::X86_64);
+        assert_eq!(file.format(), object::BinaryFormat::Coff);
+    }
+
+    #[test]
+    fn cross_compiled_object_with_options() {
+        // f is never called so it is only compiled when unused
+        // functions are kept.
+        let program = parse_string("(let f (fn (n) n)) 1").unwrap();
+        let path = std::env::temp_dir().join(format!("lustc-cross-opts-{}.o", std::process::id()));
+        let compiles_f = |options| {
+            let target = "x86_64-pc-windows-gnu".parse().unwrap();
+            compile_to_object_for_with_options(&program, &path, target, options).unwrap();
+            let bytes = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let file = object::File::parse(&bytes).unwrap();
+            let res = file
+                .symbols()
+                .any(|s| s.name() == Ok("__anon_fn_0") && !s.is_undefined());
+            res
+        };
+        assert!(!compiles_f(CompileOptions::default()));
+        assert!(compiles_f(CompileOptions::unoptimized()));
+    }
+}
@@ -0,0 +1,198 @@
+//! Constructors for building programs out of expressions without
+//! writing out the lists that make them up by hand. Every constructor
+//! makes a list of the shape that the matching is_* function looks
+//! for:
+//!
+//! ```
+//! use lustc::Expr;
+//!
+//! let program = Expr::let_(
+//!     vec![("x", Expr::int(1))],
+//!     vec![Expr::if_(
+//!         Expr::call("lt", vec![Expr::symbol("x"), Expr::int(2)]),
+//!         Expr::call("add", vec![Expr::symbol("x"), Expr::int(1)]),
+//!         Expr::int(0),
+//!     )],
+//! );
+//! assert_eq!(lustc::compiler::eval_program(&[program]), Ok(Expr::int(2)));
+//! ```
+
+use crate::Expr;
+
+impl Expr {
+    /// Makes an integer.
+    pub fn int(i: i64) -> Self {
+        Expr::Integer(i)
+    }
+
+    /// Makes a symbol named NAME.
+    pub fn symbol(name: &str) -> Self {
+        Expr::Symbol(name.to_string())
+    }
+
+    /// Makes a string literal.
+    pub fn string(s: &str) -> Self {
+        Expr::String(s.to_string())
+    }
+
+    /// Makes a list out of EXPRS. The empty list is nil.
+    fn list_or_nil(exprs: Vec<Expr>) -> Self {
+        if exprs.is_empty() {
+            Expr::Nil
+        } else {
+            Expr::list(exprs)
+        }
+    }
+
+    /// Makes a call to the function or primitive named NAME with ARGS.
+    pub fn call(name: &str, args: Vec<Expr>) -> Self {
+        let mut v = Vec::with_capacity(args.len() + 1);
+        v.push(Expr::symbol(name));
+        v.extend(args);
+        Expr::list(v)
+    }
+
+    /// Makes an if expression.
+    pub fn if_(cond: Expr, then: Expr, else_: Expr) -> Self {
+        Expr::list(vec![Expr::symbol("if"), cond, then, else_])
+    }
+
+    /// Makes a scoped let expression that binds each name in BINDINGS
+    /// to its value while BODY runs. BODY should have at least one
+    /// expression.
+    pub fn let_(bindings: Vec<(&str, Expr)>, body: Vec<Expr>) -> Self {
+        let bindings = bindings
+            .into_iter()
+            .map(|(name, val)| Expr::list(vec![Expr::symbol(name), val]))
+            .collect();
+        let mut v = vec![Expr::symbol("let"), Expr::list_or_nil(bindings)];
+        v.extend(body);
+        Expr::list(v)
+    }
+
+    /// Makes a let expression that binds NAME to VAL in the rest of
+    /// the scope it appears in.
+    pub fn bind(name: &str, val: Expr) -> Self {
+        Expr::list(vec![Expr::symbol("let"), Expr::symbol(name), val])
+    }
+
+    /// Makes a function taking PARAMS that runs BODY. BODY should have
+    /// at least one expression.
+    pub fn fn_(params: Vec<&str>, body: Vec<Expr>) -> Self {
+        let params = params.into_iter().map(Expr::symbol).collect();
+        let mut v = vec![Expr::symbol("fn"), Expr::list_or_nil(params)];
+        v.extend(body);
+        Expr::list(v)
+    }
+
+    /// Makes a begin expression that runs EXPRS in order.
+    pub fn begin(exprs: Vec<Expr>) -> Self {
+        Expr::call("begin", exprs)
+    }
+
+    /// Makes a quote expression for the data DATUM.
+    pub fn quote(datum: Expr) -> Self {
+        Expr::call("quote", vec![datum])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse_string, roundtrip_string, Expr};
+
+    fn parse(source: &str) -> Expr {
+        parse_string(source).unwrap().remove(0)
+    }
+
+    #[test]
+    fn matches_parsed() {
+        let x = || Expr::symbol("x");
+        assert_eq!(
+            Expr::call("add", vec![x(), Expr::int(1)]),
+            parse("(add x 1)")
+        );
+        assert_eq!(
+            Expr::if_(Expr::Bool(true), Expr::int(1), Expr::int(2)),
+            parse("(if #t 1 2)")
+        );
+        assert_eq!(
+            Expr::let_(vec![("x", Expr::int(1)), ("y", Expr::int(2))], vec![x()]),
+            parse("(let ((x 1) (y 2)) x)")
+        );
+        assert_eq!(Expr::let_(vec![], vec![x()]), parse("(let () x)"));
+        assert_eq!(Expr::bind("x", Expr::int(1)), parse("(let x 1)"));
+        assert_eq!(Expr::fn_(vec!["x"], vec![x()]), parse("(fn (x) x)"));
+        assert_eq!(Expr::fn_(vec![], vec![x()]), parse("(fn () x)"));
+        assert_eq!(Expr::begin(vec![x(), x()]), parse("(begin x x)"));
+        assert_eq!(Expr::quote(x()), parse("'x"));
+        assert_eq!(Expr::string("hi"), parse("\"hi\""));
+    }
+
+    #[test]
+    fn predicates() {
+        let x = || Expr::symbol("x");
+
+        let call = Expr::call("f", vec![x()]);
+        assert_eq!(call.is_fncall(), Some((&Expr::symbol("f"), &[x()][..])));
+        let primcall = Expr::call("add1", vec![x()]);
+        assert_eq!(primcall.is_primcall(), Some(("add1", &[x()][..])));
+
+        let if_ = Expr::if_(x(), Expr::int(1), Expr::int(2));
+        assert_eq!(
+            if_.is_conditional(),
+            Some((&x(), &Expr::int(1), &Expr::int(2)))
+        );
+
+        let let_ = Expr::let_(vec![("x", Expr::int(1))], vec![x()]);
+        let (bindings, body) = let_.is_scoped_let().unwrap();
+        assert_eq!(bindings, vec![(&"x".to_string(), &Expr::int(1))]);
+        assert_eq!(body, &[x()]);
+
+        let bind = Expr::bind("x", Expr::int(1));
+        assert_eq!(bind.is_let(), Some((&"x".to_string(), &Expr::int(1))));
+
+        let fn_ = Expr::fn_(vec!["x"], vec![x()]);
+        assert_eq!(fn_.is_fndef(), Some((vec![&"x".to_string()], &[x()][..])));
+
+        let begin = Expr::begin(vec![x()]);
+        assert_eq!(begin.is_begin(), Some(&[x()][..]));
+
+        let quote = Expr::quote(x());
+        assert_eq!(quote.is_quote(), Some(&x()));
+    }
+
+    #[test]
+    fn built_program() {
+        // (let f (fn (n) (if (eq n 0) 1 (mul n (f (sub n 1))))))
+        // (f 5)
+        let n = || Expr::symbol("n");
+        let program = vec![
+            Expr::bind(
+                "f",
+                Expr::fn_(
+                    vec!["n"],
+                    vec![Expr::if_(
+                        Expr::call("eq", vec![n(), Expr::int(0)]),
+                        Expr::int(1),
+                        Expr::call(
+                            "mul",
+                            vec![
+                                n(),
+                                Expr::call("f", vec![Expr::call("sub", vec![n(), Expr::int(1)])]),
+                            ],
+                        ),
+                    )],
+                ),
+            ),
+            Expr::call("f", vec![Expr::int(5)]),
+        ];
+        assert_eq!(
+            crate::compiler::roundtrip_program(&program),
+            roundtrip_string("(let f (fn (n) (if (eq n 0) 1 (mul n (f (sub n 1)))))) (f 5)")
+        );
+        assert_eq!(
+            crate::compiler::roundtrip_program(&program),
+            Ok(Expr::int(120))
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Letters, digits, and spaces
+//! Upper and lower case for now only in ASCII
+
+use cranelift::prelude::*;
+
+use crate::compiler::Context;
+use crate::errors::LustError;
+use crate::{fatal, Expr};
+
+/// The whitespace characters other than space. They are all next to
+/// each other.
+const WHITESPACE_CONTROLS: (char, char) = ('\t', '\r');
+
+/// Emits a check of whether C, a character, is between FIRST and LAST
+/// inclusive. Characters are ordered the same way as their
+/// immediates so this can compare the immediates directly.
+fn emit_char_between(c: Value, first: char, last: char, ctx: &mut Context) -> Value {
+    let first = Expr::Char(first).immediate_rep();
+    let last = Expr::Char(last).immediate_rep();
+    // Subtracting FIRST moves everything below it to the top of the
+    // unsigned range so one comparison checks both ends.
+    let offset = ctx.builder.ins().iadd_imm(c, -first);
+    ctx.builder
+        .ins()
+        .icmp_imm(IntCC::UnsignedLessThanOrEqual, offset, last - first)
+}
+
+/// Emits the code to move C, a character, from the range starting at
+/// FROM to the range starting at TO if it is one of the letters of
+/// the alphabet in the FROM range. Other characters are left as is.
+fn emit_change_case(c: Value, from: char, to: char, ctx: &mut Context) -> Value {
+    let last = (from as u8 + 25) as char;
+    let is_letter = emit_char_between(c, from, last, ctx);
+    let is_letter = ctx.builder.ins().bint(ctx.word, is_letter);
+    let distance = Expr::Char(to).immediate_rep() - Expr::Char(from).immediate_rep();
+    let change = ctx.builder.ins().imul_imm(is_letter, distance);
+    ctx.builder.ins().iadd(c, change)
+}
+
+/// Emits the code for (char-upcase C) or (char-downcase C), whichever
+/// NAME is. Only ASCII letters change case.
+pub(crate) fn emit_char_case(name: &str, c: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    fatal::emit_check_char(c, ctx)?;
+    Ok(match name {
+        "char-upcase" => emit_change_case(c, 'a', 'A', ctx),
+        "char-downcase" => emit_change_case(c, 'A', 'a', ctx),
+        _ => return Err(format!("internal error: {} does not change case", name).into()),
+    })
+}
+
+/// Emits the code for the character class predicate NAME applied to
+/// C. The result is a word that is one if C is in the class and zero
+/// if it isn't. Only ASCII characters are in any of the classes.
+pub(crate) fn emit_char_class(name: &str, c: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    fatal::emit_check_char(c, ctx)?;
+    let res = match name {
+        "char-alphabetic?" => {
+            let lower = emit_char_between(c, 'a', 'z', ctx);
+            let upper = emit_char_between(c, 'A', 'Z', ctx);
+            ctx.builder.ins().bor(lower, upper)
+        }
+        "char-numeric?" => emit_char_between(c, '0', '9', ctx),
+        "char-whitespace?" => {
+            let (first, last) = WHITESPACE_CONTROLS;
+            let control = emit_char_between(c, first, last, ctx);
+            let space =
+                ctx.builder
+                    .ins()
+                    .icmp_imm(IntCC::Equal, c, Expr::Char(' ').immediate_rep());
+            ctx.builder.ins().bor(control, space)
+        }
+        _ => return Err(format!("internal error: {} is not a character class", name).into()),
+    };
+    Ok(ctx.builder.ins().bint(ctx.word, res))
+}
+
+/// Determines if C is in the class that the character class predicate
+/// NAME checks for. Agrees with emit_char_class.
+pub(crate) fn char_in_class(name: &str, c: char) -> Option<bool> {
+    let (first, last) = WHITESPACE_CONTROLS;
+    Some(match name {
+        "char-alphabetic?" => c.is_ascii_alphabetic(),
+        "char-numeric?" => c.is_ascii_digit(),
+        "char-whitespace?" => (first..=last).contains(&c) || c == ' ',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::roundtrip_string;
+
+    #[test]
+    fn change_case() {
+        let cases = [
+            ("(char-upcase #\\a)", 'A'),
+            ("(char-upcase #\\z)", 'Z'),
+            ("(char-upcase #\\A)", 'A'),
+            ("(char-upcase #\\5)", '5'),
+            ("(char-upcase #\\{)", '{'),
+            ("(char-downcase #\\Q)", 'q'),
+            ("(char-downcase #\\q)", 'q'),
+            ("(char-downcase #\\@)", '@'),
+            ("(char-downcase #\\[)", '['),
+            // Everything outside of ASCII is left alone for now.
+            ("(char-upcase (integer->char 233))", 'é'),
+            ("(char-downcase (integer->char 201))", 'É'),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(
+                roundtrip_string(source).unwrap(),
+                Expr::Char(expected),
+                "{}",
+                source
+            );
+        }
+        let source = r#"(map char-upcase (string->list "Hello, world!"))"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string(r#"(string->list "HELLO, WORLD!")"#).unwrap()
+        );
+    }
+
+    #[test]
+    fn char_classes() {
+        let names = ["char-alphabetic?", "char-numeric?", "char-whitespace?"];
+        let chars = [
+            'a', 'z', 'A', 'Z', '0', '5', '9', ' ', '\t', '\n', '\r', '\x0b', '\x0c', '@', '[',
+            '`', '{', '/', ':', '\x08', '\x0e', '!', 'é', '\u{a0}',
+        ];
+        for name in names {
+            for c in chars {
+                // The character is passed through a function so the
+                // call isn't folded.
+                let c_expr = format!("(integer->char {})", c as u32);
+                let source = format!("(let id (fn (x) x)) ({} (id {}))", name, c_expr);
+                assert_eq!(
+                    roundtrip_string(&source).unwrap(),
+                    Expr::Bool(char_in_class(name, c).unwrap()),
+                    "{}",
+                    source
+                );
+                let folded = format!("({} {})", name, c_expr);
+                assert_eq!(
+                    roundtrip_string(&folded).unwrap(),
+                    roundtrip_string(&source).unwrap(),
+                    "{}",
+                    folded
+                );
+            }
+        }
+        assert_eq!(
+            roundtrip_string("(char-numeric? #\\5)").unwrap(),
+            Expr::Bool(true)
+        );
+        let source = "(let p char-whitespace?) (list (p #\\space) (p #\\x))";
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(#t #f)").unwrap()
+        );
+    }
+}
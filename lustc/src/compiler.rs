@@ -10,28 +10,49 @@
 //! - [Mark Bell](https://hellopoetry.com/poem/1927377/give-us-a-clue/)
 
 use std::collections::HashMap;
+use std::path::Path;
 
+use crate::backend::LustModule;
 use crate::conditional;
-use crate::conversions::{print_lustc_word, println_lustc_word};
+use crate::conversions;
+use crate::conversions::{print_lustc_word, println_lustc_word, write_lustc_word};
 use crate::data;
+use crate::errors::LustError;
 use crate::escape;
 use crate::fatal;
+use crate::floats;
+use crate::fluid;
+use crate::fold;
 use crate::foreign;
+use crate::globals::{self, Globals};
 use crate::heap::define_alloc;
+use crate::inline;
 use crate::locals;
+use crate::options::CompileOptions;
+use crate::peephole::{self, Known};
 use crate::primitives;
 use crate::procedures;
+use crate::records;
 use crate::renamer;
+use crate::sequence;
+use crate::strings::string_to_list_lustc_word;
+use crate::symbols::{self, SharedInterner};
+use crate::timer;
+use crate::timer::CompileTimes;
 use crate::Expr;
 use cranelift::frontend::FunctionBuilder;
 use cranelift::prelude::*;
 use cranelift_jit::{JITBuilder, JITModule};
 use cranelift_module::DataContext;
-use cranelift_module::{Linkage, Module};
+use cranelift_module::{FuncId, Linkage, Module};
 use primitives::define_contiguous_to_list;
 use primitives::string_is_primitive;
 use procedures::emit_procedure;
 use procedures::LustFn;
+use target_lexicon::Triple;
+
+/// The most arguments that lust_entry can be called with.
+const MAX_ENTRY_ARGS: usize = 6;
 
 /// Manages the state needed for compilation by cranelift and
 /// execution of a program.
@@ -44,17 +65,61 @@ pub struct JIT {
     /// The main context for code generation.
     pub context: codegen::Context,
 
-    /// Used to emit code directly into memory for execution.
-    pub module: JITModule,
+    /// Used to emit code, either directly into memory for execution
+    /// or into an object file.
+    pub module: LustModule,
 
     // Stores information about data objects that the JIT owns.
     pub data_ctx: DataContext,
+
+    /// If set the textual IR of every function defined in the JIT is
+    /// appended to this.
+    pub ir: Option<String>,
+
+    /// Which passes run on programs compiled into the JIT and what
+    /// the compiled code checks for.
+    pub options: CompileOptions,
+
+    /// The number of bytes that programs run by the JIT can allocate
+    /// before the garbage collector first runs.
+    pub heap_initial_bytes: usize,
+
+    /// If set the heap grows when it fills up with live objects. If
+    /// not, programs that need more than heap_initial_bytes at once
+    /// exit with an out of memory error.
+    pub heap_growable: bool,
+
+    /// How long the JIT has spent compiling the programs given to it
+    /// so far.
+    pub compile_times: CompileTimes,
+
+    /// If set the names of the functions and data that a program
+    /// defines end with this so that programs compiled into the same
+    /// JIT don't clash. The program's entry function is named this
+    /// instead of lust_entry.
+    pub(crate) namespace: Option<String>,
+
+    /// The primitives that have been defined for use in higher order
+    /// contexts. Programs compiled into the JIT later reuse them.
+    pub(crate) primitive_fns: HashMap<String, LustFn>,
+
+    /// The size in bytes of the machine code of every function that
+    /// has been defined.
+    pub(crate) code_sizes: HashMap<FuncId, usize>,
+
+    /// The symbols and keywords that programs compiled into the JIT
+    /// have used so far.
+    pub(crate) interner: SharedInterner,
+
+    /// If set the top level definitions of programs compiled into the
+    /// JIT define these globals, which later programs can use.
+    pub(crate) globals: Option<Globals>,
 }
 
 /// Manages the state needed for compilation of a function by lustc.
 pub(crate) struct Context<'a> {
     pub builder: FunctionBuilder<'a>,
-    pub module: &'a mut JITModule,
+    pub module: &'a mut LustModule,
     pub word: types::Type,
     pub env: HashMap<String, Variable>,
     pub fnmap: HashMap<String, LustFn>,
@@ -62,10 +127,42 @@ pub(crate) struct Context<'a> {
     // variables are in a "defined but not initialized state" and
     // closures care about this.
     pub letstack: Vec<String>,
+    // Set when the expression about to be emitted is in tail
+    // position. emit_expr clears this as soon as it has looked at it
+    // so that subexpressions are not mistakenly considered to be in
+    // tail position.
+    pub tail_position: bool,
+    // The name of the procedure currently being compiled and the
+    // block just after its entry that self tail calls can jump back
+    // to. None when we are not compiling a procedure.
+    pub loop_header: Option<(String, Block)>,
+    // The handler blocks of the catch expressions that the code being
+    // emitted is inside of, innermost last. Raised errors jump to the
+    // innermost one instead of returning.
+    pub handlers: Vec<Block>,
+    // What is known about the types of values that have been emitted
+    // and the conditions that booleans were made from. See
+    // peephole.rs.
+    pub known: HashMap<Value, Known>,
+    pub conditions: HashMap<Value, Value>,
+    // Copied from the JIT.
+    pub options: CompileOptions,
+    // The depth count from when the procedure being compiled was
+    // entered. Put back before every return. See depth.rs.
+    pub entry_depth: Option<Value>,
 }
 
 impl Default for JIT {
     fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl JIT {
+    /// Makes a new JIT with the runtime functions defined. If IR is
+    /// set the IR of every function defined afterwards is recorded
+    /// there.
+    pub fn new(ir: Option<String>) -> Self {
         let mut builder = JITBuilder::new(cranelift_module::default_libcall_names());
 
         // Register the print function.
@@ -73,25 +170,174 @@ impl Default for JIT {
         builder.symbol("print_lustc_word", print_addr);
         let println_addr = println_lustc_word as *const u8;
         builder.symbol("println_lustc_word", println_addr);
+        let write_addr = write_lustc_word as *const u8;
+        builder.symbol("write_lustc_word", write_addr);
+
+        // Register runtime functions for strings.
+        let string_to_list_addr = string_to_list_lustc_word as *const u8;
+        builder.symbol("string_to_list_lustc_word", string_to_list_addr);
+        let string_append_addr = crate::strings::string_append_lustc_word as *const u8;
+        builder.symbol("string_append_lustc_word", string_append_addr);
+
+        // Register runtime functions for hash tables.
+        let make_hash_table_addr = crate::hashtables::make_hash_table_lustc_word as *const u8;
+        builder.symbol("make_hash_table_lustc_word", make_hash_table_addr);
+        let hash_ref_addr = crate::hashtables::hash_ref_lustc_word as *const u8;
+        builder.symbol("hash_ref_lustc_word", hash_ref_addr);
+        let hash_set_addr = crate::hashtables::hash_set_lustc_word as *const u8;
+        builder.symbol("hash_set_lustc_word", hash_set_addr);
+
+        // Register structural equality.
+        let equal_addr = crate::equality::equal_lustc_word as *const u8;
+        builder.symbol("equal_lustc_word", equal_addr);
+
+        // Register the function that reads expressions from stdin.
+        let read_addr = crate::input::read_lustc_word as *const u8;
+        builder.symbol("read_lustc_word", read_addr);
+
+        // Register the symbol generator.
+        let gensym_addr = crate::symbols::gensym_lustc_word as *const u8;
+        builder.symbol("gensym_lustc_word", gensym_addr);
+        let symbol_to_string_addr = crate::symbols::symbol_to_string_lustc_word as *const u8;
+        builder.symbol("symbol_to_string_lustc_word", symbol_to_string_addr);
+        let string_to_symbol_addr = crate::symbols::string_to_symbol_lustc_word as *const u8;
+        builder.symbol("string_to_symbol_lustc_word", string_to_symbol_addr);
+
+        // Register the function that records errors raised by
+        // programs.
+        let raise_addr = crate::fatal::raise_lustc_word as *const u8;
+        builder.symbol("raise_lustc_word", raise_addr);
+        let throw_addr = crate::fatal::throw_lustc_word as *const u8;
+        builder.symbol("throw_lustc_word", throw_addr);
+        let catch_addr = crate::fatal::catch_lustc_word as *const u8;
+        builder.symbol("catch_lustc_word", catch_addr);
+        let assert_addr = crate::fatal::assert_failed_lustc_word as *const u8;
+        builder.symbol("assert_failed_lustc_word", assert_addr);
+        let fatal_addr = crate::fatal::fatal_lustc_word as *const u8;
+        builder.symbol("fatal_lustc_word", fatal_addr);
+
+        // Register the stack overflow error raised by procedures.
+        let stack_overflow_addr = crate::depth::stack_overflow_lustc_word as *const u8;
+        builder.symbol("stack_overflow_lustc_word", stack_overflow_addr);
+
+        // Register the clock used by (time expr).
+        let clock_addr = timer::clock_lustc_word as *const u8;
+        builder.symbol("clock_lustc_word", clock_addr);
+        let elapsed_addr = timer::print_elapsed_lustc_word as *const u8;
+        builder.symbol("print_elapsed_lustc_word", elapsed_addr);
+
+        // Register the garbage collector's allocator.
+        let alloc_addr = crate::gc::gc_alloc_lustc as *const u8;
+        builder.symbol("gc_alloc_lustc", alloc_addr);
+        let refill_addr = crate::gc::gc_refill_lustc as *const u8;
+        builder.symbol("gc_refill_lustc", refill_addr);
+
+        Self::with_module(LustModule::JIT(JITModule::new(builder)), ir)
+    }
 
-        let module = JITModule::new(builder);
+    /// Makes a new JIT that compiles into an object file instead of
+    /// memory. Runtime functions are left as imports for the linker
+    /// to resolve.
+    pub fn object(name: &str) -> Result<Self, LustError> {
+        Ok(Self::with_module(LustModule::object(name, None)?, None))
+    }
+
+    /// Like object but the object file is for the machine described by
+    /// TARGET instead of the host. Words are as wide as TARGET's
+    /// pointers.
+    pub fn object_for(name: &str, target: Triple) -> Result<Self, LustError> {
+        Ok(Self::with_module(
+            LustModule::object(name, Some(target))?,
+            None,
+        ))
+    }
+
+    fn with_module(module: LustModule, ir: Option<String>) -> Self {
         let mut jit = Self {
             builder_context: FunctionBuilderContext::new(),
             context: module.make_context(),
             module,
             data_ctx: DataContext::new(),
+            ir,
+            options: CompileOptions::default(),
+            heap_initial_bytes: crate::gc::MIN_THRESHOLD,
+            heap_growable: true,
+            compile_times: CompileTimes::default(),
+            namespace: None,
+            primitive_fns: HashMap::new(),
+            code_sizes: HashMap::new(),
+            interner: SharedInterner::default(),
+            globals: None,
         };
         define_alloc(&mut jit).unwrap();
         define_contiguous_to_list(&mut jit).unwrap();
         crate::fatal::emit_error_strings(&mut jit).unwrap();
         jit
     }
+
+    /// Puts NAME, the name of a function or data that a program
+    /// defines, in the JIT's namespace.
+    pub(crate) fn namespaced(&self, name: &str) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{}_{}", name, namespace),
+            None => name.to_string(),
+        }
+    }
+
+    /// Gets the name of every symbol and keyword that programs
+    /// compiled into or run by the JIT have made, in the order that
+    /// they were first seen. Useful for figuring out where a symbol
+    /// came from.
+    pub fn interned_symbols(&self) -> Vec<String> {
+        symbols::names(&self.interner)
+    }
+
+    /// Gets how the heap ought to behave for programs run by the JIT.
+    pub(crate) fn heap_config(&self) -> crate::gc::HeapConfig {
+        crate::gc::HeapConfig {
+            initial_bytes: self.heap_initial_bytes,
+            growable: self.heap_growable,
+        }
+    }
+
+    /// Defines the function in the JIT's context as NAME and then
+    /// clears the context so that the next function can be built.
+    pub(crate) fn define_function(&mut self, name: &str) -> Result<FuncId, LustError> {
+        let id = self
+            .module
+            .declare_function(name, Linkage::Export, &self.context.func.signature)
+            .map_err(LustError::from)?;
+
+        let start = std::time::Instant::now();
+        let compiled = self
+            .module
+            .define_function(
+                id,
+                &mut self.context,
+                &mut codegen::binemit::NullTrapSink {},
+            )
+            .map_err(LustError::from)?;
+        self.compile_times.codegen += start.elapsed();
+        self.code_sizes.insert(id, compiled.size as usize);
+
+        if let Some(ir) = &mut self.ir {
+            ir.push_str(&format!(
+                "; {}\n{}\n",
+                name,
+                self.context.func.display(self.module.isa())
+            ));
+        }
+
+        self.module.clear_context(&mut self.context);
+
+        Ok(id)
+    }
 }
 
 impl<'a> Context<'a> {
     pub fn new(
         builder: FunctionBuilder<'a>,
-        module: &'a mut JITModule,
+        module: &'a mut LustModule,
         word: types::Type,
         env: HashMap<String, Variable>,
         fnmap: HashMap<String, LustFn>,
@@ -104,62 +350,222 @@ impl<'a> Context<'a> {
             env,
             fnmap,
             letstack,
+            tail_position: false,
+            loop_header: None,
+            handlers: Vec::new(),
+            known: HashMap::new(),
+            conditions: HashMap::new(),
+            options: CompileOptions::default(),
+            entry_depth: None,
         }
     }
 }
 
-/// Emits the code for an expression using the given builder.
-pub(crate) fn emit_expr(expr: &Expr, ctx: &mut Context) -> Result<Value, String> {
+impl Expr {
+    /// Determines if the expression is a reference to one of
+    /// lust_entry's arguments and if it is returns which one.
+    fn is_entry_arg(&self) -> Option<usize> {
+        match self {
+            Self::List(v, _) => match &v[..] {
+                [Expr::Symbol(s), Expr::Integer(i)] if s == "__entry-arg" && *i >= 0 => {
+                    Some(*i as usize)
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The name of the variable that holds the INDEXth argument to
+/// lust_entry.
+fn entry_arg_name(index: usize) -> String {
+    format!("__entry-arg-{}", index)
+}
+
+/// Emits the code to get the INDEXth argument to lust_entry.
+fn emit_entry_arg(index: usize, ctx: &mut Context) -> Result<Value, LustError> {
+    match ctx.env.get(&entry_arg_name(index)) {
+        Some(&var) => Ok(ctx.builder.use_var(var)),
+        None => Err(LustError::Compile(format!(
+            "internal error: lust_entry has no argument {}",
+            index
+        ))),
+    }
+}
+
+/// Emits the code for an expression using the given builder. Errors
+/// are located at the innermost list that was read from source.
+pub(crate) fn emit_expr(expr: &Expr, ctx: &mut Context) -> Result<Value, LustError> {
+    emit_unlocated_expr(expr, ctx).map_err(|e| e.at(expr.span()))
+}
+
+fn emit_unlocated_expr(expr: &Expr, ctx: &mut Context) -> Result<Value, LustError> {
+    let tail = std::mem::replace(&mut ctx.tail_position, false);
     Ok(match expr {
-        Expr::Integer(_) => ctx.builder.ins().iconst(ctx.word, expr.immediate_rep()),
+        Expr::Integer(_) => {
+            let val = ctx.builder.ins().iconst(ctx.word, expr.immediate_rep());
+            peephole::record(val, Known::Fixnum, ctx)
+        }
+        Expr::Float(f) => {
+            let val = ctx.builder.ins().f64const(*f);
+            floats::emit_box_float(val, ctx)?
+        }
         Expr::Char(_) => ctx.builder.ins().iconst(ctx.word, expr.immediate_rep()),
         Expr::Bool(_) => ctx.builder.ins().iconst(ctx.word, expr.immediate_rep()),
         Expr::Nil => ctx.builder.ins().iconst(ctx.word, expr.immediate_rep()),
+        Expr::Eof => ctx.builder.ins().iconst(ctx.word, expr.immediate_rep()),
         Expr::Symbol(name) => locals::emit_var_access(name, ctx)?,
-        Expr::List(v) => {
+        Expr::Keyword(_) => ctx.builder.ins().iconst(ctx.word, expr.immediate_rep()),
+        Expr::List(v, _) => {
             if let Some((name, args)) = expr.is_primcall() {
-                primitives::emit_primcall(name, args, ctx)?
+                primitives::emit_primcall(name, args, tail, ctx)?
             } else if let Some((symbol, binding)) = expr.is_let() {
                 locals::emit_let(symbol, binding, ctx)?
             } else if let Some((symbol, binding)) = expr.is_set() {
                 locals::emit_set(symbol, binding, ctx)?
             } else if let Some((cond, then, else_)) = expr.is_conditional() {
-                conditional::emit_conditional(cond, then, else_, ctx)?
-            } else if let Some((message, exit_code)) = expr.is_error() {
-                fatal::emit_error(message, exit_code, ctx)?
+                conditional::emit_conditional(cond, then, else_, tail, ctx)?
+            } else if let Some(clauses) = expr.is_cond() {
+                conditional::emit_cond(clauses, tail, ctx)?
+            } else if let Some((key, clauses)) = expr.is_case() {
+                conditional::emit_case(key, clauses, tail, ctx)?
+            } else if let Some((test, body)) = expr.is_when() {
+                conditional::emit_one_armed(test, body, false, tail, ctx)?
+            } else if let Some((test, body)) = expr.is_unless() {
+                conditional::emit_one_armed(test, body, true, tail, ctx)?
+            } else if let Some(exprs) = expr.is_begin() {
+                sequence::emit_sequence(exprs, tail, ctx)?
+            } else if let Some((test, body)) = expr.is_while() {
+                sequence::emit_while(test, body, ctx)?
+            } else if let Some(exprs) = expr.is_and() {
+                conditional::emit_and(exprs, tail, ctx)?
+            } else if let Some(exprs) = expr.is_or() {
+                conditional::emit_or(exprs, ctx)?
+            } else if let Some(op) = expr.is_record_op() {
+                records::emit_record_op(op, ctx)?
+            } else if let Some(slot) = expr.is_global_ref() {
+                globals::emit_global_ref(slot, ctx)
+            } else if let Some((slot, value)) = expr.is_global_set() {
+                globals::emit_global_set(slot, value, ctx)?
+            } else if let Some(index) = expr.is_entry_arg() {
+                emit_entry_arg(index, ctx)?
+            } else if let Some((message, irritants)) = expr.is_error() {
+                fatal::emit_raise(message, irritants, ctx)?
+            } else if let Some((quoted, asserted)) = expr.is_lowered_assert() {
+                fatal::emit_assert(quoted, asserted, expr.span(), ctx)?
+            } else if let Some((tag, body)) = expr.is_catch() {
+                fatal::emit_catch(tag, body, ctx)?
+            } else if let Some((body, cleanup)) = expr.is_unwind_protect() {
+                fluid::emit_unwind_protect(body, cleanup, ctx)?
+            } else if let Some(timed) = expr.is_time() {
+                timer::emit_time(timed, ctx)?
             } else if let Some((name, args)) = expr.is_foreign_call() {
                 foreign::emit_foreign_call(&name, args, ctx)?
             } else if let Some((head, args)) = expr.is_fncall() {
-                procedures::emit_fncall(head, args, ctx)?
+                match head {
+                    Expr::Symbol(_) | Expr::List(_, _) => {
+                        procedures::emit_fncall(head, args, tail, ctx)?
+                    }
+                    // Anything else is a literal which can never be
+                    // called.
+                    _ => return Err(LustError::IllegalApplication(expr.clone())),
+                }
             } else if v.len() == 0 {
                 // () == Expr::Nil
                 ctx.builder.ins().iconst(ctx.word, expr.immediate_rep())
             } else {
-                return Err(format!("illegal function application {:?}", v));
+                return Err(LustError::IllegalApplication(expr.clone()));
             }
         }
-        Expr::String(s) => {
-            return Err(format!(
-                "unexpected string data in compilation pass: ({:?})",
-                s
-            ))
-        }
+        // Strings are normally replaced by references to the
+        // program's data before compilation, but if one makes it here
+        // its contents are allocated on the heap now and the pointer
+        // is compiled in.
+        Expr::String(_) => ctx.builder.ins().iconst(ctx.word, expr.immediate_rep()),
+        // Pairs and vectors are what the heap gives back to us. Like
+        // strings they are allocated now and compiled in as a
+        // constant.
+        Expr::Pair(..) => ctx.builder.ins().iconst(ctx.word, expr.immediate_rep()),
+        Expr::Vector(_) => ctx.builder.ins().iconst(ctx.word, expr.immediate_rep()),
+        Expr::HashTable(_) => ctx.builder.ins().iconst(ctx.word, expr.immediate_rep()),
+        Expr::Record(..) => ctx.builder.ins().iconst(ctx.word, expr.immediate_rep()),
+        Expr::Procedure(_) => ctx.builder.ins().iconst(ctx.word, expr.immediate_rep()),
     })
 }
 
-pub fn roundtrip_program(program: &mut [Expr]) -> Result<Expr, String> {
-    let mut jit = JIT::default();
+/// Runs the compiler's passes over PROGRAM and emits it, along with
+/// every function that it uses, into JIT. Returns the id of the
+/// lust_entry function that runs the program. lust_entry takes one
+/// integer argument for each of PARAMS which are bound to them before
+/// the program runs. If JIT has a namespace lust_entry is named that
+/// instead.
+pub(crate) fn compile_program(
+    program: &[Expr],
+    params: &[&str],
+    jit: &mut JIT,
+) -> Result<FuncId, LustError> {
+    let _symbols = symbols::enter(&jit.interner);
+
+    // A program evaluates to its last expression. Programs without
+    // any, or that end with a definition, evaluate to nil instead.
+    let ends_with_value = program
+        .last()
+        .is_some_and(|e| e.is_define().is_none() && e.is_define_record_type().is_none());
+    let nil = if ends_with_value {
+        None
+    } else {
+        Some(Expr::Nil)
+    };
+
+    // Parameters are bound with let expressions at the top of the
+    // program so that they get the same treatment as any other
+    // variable.
+    let program = &mut params
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            Expr::list(vec![
+                Expr::Symbol("let".to_string()),
+                Expr::Symbol(p.to_string()),
+                Expr::list(vec![
+                    Expr::Symbol("__entry-arg".to_string()),
+                    Expr::Integer(i as i64),
+                ]),
+            ])
+        })
+        .chain(program.iter().cloned())
+        .chain(nil)
+        .collect::<Vec<_>>();
+
+    conversions::check_fixnum_ranges(program)?;
 
     // Rename symbols so that they are all unique.
-    renamer::make_names_unique(program)?;
+    match &mut jit.globals {
+        Some(globals) => {
+            renamer::make_names_unique_with_globals(program, globals)?;
+            globals::replace_globals(program, globals);
+        }
+        None => renamer::make_names_unique(program)?,
+    }
 
     // Collect primitives that are used as higher order functions.
     let higher_order_primitives = primitives::collect_higher_order_primitives(program)?;
-    // Emit the primitive functions that are used in higher order contexts.
-    let primitive_fns = primitives::emit_primitives(&mut jit, higher_order_primitives)?;
+    // Emit the primitive functions that are used in higher order
+    // contexts and haven't been emitted into the JIT already.
+    let new_primitives = higher_order_primitives
+        .into_iter()
+        .filter(|p| !jit.primitive_fns.contains_key(p))
+        .collect();
+    for f in primitives::emit_primitives(jit, new_primitives)? {
+        jit.primitive_fns.insert(f.name.clone(), f);
+    }
 
     // Initialize program data.
-    let data = data::collect_data(program);
+    let mut data = data::collect_data(program);
+    for d in &mut data {
+        d.name = jit.namespaced(&d.name);
+    }
     // Replace it with references to its location in the JIT.
     data::replace_data(program, &data);
 
@@ -167,15 +573,20 @@ pub fn roundtrip_program(program: &mut [Expr]) -> Result<Expr, String> {
         let _t = crate::timer::timeit("data creation");
         // Store the data in the JIT.
         for d in data {
-            data::create_data(d, &mut jit)?;
+            data::create_data(d, jit)?;
         }
     }
 
+    let lifting = std::time::Instant::now();
+
     // Transforms the program so that anonymous functions are lifted
     // to the top of the program and replaced with their anyonmous
     // names. There is some cool manuvering here that happens to make
     // sure that the bodies of the collected functions are updated.
     let mut functions = procedures::collect_functions(program)?;
+    for f in &mut functions {
+        f.name = jit.namespaced(&f.name);
+    }
     // Annotation needs to happen before replacement so that we can
     // traverse the body of nested functions for free variables that
     // outer functions need to caputre.
@@ -186,96 +597,402 @@ pub fn roundtrip_program(program: &mut [Expr]) -> Result<Expr, String> {
     // Replace functions with their anonymous names.
     procedures::replace_functions(program, &mut functions);
 
+    // Calls to functions that we know about are checked for the
+    // right number of arguments now rather than when they run.
+    let arg_counts = procedures::build_arg_count_map(program, &functions);
+    procedures::check_arg_counts(program, &functions, &arg_counts)?;
+
+    // Calls to small functions are replaced with their bodies.
+    inline::inline_functions(program, &mut functions, jit.options.inline_threshold);
+
+    // Primitive calls on literals are replaced with their results.
+    if jit.options.fold_constants {
+        fold::fold_constants(program, &mut functions, jit.options.checked_arithmetic);
+    }
+
+    // Functions that can never be called don't need to be compiled.
+    if jit.options.remove_unused_functions {
+        procedures::remove_unused_functions(program, &mut functions);
+    }
+
     // Annotate escaped variables in closures
     escape::annotate_escaped_variables(&mut functions, program)?;
 
+    jit.compile_times.lifting += lifting.elapsed();
+
     // Build a map from anonymous names to values
     let mut fnmap = procedures::build_fn_map(functions);
     // Extend the function map with the builtin functions
-    fnmap.extend(primitive_fns.into_iter().map(|f| (f.name.clone(), f)));
+    fnmap.extend(
+        jit.primitive_fns
+            .iter()
+            .map(|(name, f)| (name.clone(), f.clone())),
+    );
 
     {
         let _t = crate::timer::timeit("procedure compilation");
         // Emit all the non-primitive functions into the JIT.
         for (_, f) in fnmap.iter().filter(|(name, _)| !string_is_primitive(name)) {
-            emit_procedure(
-                &mut jit,
-                &f.name,
-                &f.params,
-                &f.body,
-                &f.varadic_symbol,
-                &fnmap,
-            )?;
+            emit_procedure(jit, &f.name, &f.params, &f.body, &f.varadic_symbol, &fnmap)?;
         }
     }
 
-    let code_fn = {
-        let _t = crate::timer::timeit("lust_entry compilation");
+    let _t = crate::timer::timeit("lust_entry compilation");
 
-        let word = jit.module.target_config().pointer_type();
+    let word = jit.module.target_config().pointer_type();
 
-        // Signature for the function that we're compiling. This function
-        // takes no arguments and returns an integer.
-        jit.context.func.signature.returns.push(AbiParam::new(word));
+    // Signature for the function that we're compiling. This function
+    // takes an integer for each parameter and returns an integer.
+    for _ in params {
+        jit.context.func.signature.params.push(AbiParam::new(word));
+    }
+    jit.context.func.signature.returns.push(AbiParam::new(word));
 
-        // Create a new builder for building our function and create a new
-        // block to compile into.
-        let mut builder = FunctionBuilder::new(&mut jit.context.func, &mut jit.builder_context);
-        let entry_block = builder.create_block();
+    // Create a new builder for building our function and create a new
+    // block to compile into.
+    let mut builder = FunctionBuilder::new(&mut jit.context.func, &mut jit.builder_context);
+    let entry_block = builder.create_block();
 
-        // Give the paramaters that we set up earlier to this entry block.
-        builder.append_block_params_for_function_params(entry_block);
-        // Start putting code in the new block.
-        builder.switch_to_block(entry_block);
+    // Give the paramaters that we set up earlier to this entry block.
+    builder.append_block_params_for_function_params(entry_block);
+    // Start putting code in the new block.
+    builder.switch_to_block(entry_block);
 
-        let env = HashMap::new();
+    let env = HashMap::new();
 
-        let mut ctx = Context::new(builder, &mut jit.module, word, env, fnmap, Vec::new());
+    let options = jit.options;
+    let mut ctx = Context::new(builder, &mut jit.module, word, env, fnmap, Vec::new());
+    ctx.options = options;
 
-        let vals = program
-            .iter()
-            .map(|e| emit_expr(e, &mut ctx))
-            .collect::<Result<Vec<_>, _>>()?;
+    // Arguments come in as integers and need to be tagged as fixnums
+    // before the program can use them.
+    for i in 0..params.len() {
+        let arg = ctx.builder.block_params(entry_block)[i];
+        let arg = ctx.builder.ins().ishl_imm(arg, conversions::FIXNUM_SHIFT);
+        locals::emit_var_decl_and_assign(&entry_arg_name(i), arg, &mut ctx)?;
+    }
 
-        // Emit a return instruction to return the result.
-        ctx.builder.ins().return_(&[*vals
-            .last()
-            .ok_or("expected at least one expression".to_string())?]);
+    let vals = program
+        .iter()
+        .map(|e| emit_expr(e, &mut ctx))
+        .collect::<Result<Vec<_>, _>>()?;
 
-        // Clean up
-        ctx.builder.seal_all_blocks();
-        ctx.builder.finalize();
+    // Emit a return instruction to return the result.
+    ctx.builder.ins().return_(&[*vals
+        .last()
+        .ok_or("expected at least one expression".to_string())?]);
 
-        let id = jit
-            .module
-            .declare_function("lust_entry", Linkage::Export, &jit.context.func.signature)
-            .map_err(|e| e.to_string())?;
+    // Clean up
+    ctx.builder.seal_all_blocks();
+    ctx.builder.finalize();
 
-        jit.module
-            .define_function(id, &mut jit.context, &mut codegen::binemit::NullTrapSink {})
-            .map_err(|e| e.to_string())?;
+    let name = jit
+        .namespace
+        .clone()
+        .unwrap_or_else(|| "lust_entry".to_string());
+    jit.define_function(&name)
+}
 
-        // If you want to dump the generated IR this is the way:
-        // println!("{}", jit.context.func.display(jit.module.isa()));
+/// Compiles and runs PROGRAM returning the result of its last
+/// expression.
+pub fn eval_program(program: &[Expr]) -> Result<Expr, LustError> {
+    eval_program_with_args(program, &[], &[])
+}
 
+/// Compiles and runs PROGRAM with each of PARAMS bound to the integer
+/// in ARGS at the same position. Returns the result of the program's
+/// last expression.
+pub fn eval_program_with_args(
+    program: &[Expr],
+    params: &[&str],
+    args: &[i64],
+) -> Result<Expr, LustError> {
+    eval_program_in(JIT::default(), program, params, args)
+}
+
+/// Like eval_program_with_args but compiles PROGRAM into JIT. Lets
+/// the caller decide how the program is compiled, for example with
+/// checked arithmetic.
+pub fn eval_program_in(
+    mut jit: JIT,
+    program: &[Expr],
+    params: &[&str],
+    args: &[i64],
+) -> Result<Expr, LustError> {
+    check_entry_args(params, args)?;
+    let entry = compile_entry(&mut jit, program, params)?;
+    // Safety: lust_entry was just compiled into JIT.
+    unsafe { run_entry(&jit, entry, args) }
+}
+
+/// Checks that lust_entry, compiled with PARAMS, can be called with
+/// ARGS.
+pub(crate) fn check_entry_args(params: &[&str], args: &[i64]) -> Result<(), LustError> {
+    if params.len() != args.len() {
+        return Err(LustError::ArityMismatch {
+            name: "lust_entry".to_string(),
+            expected: params.len(),
+            got: args.len(),
+            varadic: false,
+        });
+    }
+    if let Some(arg) = args
+        .iter()
+        .find(|a| !(conversions::FIXNUM_MIN..=conversions::FIXNUM_MAX).contains(a))
+    {
+        return Err(LustError::Compile(format!(
+            "lust_entry argument out of range: {}",
+            arg
+        )));
+    }
+    // Arguments are passed in registers so we can call lust_entry
+    // without needing to know how to push things onto the stack.
+    if args.len() > MAX_ENTRY_ARGS {
+        return Err(LustError::Compile(format!(
+            "lust_entry can take at most {} arguments, got {}",
+            MAX_ENTRY_ARGS,
+            args.len()
+        )));
+    }
+    Ok(())
+}
+
+/// A finalized lust_entry function. Its signature has been checked
+/// to take PARAMS words and return one so run_entry knows how to call
+/// it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Entry {
+    code_ptr: *const u8,
+    params: usize,
+}
+
+/// Finalizes the functions defined in MODULE and gets the lust_entry
+/// function ID out of it. Fails if ID doesn't use the native calling
+/// convention to take PARAMS words and return one word as calling it
+/// any other way would be undefined behavior.
+pub(crate) fn finalize_entry(
+    module: &mut LustModule,
+    id: FuncId,
+    params: usize,
+) -> Result<Entry, LustError> {
+    let word = AbiParam::new(module.target_config().pointer_type());
+    let call_conv = module.isa().default_call_conv();
+    let signature = &module.declarations().get_function_decl(id).signature;
+    if signature.call_conv != call_conv
+        || signature.params != vec![word; params]
+        || signature.returns != [word]
+    {
+        return Err(LustError::Compile(format!(
+            "internal error: lust_entry has signature {} but is called with {} arguments",
+            signature, params
+        )));
+    }
+
+    module.finalize_definitions();
+    let code_ptr = module.get_finalized_function(id)?;
+    Ok(Entry { code_ptr, params })
+}
+
+/// Compiles PROGRAM into JIT and returns its finalized lust_entry
+/// function. The function is good for as long as JIT is. If PROGRAM
+/// fails to compile JIT can still be used for other programs.
+pub(crate) fn compile_entry(
+    jit: &mut JIT,
+    program: &[Expr],
+    params: &[&str],
+) -> Result<Entry, LustError> {
+    let res = compile_program(program, params, jit)
+        .and_then(|id| finalize_entry(&mut jit.module, id, params.len()));
+    if res.is_err() {
+        // Throw away whatever was being built when compilation
+        // stopped.
         jit.module.clear_context(&mut jit.context);
+        jit.builder_context = FunctionBuilderContext::new();
+    }
+    res
+}
+
+/// Calls ENTRY with ARGS and reads back its result. The heap is
+/// configured the way JIT asks for.
+///
+/// # Safety
+///
+/// ENTRY must have been finalized in JIT's module. Its signature and
+/// the number of ARGS are checked so the code being freed is the only
+/// way that this can go wrong.
+pub(crate) unsafe fn run_entry(jit: &JIT, entry: Entry, args: &[i64]) -> Result<Expr, LustError> {
+    if args.len() != entry.params {
+        return Err(LustError::ArityMismatch {
+            name: "lust_entry".to_string(),
+            expected: entry.params,
+            got: args.len(),
+            varadic: false,
+        });
+    }
+    let code_ptr = entry.code_ptr;
+    let _symbols = symbols::enter(&jit.interner);
+    let _t = crate::timer::timeit("program execution");
+    // Collections scan the stack between here and wherever the
+    // program is when it allocates.
+    let res = crate::gc::with_stack_base(jit.heap_config(), || {
+        use std::mem::transmute;
+        match *args {
+            [] => {
+                let f: extern "C" fn() -> i64 = transmute(code_ptr);
+                f()
+            }
+            [a] => {
+                let f: extern "C" fn(i64) -> i64 = transmute(code_ptr);
+                f(a)
+            }
+            [a, b] => {
+                let f: extern "C" fn(i64, i64) -> i64 = transmute(code_ptr);
+                f(a, b)
+            }
+            [a, b, c] => {
+                let f: extern "C" fn(i64, i64, i64) -> i64 = transmute(code_ptr);
+                f(a, b, c)
+            }
+            [a, b, c, d] => {
+                let f: extern "C" fn(i64, i64, i64, i64) -> i64 = transmute(code_ptr);
+                f(a, b, c, d)
+            }
+            [a, b, c, d, e] => {
+                let f: extern "C" fn(i64, i64, i64, i64, i64) -> i64 = transmute(code_ptr);
+                f(a, b, c, d, e)
+            }
+            [a, b, c, d, e, g] => {
+                let f: extern "C" fn(i64, i64, i64, i64, i64, i64) -> i64 = transmute(code_ptr);
+                f(a, b, c, d, e, g)
+            }
+            _ => unreachable!(),
+        }
+    });
+    if res == conversions::RAISED_VALUE {
+        return Err(fatal::take_raised()
+            .unwrap_or_else(|| LustError::Compile("internal error: no raised error".to_string())));
+    }
+    Ok(Expr::from_immediate(res))
+}
+
+/// Compiles PROGRAM without running it and returns the Cranelift IR
+/// of every function that was emitted. Useful for figuring out why
+/// generated code is wrong.
+pub fn compile_to_ir(program: &[Expr]) -> Result<String, String> {
+    compile_to_ir_with_options(program, CompileOptions::default())
+}
 
-        jit.module.finalize_definitions();
+/// Like compile_to_ir but compiles PROGRAM with OPTIONS. Useful for
+/// seeing what a pass does to a program.
+pub fn compile_to_ir_with_options(
+    program: &[Expr],
+    options: CompileOptions,
+) -> Result<String, String> {
+    let mut jit = JIT::new(Some(String::new()));
+    jit.options = options;
+    compile_program(program, &[], &mut jit).map_err(|e| e.to_string())?;
+    Ok(jit.ir.unwrap_or_default())
+}
 
-        let code_ptr = jit.module.get_finalized_function(id);
+/// Compiles PROGRAM without running it and returns the machine code
+/// of its lust_entry function, ready to be given to a disassembler.
+/// The length of the code is the size of the function. Functions that
+/// lust_entry calls, including the program's lambdas, are compiled on
+/// their own and aren't included.
+pub fn disassemble(program: &[Expr]) -> Result<Vec<u8>, String> {
+    let mut jit = JIT::default();
+    let id = compile_program(program, &[], &mut jit).map_err(|e| e.to_string())?;
+    let entry = finalize_entry(&mut jit.module, id, 0).map_err(|e| e.to_string())?;
+    let size = jit.code_sizes[&id];
+    // Safety: the entry was finalized with SIZE bytes of code and JIT,
+    // which owns the code, is still around.
+    let code = unsafe { std::slice::from_raw_parts(entry.code_ptr, size) };
+    Ok(code.to_vec())
+}
+
+/// Compiles PROGRAM into an object file at PATH. The object file
+/// exports a lust_entry function that runs the program and returns
+/// the immediate representation of its result. Runtime functions, and
+/// malloc which the heap allocator uses, are imported and need to be
+/// provided when linking.
+pub fn compile_to_object(program: &[Expr], path: &Path) -> Result<(), String> {
+    compile_to_object_with_options(program, path, CompileOptions::default())
+}
+
+/// Like compile_to_object but compiles PROGRAM with OPTIONS.
+pub fn compile_to_object_with_options(
+    program: &[Expr],
+    path: &Path,
+    options: CompileOptions,
+) -> Result<(), String> {
+    let mut jit = JIT::object("lust").map_err(|e| e.to_string())?;
+    jit.options = options;
+    compile_program(program, &[], &mut jit).map_err(|e| e.to_string())?;
+    jit.module.write_object(path).map_err(|e| e.to_string())
+}
+
+/// Like compile_to_object but cross compiles for TARGET. The runtime
+/// functions need to be built for TARGET as well to link against it.
+pub fn compile_to_object_for(program: &[Expr], path: &Path, target: Triple) -> Result<(), String> {
+    compile_to_object_for_with_options(program, path, target, CompileOptions::default())
+}
+
+/// Like compile_to_object_for but compiles PROGRAM with OPTIONS.
+pub fn compile_to_object_for_with_options(
+    program: &[Expr],
+    path: &Path,
+    target: Triple,
+    options: CompileOptions,
+) -> Result<(), String> {
+    let mut jit = JIT::object_for("lust", target).map_err(|e| e.to_string())?;
+    jit.options = options;
+    compile_program(program, &[], &mut jit).map_err(|e| e.to_string())?;
+    jit.module.write_object(path).map_err(|e| e.to_string())
+}
+
+/// Like eval_program but with errors converted into strings.
+pub fn roundtrip_program(program: &[Expr]) -> Result<Expr, String> {
+    eval_program(program).map_err(|e| e.to_string())
+}
 
-        unsafe { std::mem::transmute::<_, fn() -> i64>(code_ptr) }
+/// Like roundtrip_program but compiles PROGRAM with OPTIONS.
+pub fn roundtrip_program_with_options(
+    program: &[Expr],
+    options: CompileOptions,
+) -> Result<Expr, String> {
+    let jit = JIT {
+        options,
+        ..Default::default()
     };
+    eval_program_in(jit, program, &[], &[]).map_err(|e| e.to_string())
+}
 
-    let _t = crate::timer::timeit("program execution");
-    Ok(Expr::from_immediate(code_fn()))
+/// Like roundtrip_program but also returns how long compiling
+/// PROGRAM took.
+pub fn roundtrip_program_timed(program: &[Expr]) -> Result<(Expr, CompileTimes), String> {
+    let mut jit = JIT::default();
+    let entry = compile_entry(&mut jit, program, &[]).map_err(|e| e.to_string())?;
+    // Safety: lust_entry was just compiled into JIT.
+    let res = unsafe { run_entry(&jit, entry, &[]) }.map_err(|e| e.to_string())?;
+    Ok((res, jit.compile_times))
+}
+
+/// Like eval_program_with_args but with errors converted into
+/// strings.
+pub fn roundtrip_program_with_args(
+    program: &[Expr],
+    params: &[&str],
+    args: &[i64],
+) -> Result<Expr, String> {
+    eval_program_with_args(program, params, args).map_err(|e| e.to_string())
 }
 
 /// Compiles an expression and returns the result converted back into
 /// an expression.
 #[cfg(test)]
-pub fn roundtrip_expr(expr: Expr) -> Result<Expr, String> {
+pub fn roundtrip_expr(expr: Expr) -> Result<Expr, LustError> {
     let mut jit = JIT::default();
+    let _symbols = symbols::enter(&jit.interner);
 
     let word = jit.module.target_config().pointer_type();
 
@@ -326,29 +1043,18 @@ pub fn roundtrip_expr(expr: Expr) -> Result<Expr, String> {
         ctx.builder.func.signature.clone()
     };
 
-    let id = jit
-        .module
-        .declare_function("lust_entry", Linkage::Export, &signature)
-        .map_err(|e| e.to_string())?;
-
-    jit.module
-        .define_function(id, &mut jit.context, &mut codegen::binemit::NullTrapSink {})
-        .map_err(|e| e.to_string())?;
+    jit.context.func.signature = signature;
+    let id = jit.define_function("lust_entry")?;
+    let entry = finalize_entry(&mut jit.module, id, 0)?;
 
-    jit.module.clear_context(&mut jit.context);
-
-    jit.module.finalize_definitions();
-
-    let code_ptr = jit.module.get_finalized_function(id);
-
-    let code_fn = unsafe { std::mem::transmute::<_, fn() -> i64>(code_ptr) };
-
-    Ok(Expr::from_immediate(code_fn()))
+    // Safety: lust_entry was just compiled into JIT.
+    unsafe { run_entry(&jit, entry, &[]) }
 }
 
 #[cfg(test)]
-pub fn roundtrip_exprs(exprs: &[Expr]) -> Result<Expr, String> {
+pub fn roundtrip_exprs(exprs: &[Expr]) -> Result<Expr, LustError> {
     let mut jit = JIT::default();
+    let _symbols = symbols::enter(&jit.interner);
 
     let word = jit.module.target_config().pointer_type();
 
@@ -391,25 +1097,71 @@ pub fn roundtrip_exprs(exprs: &[Expr]) -> Result<Expr, String> {
     ctx.builder.seal_all_blocks();
     ctx.builder.finalize();
 
-    let id = jit
-        .module
-        .declare_function("lust_entry", Linkage::Export, &jit.context.func.signature)
-        .map_err(|e| e.to_string())?;
-
-    jit.module
-        .define_function(id, &mut jit.context, &mut codegen::binemit::NullTrapSink {})
-        .map_err(|e| e.to_string())?;
+    let id = jit.define_function("lust_entry")?;
+    let entry = finalize_entry(&mut jit.module, id, 0)?;
 
-    // If you want to dump the generated IR this is the way:
-    // println!("{}", jit.context.func.display(jit.module.isa()));
-
-    jit.module.clear_context(&mut jit.context);
-
-    jit.module.finalize_definitions();
+    // Safety: lust_entry was just compiled into JIT.
+    unsafe { run_entry(&jit, entry, &[]) }
+}
 
-    let code_ptr = jit.module.get_finalized_function(id);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_string;
+
+    #[test]
+    fn entry_signature_checked() {
+        let program = parse_string("(add n 1)").unwrap();
+        let mut jit = JIT::default();
+        let id = compile_program(&program, &["n"], &mut jit).unwrap();
+        assert!(finalize_entry(&mut jit.module, id, 0).is_err());
+        let entry = finalize_entry(&mut jit.module, id, 1).unwrap();
+        // Safety: lust_entry was just compiled into JIT.
+        assert_eq!(
+            unsafe { run_entry(&jit, entry, &[41]) },
+            Ok(Expr::Integer(42))
+        );
+        assert_eq!(
+            unsafe { run_entry(&jit, entry, &[]) },
+            Err(LustError::ArityMismatch {
+                name: "lust_entry".to_string(),
+                expected: 1,
+                got: 0,
+                varadic: false,
+            })
+        );
+    }
 
-    let code_fn = unsafe { std::mem::transmute::<_, fn() -> i64>(code_ptr) };
+    #[test]
+    fn empty_programs() {
+        assert_eq!(eval_program(&[]), Ok(Expr::Nil));
+        assert_eq!(roundtrip_program(&[]), Ok(Expr::Nil));
+        assert_eq!(eval_program_with_args(&[], &["n"], &[1]), Ok(Expr::Nil));
+
+        let program = parse_string("(define x 1)").unwrap();
+        assert_eq!(eval_program(&program), Ok(Expr::Nil));
+        let program = parse_string("(define (f) 1) (define (g) (f))").unwrap();
+        assert_eq!(eval_program(&program), Ok(Expr::Nil));
+        let program = parse_string("(define x 1) x").unwrap();
+        assert_eq!(eval_program(&program), Ok(Expr::Integer(1)));
+    }
 
-    Ok(Expr::from_immediate(code_fn()))
+    #[test]
+    fn cross_compiled_word() {
+        // The entry's words are the target's pointers.
+        let program = parse_string("(add n 1)").unwrap();
+        let mut jit = JIT::object_for("lust", "x86_64-apple-darwin".parse().unwrap()).unwrap();
+        let id = compile_program(&program, &["n"], &mut jit).unwrap();
+        let word = jit.module.target_config().pointer_type();
+        let signature = &jit.module.declarations().get_function_decl(id).signature;
+        assert_eq!(signature.params, vec![AbiParam::new(word)]);
+        assert_eq!(signature.returns, vec![AbiParam::new(word)]);
+        assert_eq!(word, types::I64);
+
+        // Cranelift can't compile for every target and only has the
+        // host's backend built in.
+        for target in &["i686-unknown-linux-gnu", "aarch64-apple-darwin"] {
+            assert!(JIT::object_for("lust", target.parse().unwrap()).is_err());
+        }
+    }
 }
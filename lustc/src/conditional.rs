@@ -2,12 +2,15 @@ use cranelift::prelude::*;
 
 use crate::compiler::emit_expr;
 use crate::compiler::Context;
+use crate::errors::LustError;
+use crate::peephole;
+use crate::sequence::emit_sequence;
 use crate::Expr;
 
 impl Expr {
     pub fn is_conditional(&self) -> Option<(&Expr, &Expr, &Expr)> {
         match self {
-            Self::List(v) => {
+            Self::List(v, _) => {
                 if let Some(Expr::Symbol(s)) = v.first() {
                     if s == "if" && v.len() == 4 {
                         Some((&v[1], &v[2], &v[3]))
@@ -21,19 +24,87 @@ impl Expr {
             _ => None,
         }
     }
+
+    /// Determines if the expression is an and expression and if it is
+    /// returns the expressions being and'ed together.
+    pub fn is_and(&self) -> Option<&[Expr]> {
+        self.is_logical("and")
+    }
+
+    /// Determines if the expression is an or expression and if it is
+    /// returns the expressions being or'ed together.
+    pub fn is_or(&self) -> Option<&[Expr]> {
+        self.is_logical("or")
+    }
+
+    /// Determines if the expression is a cond expression and if it is
+    /// returns its clauses.
+    pub fn is_cond(&self) -> Option<&[Expr]> {
+        self.is_logical("cond")
+    }
+
+    /// Determines if the expression is a when expression and if it is
+    /// returns its test and body.
+    pub fn is_when(&self) -> Option<(&Expr, &[Expr])> {
+        self.is_one_armed("when")
+    }
+
+    /// Determines if the expression is an unless expression and if it
+    /// is returns its test and body.
+    pub fn is_unless(&self) -> Option<(&Expr, &[Expr])> {
+        self.is_one_armed("unless")
+    }
+
+    /// Determines if the expression is a case expression and if it is
+    /// returns its key and clauses.
+    pub fn is_case(&self) -> Option<(&Expr, &[Expr])> {
+        match self.is_logical("case") {
+            Some([key, clauses @ ..]) => Some((key, clauses)),
+            _ => None,
+        }
+    }
+
+    fn is_one_armed(&self, name: &str) -> Option<(&Expr, &[Expr])> {
+        match self.is_logical(name) {
+            Some([test, body @ ..]) => Some((test, body)),
+            _ => None,
+        }
+    }
+
+    fn is_logical(&self, name: &str) -> Option<&[Expr]> {
+        match self {
+            Self::List(v, _) => match v.first() {
+                Some(Expr::Symbol(s)) if s == name => Some(&v[1..]),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Emits a value that is non-zero if VAL is #t and zero otherwise,
+/// for branching on. Booleans that come straight from a comparison
+/// are branched on using the comparison's result instead of being
+/// checked again.
+pub(crate) fn emit_is_true(val: Value, ctx: &mut Context) -> Value {
+    match peephole::condition(val, ctx) {
+        Some(condition) => condition,
+        None => ctx
+            .builder
+            .ins()
+            .icmp_imm(IntCC::Equal, val, Expr::Bool(true).immediate_rep()),
+    }
 }
 
 pub(crate) fn emit_conditional(
     cond: &Expr,
     then: &Expr,
     else_: &Expr,
+    tail: bool,
     ctx: &mut Context,
-) -> Result<Value, String> {
+) -> Result<Value, LustError> {
     let cond = emit_expr(cond, ctx)?;
-    let cond = ctx
-        .builder
-        .ins()
-        .icmp_imm(IntCC::Equal, cond, Expr::Bool(true).immediate_rep());
+    let cond = emit_is_true(cond, ctx);
 
     let then_block = ctx.builder.create_block();
     let else_block = ctx.builder.create_block();
@@ -53,6 +124,9 @@ pub(crate) fn emit_conditional(
     ctx.builder.switch_to_block(then_block);
     ctx.builder.seal_block(then_block);
 
+    // If the conditional is in tail position then so are both of its
+    // branches.
+    ctx.tail_position = tail;
     let then_return = emit_expr(then, ctx)?;
 
     ctx.builder.ins().jump(merge_block, &[then_return]);
@@ -61,6 +135,7 @@ pub(crate) fn emit_conditional(
     ctx.builder.switch_to_block(else_block);
     ctx.builder.seal_block(else_block);
 
+    ctx.tail_position = tail;
     let else_return = emit_expr(else_, ctx)?;
 
     ctx.builder.ins().jump(merge_block, &[else_return]);
@@ -75,9 +150,280 @@ pub(crate) fn emit_conditional(
     Ok(res)
 }
 
+/// Emits a when expression, or an unless expression if UNLESS is
+/// set, by lowering it into a conditional with a nil branch:
+///
+/// (when a 1 2) => (if a (begin 1 2) ())
+/// (unless a 1 2) => (if a () (begin 1 2))
+pub(crate) fn emit_one_armed(
+    test: &Expr,
+    body: &[Expr],
+    unless: bool,
+    tail: bool,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let mut sequence = vec![Expr::Symbol("begin".to_string())];
+    sequence.extend_from_slice(body);
+    let sequence = Expr::list(sequence);
+
+    if unless {
+        emit_conditional(test, &Expr::Nil, &sequence, tail, ctx)
+    } else {
+        emit_conditional(test, &sequence, &Expr::Nil, tail, ctx)
+    }
+}
+
+/// Splits a cond CLAUSE into its test and body. The test of an else
+/// clause is None.
+fn cond_clause(clause: &Expr) -> Result<(Option<&Expr>, &[Expr]), LustError> {
+    match clause {
+        Expr::List(v, _) => match v.first() {
+            Some(Expr::Symbol(s)) if s == "else" => Ok((None, &v[1..])),
+            Some(test) => Ok((Some(test), &v[1..])),
+            None => unreachable!("empty lists are nil"),
+        },
+        _ => Err(LustError::Compile(format!(
+            "cond clause must be a list, got: {}",
+            clause
+        ))),
+    }
+}
+
+/// Emits a cond expression by lowering it into a chain of
+/// conditionals:
+///
+/// (cond (a 1) (b 2) (else 3)) => (if a (begin 1) (cond (b 2) (else 3)))
+///
+/// A cond where no clause matches evaluates to nil.
+pub(crate) fn emit_cond(
+    clauses: &[Expr],
+    tail: bool,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let (clause, rest) = match clauses.split_first() {
+        Some(split) => split,
+        None => {
+            return Ok(ctx
+                .builder
+                .ins()
+                .iconst(ctx.word, Expr::Nil.immediate_rep()))
+        }
+    };
+    let (test, body) = cond_clause(clause)?;
+    let test = match test {
+        Some(test) => test,
+        None if rest.is_empty() => return emit_sequence(body, tail, ctx),
+        None => {
+            return Err(LustError::Compile(
+                "else clause must be the last clause in cond".to_string(),
+            ))
+        }
+    };
+
+    let sequence = |keyword: &str, exprs: &[Expr]| {
+        let mut v = vec![Expr::Symbol(keyword.to_string())];
+        v.extend_from_slice(exprs);
+        Expr::list(v)
+    };
+
+    emit_conditional(
+        test,
+        &sequence("begin", body),
+        &sequence("cond", rest),
+        tail,
+        ctx,
+    )
+}
+
+/// Emits a case expression. KEY is evaluated once and then compared
+/// against the datums of each clause in turn. The body of the first
+/// clause with a datum that is eq? to the key is the result:
+///
+/// (case (f) ((1 2) a) ((3) b) (else c))
+///
+/// The renaming pass leaves integers, characters, booleans and nil as
+/// is and quotes symbols, which by now have become program data. A
+/// case where no clause matches evaluates to nil.
+pub(crate) fn emit_case(
+    key: &Expr,
+    clauses: &[Expr],
+    tail: bool,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let key = emit_expr(key, ctx)?;
+
+    let merge_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(merge_block, ctx.word);
+
+    let mut matched_else = false;
+    for (i, clause) in clauses.iter().enumerate() {
+        let (datums, body) = match clause {
+            Expr::List(v, _) => (&v[0], &v[1..]),
+            _ => {
+                return Err(LustError::Compile(format!(
+                    "case clause must be a list, got: {}",
+                    clause
+                )))
+            }
+        };
+
+        if let Expr::Symbol(s) = datums {
+            if s == "else" {
+                if i != clauses.len() - 1 {
+                    return Err(LustError::Compile(
+                        "else clause must be the last clause in case".to_string(),
+                    ));
+                }
+                let res = emit_sequence(body, tail, ctx)?;
+                ctx.builder.ins().jump(merge_block, &[res]);
+                matched_else = true;
+                break;
+            }
+        }
+
+        // The key matches the clause if it is equal to any of the
+        // datums.
+        let mut matches = ctx.builder.ins().iconst(ctx.word, 0);
+        for datum in case_datums(datums)? {
+            let is_datum = if is_word_literal(datum) {
+                ctx.builder
+                    .ins()
+                    .icmp_imm(IntCC::Equal, key, datum.immediate_rep())
+            } else {
+                let datum = emit_expr(datum, ctx)?;
+                ctx.builder.ins().icmp(IntCC::Equal, key, datum)
+            };
+            let is_datum = ctx.builder.ins().bint(ctx.word, is_datum);
+            matches = ctx.builder.ins().bor(matches, is_datum);
+        }
+
+        let body_block = ctx.builder.create_block();
+        let next_block = ctx.builder.create_block();
+        ctx.builder.ins().brnz(matches, body_block, &[]);
+        ctx.builder.ins().jump(next_block, &[]);
+
+        ctx.builder.switch_to_block(body_block);
+        ctx.builder.seal_block(body_block);
+        let res = emit_sequence(body, tail, ctx)?;
+        ctx.builder.ins().jump(merge_block, &[res]);
+
+        ctx.builder.switch_to_block(next_block);
+        ctx.builder.seal_block(next_block);
+    }
+
+    if !matched_else {
+        let nil = ctx
+            .builder
+            .ins()
+            .iconst(ctx.word, Expr::Nil.immediate_rep());
+        ctx.builder.ins().jump(merge_block, &[nil]);
+    }
+
+    ctx.builder.switch_to_block(merge_block);
+    ctx.builder.seal_block(merge_block);
+
+    Ok(ctx.builder.block_params(merge_block)[0])
+}
+
+/// Gets the datums in the list of datums DATUMS that starts a case
+/// clause. The empty list, written (), has none.
+pub(crate) fn case_datums(datums: &Expr) -> Result<&[Expr], LustError> {
+    match datums {
+        Expr::List(v, _) => Ok(v),
+        Expr::Nil => Ok(&[]),
+        _ => Err(LustError::Compile(format!(
+            "case clause must start with a list of datums, got: {}",
+            datums
+        ))),
+    }
+}
+
+/// Determines if EXPR is a literal whose value is a single word and
+/// can be compared against directly.
+pub(crate) fn is_word_literal(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Integer(_) | Expr::Char(_) | Expr::Bool(_) | Expr::Nil | Expr::Keyword(_)
+    )
+}
+
+/// Emits EXPRS one after another jumping to the end as soon as one
+/// of them is false (if STOP_ON_FALSE is set) or true (otherwise). The
+/// value that caused the jump is the result. If none cause a jump the
+/// result is whatever FINALLY emits. As in Scheme, only #f is
+/// considered false.
+fn emit_short_circuit<F>(
+    exprs: &[Expr],
+    stop_on_false: bool,
+    ctx: &mut Context,
+    finally: F,
+) -> Result<Value, LustError>
+where
+    F: FnOnce(&mut Context) -> Result<Value, LustError>,
+{
+    let merge_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(merge_block, ctx.word);
+
+    for e in exprs {
+        let val = emit_expr(e, ctx)?;
+        let is_false =
+            ctx.builder
+                .ins()
+                .icmp_imm(IntCC::Equal, val, Expr::Bool(false).immediate_rep());
+
+        let next_block = ctx.builder.create_block();
+        if stop_on_false {
+            ctx.builder.ins().brnz(is_false, merge_block, &[val]);
+        } else {
+            ctx.builder.ins().brz(is_false, merge_block, &[val]);
+        }
+        ctx.builder.ins().jump(next_block, &[]);
+
+        ctx.builder.switch_to_block(next_block);
+        ctx.builder.seal_block(next_block);
+    }
+
+    let val = finally(ctx)?;
+    ctx.builder.ins().jump(merge_block, &[val]);
+
+    ctx.builder.switch_to_block(merge_block);
+    ctx.builder.seal_block(merge_block);
+
+    Ok(ctx.builder.block_params(merge_block)[0])
+}
+
+/// Emits an and expression. The result is the first false value or
+/// the value of the last expression. `(and)` is #t.
+pub(crate) fn emit_and(exprs: &[Expr], tail: bool, ctx: &mut Context) -> Result<Value, LustError> {
+    match exprs.split_last() {
+        None => Ok(ctx
+            .builder
+            .ins()
+            .iconst(ctx.word, Expr::Bool(true).immediate_rep())),
+        Some((last, rest)) => emit_short_circuit(rest, true, ctx, |ctx| {
+            // The last expression's value is the value of the whole
+            // expression so it inherits our tail position.
+            ctx.tail_position = tail;
+            emit_expr(last, ctx)
+        }),
+    }
+}
+
+/// Emits an or expression. The result is the first value that isn't
+/// false or nil if there isn't one.
+pub(crate) fn emit_or(exprs: &[Expr], ctx: &mut Context) -> Result<Value, LustError> {
+    emit_short_circuit(exprs, false, ctx, |ctx| {
+        Ok(ctx
+            .builder
+            .ins()
+            .iconst(ctx.word, Expr::Nil.immediate_rep()))
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::roundtrip_string;
 
     fn test_evaluation(exprs: &[Expr], expected: Expr) {
         assert_eq!(crate::compiler::roundtrip_exprs(exprs).unwrap(), expected)
@@ -85,7 +431,7 @@ mod tests {
 
     #[test]
     fn if_return() {
-        let ast = [Expr::List(vec![
+        let ast = [Expr::list(vec![
             Expr::Symbol("if".to_string()),
             Expr::Bool(true),
             Expr::Integer(10),
@@ -94,7 +440,7 @@ mod tests {
         let expected = Expr::Integer(10);
         test_evaluation(&ast, expected);
 
-        let ast = [Expr::List(vec![
+        let ast = [Expr::list(vec![
             Expr::Symbol("if".to_string()),
             Expr::Bool(false),
             Expr::Integer(10),
@@ -106,9 +452,9 @@ mod tests {
 
     #[test]
     fn if_let() {
-        let ast = [Expr::List(vec![
+        let ast = [Expr::list(vec![
             Expr::Symbol("if".to_string()),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("tel".to_string()),
                 Expr::Bool(true),
@@ -119,4 +465,192 @@ mod tests {
         let expected = Expr::Bool(true);
         test_evaluation(&ast, expected);
     }
+
+    #[test]
+    fn cond() {
+        let source = r#"
+(let sign (fn (n)
+  (cond ((lt n 0) -1)
+        (else (cons n n) 1))))
+(cons (sign -5) (sign 5))
+"#;
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(Expr::Integer(-1), Expr::Integer(1))
+        );
+
+        let source = r#"
+(let classify (fn (n)
+  (cond ((lt n 0) -1)
+        ((eq n 0) 0)
+        (else 1))))
+(cons (classify 0) (classify 10))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(Expr::Integer(0), Expr::Integer(1))
+        );
+    }
+
+    #[test]
+    fn case() {
+        let source = r#"
+(let classify (fn (n)
+  (case n
+    ((0) 'zero)
+    ((1 3 5 7 9) 'odd)
+    ((2 4 6 8) 'even)
+    ((-1) 'negative)
+    (else 'big))))
+(list (classify 0) (classify 7) (classify 4) (classify -1) (classify 10))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(zero odd even negative big)").unwrap()
+        );
+
+        let source = r#"
+(let kind (fn (x)
+  (case x
+    ((#\a #\e) 1)
+    ((#t ()) 2)
+    ((red green) 3))))
+(list (kind #\e) (kind ()) (kind 'green) (kind 'blue) (kind 5))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(1 2 3 () ())").unwrap()
+        );
+    }
+
+    #[test]
+    fn case_key_evaluated_once() {
+        let source = r#"
+(let count 0)
+(let next (fn () (set! count (add1 count)) count))
+(let res (case (next) ((5) 'five) ((3) 'three) (else 'other)))
+(cons res count)
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::Pair(
+                Box::new(Expr::Symbol("other".to_string())),
+                Box::new(Expr::Integer(1))
+            )
+        );
+    }
+
+    #[test]
+    fn case_errors() {
+        assert_eq!(
+            roundtrip_string("(case 1 (else 1) ((1) 2))").unwrap_err(),
+            "else clause must be the last clause in case at line 1, column 1"
+        );
+        assert!(roundtrip_string("(case 1 ((\"a\") 1))").is_err());
+    }
+
+    #[test]
+    fn cond_first_match() {
+        let source = r#"
+(let x 0)
+(cond (#t (set x (add1 x)) x)
+      (#t (set x 10) x))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(1));
+    }
+
+    #[test]
+    fn cond_no_match() {
+        assert_eq!(
+            roundtrip_string("(cond (#f 1) ((eq 1 2) 2))").unwrap(),
+            Expr::Nil
+        );
+        assert_eq!(roundtrip_string("(cond)").unwrap(), Expr::Nil);
+    }
+
+    #[test]
+    fn cond_in_tail_position() {
+        let source = r#"
+(let count (fn (n)
+  (cond ((eq n 0) 0)
+        (else (count (sub n 1))))))
+(count 1000000)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(0));
+    }
+
+    #[test]
+    fn cond_else_not_last() {
+        let err = roundtrip_string("(cond (else 1) (#t 2))").unwrap_err();
+        assert_eq!(
+            err,
+            "else clause must be the last clause in cond at line 1, column 1"
+        );
+    }
+
+    #[test]
+    fn when_unless() {
+        assert_eq!(roundtrip_string("(when #t 1 2)").unwrap(), Expr::Integer(2));
+        assert_eq!(roundtrip_string("(when #f 1 2)").unwrap(), Expr::Nil);
+        assert_eq!(
+            roundtrip_string("(unless #f 1 2)").unwrap(),
+            Expr::Integer(2)
+        );
+        assert_eq!(roundtrip_string("(unless #t 1 2)").unwrap(), Expr::Nil);
+    }
+
+    #[test]
+    fn when_unless_skip_body() {
+        let source = r#"
+(let x 0)
+(when (eq 1 2) (set x 1))
+(unless (eq 1 1) (set x 2))
+x
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(0));
+
+        let source = r#"
+(let x 0)
+(when (eq 1 1) (set x (add1 x)))
+(unless (eq 1 2) (set x (add1 x)))
+x
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(2));
+    }
+
+    #[test]
+    fn and() {
+        assert_eq!(roundtrip_string("(and)").unwrap(), Expr::Bool(true));
+        assert_eq!(roundtrip_string("(and 1 2 3)").unwrap(), Expr::Integer(3));
+        assert_eq!(roundtrip_string("(and 1 #f 3)").unwrap(), Expr::Bool(false));
+        assert_eq!(roundtrip_string("(and #f)").unwrap(), Expr::Bool(false));
+    }
+
+    #[test]
+    fn or() {
+        assert_eq!(roundtrip_string("(or)").unwrap(), Expr::Nil);
+        assert_eq!(roundtrip_string("(or #f 2 3)").unwrap(), Expr::Integer(2));
+        assert_eq!(roundtrip_string("(or #f #f)").unwrap(), Expr::Nil);
+    }
+
+    #[test]
+    fn short_circuit() {
+        let source = r#"
+(let x 0)
+(or 1 (set x 1))
+(and #f (set x 2))
+x
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(0));
+    }
+
+    #[test]
+    fn and_in_tail_position() {
+        let source = r#"
+(let count (fn (n) (and #t (if (eq n 0) n (count (sub n 1))))))
+(count 1000000)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(0));
+    }
 }
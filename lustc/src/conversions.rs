@@ -1,11 +1,20 @@
+use std::cell::RefCell;
 use std::fmt;
+use std::io::Write;
 
-use crate::{Expr, UWord, Word};
+use crate::errors::LustError;
+use crate::{hashtables, records, symbols, Expr, UWord, Word};
 
 pub(crate) static FIXNUM_SHIFT: Word = 2;
 pub(crate) static FIXNUM_MASK: Word = 0b11;
 pub(crate) static FIXNUM_TAG: Word = 0;
 
+/// The largest integer that fits in a fixnum. Integers lose
+/// FIXNUM_SHIFT bits of their range to the tag.
+pub const FIXNUM_MAX: i64 = i64::MAX >> 2;
+/// The smallest integer that fits in a fixnum.
+pub const FIXNUM_MIN: i64 = i64::MIN >> 2;
+
 pub(crate) static CHAR_SHIFT: Word = 8;
 pub(crate) static CHAR_MASK: Word = 0b11111111;
 pub(crate) static CHAR_TAG: Word = 0b00001111;
@@ -16,6 +25,32 @@ pub(crate) static BOOL_TAG: Word = 0b0011111;
 
 pub(crate) static NIL_VALUE: Word = 0b00101111;
 
+/// The end of file object that (read) returns when there is nothing
+/// left to read.
+pub(crate) static EOF_VALUE: Word = 0b01101111;
+
+/// Returned by a function in place of its result when the program has
+/// raised an error. No value is ever represented by this word so the
+/// caller can tell that it should return right away as well.
+pub(crate) static RAISED_VALUE: Word = 0b01001111;
+
+/// Returned by a procedure in place of its result when it has left a
+/// tail call for its caller to make. Only used when trampolining. See
+/// trampoline.rs.
+pub(crate) static TAIL_CALL_VALUE: Word = 0b01111111;
+
+/// Symbols are the number that their name was interned as shifted
+/// over and tagged.
+pub(crate) static SYMBOL_SHIFT: Word = 8;
+pub(crate) static SYMBOL_MASK: Word = 0b11111111;
+pub(crate) static SYMBOL_TAG: Word = 0b00111111;
+
+/// Keywords are interned with symbols and tagged differently so that
+/// :foo and foo are never eq?.
+pub(crate) static KEYWORD_SHIFT: Word = 8;
+pub(crate) static KEYWORD_MASK: Word = 0b11111111;
+pub(crate) static KEYWORD_TAG: Word = 0b01011111;
+
 /// Values on the heap use their last three bits (values 0..7) to
 /// store their type tag. The tag mask extracts that tag value.
 pub(crate) static HEAP_TAG_MASK: Word = 0b111;
@@ -26,9 +61,29 @@ pub(crate) static HEAP_PTR_MASK: Word = !HEAP_TAG_MASK;
 /// Tag for a cons object
 pub(crate) static PAIR_TAG: Word = 0b001;
 
+/// Tag for a string object
+pub(crate) static STRING_TAG: Word = 0b010;
+
+/// Tag for a boxed floating point number
+pub(crate) static FLOAT_TAG: Word = 0b011;
+
+/// Tag for a vector object
+pub(crate) static VECTOR_TAG: Word = 0b101;
+
 /// Tag for a closure object
 pub(crate) static CLOSURE_TAG: Word = 0b110;
 
+/// Every three bit tag is taken so hash tables and records are
+/// aligned to thirty two bytes and tagged with five bits instead.
+/// Immediates other than fixnums all end in 0b1111 so this doesn't
+/// clash with them.
+pub(crate) static HASH_TABLE_TAG: Word = 0b00111;
+pub(crate) static HASH_TABLE_MASK: Word = 0b11111;
+
+/// Tag for a record object. See records.rs.
+pub(crate) static RECORD_TAG: Word = 0b10111;
+pub(crate) static RECORD_MASK: Word = 0b11111;
+
 pub fn word_is_char(what: Word) -> bool {
     what & CHAR_MASK == CHAR_TAG
 }
@@ -45,12 +100,54 @@ pub fn word_is_nil(what: Word) -> bool {
     what == NIL_VALUE
 }
 
+pub fn word_is_eof(what: Word) -> bool {
+    what == EOF_VALUE
+}
+
+pub fn word_is_symbol(what: Word) -> bool {
+    what & SYMBOL_MASK == SYMBOL_TAG
+}
+
+pub fn word_is_keyword(what: Word) -> bool {
+    what & KEYWORD_MASK == KEYWORD_TAG
+}
+
 pub fn word_is_pair(what: Word) -> bool {
     what & HEAP_TAG_MASK == PAIR_TAG
 }
 
+pub fn word_is_string(what: Word) -> bool {
+    what & HEAP_TAG_MASK == STRING_TAG
+}
+
+pub fn word_is_float(what: Word) -> bool {
+    what & HEAP_TAG_MASK == FLOAT_TAG
+}
+
+pub fn word_is_vector(what: Word) -> bool {
+    what & HEAP_TAG_MASK == VECTOR_TAG
+}
+
+pub fn word_is_hash_table(what: Word) -> bool {
+    what & HASH_TABLE_MASK == HASH_TABLE_TAG
+}
+
+pub fn word_is_record(what: Word) -> bool {
+    what & RECORD_MASK == RECORD_TAG
+}
+
+pub fn word_is_closure(what: Word) -> bool {
+    what & HEAP_TAG_MASK == CLOSURE_TAG
+}
+
 pub fn word_is_object(what: Word) -> bool {
     word_is_pair(what)
+        || word_is_float(what)
+        || word_is_string(what)
+        || word_is_vector(what)
+        || word_is_hash_table(what)
+        || word_is_record(what)
+        || word_is_closure(what)
 }
 
 pub fn word_is_immediate(what: Word) -> bool {
@@ -58,7 +155,16 @@ pub fn word_is_immediate(what: Word) -> bool {
         || word_is_char(what)
         || word_is_bool(what)
         || word_is_nil(what)
+        || word_is_eof(what)
+        || word_is_symbol(what)
+        || word_is_keyword(what)
         || word_is_pair(what)
+        || word_is_float(what)
+        || word_is_string(what)
+        || word_is_vector(what)
+        || word_is_hash_table(what)
+        || word_is_record(what)
+        || word_is_closure(what)
 }
 
 pub fn word_get_object_address(what: Word) -> UWord {
@@ -66,32 +172,170 @@ pub fn word_get_object_address(what: Word) -> UWord {
     (what & HEAP_PTR_MASK) as UWord
 }
 
+/// Allocates the pairs that make up LIST. A dot before the last
+/// element, as in (1 2 . 3), makes that element the final cdr.
 pub fn list_to_immediate(list: &[Expr]) -> Word {
-    if let Some(e) = list.first() {
-        let mut pair = Vec::with_capacity(2);
-        pair.push(e.immediate_rep());
-        pair.push(list_to_immediate(&list[1..]));
-        let ptr_word = pair.as_mut_ptr() as Word;
-        std::mem::forget(pair);
-        ptr_word | PAIR_TAG
-    } else {
-        NIL_VALUE
+    match list {
+        [Expr::Symbol(dot), tail] if dot == "." => tail.immediate_rep(),
+        [e, rest @ ..] => {
+            let mut pair = Vec::with_capacity(2);
+            pair.push(e.immediate_rep());
+            pair.push(list_to_immediate(rest));
+            let ptr_word = pair.as_mut_ptr() as Word;
+            std::mem::forget(pair);
+            ptr_word | PAIR_TAG
+        }
+        [] => NIL_VALUE,
     }
 }
 
-pub fn list_from_immediate(ptr_word: Word) -> Expr {
+/// Allocates a single cons cell holding CAR and CDR.
+pub fn pair_to_immediate(car: &Expr, cdr: &Expr) -> Word {
+    let mut pair = Vec::with_capacity(2);
+    pair.push(car.immediate_rep());
+    pair.push(cdr.immediate_rep());
+    let ptr_word = pair.as_mut_ptr() as Word;
+    std::mem::forget(pair);
+    ptr_word | PAIR_TAG
+}
+
+/// Reads the cons cell that PTR_WORD points to back off the heap.
+pub fn pair_from_immediate(ptr_word: Word) -> Expr {
     debug_assert_eq!(ptr_word & HEAP_TAG_MASK, PAIR_TAG);
     let ptr = (ptr_word & HEAP_PTR_MASK) as *mut Word;
     let slice = unsafe { std::slice::from_raw_parts(ptr, 2) };
-    let first = Expr::from_immediate(slice[0]);
-    let rest = Expr::from_immediate(slice[1]);
+    let car = Expr::from_immediate(slice[0]);
+    let cdr = Expr::from_immediate(slice[1]);
+
+    Expr::Pair(Box::new(car), Box::new(cdr))
+}
 
-    Expr::List(vec![first, rest])
+/// Gets a float that isn't on the heap. Used for floats in data and
+/// ones made outside of compiled code. Compiled code boxes floats on
+/// the heap instead.
+pub fn float_to_immediate(f: f64) -> Word {
+    let ptr_word = crate::symbols::intern_float(f) as Word;
+    ptr_word | FLOAT_TAG
 }
 
+pub fn float_from_immediate(ptr_word: Word) -> Expr {
+    debug_assert_eq!(ptr_word & HEAP_TAG_MASK, FLOAT_TAG);
+    let ptr = (ptr_word & HEAP_PTR_MASK) as *const f64;
+    Expr::Float(unsafe { *ptr })
+}
+
+/// Strings are stored as a one word length prefix followed by their
+/// UTF-8 encoded bytes. There is always a null terminator after the
+/// bytes so that the payload can be handed to C functions as is. The
+/// length prefix means that strings can contain nulls, though C won't
+/// see anything after the first one.
 pub fn string_to_immediate(string: &str) -> Word {
-    let chars = string.chars().map(|c| Expr::Char(c)).collect::<Vec<_>>();
-    list_to_immediate(&chars)
+    let bytes = string.as_bytes();
+    let word_size = std::mem::size_of::<Word>();
+    // Room for the length, the bytes, and the null terminator.
+    let words = 1 + (bytes.len() + 1).div_ceil(word_size);
+    let mut storage: Vec<Word> = vec![0; words];
+    storage[0] = bytes.len() as Word;
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            bytes.as_ptr(),
+            storage.as_mut_ptr().add(1) as *mut u8,
+            bytes.len(),
+        );
+    }
+    let ptr_word = storage.as_mut_ptr() as Word;
+    std::mem::forget(storage);
+    ptr_word | STRING_TAG
+}
+
+/// Gets the bytes of the string that PTR_WORD points to.
+pub(crate) fn string_bytes<'a>(ptr_word: Word) -> &'a [u8] {
+    debug_assert_eq!(ptr_word & HEAP_TAG_MASK, STRING_TAG);
+    let ptr = (ptr_word & HEAP_PTR_MASK) as *const Word;
+    unsafe {
+        let len = *ptr as usize;
+        std::slice::from_raw_parts(ptr.add(1) as *const u8, len)
+    }
+}
+
+pub fn string_from_immediate(ptr_word: Word) -> Expr {
+    Expr::String(String::from_utf8_lossy(string_bytes(ptr_word)).into_owned())
+}
+
+/// Vectors are stored as a one word length prefix followed by their
+/// elements. The length is stored as a fixnum so that nothing
+/// walking the heap mistakes it for a pointer.
+pub fn vector_to_immediate(elements: &[Expr]) -> Word {
+    let mut storage: Vec<Word> = Vec::with_capacity(1 + elements.len());
+    storage.push(Expr::Integer(elements.len() as i64).immediate_rep());
+    storage.extend(elements.iter().map(|e| e.immediate_rep()));
+    let ptr_word = storage.as_mut_ptr() as Word;
+    std::mem::forget(storage);
+    ptr_word | VECTOR_TAG
+}
+
+/// Gets the elements of the vector that PTR_WORD points to.
+/// Determines if the vector WORD is really multiple values. See
+/// values.rs.
+fn word_is_values(word: Word) -> bool {
+    unsafe { *((word & HEAP_PTR_MASK) as *const Word) < 0 }
+}
+
+pub(crate) fn vector_words<'a>(ptr_word: Word) -> &'a [Word] {
+    debug_assert_eq!(ptr_word & HEAP_TAG_MASK, VECTOR_TAG);
+    let ptr = (ptr_word & HEAP_PTR_MASK) as *const Word;
+    unsafe {
+        // Multiple values are stored as vectors with the bitwise not
+        // of their count as the length.
+        let len = *ptr >> FIXNUM_SHIFT;
+        let len = if len < 0 { !len } else { len } as usize;
+        std::slice::from_raw_parts(ptr.add(1), len)
+    }
+}
+
+pub fn vector_from_immediate(ptr_word: Word) -> Expr {
+    if word_is_values(ptr_word) {
+        // Only the first of multiple values makes it out of a program.
+        return match vector_words(ptr_word).first() {
+            Some(&w) => Expr::from_immediate(w),
+            None => Expr::Nil,
+        };
+    }
+    Expr::Vector(
+        vector_words(ptr_word)
+            .iter()
+            .map(|&w| Expr::from_immediate(w))
+            .collect(),
+    )
+}
+
+/// Checks that every integer literal in PROGRAM fits in a fixnum.
+/// Integers that don't would silently wrap around when they are
+/// tagged.
+pub(crate) fn check_fixnum_ranges(program: &[Expr]) -> Result<(), LustError> {
+    program.iter().try_for_each(check_fixnum_range)
+}
+
+fn check_fixnum_range(expr: &Expr) -> Result<(), LustError> {
+    match expr {
+        Expr::Integer(i) if !(FIXNUM_MIN..=FIXNUM_MAX).contains(i) => Err(LustError::Compile(
+            format!("integer literal out of range: {}", i),
+        )),
+        Expr::List(v, span) => v
+            .iter()
+            .try_for_each(check_fixnum_range)
+            .map_err(|e| e.at(*span)),
+        Expr::Pair(car, cdr) => {
+            check_fixnum_range(car)?;
+            check_fixnum_range(cdr)
+        }
+        Expr::Vector(v) => v.iter().try_for_each(check_fixnum_range),
+        Expr::HashTable(entries) => entries.iter().try_for_each(|(key, value)| {
+            check_fixnum_range(key)?;
+            check_fixnum_range(value)
+        }),
+        _ => Ok(()),
+    }
 }
 
 impl Expr {
@@ -103,19 +347,33 @@ impl Expr {
         debug_assert!(self.is_immediate(), "expected immediate type");
         match self {
             Expr::Integer(i) => (i << FIXNUM_SHIFT) | FIXNUM_TAG,
+            Expr::Float(f) => float_to_immediate(*f),
             Expr::Char(c) => ((*c as Word) << CHAR_SHIFT) | CHAR_TAG,
             Expr::Bool(b) => ((*b as Word) << BOOL_SHIFT) | BOOL_TAG,
             Expr::Nil => NIL_VALUE,
-            Expr::List(v) => list_to_immediate(v),
-            Expr::Symbol(_) => todo!("symbol immediates unsupported"),
+            Expr::Eof => EOF_VALUE,
+            Expr::List(v, _) => list_to_immediate(v),
+            Expr::Pair(car, cdr) => pair_to_immediate(car, cdr),
+            Expr::Symbol(s) => ((symbols::intern(s) as Word) << SYMBOL_SHIFT) | SYMBOL_TAG,
+            Expr::Keyword(s) => ((symbols::intern(s) as Word) << KEYWORD_SHIFT) | KEYWORD_TAG,
             Expr::String(s) => string_to_immediate(s),
+            Expr::Vector(v) => vector_to_immediate(v),
+            Expr::HashTable(entries) => hashtables::hash_table_to_immediate(entries),
+            Expr::Record(type_id, fields) => records::record_to_immediate(*type_id, fields),
+            Expr::Procedure(word) => *word,
         }
     }
 
     pub fn from_immediate(what: Word) -> Expr {
         debug_assert!(word_is_immediate(what), "expected immediate type");
         match () {
-            _ if word_is_pair(what) => list_from_immediate(what),
+            _ if word_is_pair(what) => pair_from_immediate(what),
+            _ if word_is_float(what) => float_from_immediate(what),
+            _ if word_is_string(what) => string_from_immediate(what),
+            _ if word_is_vector(what) => vector_from_immediate(what),
+            _ if word_is_hash_table(what) => hashtables::hash_table_from_immediate(what),
+            _ if word_is_record(what) => records::record_from_immediate(what),
+            _ if word_is_closure(what) => Expr::Procedure(what),
             _ if word_is_int(what) => Expr::Integer(what >> FIXNUM_SHIFT),
             _ if word_is_char(what) => {
                 Expr::Char(unsafe { std::mem::transmute_copy(&(what >> CHAR_SHIFT)) })
@@ -123,100 +381,259 @@ impl Expr {
             _ if word_is_bool(what) => {
                 Expr::Bool(unsafe { std::mem::transmute_copy(&(what >> BOOL_SHIFT)) })
             }
+            _ if word_is_symbol(what) => {
+                let id = (what as UWord >> SYMBOL_SHIFT) as usize;
+                Expr::Symbol(symbols::name(id).expect("symbol was never interned"))
+            }
+            _ if word_is_keyword(what) => {
+                let id = (what as UWord >> KEYWORD_SHIFT) as usize;
+                Expr::Keyword(symbols::name(id).expect("keyword was never interned"))
+            }
             _ if word_is_nil(what) => Expr::Nil,
+            _ if word_is_eof(what) => Expr::Eof,
             _ => Expr::Nil,
         }
     }
 }
 
+thread_local! {
+    /// Where output from the print functions goes when it has been
+    /// sent somewhere other than stdout.
+    static OUTPUT: RefCell<Option<Box<dyn Write>>> = RefCell::new(None);
+}
+
+/// Sends everything that programs run on this thread print to W
+/// instead of stdout. Returns where output was going before, if it
+/// wasn't stdout.
+pub fn set_output(w: Box<dyn Write>) -> Option<Box<dyn Write>> {
+    OUTPUT.with(|output| output.borrow_mut().replace(w))
+}
+
+/// Sends output from programs run on this thread back to stdout.
+/// Returns where output was going before, if it wasn't stdout.
+pub fn reset_output() -> Option<Box<dyn Write>> {
+    OUTPUT.with(|output| output.borrow_mut().take())
+}
+
+/// Writes S to this thread's output.
+pub(crate) fn write_output(s: &str) {
+    OUTPUT.with(|output| match &mut *output.borrow_mut() {
+        Some(w) => {
+            let _ = w.write_all(s.as_bytes());
+            let _ = w.flush();
+        }
+        None => {
+            let mut stdout = std::io::stdout();
+            let _ = stdout.write_all(s.as_bytes());
+            let _ = stdout.flush();
+        }
+    })
+}
+
+/// A buffer that can be handed to set_output while keeping a handle
+/// to it to read what was written.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct SharedBuffer(std::rc::Rc<RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl SharedBuffer {
+    pub(crate) fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+#[cfg(test)]
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Calls F and returns its result along with everything that was
+/// printed while it ran.
+#[cfg(test)]
+pub(crate) fn capture_output<F, R>(f: F) -> (R, String)
+where
+    F: FnOnce() -> R,
+{
+    let buffer = SharedBuffer::default();
+    let previous = set_output(Box::new(buffer.clone()));
+    let res = f();
+    match previous {
+        Some(previous) => set_output(previous),
+        None => reset_output(),
+    };
+    (res, buffer.contents())
+}
+
+#[no_mangle]
 pub extern "C" fn print_lustc_word(word: Word) -> Word {
     let expr = Expr::from_immediate(word);
-    print!("{}", expr);
+    write_output(&Displayed(&expr).to_string());
     Expr::Nil.immediate_rep()
 }
 
+#[no_mangle]
 pub extern "C" fn println_lustc_word(word: Word) -> Word {
     let expr = Expr::from_immediate(word);
-    println!("{}", expr);
+    write_output(&format!("{}\n", Displayed(&expr)));
     Expr::Nil.immediate_rep()
 }
 
-/// Tries to convert E into a string. E is convertable into a string
-/// if it is a well formed list that contains only characters.
-fn try_stringify_list(e: &Expr) -> Option<String> {
-    let l = match e {
-        Expr::List(l) => l,
-        _ => return None,
-    };
-
-    let c = match l[0] {
-        Expr::Char(c) => c,
-        _ => return None,
-    };
+#[no_mangle]
+pub extern "C" fn write_lustc_word(word: Word) -> Word {
+    let expr = Expr::from_immediate(word);
+    write_output(&expr.to_string());
+    Expr::Nil.immediate_rep()
+}
 
-    let r = match l[1] {
-        Expr::Nil => "".to_string(),
-        _ => match try_stringify_list(&l[1]) {
-            Some(s) => s,
-            None => return None,
-        },
-    };
+/// Wraps an expression so that it is formatted for people rather
+/// than for the reader. Strings and characters are written out as is
+/// instead of as literals. Lists of characters are how programs
+/// written before strings build text so they are written in quotes
+/// like a string would be.
+pub(crate) struct Displayed<'a>(pub &'a Expr);
 
-    Some(format!("{}{}", c, r))
+impl fmt::Display for Displayed<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_expr(self.0, false, f)
+    }
 }
 
-fn list_is_well_formed(l: &[Expr]) -> bool {
-    match &l[1] {
-        Expr::Nil => true,
-        Expr::List(l) => list_is_well_formed(&l),
-        _ => false,
+/// Gets the characters in E if it is a non-empty, nil terminated list
+/// of characters.
+fn char_list(mut e: &Expr) -> Option<String> {
+    let mut res = String::new();
+    while let Expr::Pair(car, cdr) = e {
+        match **car {
+            Expr::Char(c) => res.push(c),
+            _ => return None,
+        }
+        e = cdr;
+    }
+    match e {
+        Expr::Nil if !res.is_empty() => Some(res),
+        _ => None,
     }
 }
 
-fn stringify_well_formed_list(e: &Expr) -> String {
+/// Writes the elements of a list or chain of pairs starting at E
+/// separated by spaces. If the chain doesn't end in nil the final cdr
+/// is written with a dot before it.
+fn write_tail(e: &Expr, readable: bool, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match e {
-        Expr::List(l) => match &l[1] {
-            Expr::Nil => format!("{}", l[0]),
-            _ => format!("{}, {}", l[0], stringify_well_formed_list(&l[1])),
-        },
+        Expr::Nil => Ok(()),
+        Expr::Pair(car, cdr) => {
+            write!(f, " ")?;
+            write_expr(car, readable, f)?;
+            write_tail(cdr, readable, f)
+        }
         _ => {
-            debug_assert!(false, "list is not well formed");
-            format!("{}", e)
+            write!(f, " . ")?;
+            write_expr(e, readable, f)
         }
     }
 }
 
-fn try_prettify_list(e: &Expr) -> Option<String> {
-    let l = match e {
-        Expr::List(l) => l,
-        _ => return None,
-    };
-
-    if !list_is_well_formed(&l) {
-        return None;
+/// Writes E as an s-expression. If READABLE is set the output can be
+/// parsed back into the same expression.
+fn write_expr(e: &Expr, readable: bool, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match e {
+        Expr::Integer(i) => write!(f, "{}", i),
+        // Debug formatting always includes a decimal point which
+        // keeps floats distinguishable from integers.
+        Expr::Float(x) => write!(f, "{:?}", x),
+        Expr::Char(c) if readable => match c {
+            ' ' => write!(f, "#\\space"),
+            '\n' => write!(f, "#\\newline"),
+            '\t' => write!(f, "#\\tab"),
+            c => write!(f, "#\\{}", c),
+        },
+        Expr::Char(c) => write!(f, "{}", c),
+        Expr::Bool(true) => write!(f, "#t"),
+        Expr::Bool(false) => write!(f, "#f"),
+        Expr::Nil => write!(f, "()"),
+        Expr::Eof => write!(f, "#<eof>"),
+        Expr::Procedure(_) => write!(f, "#<procedure>"),
+        Expr::List(v, _) => {
+            write!(f, "(")?;
+            for (i, e) in v.iter().enumerate() {
+                if i != 0 {
+                    write!(f, " ")?;
+                }
+                write_expr(e, readable, f)?;
+            }
+            write!(f, ")")
+        }
+        Expr::Pair(..) if !readable && char_list(e).is_some() => {
+            write!(f, "\"{}\"", char_list(e).unwrap())
+        }
+        Expr::Pair(car, cdr) => {
+            write!(f, "(")?;
+            write_expr(car, readable, f)?;
+            write_tail(cdr, readable, f)?;
+            write!(f, ")")
+        }
+        Expr::Symbol(s) => write!(f, "{}", s),
+        Expr::Keyword(s) => write!(f, ":{}", s),
+        Expr::String(s) if readable => {
+            write!(f, "\"")?;
+            for c in s.chars() {
+                match c {
+                    '"' => write!(f, "\\\"")?,
+                    '\\' => write!(f, "\\\\")?,
+                    '\n' => write!(f, "\\n")?,
+                    '\t' => write!(f, "\\t")?,
+                    c => write!(f, "{}", c)?,
+                }
+            }
+            write!(f, "\"")
+        }
+        Expr::String(s) => write!(f, "{}", s),
+        Expr::Vector(v) => {
+            write!(f, "#(")?;
+            for (i, e) in v.iter().enumerate() {
+                if i != 0 {
+                    write!(f, " ")?;
+                }
+                write_expr(e, readable, f)?;
+            }
+            write!(f, ")")
+        }
+        Expr::HashTable(entries) => {
+            write!(f, "#hash(")?;
+            for (i, (key, value)) in entries.iter().enumerate() {
+                if i != 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "(")?;
+                write_expr(key, readable, f)?;
+                write!(f, " . ")?;
+                write_expr(value, readable, f)?;
+                write!(f, ")")?;
+            }
+            write!(f, ")")
+        }
+        Expr::Record(id, fields) => {
+            let (name, names) = records::type_info(*id).unwrap_or_default();
+            write!(f, "#<{}", name)?;
+            for (name, field) in names.iter().zip(fields) {
+                write!(f, " {}: ", name)?;
+                write_expr(field, readable, f)?;
+            }
+            write!(f, ">")
+        }
     }
-
-    Some(format!("({})", stringify_well_formed_list(e)))
 }
 
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Expr::Integer(i) => write!(f, "{}", i),
-            Expr::Char(c) => write!(f, "'{}'", c),
-            Expr::Bool(b) => write!(f, "{}", b),
-            Expr::Nil => write!(f, "nil"),
-            Expr::List(l) => match try_stringify_list(self) {
-                Some(s) => write!(f, "\"{}\"", s),
-                None => match try_prettify_list(self) {
-                    Some(s) => write!(f, "{}", s),
-                    None => write!(f, "({}, {})", l[0], l[1]),
-                },
-            },
-            // sbcl capitalizes symbols when writing them out to stdout.
-            Expr::Symbol(s) => write!(f, "{}", s.to_uppercase()),
-            Expr::String(s) => write!(f, "{}", s),
-        }
+        write_expr(self, true, f)
     }
 }
 
@@ -235,6 +652,103 @@ mod tests {
         }
     }
 
+    #[test]
+    fn fixnum_range() {
+        test_roundtrip(Expr::Integer(FIXNUM_MAX));
+        test_roundtrip(Expr::Integer(FIXNUM_MIN));
+        assert_eq!(
+            crate::roundtrip_string("(cons most-positive-fixnum most-negative-fixnum)").unwrap(),
+            Expr::Pair(
+                Box::new(Expr::Integer(FIXNUM_MAX)),
+                Box::new(Expr::Integer(FIXNUM_MIN))
+            )
+        );
+        // Constants can be shadowed.
+        assert_eq!(
+            crate::roundtrip_string("(let most-positive-fixnum 1) most-positive-fixnum").unwrap(),
+            Expr::Integer(1)
+        );
+    }
+
+    #[test]
+    fn out_of_range_literals() {
+        let eval = |program: Vec<Expr>| {
+            crate::compiler::eval_program(&program).map_err(LustError::without_location)
+        };
+        let too_big = || Expr::Integer(FIXNUM_MAX + 1);
+        let error = Err(LustError::Compile(format!(
+            "integer literal out of range: {}",
+            FIXNUM_MAX + 1
+        )));
+        assert_eq!(eval(vec![too_big()]), error);
+        assert_eq!(
+            eval(vec![Expr::call("add", vec![Expr::int(1), too_big()])]),
+            error
+        );
+        assert_eq!(eval(vec![Expr::quote(Expr::list(vec![too_big()]))]), error);
+        assert_eq!(
+            eval(vec![Expr::Integer(FIXNUM_MIN - 1)]),
+            Err(LustError::Compile(format!(
+                "integer literal out of range: {}",
+                FIXNUM_MIN - 1
+            )))
+        );
+        assert!(crate::compiler::eval_program_with_args(
+            &[Expr::symbol("a")],
+            &["a"],
+            &[FIXNUM_MAX + 1]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn roundtrip_float() {
+        for f in &[0.0, -1.5, 3.25, f64::MAX, f64::MIN_POSITIVE] {
+            test_roundtrip(Expr::Float(*f));
+        }
+    }
+
+    #[test]
+    fn float_immediate() {
+        let word = Expr::Float(2.5).immediate_rep();
+        assert!(word_is_float(word));
+        assert!(!word_is_int(word));
+        assert_eq!(Expr::from_immediate(word), Expr::Float(2.5));
+        // Converting the same float again reuses its box instead of
+        // making a new one.
+        assert_eq!(Expr::Float(2.5).immediate_rep(), word);
+        assert_ne!(Expr::Float(-2.5).immediate_rep(), word);
+        // Floats in constant data and in vectors come back whole.
+        let source = "(let f (fn () '(1.5 2.5)))
+                      (let v (make-vector 1 1.5))
+                      (list (f) (f) (vector-ref v 0))";
+        assert_eq!(
+            crate::roundtrip_string(source).unwrap(),
+            crate::roundtrip_string("'((1.5 2.5) (1.5 2.5) 1.5)").unwrap()
+        );
+    }
+
+    #[test]
+    fn roundtrip_string() {
+        test_roundtrip(Expr::String("hello".to_string()));
+        test_roundtrip(Expr::String("".to_string()));
+        test_roundtrip(Expr::String("🚨🥺".to_string()));
+        test_roundtrip(Expr::String("nulls\0in\0the\0middle".to_string()));
+    }
+
+    #[test]
+    fn string_immediate() {
+        let word = Expr::String("hello".to_string()).immediate_rep();
+        assert!(word_is_string(word));
+        assert!(!word_is_pair(word));
+        assert!(!word_is_int(word));
+        assert!(!word_is_nil(word));
+        assert_eq!(string_bytes(word), b"hello");
+        // There is a null terminator after the bytes.
+        let ptr = (word & HEAP_PTR_MASK) as *const u8;
+        assert_eq!(unsafe { *ptr.add(std::mem::size_of::<Word>() + 5) }, 0);
+    }
+
     #[test]
     fn roundtrip_bool() {
         test_roundtrip(Expr::Bool(false));
@@ -253,18 +767,98 @@ mod tests {
     }
 
     #[test]
-    fn list_well_formed() {
-        let good = vec![
-            Expr::Char('a'),
-            Expr::List(vec![Expr::Integer(10), Expr::Nil]),
-        ];
-        let bad = vec![
+    fn display() {
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        let list = cons(
+            Expr::Integer(1),
+            cons(
+                cons(Expr::Char('a'), Expr::Nil),
+                cons(Expr::Bool(true), Expr::Nil),
+            ),
+        );
+        assert_eq!(list.to_string(), "(1 (#\\a) #t)");
+
+        let dotted = cons(Expr::Integer(1), cons(Expr::Integer(2), Expr::Integer(3)));
+        assert_eq!(dotted.to_string(), "(1 2 . 3)");
+
+        assert_eq!(Expr::Nil.to_string(), "()");
+        assert_eq!(Expr::Bool(false).to_string(), "#f");
+        assert_eq!(Expr::Char(' ').to_string(), "#\\space");
+        assert_eq!(Expr::Float(1.0).to_string(), "1.0");
+        assert_eq!(
+            Expr::String("say \"hi\"\n".to_string()).to_string(),
+            "\"say \\\"hi\\\"\\n\""
+        );
+        assert_eq!(
+            Expr::list(vec![Expr::Symbol("add1".to_string()), Expr::Integer(41)]).to_string(),
+            "(add1 41)"
+        );
+    }
+
+    #[test]
+    fn display_is_readable() {
+        let source = "(1 (#\\a #\\space) #t #f \"a \\\"b\\\"\\n\" (foo . 2.5))";
+        let exprs = crate::reader::parse(source).unwrap();
+        let written = exprs[0].to_string();
+        assert_eq!(crate::reader::parse(&written).unwrap(), exprs);
+    }
+
+    #[test]
+    fn displayed() {
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        let list = cons(
             Expr::Char('a'),
-            Expr::List(vec![Expr::Integer(10), Expr::Integer(11)]),
-        ];
+            cons(Expr::String("bc".to_string()), Expr::Nil),
+        );
+        assert_eq!(Displayed(&list).to_string(), "(a bc)");
+
+        // Lists of characters are displayed like strings but written
+        // as lists.
+        let chars = cons(Expr::Char('h'), cons(Expr::Char('i'), Expr::Nil));
+        assert_eq!(Displayed(&chars).to_string(), "\"hi\"");
+        assert_eq!(chars.to_string(), "(#\\h #\\i)");
+        let nested = cons(chars.clone(), cons(Expr::Integer(1), Expr::Nil));
+        assert_eq!(Displayed(&nested).to_string(), "(\"hi\" 1)");
+        let dotted = cons(Expr::Char('h'), Expr::Char('i'));
+        assert_eq!(Displayed(&dotted).to_string(), "(h . i)");
+    }
 
-        assert!(list_is_well_formed(&good));
-        assert!(!list_is_well_formed(&bad));
+    #[test]
+    fn roundtrip_pair() {
+        test_roundtrip(Expr::Pair(
+            Box::new(Expr::Integer(1)),
+            Box::new(Expr::Char('a')),
+        ));
+        test_roundtrip(Expr::Pair(
+            Box::new(Expr::Pair(Box::new(Expr::Bool(true)), Box::new(Expr::Nil))),
+            Box::new(Expr::Nil),
+        ));
+    }
+
+    #[test]
+    fn roundtrip_vector() {
+        test_roundtrip(Expr::Vector(vec![]));
+        test_roundtrip(Expr::Vector(vec![
+            Expr::Integer(1),
+            Expr::String("two".to_string()),
+            Expr::Vector(vec![Expr::Char('3')]),
+        ]));
+    }
+
+    #[test]
+    fn vector_immediate() {
+        let word = Expr::Vector(vec![Expr::Integer(1), Expr::Nil]).immediate_rep();
+        assert!(word_is_vector(word));
+        assert!(!word_is_pair(word));
+        assert!(!word_is_float(word));
+        assert_eq!(
+            vector_words(word),
+            &[Expr::Integer(1).immediate_rep(), NIL_VALUE]
+        );
+        assert_eq!(
+            Expr::Vector(vec![Expr::Integer(1), Expr::Bool(false)]).to_string(),
+            "#(1 #f)"
+        );
     }
 
     #[test]
@@ -274,7 +868,7 @@ mod tests {
 
     #[test]
     fn rountrip_list() {
-        let start = Expr::List(vec![Expr::Integer(1), Expr::Bool(false)]);
+        let start = Expr::list(vec![Expr::Integer(1), Expr::Bool(false)]);
         let start_immediate = start.immediate_rep();
         let end = Expr::from_immediate(start_immediate);
 
@@ -283,10 +877,84 @@ mod tests {
         // represented in Lust vs Rust.
         assert_eq!(
             end,
-            Expr::List(vec![
-                Expr::Integer(1),
-                Expr::List(vec![Expr::Bool(false), Expr::Nil])
-            ])
+            Expr::Pair(
+                Box::new(Expr::Integer(1)),
+                Box::new(Expr::Pair(Box::new(Expr::Bool(false)), Box::new(Expr::Nil)))
+            )
         )
     }
+
+    #[test]
+    fn nested_structures_from_heap() {
+        // Structures built by a program are read back from the heap
+        // all the way down.
+        let source = r#"
+(let v (make-vector 2 #\3))
+(vector-set! v 1 (cons 4.5 ()))
+(cons (cons 1 (cons "two" ())) (cons v ()))
+"#;
+        let pair = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        let expected = pair(
+            pair(
+                Expr::Integer(1),
+                pair(Expr::String("two".to_string()), Expr::Nil),
+            ),
+            pair(
+                Expr::Vector(vec![Expr::Char('3'), pair(Expr::Float(4.5), Expr::Nil)]),
+                Expr::Nil,
+            ),
+        );
+        assert_eq!(crate::roundtrip_string(source).unwrap(), expected);
+        assert_eq!(
+            crate::roundtrip_string("'((1 \"two\") 3)")
+                .unwrap()
+                .to_string(),
+            "((1 \"two\") 3)"
+        );
+    }
+
+    #[test]
+    fn closures_from_heap() {
+        let res = crate::roundtrip_string("(let f (fn (x) x)) (list f f (fn () 1))").unwrap();
+        let closures = match res {
+            Expr::Pair(a, rest) => match *rest {
+                Expr::Pair(b, rest) => match *rest {
+                    Expr::Pair(c, _) => [*a, *b, *c],
+                    _ => panic!(),
+                },
+                _ => panic!(),
+            },
+            _ => panic!(),
+        };
+        assert!(matches!(closures[0], Expr::Procedure(w) if word_is_closure(w)));
+        // The same closure twice is equal but different ones aren't.
+        assert_eq!(closures[0], closures[1]);
+        assert_ne!(closures[0], closures[2]);
+        assert_eq!(closures[2].to_string(), "#<procedure>");
+
+        let (_, output) =
+            capture_output(|| crate::roundtrip_string("(println (list 1 (fn (n) n)))"));
+        assert_eq!(output, "(1 #<procedure>)\n");
+    }
+
+    #[test]
+    fn output_sink() {
+        let buffer = SharedBuffer::default();
+        assert!(set_output(Box::new(buffer.clone())).is_none());
+        let res = crate::roundtrip_string("(display 42) (newline) (write \"hi\")");
+        assert!(reset_output().is_some());
+        assert_eq!(res.unwrap(), Expr::Nil);
+        assert_eq!(buffer.contents(), "42\n\"hi\"");
+
+        // Setting the output again gives back the old sink and nested
+        // captures each get their own output.
+        let (inner, outer) = capture_output(|| {
+            print_lustc_word(Expr::Integer(1).immediate_rep());
+            let (_, inner) = capture_output(|| crate::roundtrip_string("(display 2)"));
+            print_lustc_word(Expr::Integer(3).immediate_rep());
+            inner
+        });
+        assert_eq!(inner, "2");
+        assert_eq!(outer, "13");
+    }
 }
@@ -2,31 +2,36 @@
 //! time. Programs can then access them directly instead of needing to
 //! do any work themselves. Herein lies the code for that.
 
+use crate::backend::LustModule;
 use crate::compiler::{Context, JIT};
+use crate::conversions;
+use crate::errors::LustError;
 use crate::Expr;
 use crate::PreorderStatus;
 use crate::Word;
 
 use cranelift::prelude::*;
-use cranelift_module::Module;
+use cranelift_module::{DataId, Linkage, Module};
 
 impl Expr {
+    /// Determines if the expression is a quote expression and if it
+    /// is returns the quoted expression.
+    pub fn is_quote(&self) -> Option<&Expr> {
+        match self {
+            Expr::List(v, _) => match &v[..] {
+                [Expr::Symbol(s), quoted] if s == "quote" => Some(quoted),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
     /// A value is a complex constant if it appears inside of a quote
     /// expression. In that case we construct its value at compile time
     /// and store it in the programs data.
     pub fn is_complex_const(&self) -> Option<Word> {
         match self {
-            Expr::List(v) => {
-                if let Some(Expr::Symbol(s)) = v.first() {
-                    if s == "quote" && v.len() == 2 {
-                        Some(v[1].immediate_rep())
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            }
+            Expr::List(..) => self.is_quote().map(Expr::immediate_rep),
             Expr::String(_) => Some(self.immediate_rep()),
             _ => None,
         }
@@ -59,6 +64,10 @@ fn collect_data_w_count(program: &[Expr], count: &mut usize) -> Vec<LustData> {
                     data: repr,
                 });
                 *count += 1;
+                // Strings inside of quoted data are part of it and
+                // not data of their own. Replacement doesn't look
+                // inside either.
+                return PreorderStatus::Skip;
             }
             PreorderStatus::Continue
         });
@@ -90,11 +99,11 @@ fn replace_data_w_count(program: &mut [Expr], data: &[LustData], count: &mut usi
     }
 }
 
-pub(crate) fn emit_data_access(name: &str, ctx: &mut Context) -> Result<Value, String> {
+pub(crate) fn emit_data_access(name: &str, ctx: &mut Context) -> Result<Value, LustError> {
     let sym = ctx
         .module
         .declare_data(name, cranelift_module::Linkage::Export, true, false)
-        .map_err(|e| e.to_string())?;
+        .map_err(LustError::from)?;
     let local_id = ctx.module.declare_data_in_func(sym, ctx.builder.func);
 
     let data_ptr = ctx.builder.ins().symbol_value(ctx.word, local_id);
@@ -127,17 +136,22 @@ pub(crate) fn replace_data(program: &mut [Expr], data: &[LustData]) {
 
 /// Gives ownership of DATA to JIT and assocaites its name with its
 /// value internally.
-pub(crate) fn create_data(data: LustData, jit: &mut JIT) -> Result<(), String> {
+pub(crate) fn create_data(data: LustData, jit: &mut JIT) -> Result<(), LustError> {
+    if let LustModule::Object(_) = jit.module {
+        create_object_data(&data.name, Linkage::Export, &[data.data], true, jit)?;
+        return Ok(());
+    }
+
     let contents = Box::new(data.data.to_ne_bytes());
     jit.data_ctx.define(contents);
     let id = jit
         .module
-        .declare_data(&data.name, cranelift_module::Linkage::Export, true, false)
-        .map_err(|e| e.to_string())?;
+        .declare_data(&data.name, Linkage::Export, true, false)
+        .map_err(LustError::from)?;
 
     jit.module
         .define_data(id, &jit.data_ctx)
-        .map_err(|e| e.to_string())?;
+        .map_err(LustError::from)?;
 
     jit.data_ctx.clear();
 
@@ -148,6 +162,98 @@ pub(crate) fn create_data(data: LustData, jit: &mut JIT) -> Result<(), String> {
     Ok(())
 }
 
+/// Creates a data object called NAME holding WORDS for an object
+/// file. Data is built on the compiler's heap which won't be around
+/// when the object file is run so if SCAN is set any of WORDS that
+/// point into the heap have what they point to copied into data
+/// objects of their own and are replaced with relocations to them.
+fn create_object_data(
+    name: &str,
+    linkage: Linkage,
+    words: &[Word],
+    scan: bool,
+    jit: &mut JIT,
+) -> Result<DataId, LustError> {
+    let mut relocations = Vec::new();
+    if scan {
+        for (i, &word) in words.iter().enumerate() {
+            let child_name = format!("{}_{}", name, i);
+            let tag = word & conversions::HEAP_TAG_MASK;
+            let ptr = (word & conversions::HEAP_PTR_MASK) as *const Word;
+            let child = if conversions::word_is_hash_table(word) {
+                return Err(LustError::Compile(
+                    "hash tables can not be compiled into an object file".to_string(),
+                ));
+            } else if conversions::word_is_record(word) {
+                return Err(LustError::Compile(
+                    "records can not be compiled into an object file".to_string(),
+                ));
+            } else if tag == conversions::PAIR_TAG {
+                let pair = unsafe { [*ptr, *ptr.add(1)] };
+                create_object_data(&child_name, Linkage::Local, &pair, true, jit)?
+            } else if tag == conversions::STRING_TAG {
+                let bytes = conversions::string_bytes(word);
+                // Room for the length, the bytes, and the null
+                // terminator just like on the heap.
+                let len = 1 + (bytes.len() + 1).div_ceil(std::mem::size_of::<Word>());
+                let string = unsafe { std::slice::from_raw_parts(ptr, len) };
+                create_object_data(&child_name, Linkage::Local, string, false, jit)?
+            } else if tag == conversions::FLOAT_TAG {
+                let float = unsafe { [*ptr] };
+                create_object_data(&child_name, Linkage::Local, &float, false, jit)?
+            } else if tag == conversions::VECTOR_TAG {
+                let len = 1 + conversions::vector_words(word).len();
+                let vector = unsafe { std::slice::from_raw_parts(ptr, len) };
+                create_object_data(&child_name, Linkage::Local, vector, true, jit)?
+            } else if tag == conversions::CLOSURE_TAG {
+                return Err(LustError::Compile(
+                    "internal error: closure found in program data".to_string(),
+                ));
+            } else {
+                continue;
+            };
+            relocations.push((i, child, tag));
+        }
+    }
+
+    let contents: Vec<u8> = words
+        .iter()
+        .enumerate()
+        .flat_map(|(i, word)| {
+            // Relocated words are filled in by the linker.
+            if relocations.iter().any(|(r, _, _)| *r == i) {
+                0
+            } else {
+                *word
+            }
+            .to_ne_bytes()
+            .to_vec()
+        })
+        .collect();
+    jit.data_ctx.define(contents.into_boxed_slice());
+
+    for (i, child, tag) in relocations {
+        let child = jit.module.declare_data_in_data(child, &mut jit.data_ctx);
+        let offset = (i * std::mem::size_of::<Word>()) as u32;
+        // The tag is added to the address of the object just as it
+        // is when tagging a pointer.
+        jit.data_ctx.write_data_addr(offset, child, tag);
+    }
+
+    let id = jit
+        .module
+        .declare_data(name, linkage, true, false)
+        .map_err(LustError::from)?;
+
+    jit.module
+        .define_data(id, &jit.data_ctx)
+        .map_err(LustError::from)?;
+
+    jit.data_ctx.clear();
+
+    Ok(id)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -170,13 +276,25 @@ mod tests {
 
         assert_eq!(
             Expr::from_immediate(data[2].data),
-            Expr::List(vec![
-                Expr::Integer(2),
-                Expr::List(vec![Expr::Integer(3), Expr::Nil])
-            ])
+            Expr::Pair(
+                Box::new(Expr::Integer(2)),
+                Box::new(Expr::Pair(Box::new(Expr::Integer(3)), Box::new(Expr::Nil)))
+            )
         )
     }
 
+    #[test]
+    fn strings_in_quoted_data() {
+        // The string inside the first quote isn't data of its own so
+        // the second quote still gets its own data.
+        let source = r#"(list '(1 "two") '(a . 1.5))"#;
+        assert_eq!(collect_data(&parse_string(source).unwrap()).len(), 2);
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string(r#"(list (list 1 "two") (cons 'a 1.5))"#).unwrap()
+        );
+    }
+
     #[test]
     fn test_data() {
         let expected_source = r#"
@@ -186,4 +304,44 @@ mod tests {
         let res = roundtrip_file("examples/data.lisp").unwrap();
         assert_eq!(expected, res)
     }
+
+    #[test]
+    fn quoted_list() {
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string("'(1 2 3)").unwrap(),
+            cons(
+                Expr::Integer(1),
+                cons(Expr::Integer(2), cons(Expr::Integer(3), Expr::Nil))
+            )
+        );
+        assert_eq!(
+            roundtrip_string("(car (cdr '(1 (2 3))))").unwrap(),
+            cons(Expr::Integer(2), cons(Expr::Integer(3), Expr::Nil))
+        );
+    }
+
+    #[test]
+    fn quoted_symbols() {
+        let symbol = |s: &str| Expr::Symbol(s.to_string());
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(roundtrip_string("'foo").unwrap(), symbol("foo"));
+        assert_eq!(roundtrip_string("(quote foo)").unwrap(), symbol("foo"));
+
+        // Quoted names aren't variables even if they are bound or are
+        // the names of builtins.
+        assert_eq!(
+            roundtrip_string("(let x 1) '(x let add)").unwrap(),
+            cons(
+                symbol("x"),
+                cons(symbol("let"), cons(symbol("add"), Expr::Nil))
+            )
+        );
+
+        assert_eq!(
+            roundtrip_string("(eq? 'a (car '(a b)))").unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(roundtrip_string("(eq? 'a 'b)").unwrap(), Expr::Bool(false));
+    }
 }
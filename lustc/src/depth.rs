@@ -0,0 +1,169 @@
+//! Recursion that isn't in tail position uses up the stack and
+//! running out of stack kills the process. To turn that into an error
+//! procedures count how deeply calls to them are nested. On entry a
+//! procedure adds one to the count and if that takes it past the
+//! JIT's stack limit it raises a stack overflow error instead of
+//! running. Every return, including a raise leaving the procedure,
+//! puts the count back to what it was on entry.
+//!
+//! Self tail calls jump back to just after the entry so loops don't
+//! count towards the limit.
+
+use std::cell::UnsafeCell;
+
+use cranelift::prelude::*;
+
+use crate::backend::LustModule;
+use crate::compiler::Context;
+use crate::errors::LustError;
+use crate::{fatal, primitives, Word};
+
+/// The stack limit that JITs start out with. Deep enough for most
+/// recursion while leaving room to spare on an eight megabyte stack.
+pub const DEFAULT_STACK_LIMIT: usize = 10000;
+
+thread_local! {
+    // The number of procedure calls nested on this thread. Compiled
+    // code loads and stores this directly so it has to stay put.
+    static DEPTH: UnsafeCell<Word> = const { UnsafeCell::new(0) };
+}
+
+/// Gets the address of the current thread's depth count. Programs run
+/// on the thread that they were compiled on so this can be compiled
+/// in.
+fn depth_address() -> i64 {
+    DEPTH.with(|depth| depth.get() as i64)
+}
+
+/// Raises a stack overflow error for going past LIMIT nested calls.
+/// Returns RAISED_VALUE.
+#[no_mangle]
+pub extern "C" fn stack_overflow_lustc_word(limit: Word) -> Word {
+    fatal::raise(LustError::StackOverflow(limit as usize))
+}
+
+/// Emits the entry of a procedure. Raises a stack overflow error if
+/// the call would nest more than LIMIT deep. Returns the depth count
+/// from before the call which `emit_leave` restores. A LIMIT of zero
+/// turns the check off, as does compiling to an object file as those
+/// have no runtime to keep the count in.
+pub(crate) fn emit_enter(limit: usize, ctx: &mut Context) -> Result<Option<Value>, LustError> {
+    if limit == 0 || !matches!(ctx.module, LustModule::JIT(_)) {
+        return Ok(None);
+    }
+    let address = ctx.builder.ins().iconst(ctx.word, depth_address());
+    let depth = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::trusted(), address, 0);
+    let entered = ctx.builder.ins().iadd_imm(depth, 1);
+    let too_deep = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::SignedGreaterThan, entered, limit as i64);
+
+    let overflow_block = ctx.builder.create_block();
+    let ok_block = ctx.builder.create_block();
+    ctx.builder.ins().brnz(too_deep, overflow_block, &[]);
+    ctx.builder.ins().jump(ok_block, &[]);
+
+    ctx.builder.switch_to_block(overflow_block);
+    ctx.builder.seal_block(overflow_block);
+    let limit = ctx.builder.ins().iconst(ctx.word, limit as i64);
+    let raised = primitives::emit_runtime_call("stack_overflow_lustc_word", &[limit], ctx)?;
+    // The count was never changed so there is nothing to put back.
+    ctx.builder.ins().return_(&[raised]);
+
+    ctx.builder.switch_to_block(ok_block);
+    ctx.builder.seal_block(ok_block);
+    ctx.builder
+        .ins()
+        .store(MemFlags::trusted(), entered, address, 0);
+    Ok(Some(depth))
+}
+
+/// Emits the code that puts the depth count back to what it was when
+/// the procedure being emitted was entered. Goes before every return
+/// from the procedure.
+pub(crate) fn emit_leave(ctx: &mut Context) {
+    if let Some(depth) = ctx.entry_depth {
+        let address = ctx.builder.ins().iconst(ctx.word, depth_address());
+        ctx.builder
+            .ins()
+            .store(MemFlags::trusted(), depth, address, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::{eval_program_in, JIT};
+    use crate::errors::LustError;
+    use crate::{parse_string, roundtrip_string, Expr};
+
+    fn eval_with_limit(source: &str, limit: usize) -> Result<Expr, LustError> {
+        let mut jit = JIT::default();
+        jit.options.stack_limit = limit;
+        eval_program_in(jit, &parse_string(source).unwrap(), &[], &[])
+    }
+
+    const SUM: &str = r#"
+(let sum (fn (n) (if (eq? n 0) 0 (add n (sum (sub1 n))))))
+"#;
+
+    #[test]
+    fn unbounded_recursion() {
+        let source = "(let f (fn (n) (add1 (f n)))) (f 1)";
+        assert_eq!(
+            eval_with_limit(source, 1000).map_err(LustError::without_location),
+            Err(LustError::StackOverflow(1000))
+        );
+        assert_eq!(
+            roundtrip_string(source).unwrap_err(),
+            "stack overflow: more than 10000 nested calls"
+        );
+    }
+
+    #[test]
+    fn recursion_within_limit() {
+        let source = format!("{} (sum 100)", SUM);
+        assert_eq!(eval_with_limit(&source, 101), Ok(Expr::Integer(5050)));
+        assert_eq!(
+            eval_with_limit(&source, 100),
+            Err(LustError::StackOverflow(100))
+        );
+        // Zero turns the limit off.
+        assert_eq!(eval_with_limit(&source, 0), Ok(Expr::Integer(5050)));
+    }
+
+    #[test]
+    fn depth_restored() {
+        // Returning, including raising out of a procedure, puts the
+        // count back so many calls in a row don't add up.
+        let source = format!(
+            r#"
+{}
+(let loop (fn (i acc)
+  (if (eq? i 0)
+      acc
+      (loop (sub1 i) (add acc (sum 50))))))
+(let raises (fn (n) (if (eq? n 0) (throw 'done 0) (add1 (raises (sub1 n))))))
+(let again (fn (i) (if (eq? i 0) 'ok (begin (catch 'done (raises 50)) (again (sub1 i))))))
+(list (loop 100 0) (again 100))
+"#,
+            SUM
+        );
+        assert_eq!(
+            eval_with_limit(&source, 60).unwrap(),
+            roundtrip_string("'(127500 ok)").unwrap()
+        );
+    }
+
+    #[test]
+    fn tail_calls_not_counted() {
+        let source = "(let f (fn (n) (if (eq? n 0) 'done (f (sub1 n))))) (f 100000)";
+        assert_eq!(
+            eval_with_limit(source, 10).unwrap(),
+            Expr::Symbol("done".to_string())
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Structural equality. (eq? a b) asks if A and B are the same object
+//! while (equal? a b) asks if they look the same when written out.
+//! Pairs and vectors are equal if their elements are, strings if they
+//! have the same bytes, and floats if they have the same value.
+//! Anything else is only equal to itself.
+
+use std::collections::HashSet;
+
+use crate::conversions::{
+    string_bytes, vector_words, word_is_float, word_is_pair, word_is_string, word_is_vector,
+    HEAP_PTR_MASK,
+};
+use crate::{Expr, Word};
+
+/// Gets the car and cdr of the pair that PTR_WORD points to.
+fn pair_words<'a>(ptr_word: Word) -> &'a [Word] {
+    let ptr = (ptr_word & HEAP_PTR_MASK) as *const Word;
+    unsafe { std::slice::from_raw_parts(ptr, 2) }
+}
+
+fn float_value(ptr_word: Word) -> f64 {
+    let ptr = (ptr_word & HEAP_PTR_MASK) as *const f64;
+    unsafe { *ptr }
+}
+
+/// Determines if the values A and B are structurally equal. The
+/// comparison keeps its own stack so long lists don't overflow the
+/// native one. Each pair of objects is only compared once which stops
+/// cyclic structures from being walked forever. Meeting a pair again
+/// means it is already being compared and the first comparison decides
+/// the answer.
+pub(crate) fn words_equal(a: Word, b: Word) -> bool {
+    let mut pending = vec![(a, b)];
+    let mut seen = HashSet::new();
+    while let Some((a, b)) = pending.pop() {
+        if a == b {
+            continue;
+        }
+        if word_is_pair(a) && word_is_pair(b) {
+            if seen.insert((a, b)) {
+                let (a, b) = (pair_words(a), pair_words(b));
+                pending.push((a[1], b[1]));
+                pending.push((a[0], b[0]));
+            }
+        } else if word_is_vector(a) && word_is_vector(b) {
+            if seen.insert((a, b)) {
+                let (a, b) = (vector_words(a), vector_words(b));
+                if a.len() != b.len() {
+                    return false;
+                }
+                pending.extend(a.iter().copied().zip(b.iter().copied()).rev());
+            }
+        } else if word_is_string(a) && word_is_string(b) {
+            if string_bytes(a) != string_bytes(b) {
+                return false;
+            }
+        } else if word_is_float(a) && word_is_float(b) {
+            if float_value(a) != float_value(b) {
+                return false;
+            }
+        } else {
+            return false;
+        }
+    }
+    true
+}
+
+/// Returns #t if A and B are structurally equal and #f otherwise.
+#[no_mangle]
+pub extern "C" fn equal_lustc_word(a: Word, b: Word) -> Word {
+    Expr::Bool(words_equal(a, b)).immediate_rep()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{roundtrip_string, Expr};
+
+    fn equal(source: &str) -> bool {
+        match roundtrip_string(source).unwrap() {
+            Expr::Bool(b) => b,
+            e => panic!("expected a bool, got {}", e),
+        }
+    }
+
+    #[test]
+    fn equal_lists() {
+        // Equal but not eq?.
+        assert!(equal("(equal? (list 1 2 3) (list 1 2 3))"));
+        assert!(!equal("(eq? (list 1 2 3) (list 1 2 3))"));
+        assert!(equal(
+            "(equal? '(1 (2 #\\a) . 3) (cons 1 (cons (list 2 #\\a) 3)))"
+        ));
+        assert!(!equal("(equal? (list 1 2 3) (list 1 2))"));
+        assert!(!equal("(equal? (list 1 2) (list 1 2 3))"));
+        assert!(!equal("(equal? (list 1 (list 2)) (list 1 (list 3)))"));
+        assert!(equal("(equal? () ())"));
+        assert!(!equal("(equal? () (list ()))"));
+    }
+
+    #[test]
+    fn equal_atoms() {
+        assert!(equal(r#"(equal? "abc" (string-append "a" "bc"))"#));
+        assert!(!equal(r#"(equal? "abc" "abd")"#));
+        assert!(!equal(r#"(equal? "abc" "ab")"#));
+        assert!(equal("(equal? 1.5 (add 1.0 0.5))"));
+        assert!(!equal("(equal? 1.5 2.5)"));
+        assert!(equal("(equal? 'a 'a)"));
+        assert!(!equal("(equal? 'a 'b)"));
+        assert!(!equal(r#"(equal? 1 "1")"#));
+        assert!(equal("(let f (fn () 1)) (equal? f f)"));
+        assert!(!equal("(equal? (fn () 1) (fn () 1))"));
+    }
+
+    #[test]
+    fn equal_vectors() {
+        let source = r#"
+(let a (make-vector 2 (list 1 2)))
+(let b (make-vector 2 (list 1 2)))
+(equal? a b)
+"#;
+        assert!(equal(source));
+        let source = r#"
+(let a (make-vector 2 1))
+(let b (make-vector 2 1))
+(vector-set! b 1 2)
+(equal? a b)
+"#;
+        assert!(!equal(source));
+        assert!(!equal("(equal? (make-vector 2 1) (make-vector 3 1))"));
+        assert!(!equal("(equal? (make-vector 2 1) (list 1 1))"));
+    }
+
+    #[test]
+    fn equal_cycles() {
+        let source = r#"
+(let a (make-vector 1 0))
+(vector-set! a 0 a)
+(let b (make-vector 1 0))
+(vector-set! b 0 b)
+(equal? a b)
+"#;
+        assert!(equal(source));
+        let source = r#"
+(let a (make-vector 2 0))
+(vector-set! a 0 a)
+(let b (make-vector 2 1))
+(vector-set! b 0 b)
+(equal? a b)
+"#;
+        assert!(!equal(source));
+    }
+
+    #[test]
+    fn equal_long_lists() {
+        let source = r#"
+(let build (fn (n)
+  (let loop ((i 0) (acc ()))
+    (if (eq? i n) acc (loop (add1 i) (cons i acc))))))
+(equal? (build 100000) (build 100000))
+"#;
+        assert!(equal(source));
+    }
+
+    #[test]
+    fn higher_order_equal() {
+        let source = r#"
+(let same equal?)
+(same (list 1 "two") (list 1 "two"))
+"#;
+        assert!(equal(source));
+    }
+}
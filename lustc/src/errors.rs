@@ -2,10 +2,192 @@
 //! Compilers as well
 //! Errors to
 
-use crate::location::Location;
+use std::fmt;
+
+use crate::location::{Location, Span};
 use crate::reader;
 use crate::tokenizer::{Token, TokenType};
+use crate::Expr;
 use colored::*;
+use cranelift_module::ModuleError;
+
+/// An error encountered while compiling or running a program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LustError {
+    /// A symbol was used that has no binding in scope.
+    UnboundSymbol(String),
+    /// A list was called whose head is a name that has no binding in
+    /// scope and isn't a primitive.
+    UnboundFunction(String),
+    /// A primitive or function was called with the wrong number of
+    /// arguments. A varadic function expects at least EXPECTED.
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        got: usize,
+        varadic: bool,
+    },
+    /// A list was evaluated that isn't a valid application.
+    IllegalApplication(Expr),
+    /// Cranelift failed to declare or define something.
+    CraneliftError(String),
+    /// Any other compilation error. Most of these are internal
+    /// errors.
+    Compile(String),
+    /// The program called (error MESSAGE IRRITANTS...).
+    UserError {
+        message: String,
+        irritants: Vec<Expr>,
+    },
+    /// (read) was given input that isn't a valid expression.
+    Read(String),
+    /// (throw TAG VALUE) was called outside of any catch for TAG.
+    UncaughtThrow { tag: Box<Expr>, value: Box<Expr> },
+    /// The program did something that would have ended the process,
+    /// like calling car on something that isn't a pair, while it was
+    /// compiled to raise fatal errors instead.
+    Fatal(String),
+    /// A procedure was called with more than this many calls already
+    /// nested.
+    StackOverflow(usize),
+    /// (assert EXPR) found that EXPR wasn't true. LOC is where the
+    /// assert is in the source if it is known.
+    AssertionFailed {
+        expr: Box<Expr>,
+        loc: Option<Location>,
+    },
+    /// ERROR happened while compiling the expression at LOC.
+    Located {
+        loc: Location,
+        error: Box<LustError>,
+    },
+}
+
+impl LustError {
+    /// Attaches SPAN to the error unless it already has a location.
+    /// Errors are located as they pass up through the lists being
+    /// compiled so the location that sticks is the innermost list
+    /// that was read from source.
+    pub(crate) fn at(self, span: Span) -> Self {
+        match (self, span) {
+            (e @ LustError::Located { .. }, _) | (e, Span(None)) => e,
+            (e, Span(Some(loc))) => LustError::Located {
+                loc,
+                error: Box::new(e),
+            },
+        }
+    }
+
+    /// Gets where in the source the error happened if it is known.
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            LustError::Located { loc, .. } => Some(*loc),
+            LustError::AssertionFailed { loc, .. } => *loc,
+            _ => None,
+        }
+    }
+
+    /// Gets the error without any location information.
+    pub fn without_location(self) -> Self {
+        match self {
+            LustError::Located { error, .. } => *error,
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for LustError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LustError::UnboundSymbol(s) => write!(f, "undefined variable ({})", s),
+            LustError::UnboundFunction(s) => write!(f, "undefined function ({})", s),
+            LustError::ArityMismatch {
+                name,
+                expected,
+                got,
+                varadic,
+            } => {
+                let at_least = if *varadic { "at least " } else { "" };
+                write!(
+                    f,
+                    "{} expected {}{} args and got {}",
+                    name, at_least, expected, got
+                )
+            }
+            LustError::IllegalApplication(e) => write!(f, "illegal function application {}", e),
+            LustError::CraneliftError(s) => write!(f, "{}", s),
+            LustError::Compile(s) => write!(f, "{}", s),
+            LustError::UserError { message, irritants } => {
+                write!(f, "{}", message)?;
+                for irritant in irritants {
+                    write!(f, " {}", irritant)?;
+                }
+                Ok(())
+            }
+            LustError::Read(s) => write!(f, "read: {}", s),
+            LustError::UncaughtThrow { tag, value } => {
+                write!(f, "uncaught throw to {}: {}", tag, value)
+            }
+            LustError::Fatal(s) => write!(f, "{}", s),
+            LustError::StackOverflow(limit) => {
+                write!(f, "stack overflow: more than {} nested calls", limit)
+            }
+            LustError::AssertionFailed { expr, loc } => {
+                write!(f, "assertion failed: {}", expr)?;
+                match loc {
+                    Some(loc) => write_location(f, loc),
+                    None => Ok(()),
+                }
+            }
+            LustError::Located { loc, error } => {
+                write!(f, "{}", error)?;
+                write_location(f, loc)
+            }
+        }
+    }
+}
+
+/// Writes where LOC starts for the end of an error message.
+fn write_location(f: &mut fmt::Formatter<'_>, loc: &Location) -> fmt::Result {
+    // Locations count from zero but people count from one.
+    write!(
+        f,
+        " at line {}, column {}",
+        loc.start.line + 1,
+        loc.start.col + 1
+    )
+}
+
+impl std::error::Error for LustError {}
+
+/// A syntax error found while parsing a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The byte offset in the source where the error starts.
+    pub offset: usize,
+    /// What went wrong.
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<String> for LustError {
+    fn from(s: String) -> Self {
+        LustError::Compile(s)
+    }
+}
+
+impl From<ModuleError> for LustError {
+    fn from(e: ModuleError) -> Self {
+        LustError::CraneliftError(e.to_string())
+    }
+}
 
 #[derive(Debug)]
 pub(crate) struct Error {
@@ -170,14 +352,14 @@ impl Printable for Suggestion {
 impl Error {
     pub(crate) fn on_tok(what: &str, token: &Token) -> Self {
         Self {
-            loc: token.loc.clone(),
+            loc: token.loc,
             what: what.to_string(),
             suggestion: Suggestion::on_tok(token),
         }
     }
     pub(crate) fn at_loc(what: &str, loc: &Location) -> Self {
         Self {
-            loc: loc.clone(),
+            loc: *loc,
             what: what.to_string(),
             suggestion: None,
         }
@@ -227,3 +409,117 @@ impl Suggestion {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::eval_program;
+    use crate::parse_string;
+
+    fn eval_string(source: &str) -> Result<Expr, LustError> {
+        eval_program(&parse_string(source).unwrap()).map_err(LustError::without_location)
+    }
+
+    /// Gets the (line, column) that compiling SOURCE fails at.
+    fn error_location(source: &str) -> (usize, usize) {
+        let loc = eval_program(&parse_string(source).unwrap())
+            .unwrap_err()
+            .location()
+            .unwrap();
+        (loc.start.line, loc.start.col)
+    }
+
+    #[test]
+    fn unbound_symbol() {
+        assert_eq!(
+            eval_string("(add1 foo)"),
+            Err(LustError::UnboundSymbol("foo".to_string()))
+        )
+    }
+
+    #[test]
+    fn unbound_function() {
+        let source = r#"
+(let fact (fn (n) (if (eq? n 0) 1 (mul n (fact (sub1 n))))))
+(let sum-facts (fn (a b) (add (fact a) (fatc b))))
+(sum-facts 3 4)
+"#;
+        assert_eq!(
+            eval_string(source),
+            Err(LustError::UnboundFunction("fatc".to_string()))
+        );
+        assert_eq!(
+            crate::roundtrip_string(source),
+            Err("undefined function (fatc) at line 3, column 40".to_string())
+        );
+        // Only names in head position are functions.
+        assert_eq!(
+            eval_string("(let f (fn (x) x)) (f g)"),
+            Err(LustError::UnboundSymbol("g".to_string()))
+        );
+        // Bound variables, primitives, and special forms are all fine.
+        assert_eq!(
+            eval_string("(let f car) (f (cons 1 2))"),
+            Ok(Expr::Integer(1))
+        );
+    }
+
+    #[test]
+    fn arity_mismatch() {
+        assert_eq!(
+            eval_string("(add1 1 2)"),
+            Err(LustError::ArityMismatch {
+                name: "add1".to_string(),
+                expected: 1,
+                got: 2,
+                varadic: false,
+            })
+        )
+    }
+
+    #[test]
+    fn illegal_application() {
+        assert_eq!(
+            eval_string("(1 2)"),
+            Err(LustError::IllegalApplication(Expr::list(vec![
+                Expr::Integer(1),
+                Expr::Integer(2)
+            ])))
+        )
+    }
+
+    #[test]
+    fn error_messages() {
+        assert_eq!(
+            crate::roundtrip_string("(add1 foo)"),
+            Err("undefined variable (foo) at line 1, column 1".to_string())
+        );
+        assert_eq!(
+            crate::roundtrip_string("1\n  (add1 1 2)"),
+            Err("add1 expected 1 args and got 2 at line 2, column 3".to_string())
+        );
+        assert_eq!(
+            crate::roundtrip_string("(1 2)"),
+            Err("illegal function application (1 2) at line 1, column 1".to_string())
+        );
+    }
+
+    #[test]
+    fn innermost_location() {
+        assert_eq!(error_location("(add1 (add1 (car 1 2)))"), (0, 12));
+        assert_eq!(error_location("(let x 1)\n(let y (add x foo))"), (1, 7));
+        // Lifted functions and lowered forms keep their locations.
+        assert_eq!(
+            error_location("(let f (fn (a)\n  (a 1 2)\n  (1 a)))"),
+            (2, 2)
+        );
+        assert_eq!(error_location("(let ((a 1))\n  (add1 b))"), (1, 2));
+        assert_eq!(
+            error_location("(define (f) (cond (else 1) (#t 2))) (f)"),
+            (0, 12)
+        );
+        // Forms that the compiler makes up are located at the
+        // closest form that came from the source.
+        assert_eq!(error_location("(fn () (define x 1))"), (0, 7));
+    }
+}
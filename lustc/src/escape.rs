@@ -1,5 +1,6 @@
 //! Determines what variables will need to be stored on the heap.
 
+use crate::errors::LustError;
 use crate::procedures::LustFn;
 use crate::Expr;
 
@@ -32,7 +33,7 @@ fn patch_params(f: &mut LustFn, free_vars: &[String]) {
 pub fn annotate_escaped_variables(
     functions: &mut [LustFn],
     program: &mut [Expr],
-) -> Result<(), String> {
+) -> Result<(), LustError> {
     let _t = crate::timer::timeit("free variable annotation");
     let mut free_vars = Vec::new();
     for f in functions.iter() {
@@ -57,14 +58,14 @@ mod tests {
 
     #[test]
     fn test_objects() {
-        let expected = Expr::List(vec![Expr::Integer(11), Expr::Integer(10)]);
+        let expected = Expr::Pair(Box::new(Expr::Integer(11)), Box::new(Expr::Integer(10)));
         let actual = roundtrip_file("examples/objects.lisp").unwrap();
         assert_eq!(actual, expected)
     }
 
     #[test]
     fn test_set() {
-        let expected = Expr::List(vec![Expr::Integer(11), Expr::Integer(12)]);
+        let expected = Expr::Pair(Box::new(Expr::Integer(11)), Box::new(Expr::Integer(12)));
         let actual = roundtrip_file("examples/set.lisp").unwrap();
         assert_eq!(actual, expected)
     }
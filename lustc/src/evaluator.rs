@@ -0,0 +1,355 @@
+//! Evaluates programs while remembering the code compiled for them.
+//! Running a program that has been run before skips straight to
+//! calling its lust_entry function. Literal data, like strings, is
+//! compiled into the JIT along with the code so every run of a
+//! program sees the same copy of it.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use crate::compiler::{
+    check_entry_args, compile_entry, compile_program, finalize_entry, run_entry, Entry, JIT,
+};
+use crate::errors::LustError;
+use crate::Expr;
+
+/// A program that has been compiled into an evaluator's JIT.
+struct CompiledProgram {
+    program: Vec<Expr>,
+    params: Vec<String>,
+    entry: Entry,
+}
+
+/// Compiles and runs programs caching the result of compilation so
+/// that running the same program again doesn't recompile it. Every
+/// program is compiled into the same JIT under a namespace of its own
+/// so they share the primitives and symbols that they have in common.
+#[derive(Default)]
+pub struct Evaluator {
+    cache: HashMap<u64, CompiledProgram>,
+    // Owns the memory that the cached entries' code is in.
+    jit: JIT,
+    // The number of programs compiled into the JIT, used to give each
+    // one its own namespace.
+    compiled: usize,
+}
+
+impl Evaluator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs PROGRAM with each of PARAMS bound to the integer in ARGS at
+    /// the same position. Compiles it first unless it, with the same
+    /// PARAMS, has been run by this evaluator before.
+    pub fn eval(
+        &mut self,
+        program: &[Expr],
+        params: &[&str],
+        args: &[i64],
+    ) -> Result<Expr, LustError> {
+        check_entry_args(params, args)?;
+
+        let key = cache_key(program, params);
+        let cached = self
+            .cache
+            .get(&key)
+            .is_some_and(|c| c.program == program && c.params == params);
+        if !cached {
+            self.jit.namespace = Some(format!("program_{}", self.compiled));
+            self.compiled += 1;
+            let entry = compile_entry(&mut self.jit, program, params)?;
+            // Two programs with the same key replace each other which
+            // is fine so long as it's rare. The code of the one that
+            // is replaced stays in the JIT until it is cleared.
+            self.cache.insert(
+                key,
+                CompiledProgram {
+                    program: program.to_vec(),
+                    params: params.iter().map(|p| p.to_string()).collect(),
+                    entry,
+                },
+            );
+        }
+        let compiled = &self.cache[&key];
+
+        // Safety: the code is freed when the cache is cleared.
+        unsafe { run_entry(&self.jit, compiled.entry, args) }
+    }
+
+    /// The number of compiled programs being held onto.
+    pub fn cached_programs(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Forgets every compiled program freeing their code.
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        // Safety: no entries are left to call into the code.
+        unsafe { self.jit.module.free_memory() }
+        self.jit = JIT::default();
+        self.compiled = 0;
+    }
+}
+
+impl Drop for Evaluator {
+    fn drop(&mut self) {
+        // Safety: calls can't outlive self so none are running.
+        unsafe { self.jit.module.free_memory() }
+    }
+}
+
+/// A function compiled on its own so that it can be called from Rust
+/// again and again. The function's code stays mapped for as long as
+/// this is around and is freed when it is dropped.
+pub struct CompiledFn {
+    params: Vec<String>,
+    entry: Entry,
+    // Owns the memory that ENTRY's code is in.
+    jit: JIT,
+}
+
+/// Compiles a function that takes PARAMS and evaluates BODY.
+pub fn compile_function(params: &[String], body: &[Expr]) -> Result<CompiledFn, String> {
+    let names: Vec<&str> = params.iter().map(String::as_str).collect();
+    let mut jit = JIT::default();
+    let entry = compile_entry(&mut jit, body, &names).map_err(|e| e.to_string())?;
+    Ok(CompiledFn {
+        params: params.to_vec(),
+        entry,
+        jit,
+    })
+}
+
+impl CompiledFn {
+    /// Calls the function with ARGS bound to its parameters. Fails if
+    /// the function doesn't evaluate to an integer.
+    pub fn call(&self, args: &[i64]) -> Result<i64, String> {
+        let params: Vec<&str> = self.params.iter().map(String::as_str).collect();
+        check_entry_args(&params, args).map_err(|e| e.to_string())?;
+        // Safety: the code is freed when self is dropped.
+        match unsafe { run_entry(&self.jit, self.entry, args) } {
+            Ok(Expr::Integer(i)) => Ok(i),
+            Ok(e) => Err(format!("expected an integer result, got {}", e)),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+impl Drop for CompiledFn {
+    fn drop(&mut self) {
+        // Safety: calls can't outlive self so none are running and
+        // the entry is dropped along with the code.
+        unsafe { self.jit.module.free_memory() }
+    }
+}
+
+/// Several programs compiled into one JIT so that they are loaded
+/// once and any of them can be run. Each program's entry function is
+/// exported under the program's name. The code is freed when this is
+/// dropped.
+pub struct CompiledModule {
+    entries: HashMap<String, Entry>,
+    // Owns the memory that the entries' code is in.
+    jit: JIT,
+}
+
+/// Compiles each of PROGRAMS, a list of names and programs, into one
+/// JIT. The functions and data each program defines are namespaced
+/// with its name so programs can't clash with each other.
+pub fn compile_many(programs: &[(&str, &mut [Expr])]) -> Result<CompiledModule, String> {
+    let mut jit = JIT::default();
+    let mut ids = Vec::new();
+    for (name, program) in programs {
+        if ids.iter().any(|(n, _)| n == name) {
+            return Err(format!("more than one program named {}", name));
+        }
+        jit.namespace = Some(name.to_string());
+        let id = compile_program(program, &[], &mut jit).map_err(|e| format!("{}: {}", name, e))?;
+        ids.push((*name, id));
+    }
+    let entries = ids
+        .into_iter()
+        .map(|(name, id)| Ok((name.to_string(), finalize_entry(&mut jit.module, id, 0)?)))
+        .collect::<Result<_, LustError>>()
+        .map_err(|e| e.to_string())?;
+    Ok(CompiledModule { entries, jit })
+}
+
+impl CompiledModule {
+    /// Runs the program called NAME. Returns None if there is no
+    /// program by that name or if it doesn't evaluate to an integer.
+    pub fn call(&self, name: &str) -> Option<i64> {
+        let entry = *self.entries.get(name)?;
+        // Safety: the code is freed when self is dropped.
+        match unsafe { run_entry(&self.jit, entry, &[]) } {
+            Ok(Expr::Integer(i)) => Some(i),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for CompiledModule {
+    fn drop(&mut self) {
+        // Safety: calls can't outlive self so none are running.
+        unsafe { self.jit.module.free_memory() }
+    }
+}
+
+fn cache_key(program: &[Expr], params: &[&str]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    program.hash(&mut hasher);
+    params.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_string;
+
+    #[test]
+    fn reuses_compiled_programs() {
+        let mut evaluator = Evaluator::new();
+        let program = parse_string("(let sq (fn (n) (mul n n))) (sq a)").unwrap();
+        assert_eq!(evaluator.eval(&program, &["a"], &[3]), Ok(Expr::Integer(9)));
+        assert_eq!(
+            evaluator.eval(&program, &["a"], &[4]),
+            Ok(Expr::Integer(16))
+        );
+        assert_eq!(evaluator.cached_programs(), 1);
+
+        // Where the program was read from doesn't matter.
+        let moved = parse_string("\n\n  (let sq (fn (n) (mul n n)))\n(sq a)").unwrap();
+        assert_eq!(evaluator.eval(&moved, &["a"], &[5]), Ok(Expr::Integer(25)));
+        assert_eq!(evaluator.cached_programs(), 1);
+
+        // A different program or different parameters are compiled
+        // again.
+        let other = parse_string("(add a 1)").unwrap();
+        assert_eq!(evaluator.eval(&other, &["a"], &[1]), Ok(Expr::Integer(2)));
+        let renamed = parse_string("(let sq (fn (n) (mul n n))) (sq b)").unwrap();
+        assert!(evaluator.eval(&renamed, &["a"], &[1]).is_err());
+        assert_eq!(
+            evaluator.eval(&renamed, &["b"], &[6]),
+            Ok(Expr::Integer(36))
+        );
+        assert_eq!(evaluator.cached_programs(), 3);
+
+        evaluator.clear();
+        assert_eq!(evaluator.cached_programs(), 0);
+    }
+
+    #[test]
+    fn programs_share_one_jit() {
+        let mut evaluator = Evaluator::new();
+        // Failing to compile part way through a program leaves the JIT
+        // ready for the next one.
+        let broken = parse_string("(add1 (car 1 2))").unwrap();
+        assert!(evaluator.eval(&broken, &[], &[]).is_err());
+        // Both programs lift a function so they'd clash if they
+        // weren't namespaced.
+        let inc = parse_string("(let f (fn (x) (add x 1))) (f a)").unwrap();
+        let triple = parse_string("(let f (fn (x) (mul x 3))) (f a)").unwrap();
+        assert_eq!(evaluator.eval(&inc, &["a"], &[1]), Ok(Expr::Integer(2)));
+        assert_eq!(evaluator.eval(&triple, &["a"], &[2]), Ok(Expr::Integer(6)));
+        assert_eq!(evaluator.eval(&inc, &["a"], &[3]), Ok(Expr::Integer(4)));
+        assert_eq!(evaluator.cached_programs(), 2);
+
+        evaluator.clear();
+        assert_eq!(evaluator.eval(&triple, &["a"], &[3]), Ok(Expr::Integer(9)));
+    }
+
+    #[test]
+    fn compiled_function() {
+        let params = vec!["x".to_string(), "y".to_string()];
+        let body = parse_string("(let sq (fn (n) (mul n n))) (add (sq x) y)").unwrap();
+        let f = compile_function(&params, &body).unwrap();
+        for i in 0..100 {
+            assert_eq!(f.call(&[i, 1]), Ok(i * i + 1));
+        }
+        assert!(f.call(&[1]).is_err());
+
+        let f = compile_function(&params, &parse_string("(cons x y)").unwrap()).unwrap();
+        assert_eq!(
+            f.call(&[1, 2]),
+            Err("expected an integer result, got (1 . 2)".to_string())
+        );
+
+        let f = compile_function(&[], &parse_string("(error \"nope\")").unwrap()).unwrap();
+        assert!(f.call(&[]).unwrap_err().contains("nope"));
+
+        assert!(compile_function(&[], &parse_string("(add z 1)").unwrap()).is_err());
+    }
+
+    #[test]
+    fn compiled_functions_are_freed() {
+        // Each function's code is unmapped when it is dropped so
+        // making lots of them one after the other doesn't run out of
+        // memory or disturb the ones that are still around.
+        let params = vec!["x".to_string()];
+        let kept = compile_function(&params, &parse_string("(add x 1)").unwrap()).unwrap();
+        for i in 0..200 {
+            let source = format!("(list \"s\" {})", i);
+            let body = parse_string(&format!("(let l {}) (add x (length l))", source)).unwrap();
+            let f = compile_function(&params, &body).unwrap();
+            assert_eq!(f.call(&[i]), Ok(i + 2));
+        }
+        assert_eq!(kept.call(&[1]), Ok(2));
+    }
+
+    #[test]
+    fn heap_results_from_cached_programs() {
+        let mut evaluator = Evaluator::new();
+        let program = parse_string("(list \"hi\" 1.5 (cons a a))").unwrap();
+        for i in 0..3 {
+            let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+            assert_eq!(
+                evaluator.eval(&program, &["a"], &[i]).unwrap(),
+                cons(
+                    Expr::String("hi".to_string()),
+                    cons(
+                        Expr::Float(1.5),
+                        cons(cons(Expr::Integer(i), Expr::Integer(i)), Expr::Nil)
+                    )
+                )
+            );
+        }
+        assert_eq!(evaluator.cached_programs(), 1);
+    }
+
+    #[test]
+    fn bad_arguments() {
+        let mut evaluator = Evaluator::new();
+        let program = parse_string("a").unwrap();
+        assert!(evaluator.eval(&program, &["a"], &[]).is_err());
+        assert_eq!(evaluator.cached_programs(), 0);
+    }
+
+    #[test]
+    fn many_programs_in_one_module() {
+        // Both programs lift a function, use quoted data, and use car
+        // as a higher order function so each would define the same
+        // names if they weren't namespaced.
+        let mut double =
+            parse_string("(let f (fn (x) (mul x 2))) (f (car (map car (list '(21)))))").unwrap();
+        let mut count = parse_string(
+            r#"(let f (fn (l) (if (null? l) 0 (add1 (f (cdr l)))))) (f (map car '((a) (b) (c))))"#,
+        )
+        .unwrap();
+        let module =
+            compile_many(&[("double", &mut double[..]), ("count", &mut count[..])]).unwrap();
+        assert_eq!(module.call("double"), Some(42));
+        assert_eq!(module.call("count"), Some(3));
+        // Programs can be run again.
+        assert_eq!(module.call("double"), Some(42));
+        assert_eq!(module.call("triple"), None);
+
+        let mut other = parse_string("1").unwrap();
+        match compile_many(&[("a", &mut double[..]), ("a", &mut other[..])]) {
+            Err(e) => assert!(e.contains("more than one program named a"), "{}", e),
+            Ok(_) => panic!("programs with the same name were compiled"),
+        }
+    }
+}
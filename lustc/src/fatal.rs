@@ -1,27 +1,105 @@
 use crate::{
     compiler::{self, Context, JIT},
-    conversions,
+    conditional, conversions,
     data::LustData,
-    foreign, Expr, Word,
+    errors::LustError,
+    foreign, heap,
+    location::{Location, Span},
+    peephole, primitives, reader, Expr, Word,
 };
 use cranelift::prelude::*;
+use std::cell::RefCell;
+
+thread_local! {
+    // What the program running on this thread raised. It is taken
+    // once the program has returned or by the catch that it was
+    // thrown to.
+    static RAISED: RefCell<Option<Raised>> = const { RefCell::new(None) };
+}
+
+/// Something raised by a running program. The values in throws and
+/// calls to error are kept as words until nothing catches them so
+/// that raising a value never has to convert it.
+enum Raised {
+    Error(LustError),
+    User { message: Word, irritants: Vec<Word> },
+    Throw { tag: Word, value: Word },
+}
+
+impl Raised {
+    fn into_error(self) -> LustError {
+        match self {
+            Raised::Error(e) => e,
+            Raised::User { message, irritants } => LustError::UserError {
+                message: match Expr::from_immediate(message) {
+                    Expr::String(s) => s,
+                    e => e.to_string(),
+                },
+                irritants: irritants.into_iter().map(Expr::from_immediate).collect(),
+            },
+            Raised::Throw { tag, value } => LustError::UncaughtThrow {
+                tag: Box::new(Expr::from_immediate(tag)),
+                value: Box::new(Expr::from_immediate(value)),
+            },
+        }
+    }
+}
 
 impl Expr {
     // Determines if an expression is an error expression and returns
-    // its messsage and return code arguments.
-    pub fn is_error(&self) -> Option<(&Expr, &Expr)> {
-        if let Expr::List(v) = self {
+    // its messsage and irritants.
+    pub fn is_error(&self) -> Option<(&Expr, &[Expr])> {
+        if let Expr::List(v, _) = self {
             if let Some(Expr::Symbol(s)) = v.first() {
-                if s == "error" && v.len() == 3 {
-                    return Some((&v[1], &v[2]));
+                if s == "error" && v.len() >= 2 {
+                    return Some((&v[1], &v[2..]));
                 }
             }
         }
         None
     }
+
+    /// Determines if an expression is an assert expression and
+    /// returns the expression being asserted.
+    pub fn is_assert(&self) -> Option<&Expr> {
+        match self {
+            Expr::List(v, _) => match &v[..] {
+                [Expr::Symbol(s), asserted] if s == "assert" => Some(asserted),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Determines if an expression is a lowered assert expression,
+    /// (__assert 'EXPR EXPR), and returns the quoted expression and
+    /// the expression to evaluate.
+    pub(crate) fn is_lowered_assert(&self) -> Option<(&Expr, &Expr)> {
+        match self {
+            Expr::List(v, _) => match &v[..] {
+                [Expr::Symbol(s), quoted, asserted] if s == "__assert" => Some((quoted, asserted)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Determines if an expression is a catch expression and returns
+    /// its tag and body.
+    pub fn is_catch(&self) -> Option<(&Expr, &[Expr])> {
+        match self {
+            Expr::List(v, _) => match &v[..] {
+                [Expr::Symbol(s), tag, body @ ..] if s == "catch" && !body.is_empty() => {
+                    Some((tag, body))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
 }
 
-pub(crate) fn emit_error_strings(jit: &mut JIT) -> Result<(), String> {
+pub(crate) fn emit_error_strings(jit: &mut JIT) -> Result<(), LustError> {
     let error_strings = [
         (
             "__anon_data_bad_call_type",
@@ -35,33 +113,296 @@ pub(crate) fn emit_error_strings(jit: &mut JIT) -> Result<(), String> {
             "__anon_data_bad_arg_count",
             "fatal error: wrong number of arguments in function call",
         ),
+        (
+            "__anon_data_divide_by_zero",
+            "fatal error: division by zero",
+        ),
+        (
+            "__anon_data_out_of_bounds",
+            "fatal error: vector index out of bounds",
+        ),
+        (
+            "__anon_data_string_out_of_bounds",
+            "fatal error: string index out of bounds",
+        ),
+        (
+            "__anon_data_list_out_of_bounds",
+            "fatal error: list index out of bounds",
+        ),
+        (
+            "__anon_data_bad_char",
+            "fatal error: integer is not a valid character",
+        ),
+        ("__anon_data_overflow", "fatal error: integer overflow"),
+        (
+            "__anon_data_not_whole",
+            "fatal error: float has no exact integer value",
+        ),
     ];
-    let error_data = error_strings
+    error_strings
         .iter()
-        .map(|(name, msg)| -> Result<LustData, std::ffi::NulError> {
-            Ok(LustData {
-                name: name.to_string(),
-                // bit of a hack but we tag these as pairs so that
-                // they register as heap allocated values elsewhere.
-                data: std::ffi::CString::new(*msg)?.into_raw() as Word | conversions::PAIR_TAG,
-            })
+        .map(|(name, msg)| LustData {
+            name: name.to_string(),
+            data: conversions::string_to_immediate(msg),
         })
-        .collect::<Result<Vec<LustData>, _>>()
-        .map_err(|e| e.to_string())?;
+        .try_for_each(|d| crate::data::create_data(d, jit))
+}
 
-    error_data
-        .into_iter()
-        .map(|d| crate::data::create_data(d, jit))
-        .collect()
+/// Emits the code for the fatal error whose string is named MESSAGE
+/// and then jumps to OK_BLOCK, which is never reached. Fatal errors
+/// print their message and exit unless the raise_fatal_errors option
+/// is set in which case they are raised like (error ...) is.
+fn emit_fatal(message: &str, ok_block: Block, ctx: &mut Context) -> Result<(), LustError> {
+    let message = Expr::Symbol(message.to_string());
+    if ctx.options.raise_fatal_errors {
+        let message = compiler::emit_expr(&message, ctx)?;
+        let raised = primitives::emit_runtime_call("fatal_lustc_word", &[message], ctx)?;
+        emit_raise_onwards(raised, ctx);
+    } else {
+        foreign::emit_foreign_call("puts", &[message], ctx)?;
+        foreign::emit_foreign_call("exit", &[Expr::Integer(-1)], ctx)?;
+        ctx.builder.ins().jump(ok_block, &[]);
+    }
+    Ok(())
 }
 
-pub(crate) fn emit_error(
+/// Emits the code for (error MESSAGE IRRITANTS...). This records the
+/// error and then returns from the function being emitted. Every call
+/// checks if the callee raised an error so the error makes its way
+/// back out of lust_entry where it is turned into a
+/// LustError::UserError.
+pub(crate) fn emit_raise(
     message: &Expr,
-    exit_code: &Expr,
+    irritants: &[Expr],
     ctx: &mut Context,
-) -> Result<Value, String> {
-    foreign::emit_foreign_call("puts", &[message.clone()], ctx)?;
-    foreign::emit_foreign_call("exit", &[exit_code.clone()], ctx)
+) -> Result<Value, LustError> {
+    let message = compiler::emit_expr(message, ctx)?;
+    let word_bytes = ctx.word.bytes() as usize;
+    let irritant_loc = heap::emit_alloc((irritants.len() * word_bytes) as i64, ctx)?;
+    for (i, irritant) in irritants.iter().enumerate() {
+        let val = compiler::emit_expr(irritant, ctx)?;
+        ctx.builder
+            .ins()
+            .store(MemFlags::new(), val, irritant_loc, (i * word_bytes) as i32);
+    }
+    let count = ctx.builder.ins().iconst(ctx.word, irritants.len() as i64);
+    let raised =
+        primitives::emit_runtime_call("raise_lustc_word", &[message, count, irritant_loc], ctx)?;
+    emit_propagate_raise(raised, ctx);
+    // Never reached but every expression needs a value.
+    Ok(ctx.builder.ins().iconst(ctx.word, conversions::NIL_VALUE))
+}
+
+/// Emits the code for (catch TAG BODY...). BODY is evaluated with a
+/// handler block installed that anything raised inside of it jumps
+/// to instead of returning. The handler asks the runtime if what was
+/// raised is a throw to TAG. If it is the catch evaluates to the
+/// thrown value, otherwise the raise carries on outwards.
+pub(crate) fn emit_catch(tag: &Expr, body: &[Expr], ctx: &mut Context) -> Result<Value, LustError> {
+    let tag = compiler::emit_expr(tag, ctx)?;
+    let handler_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(exit_block, ctx.word);
+
+    ctx.handlers.push(handler_block);
+    let res = crate::sequence::emit_sequence(body, false, ctx);
+    ctx.handlers.pop();
+    ctx.builder.ins().jump(exit_block, &[res?]);
+
+    // Every raise in BODY has been emitted so the handler's
+    // predecessors are all known.
+    ctx.builder.switch_to_block(handler_block);
+    ctx.builder.seal_block(handler_block);
+    let caught = primitives::emit_runtime_call("catch_lustc_word", &[tag], ctx)?;
+    emit_propagate_raise(caught, ctx);
+    ctx.builder.ins().jump(exit_block, &[caught]);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+    Ok(ctx.builder.block_params(exit_block)[0])
+}
+
+/// Lowers (assert ASSERTED) into (__assert 'ASSERTED ASSERTED) so
+/// that the asserted expression, as it was written, is compiled into
+/// the program as data for the error message. The lowered expression
+/// keeps SPAN, the span of the assert.
+pub(crate) fn lower_assert(asserted: &Expr, span: Span) -> Expr {
+    Expr::List(
+        vec![
+            Expr::Symbol("__assert".to_string()),
+            Expr::list(vec![Expr::Symbol("quote".to_string()), asserted.clone()]),
+            asserted.clone(),
+        ],
+        span,
+    )
+}
+
+/// Emits the code for (__assert QUOTED ASSERTED). If ASSERTED doesn't
+/// evaluate to #t an assertion failure is raised carrying QUOTED and
+/// SPAN. Otherwise the assert evaluates to nil.
+pub(crate) fn emit_assert(
+    quoted: &Expr,
+    asserted: &Expr,
+    span: Span,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let val = compiler::emit_expr(asserted, ctx)?;
+    let holds = conditional::emit_is_true(val, ctx);
+    let failed_block = ctx.builder.create_block();
+    let ok_block = ctx.builder.create_block();
+    ctx.builder.ins().brz(holds, failed_block, &[]);
+    ctx.builder.ins().jump(ok_block, &[]);
+
+    ctx.builder.switch_to_block(failed_block);
+    ctx.builder.seal_block(failed_block);
+    let quoted = compiler::emit_expr(quoted, ctx)?;
+    // Assertions without a span pass -1 for each part of it.
+    let span = match span.0 {
+        Some(loc) => [loc.start.line, loc.start.col, loc.end.line, loc.end.col].map(|i| i as i64),
+        None => [-1; 4],
+    };
+    let mut args = vec![quoted];
+    args.extend(span.iter().map(|&i| ctx.builder.ins().iconst(ctx.word, i)));
+    let raised = primitives::emit_runtime_call("assert_failed_lustc_word", &args, ctx)?;
+    emit_raise_onwards(raised, ctx);
+
+    ctx.builder.switch_to_block(ok_block);
+    ctx.builder.seal_block(ok_block);
+    Ok(ctx.builder.ins().iconst(ctx.word, conversions::NIL_VALUE))
+}
+
+/// Emits a return of RAISED_VALUE if RESULT, the value returned by a
+/// call, is RAISED_VALUE. Inside of a catch this jumps to the catch's
+/// handler instead.
+pub(crate) fn emit_propagate_raise(result: Value, ctx: &mut Context) {
+    let raise_block = ctx.builder.create_block();
+    let ok_block = ctx.builder.create_block();
+
+    let raised = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::Equal, result, conversions::RAISED_VALUE);
+    ctx.builder.ins().brnz(raised, raise_block, &[]);
+    ctx.builder.ins().jump(ok_block, &[]);
+
+    ctx.builder.switch_to_block(raise_block);
+    ctx.builder.seal_block(raise_block);
+    emit_raise_onwards(result, ctx);
+
+    ctx.builder.switch_to_block(ok_block);
+    ctx.builder.seal_block(ok_block);
+}
+
+/// Carries a raise on from the current block by jumping to the
+/// innermost handler or, outside of any, returning RESULT which must
+/// be RAISED_VALUE. Leaves the current block filled.
+pub(crate) fn emit_raise_onwards(result: Value, ctx: &mut Context) {
+    match ctx.handlers.last() {
+        Some(&handler) => ctx.builder.ins().jump(handler, &[]),
+        None => {
+            crate::depth::emit_leave(ctx);
+            ctx.builder.ins().return_(&[result])
+        }
+    };
+}
+
+/// Records the error raised by (error MESSAGE IRRITANTS...). There are
+/// COUNT irritants stored one after the other starting at IRRITANTS.
+/// Returns RAISED_VALUE.
+#[no_mangle]
+pub extern "C" fn raise_lustc_word(message: Word, count: Word, irritants: Word) -> Word {
+    // Safety: the emitted code stores COUNT words at IRRITANTS right
+    // before calling this.
+    let irritants = unsafe { std::slice::from_raw_parts(irritants as *const Word, count as usize) };
+    set_raised(Raised::User {
+        message,
+        irritants: irritants.to_vec(),
+    })
+}
+
+/// Records that the assertion of EXPR failed. The assert's span
+/// starts at START_LINE, START_COL and ends at END_LINE, END_COL, or
+/// all of them are -1 if it has none. Returns RAISED_VALUE.
+#[no_mangle]
+pub extern "C" fn assert_failed_lustc_word(
+    expr: Word,
+    start_line: Word,
+    start_col: Word,
+    end_line: Word,
+    end_col: Word,
+) -> Word {
+    let location = |line: Word, col: Word| reader::Location {
+        line: line as usize,
+        col: col as usize,
+    };
+    let loc = (start_line >= 0).then(|| Location {
+        start: location(start_line, start_col),
+        end: location(end_line, end_col),
+    });
+    raise(LustError::AssertionFailed {
+        expr: Box::new(Expr::from_immediate(expr)),
+        loc,
+    })
+}
+
+/// Records the fatal error whose message is the string MESSAGE as
+/// raised. Only called when the raise_fatal_errors option is set.
+/// Returns RAISED_VALUE.
+#[no_mangle]
+pub extern "C" fn fatal_lustc_word(message: Word) -> Word {
+    let message = match Expr::from_immediate(message) {
+        Expr::String(s) => s,
+        e => e.to_string(),
+    };
+    let message = message.strip_prefix("fatal error: ").unwrap_or(&message);
+    raise(LustError::Fatal(message.to_string()))
+}
+
+/// Records ERROR as raised by the running program and returns
+/// RAISED_VALUE. Runtime functions return the result and the code
+/// that called them returns it in turn.
+pub(crate) fn raise(error: LustError) -> Word {
+    set_raised(Raised::Error(error))
+}
+
+fn set_raised(raised: Raised) -> Word {
+    RAISED.with(|r| *r.borrow_mut() = Some(raised));
+    conversions::RAISED_VALUE
+}
+
+/// Takes the error raised by the program that just ran on this
+/// thread.
+pub(crate) fn take_raised() -> Option<LustError> {
+    RAISED
+        .with(|raised| raised.borrow_mut().take())
+        .map(Raised::into_error)
+}
+
+/// Throws VALUE to the closest catch for TAG. The throw is raised
+/// like an error so that it unwinds the calls between it and the
+/// catch. If there is no catch for TAG the program stops with an
+/// uncaught throw error. Returns RAISED_VALUE.
+#[no_mangle]
+pub extern "C" fn throw_lustc_word(tag: Word, value: Word) -> Word {
+    set_raised(Raised::Throw { tag, value })
+}
+
+/// Called by a catch for TAG when something is raised inside of it.
+/// If that was a throw to TAG the throw is over and its value is
+/// returned. Otherwise returns RAISED_VALUE so that the raise carries
+/// on. Tags are compared with eq?.
+#[no_mangle]
+pub extern "C" fn catch_lustc_word(tag: Word) -> Word {
+    RAISED.with(|raised| {
+        let mut raised = raised.borrow_mut();
+        match *raised {
+            Some(Raised::Throw { tag: thrown, value }) if thrown == tag => {
+                *raised = None;
+                value
+            }
+            _ => conversions::RAISED_VALUE,
+        }
+    })
 }
 
 pub(crate) fn emit_check_tag(
@@ -69,26 +410,119 @@ pub(crate) fn emit_check_tag(
     tag: Word,
     mask: Word,
     ctx: &mut Context,
-) -> Result<(), String> {
-    let tag_val = ctx.builder.ins().band_imm(query, mask);
-    let is_tag = ctx.builder.ins().icmp_imm(IntCC::Equal, tag_val, tag);
+) -> Result<(), LustError> {
+    let is_tag = foreign::emit_is(query, tag, mask, ctx);
+    emit_check_type(is_tag, ctx)
+}
+
+/// Emits a runtime type error if IS_TYPE is false.
+pub(crate) fn emit_check_type(is_type: Value, ctx: &mut Context) -> Result<(), LustError> {
+    emit_check(is_type, "__anon_data_bad_arg_type", ctx)
+}
+
+/// Emits a division by zero error if DIVISOR is the fixnum zero. This
+/// needs to happen before any sdiv or srem instructions as those trap
+/// on a zero divisor.
+pub(crate) fn emit_check_nonzero(divisor: Value, ctx: &mut Context) -> Result<(), LustError> {
+    let is_nonzero =
+        ctx.builder
+            .ins()
+            .icmp_imm(IntCC::NotEqual, divisor, Expr::Integer(0).immediate_rep());
+    emit_check(is_nonzero, "__anon_data_divide_by_zero", ctx)
+}
 
+/// Emits an out of bounds error unless INDEX is a valid index into a
+/// vector of length LENGTH. Both are fixnums. Comparing them unsigned
+/// means negative indices wrap around and fail the check too.
+pub(crate) fn emit_check_bounds(
+    index: Value,
+    length: Value,
+    ctx: &mut Context,
+) -> Result<(), LustError> {
+    let in_bounds = ctx
+        .builder
+        .ins()
+        .icmp(IntCC::UnsignedLessThan, index, length);
+    emit_check(in_bounds, "__anon_data_out_of_bounds", ctx)
+}
+
+/// Emits an out of bounds error unless INDEX is a valid index into a
+/// string of LENGTH bytes. Both are fixnums.
+pub(crate) fn emit_check_string_bounds(
+    index: Value,
+    length: Value,
+    ctx: &mut Context,
+) -> Result<(), LustError> {
+    let in_bounds = ctx
+        .builder
+        .ins()
+        .icmp(IntCC::UnsignedLessThan, index, length);
+    emit_check(in_bounds, "__anon_data_string_out_of_bounds", ctx)
+}
+
+/// Emits an out of bounds error unless IN_BOUNDS is true. Lists don't
+/// know their length up front so the check is made while walking
+/// them.
+pub(crate) fn emit_check_list_bounds(in_bounds: Value, ctx: &mut Context) -> Result<(), LustError> {
+    emit_check(in_bounds, "__anon_data_list_out_of_bounds", ctx)
+}
+
+/// Emits a runtime type error if the fixnum QUERY is negative.
+pub(crate) fn emit_check_nonnegative(query: Value, ctx: &mut Context) -> Result<(), LustError> {
+    let is_nonnegative = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::SignedGreaterThanOrEqual, query, 0);
+    emit_check_type(is_nonnegative, ctx)
+}
+
+/// Emits an integer overflow error unless IN_RANGE is true.
+pub(crate) fn emit_check_no_overflow(in_range: Value, ctx: &mut Context) -> Result<(), LustError> {
+    emit_check(in_range, "__anon_data_overflow", ctx)
+}
+
+/// Emits an error unless IS_WHOLE is true. Checks that a float being
+/// converted to an integer doesn't have a fractional part.
+pub(crate) fn emit_check_whole(is_whole: Value, ctx: &mut Context) -> Result<(), LustError> {
+    emit_check(is_whole, "__anon_data_not_whole", ctx)
+}
+
+/// Emits a bad character error unless the fixnum QUERY is a unicode
+/// scalar value. Those are the integers up to 0x10FFFF excluding the
+/// surrogates 0xD800 through 0xDFFF.
+pub(crate) fn emit_check_char_range(query: Value, ctx: &mut Context) -> Result<(), LustError> {
+    let max = Expr::Integer(0x10FFFF).immediate_rep();
+    let in_range = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::UnsignedLessThanOrEqual, query, max);
+
+    // Subtracting the first surrogate moves all of the surrogates to
+    // the bottom of the unsigned range.
+    let surrogate_start = Expr::Integer(0xD800).immediate_rep();
+    let surrogate_count = Expr::Integer(0x800).immediate_rep();
+    let offset = ctx.builder.ins().iadd_imm(query, -surrogate_start);
+    let not_surrogate =
+        ctx.builder
+            .ins()
+            .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, offset, surrogate_count);
+
+    let is_char = ctx.builder.ins().band(in_range, not_surrogate);
+    emit_check(is_char, "__anon_data_bad_char", ctx)
+}
+
+/// Prints the error string named MESSAGE and exits if OK is false.
+fn emit_check(ok: Value, message: &str, ctx: &mut Context) -> Result<(), LustError> {
     let error_block = ctx.builder.create_block();
     let ok_block = ctx.builder.create_block();
 
-    ctx.builder.ins().brz(is_tag, error_block, &[]);
+    ctx.builder.ins().brz(ok, error_block, &[]);
     ctx.builder.ins().jump(ok_block, &[]);
 
     ctx.builder.switch_to_block(error_block);
     ctx.builder.seal_block(error_block);
 
-    emit_error(
-        &Expr::Symbol("__anon_data_bad_arg_type".to_string()),
-        &Expr::Integer(-1),
-        ctx,
-    )?;
-
-    ctx.builder.ins().jump(ok_block, &[]);
+    emit_fatal(message, ok_block, ctx)?;
 
     ctx.builder.switch_to_block(ok_block);
     ctx.builder.seal_block(ok_block);
@@ -96,7 +530,10 @@ pub(crate) fn emit_check_tag(
     Ok(())
 }
 
-pub(crate) fn emit_check_int(query: Value, ctx: &mut Context) -> Result<(), String> {
+pub(crate) fn emit_check_int(query: Value, ctx: &mut Context) -> Result<(), LustError> {
+    if peephole::is_fixnum(query, ctx) {
+        return Ok(());
+    }
     emit_check_tag(
         query,
         conversions::FIXNUM_TAG,
@@ -105,15 +542,29 @@ pub(crate) fn emit_check_int(query: Value, ctx: &mut Context) -> Result<(), Stri
     )
 }
 
-pub(crate) fn emit_check_char(query: Value, ctx: &mut Context) -> Result<(), String> {
+pub(crate) fn emit_check_char(query: Value, ctx: &mut Context) -> Result<(), LustError> {
     emit_check_tag(query, conversions::CHAR_TAG, conversions::CHAR_MASK, ctx)
 }
 
-pub(crate) fn emit_check_bool(query: Value, ctx: &mut Context) -> Result<(), String> {
-    emit_check_tag(query, conversions::BOOL_TAG, conversions::BOOL_MASK, ctx)
+pub(crate) fn emit_check_string(query: Value, ctx: &mut Context) -> Result<(), LustError> {
+    emit_check_tag(
+        query,
+        conversions::STRING_TAG,
+        conversions::HEAP_TAG_MASK,
+        ctx,
+    )
+}
+
+pub(crate) fn emit_check_symbol(query: Value, ctx: &mut Context) -> Result<(), LustError> {
+    emit_check_tag(
+        query,
+        conversions::SYMBOL_TAG,
+        conversions::SYMBOL_MASK,
+        ctx,
+    )
 }
 
-pub(crate) fn emit_check_pair(query: Value, ctx: &mut Context) -> Result<(), String> {
+pub(crate) fn emit_check_pair(query: Value, ctx: &mut Context) -> Result<(), LustError> {
     emit_check_tag(
         query,
         conversions::PAIR_TAG,
@@ -122,40 +573,71 @@ pub(crate) fn emit_check_pair(query: Value, ctx: &mut Context) -> Result<(), Str
     )
 }
 
-pub(crate) fn emit_check_callable(query: &Expr, ctx: &mut Context) -> Result<Value, String> {
-    let closure_ptr = compiler::emit_expr(query, ctx)?;
-    let tag = ctx
-        .builder
-        .ins()
-        .band_imm(closure_ptr, conversions::HEAP_TAG_MASK);
-    let cond = ctx
-        .builder
-        .ins()
-        .icmp_imm(IntCC::Equal, tag, conversions::CLOSURE_TAG);
-
-    let error_block = ctx.builder.create_block();
-    let ok_block = ctx.builder.create_block();
-
-    ctx.builder.ins().brz(cond, error_block, &[]);
-    ctx.builder.ins().jump(ok_block, &[]);
-
-    ctx.builder.switch_to_block(error_block);
-    ctx.builder.seal_block(error_block);
+pub(crate) fn emit_check_vector(query: Value, ctx: &mut Context) -> Result<(), LustError> {
+    emit_check_tag(
+        query,
+        conversions::VECTOR_TAG,
+        conversions::HEAP_TAG_MASK,
+        ctx,
+    )
+}
 
-    emit_error(
-        &Expr::Symbol("__anon_data_bad_call_type".to_string()),
-        &Expr::Integer(-1),
+/// Emits a check that QUERY is either a fixnum or a float.
+pub(crate) fn emit_check_number(query: Value, ctx: &mut Context) -> Result<(), LustError> {
+    if peephole::is_number(query, ctx) {
+        return Ok(());
+    }
+    let is_int = foreign::emit_is(
+        query,
+        conversions::FIXNUM_TAG,
+        conversions::FIXNUM_MASK,
         ctx,
-    )?;
+    );
+    let is_float = foreign::emit_is(
+        query,
+        conversions::FLOAT_TAG,
+        conversions::HEAP_TAG_MASK,
+        ctx,
+    );
+    let is_number = ctx.builder.ins().bor(is_int, is_float);
+    emit_check_type(is_number, ctx)
+}
 
-    // This ought to be unreachable but it appeases the code
-    // generator.
-    ctx.builder.ins().jump(ok_block, &[]);
+pub(crate) fn emit_check_callable(query: &Expr, ctx: &mut Context) -> Result<Value, LustError> {
+    let closure_ptr = compiler::emit_expr(query, ctx)?;
+    emit_check_closure(closure_ptr, ctx)?;
+    Ok(closure_ptr)
+}
 
-    ctx.builder.switch_to_block(ok_block);
-    ctx.builder.seal_block(ok_block);
+/// Emits a bad call type error unless QUERY is a closure.
+pub(crate) fn emit_check_closure(query: Value, ctx: &mut Context) -> Result<(), LustError> {
+    let is_closure = foreign::emit_is(
+        query,
+        conversions::CLOSURE_TAG,
+        conversions::HEAP_TAG_MASK,
+        ctx,
+    );
+    emit_check(is_closure, "__anon_data_bad_call_type", ctx)
+}
 
-    Ok(closure_ptr)
+/// Emits a wrong number of arguments error unless ACTUAL is between
+/// MIN and MAX inclusive.
+pub(crate) fn emit_check_arg_range(
+    min: usize,
+    max: usize,
+    actual: Value,
+    ctx: &mut Context,
+) -> Result<(), LustError> {
+    let above_min =
+        ctx.builder
+            .ins()
+            .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, actual, min as i64);
+    let below_max = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::UnsignedLessThanOrEqual, actual, max as i64);
+    let in_range = ctx.builder.ins().band(above_min, below_max);
+    emit_check(in_range, "__anon_data_bad_arg_count", ctx)
 }
 
 pub(crate) fn emit_check_arg_count(
@@ -163,7 +645,7 @@ pub(crate) fn emit_check_arg_count(
     actual: Value,
     ctx: &mut Context,
     is_varadic: bool,
-) -> Result<(), String> {
+) -> Result<(), LustError> {
     // If the function is varadic wrong number of arguments only if
     // there are less than the number of regular params.
     let cond = if is_varadic {
@@ -185,18 +667,199 @@ pub(crate) fn emit_check_arg_count(
     ctx.builder.switch_to_block(error_block);
     ctx.builder.seal_block(error_block);
 
-    emit_error(
-        &Expr::Symbol("__anon_data_bad_arg_count".to_string()),
-        &Expr::Integer(-1),
-        ctx,
-    )?;
-
-    // This ought to be unreachable but it appeases the code
-    // generator.
-    ctx.builder.ins().jump(ok_block, &[]);
+    emit_fatal("__anon_data_bad_arg_count", ok_block, ctx)?;
 
     ctx.builder.switch_to_block(ok_block);
     ctx.builder.seal_block(ok_block);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::eval_program;
+    use crate::errors::LustError;
+    use crate::{parse_string, Expr};
+
+    fn eval(source: &str) -> Result<Expr, LustError> {
+        eval_program(&parse_string(source).unwrap())
+    }
+
+    #[test]
+    fn raise_error() {
+        assert_eq!(
+            eval(r#"(error "oops" 42)"#),
+            Err(LustError::UserError {
+                message: "oops".to_string(),
+                irritants: vec![Expr::Integer(42)],
+            })
+        );
+        assert_eq!(
+            eval(r#"(error "no irritants")"#),
+            Err(LustError::UserError {
+                message: "no irritants".to_string(),
+                irritants: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn raise_unwinds_calls() {
+        // The error is raised from deep in a recursion and from
+        // inside of a closure called by map. Nothing after either
+        // error runs.
+        let source = r#"
+(let count-down (fn (n)
+  (if (eq n 0)
+      (error "bottom" n (cons 1 2))
+      (add 1 (count-down (sub n 1))))))
+(add (car (count-down 100)) 1)
+"#;
+        assert_eq!(
+            eval(source),
+            Err(LustError::UserError {
+                message: "bottom".to_string(),
+                irritants: vec![
+                    Expr::Integer(0),
+                    Expr::Pair(Box::new(Expr::Integer(1)), Box::new(Expr::Integer(2))),
+                ],
+            })
+        );
+
+        let source = r#"
+(let check (fn (x) (if (lt x 3) x (error "too big" x))))
+(car (map check (list 1 2 3 4)))
+"#;
+        let err = eval(source).unwrap_err();
+        assert_eq!(err.to_string(), "too big 3");
+    }
+
+    #[test]
+    fn no_error_raised() {
+        let source = r#"
+(let safe-div (fn (a b) (if (eq b 0) (error "divide by zero" a) (quotient a b))))
+(safe-div 10 2)
+"#;
+        assert_eq!(eval(source), Ok(Expr::Integer(5)));
+    }
+
+    #[test]
+    fn catch_throw_from_nested_call() {
+        // The throw happens two calls down and skips the rest of
+        // both of them.
+        let source = r#"
+(let find (fn (x lst)
+  (if (null? lst)
+      0
+      (if (eq? (car lst) x)
+          (throw 'found x)
+          (add1 (find x (cdr lst)))))))
+(let search (fn (x lst) (add 100 (find x lst))))
+(list (catch 'found (search 3 (list 1 2 3 4)))
+      (catch 'found (search 5 (list 1 2 3 4)) 'not-found)
+      (catch 'found (search 5 (list 1 2 3 4))))
+"#;
+        assert_eq!(
+            eval(source).unwrap().to_string(),
+            "(3 not-found 104)".to_string()
+        );
+    }
+
+    #[test]
+    fn nested_catches() {
+        // Each throw goes to the closest catch for its tag.
+        let source = r#"
+(let thrower (fn (tag) (throw tag (list tag))))
+(list
+  (catch 'outer (catch 'inner (thrower 'outer)) 'missed)
+  (catch 'outer (add 1 (catch 'inner (throw 'inner 2) 5)))
+  (catch 'a (catch 'b (catch 'a (thrower 'b)))))
+"#;
+        assert_eq!(
+            eval(source).unwrap().to_string(),
+            "((outer) 3 (b))".to_string()
+        );
+    }
+
+    #[test]
+    fn uncaught_throw() {
+        assert_eq!(
+            eval("(catch 'a (throw 'b 1))"),
+            Err(LustError::UncaughtThrow {
+                tag: Box::new(Expr::Symbol("b".to_string())),
+                value: Box::new(Expr::Integer(1)),
+            })
+        );
+        assert_eq!(
+            eval("(throw 'x \"y\")").unwrap_err().to_string(),
+            "uncaught throw to x: \"y\""
+        );
+        // Errors aren't throws so catches let them through.
+        assert_eq!(
+            eval(r#"(catch 'a (error "oops" 'a))"#)
+                .unwrap_err()
+                .to_string(),
+            "oops a"
+        );
+    }
+
+    #[test]
+    fn throw_cyclic_value() {
+        // Thrown values are only converted when nothing catches them
+        // so catching one that can't be converted is fine.
+        let source = r#"
+(define v (make-vector 1 0))
+(begin (vector-set! v 0 v)
+       (vector-length (catch 'x (throw 'x v) 1)))
+"#;
+        assert_eq!(eval(source), Ok(Expr::Integer(1)));
+    }
+
+    #[test]
+    fn higher_order_throw() {
+        let source = r#"
+(let t throw)
+(catch 'done (map (fn (x) (if (eq? x 3) (t 'done x) x)) (list 1 2 3 4)))
+"#;
+        assert_eq!(eval(source), Ok(Expr::Integer(3)));
+    }
+
+    #[test]
+    fn assert_holds() {
+        assert_eq!(eval("(assert (eq? 1 1))"), Ok(Expr::Nil));
+        let source = "(let f (fn (x) (assert (eq? x 1)) (add1 x))) (f 1)";
+        assert_eq!(eval(source), Ok(Expr::Integer(2)));
+    }
+
+    #[test]
+    fn assert_fails() {
+        let source = "(let x 1)\n  (assert #f)\n(add1 x)";
+        let err = eval(source).unwrap_err();
+        let loc = err.location().unwrap();
+        assert_eq!((loc.start.line, loc.start.col), (1, 2));
+        assert_eq!((loc.end.line, loc.end.col), (1, 13));
+        assert!(matches!(
+            err,
+            LustError::AssertionFailed { ref expr, .. } if **expr == Expr::Bool(false)
+        ));
+
+        // The message shows the expression as it was written, not
+        // with the names the compiler gave its variables. Only #t
+        // counts as true.
+        let source = "(let x 1)\n(let check (fn (y) (assert (eq? y x))))\n(check 2)";
+        assert_eq!(
+            eval(source).unwrap_err().to_string(),
+            "assertion failed: (eq? y x) at line 2, column 20"
+        );
+        assert!(eval("(assert 1)").is_err());
+    }
+
+    #[test]
+    fn failed_assert_passes_catches() {
+        let source = "(catch 'oops (assert (eq? 1 2)) 'caught)";
+        assert!(matches!(
+            eval(source),
+            Err(LustError::AssertionFailed { .. })
+        ));
+    }
+}
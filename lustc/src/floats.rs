@@ -0,0 +1,775 @@
+//! A float is too big to share a word
+//! So off to the heap it goes
+//! Eight bytes and a tag to find it by
+
+use cranelift::prelude::*;
+
+use crate::compiler::Context;
+use crate::conversions::{FIXNUM_MASK, FIXNUM_SHIFT, FIXNUM_TAG, FLOAT_TAG, HEAP_PTR_MASK};
+use crate::errors::LustError;
+use crate::fatal;
+use crate::foreign::emit_is;
+use crate::heap::emit_alloc;
+use crate::peephole::{self, Known};
+
+/// Arithmetic operations that have both a fixnum and a floating
+/// point implementation.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+}
+
+/// Comparisons that have both a fixnum and a floating point
+/// implementation.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum CmpOp {
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+}
+
+impl CmpOp {
+    /// Gets the comparison named by the primitive NAME if there is
+    /// one.
+    pub(crate) fn from_primitive(name: &str) -> Option<Self> {
+        Some(match name {
+            "lt" | "<" => CmpOp::Lt,
+            "gt" | ">" => CmpOp::Gt,
+            "<=" => CmpOp::Le,
+            ">=" => CmpOp::Ge,
+            "=" => CmpOp::Eq,
+            _ => return None,
+        })
+    }
+}
+
+/// Ways of rounding a number to a whole number.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum RoundOp {
+    Floor,
+    Ceiling,
+    Round,
+    Truncate,
+}
+
+impl RoundOp {
+    /// Gets the rounding named by the primitive NAME if there is one
+    /// and whether the primitive's result is an integer. (floor x)
+    /// returns a float when given a float while (floor->integer x)
+    /// returns an integer.
+    pub(crate) fn from_primitive(name: &str) -> Option<(Self, bool)> {
+        let (base, to_integer) = match name.strip_suffix("->integer") {
+            Some(base) => (base, true),
+            None => (name, false),
+        };
+        let op = match base {
+            "floor" => RoundOp::Floor,
+            "ceiling" => RoundOp::Ceiling,
+            "round" => RoundOp::Round,
+            "truncate" => RoundOp::Truncate,
+            _ => return None,
+        };
+        Some((op, to_integer))
+    }
+
+    /// Emits the rounding of VAL, a F64 value. Cranelift falls back to
+    /// calling the C library's rounding functions on targets without
+    /// instructions for these.
+    fn emit(self, val: Value, ctx: &mut Context) -> Value {
+        match self {
+            RoundOp::Floor => ctx.builder.ins().floor(val),
+            RoundOp::Ceiling => ctx.builder.ins().ceil(val),
+            // Halfway cases go to the even neighbor.
+            RoundOp::Round => ctx.builder.ins().nearest(val),
+            RoundOp::Truncate => ctx.builder.ins().trunc(val),
+        }
+    }
+}
+
+/// Emits the code to box VAL, a F64 value, on the heap. Returns a
+/// pointer to the boxed value tagged with FLOAT_TAG.
+pub(crate) fn emit_box_float(val: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    let storage = emit_alloc(types::F64.bytes().into(), ctx)?;
+    ctx.builder.ins().store(MemFlags::new(), val, storage, 0);
+    Ok(ctx.builder.ins().bor_imm(storage, FLOAT_TAG))
+}
+
+/// Emits the code to convert VAL into a F64 value. VAL is assumed to
+/// be either a fixnum or a boxed float. Fixnums are promoted to
+/// floats and boxed floats are read off the heap.
+pub(crate) fn emit_to_f64(val: Value, ctx: &mut Context) -> Value {
+    let int_block = ctx.builder.create_block();
+    let float_block = ctx.builder.create_block();
+    let merge_block = ctx.builder.create_block();
+
+    ctx.builder.append_block_param(merge_block, types::F64);
+
+    let is_int = emit_is(val, FIXNUM_TAG, FIXNUM_MASK, ctx);
+    ctx.builder.ins().brnz(is_int, int_block, &[]);
+    ctx.builder.ins().jump(float_block, &[]);
+
+    ctx.builder.switch_to_block(int_block);
+    ctx.builder.seal_block(int_block);
+
+    let int = ctx.builder.ins().sshr_imm(val, FIXNUM_SHIFT);
+    let promoted = ctx.builder.ins().fcvt_from_sint(types::F64, int);
+    ctx.builder.ins().jump(merge_block, &[promoted]);
+
+    ctx.builder.switch_to_block(float_block);
+    ctx.builder.seal_block(float_block);
+
+    let address = ctx.builder.ins().band_imm(val, HEAP_PTR_MASK);
+    let float = ctx
+        .builder
+        .ins()
+        .load(types::F64, MemFlags::new(), address, 0);
+    ctx.builder.ins().jump(merge_block, &[float]);
+
+    ctx.builder.switch_to_block(merge_block);
+    ctx.builder.seal_block(merge_block);
+
+    ctx.builder.block_params(merge_block)[0]
+}
+
+/// Emits a branch on the types of LEFT and RIGHT. If both are
+/// fixnums INT_OP is used to compute the result, otherwise both are
+/// converted to F64 values and FLOAT_OP is used. Both arguments are
+/// checked to be numbers first.
+fn emit_numeric_dispatch<I, F>(
+    left: Value,
+    right: Value,
+    ctx: &mut Context,
+    int_op: I,
+    float_op: F,
+) -> Result<Value, LustError>
+where
+    I: FnOnce(Value, Value, &mut Context) -> Result<Value, LustError>,
+    F: FnOnce(Value, Value, &mut Context) -> Result<Value, LustError>,
+{
+    // Nothing to check or branch on if both are already known to be
+    // fixnums.
+    if peephole::is_fixnum(left, ctx) && peephole::is_fixnum(right, ctx) {
+        return int_op(left, right, ctx);
+    }
+
+    fatal::emit_check_number(left, ctx)?;
+    fatal::emit_check_number(right, ctx)?;
+
+    let int_block = ctx.builder.create_block();
+    let float_block = ctx.builder.create_block();
+    let merge_block = ctx.builder.create_block();
+
+    ctx.builder.append_block_param(merge_block, ctx.word);
+
+    // Fixnums have a tag of zero so if the two values or'd together
+    // still have one we know that neither is a float.
+    let either = ctx.builder.ins().bor(left, right);
+    let both_int = emit_is(either, FIXNUM_TAG, FIXNUM_MASK, ctx);
+    ctx.builder.ins().brnz(both_int, int_block, &[]);
+    ctx.builder.ins().jump(float_block, &[]);
+
+    ctx.builder.switch_to_block(int_block);
+    ctx.builder.seal_block(int_block);
+
+    let res = int_op(left, right, ctx)?;
+    ctx.builder.ins().jump(merge_block, &[res]);
+
+    ctx.builder.switch_to_block(float_block);
+    ctx.builder.seal_block(float_block);
+
+    let fleft = emit_to_f64(left, ctx);
+    let fright = emit_to_f64(right, ctx);
+    let res = float_op(fleft, fright, ctx)?;
+    ctx.builder.ins().jump(merge_block, &[res]);
+
+    ctx.builder.switch_to_block(merge_block);
+    ctx.builder.seal_block(merge_block);
+
+    Ok(ctx.builder.block_params(merge_block)[0])
+}
+
+/// Emits the code for an arithmetic operation on LEFT and RIGHT. If
+/// either argument is a float the result is a float.
+pub(crate) fn emit_arithmetic(
+    op: ArithOp,
+    left: Value,
+    right: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let known = if peephole::is_fixnum(left, ctx) && peephole::is_fixnum(right, ctx) {
+        Known::Fixnum
+    } else {
+        Known::Number
+    };
+    let res = emit_numeric_dispatch(
+        left,
+        right,
+        ctx,
+        |left, right, ctx| {
+            if ctx.options.checked_arithmetic {
+                return emit_checked_fixnum_arithmetic(op, left, right, ctx);
+            }
+            Ok(match op {
+                ArithOp::Add => ctx.builder.ins().iadd(left, right),
+                ArithOp::Sub => ctx.builder.ins().isub(left, right),
+                ArithOp::Mul => {
+                    let accum = ctx.builder.ins().imul(left, right);
+                    // At this point we've picked up an extra 2^2 so we
+                    // need to right shift it out.
+                    //
+                    // NOTE: It is possible that it would be more
+                    // reasonable to shift things out first. I'm worried
+                    // here that this will cause integer overflows where
+                    // we wouldn't normally expect them.
+                    ctx.builder.ins().sshr_imm(accum, FIXNUM_SHIFT)
+                }
+            })
+        },
+        |left, right, ctx| {
+            let res = match op {
+                ArithOp::Add => ctx.builder.ins().fadd(left, right),
+                ArithOp::Sub => ctx.builder.ins().fsub(left, right),
+                ArithOp::Mul => ctx.builder.ins().fmul(left, right),
+            };
+            emit_box_float(res, ctx)
+        },
+    )?;
+    Ok(peephole::record(res, known, ctx))
+}
+
+/// Emits the code for an arithmetic operation on the fixnums LEFT and
+/// RIGHT that fails at runtime if the result doesn't fit in a
+/// fixnum. Fixnums are their value shifted left so a tagged result
+/// that fits in a word is a value that fits in a fixnum.
+fn emit_checked_fixnum_arithmetic(
+    op: ArithOp,
+    left: Value,
+    right: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let (res, in_range) = match op {
+        ArithOp::Add | ArithOp::Sub => {
+            let res = match op {
+                ArithOp::Add => ctx.builder.ins().iadd(left, right),
+                _ => ctx.builder.ins().isub(left, right),
+            };
+            // Addition overflows when both arguments have a sign
+            // different from the result. Subtraction is addition of
+            // the negated right argument.
+            let left_sign = ctx.builder.ins().bxor(left, res);
+            let right_sign = match op {
+                ArithOp::Add => ctx.builder.ins().bxor(right, res),
+                _ => ctx.builder.ins().bxor(left, right),
+            };
+            let signs = ctx.builder.ins().band(left_sign, right_sign);
+            let in_range = ctx
+                .builder
+                .ins()
+                .icmp_imm(IntCC::SignedGreaterThanOrEqual, signs, 0);
+            (res, in_range)
+        }
+        ArithOp::Mul => {
+            // Untagging one side first means the product carries the
+            // tag's 2^2 factor once. The product fits if the high
+            // word of the full product is just its sign.
+            let untagged = ctx.builder.ins().sshr_imm(left, FIXNUM_SHIFT);
+            let res = ctx.builder.ins().imul(untagged, right);
+            let high = ctx.builder.ins().smulhi(untagged, right);
+            let sign = ctx
+                .builder
+                .ins()
+                .sshr_imm(res, (ctx.word.bits() - 1) as i64);
+            let in_range = ctx.builder.ins().icmp(IntCC::Equal, high, sign);
+            (res, in_range)
+        }
+    };
+    fatal::emit_check_no_overflow(in_range, ctx)?;
+    Ok(res)
+}
+
+/// Emits the code for a comparison between LEFT and RIGHT. The result
+/// is a word that is one if the comparison holds and zero otherwise.
+pub(crate) fn emit_comparison(
+    op: CmpOp,
+    left: Value,
+    right: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    emit_numeric_dispatch(
+        left,
+        right,
+        ctx,
+        |left, right, ctx| {
+            let cc = match op {
+                CmpOp::Lt => IntCC::SignedLessThan,
+                CmpOp::Gt => IntCC::SignedGreaterThan,
+                CmpOp::Le => IntCC::SignedLessThanOrEqual,
+                CmpOp::Ge => IntCC::SignedGreaterThanOrEqual,
+                CmpOp::Eq => IntCC::Equal,
+            };
+            let accum = ctx.builder.ins().icmp(cc, left, right);
+            Ok(ctx.builder.ins().bint(ctx.word, accum))
+        },
+        |left, right, ctx| {
+            let cc = match op {
+                CmpOp::Lt => FloatCC::LessThan,
+                CmpOp::Gt => FloatCC::GreaterThan,
+                CmpOp::Le => FloatCC::LessThanOrEqual,
+                CmpOp::Ge => FloatCC::GreaterThanOrEqual,
+                CmpOp::Eq => FloatCC::Equal,
+            };
+            let accum = ctx.builder.ins().fcmp(cc, left, right);
+            Ok(ctx.builder.ins().bint(ctx.word, accum))
+        },
+    )
+}
+
+/// Emits a branch on the type of VAL. If it is a fixnum INT_OP is
+/// used to compute the result, otherwise it is read into a F64 value
+/// and FLOAT_OP is used. VAL is checked to be a number first.
+fn emit_unary_numeric_dispatch<I, F>(
+    val: Value,
+    ctx: &mut Context,
+    int_op: I,
+    float_op: F,
+) -> Result<Value, LustError>
+where
+    I: FnOnce(Value, &mut Context) -> Result<Value, LustError>,
+    F: FnOnce(Value, &mut Context) -> Result<Value, LustError>,
+{
+    if peephole::is_fixnum(val, ctx) {
+        return int_op(val, ctx);
+    }
+
+    fatal::emit_check_number(val, ctx)?;
+
+    let int_block = ctx.builder.create_block();
+    let float_block = ctx.builder.create_block();
+    let merge_block = ctx.builder.create_block();
+
+    ctx.builder.append_block_param(merge_block, ctx.word);
+
+    let is_int = emit_is(val, FIXNUM_TAG, FIXNUM_MASK, ctx);
+    ctx.builder.ins().brnz(is_int, int_block, &[]);
+    ctx.builder.ins().jump(float_block, &[]);
+
+    ctx.builder.switch_to_block(int_block);
+    ctx.builder.seal_block(int_block);
+
+    let res = int_op(val, ctx)?;
+    ctx.builder.ins().jump(merge_block, &[res]);
+
+    ctx.builder.switch_to_block(float_block);
+    ctx.builder.seal_block(float_block);
+
+    let address = ctx.builder.ins().band_imm(val, HEAP_PTR_MASK);
+    let float = ctx
+        .builder
+        .ins()
+        .load(types::F64, MemFlags::new(), address, 0);
+    let res = float_op(float, ctx)?;
+    ctx.builder.ins().jump(merge_block, &[res]);
+
+    ctx.builder.switch_to_block(merge_block);
+    ctx.builder.seal_block(merge_block);
+
+    Ok(ctx.builder.block_params(merge_block)[0])
+}
+
+/// Emits the code for the absolute value of VAL. The result has the
+/// same type as VAL.
+pub(crate) fn emit_abs(val: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    emit_unary_numeric_dispatch(
+        val,
+        ctx,
+        |val, ctx| {
+            // Negating a fixnum leaves its tag of zero alone so the
+            // negated value is already tagged.
+            let negated = if ctx.options.checked_arithmetic {
+                let zero = ctx.builder.ins().iconst(ctx.word, 0);
+                emit_checked_fixnum_arithmetic(ArithOp::Sub, zero, val, ctx)?
+            } else {
+                ctx.builder.ins().ineg(val)
+            };
+            let negative = ctx.builder.ins().icmp_imm(IntCC::SignedLessThan, val, 0);
+            Ok(ctx.builder.ins().select(negative, negated, val))
+        },
+        |val, ctx| {
+            let res = ctx.builder.ins().fabs(val);
+            emit_box_float(res, ctx)
+        },
+    )
+}
+
+/// Emits the code for the primitive NAME, either min or max, on LEFT
+/// and RIGHT. If either argument is a float the result is a float.
+pub(crate) fn emit_min_max(
+    name: &str,
+    left: Value,
+    right: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let is_max = name == "max";
+    emit_numeric_dispatch(
+        left,
+        right,
+        ctx,
+        |left, right, ctx| {
+            // Both values are tagged the same way so comparing them
+            // compares the fixnums.
+            let cc = if is_max {
+                IntCC::SignedGreaterThan
+            } else {
+                IntCC::SignedLessThan
+            };
+            let pick_left = ctx.builder.ins().icmp(cc, left, right);
+            Ok(ctx.builder.ins().select(pick_left, left, right))
+        },
+        |left, right, ctx| {
+            let res = if is_max {
+                ctx.builder.ins().fmax(left, right)
+            } else {
+                ctx.builder.ins().fmin(left, right)
+            };
+            emit_box_float(res, ctx)
+        },
+    )
+}
+
+/// Emits the code for the square root of VAL. The result is always a
+/// float so that the square root of an integer isn't truncated.
+pub(crate) fn emit_sqrt(val: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    fatal::emit_check_number(val, ctx)?;
+    let float = emit_to_f64(val, ctx);
+    let res = ctx.builder.ins().sqrt(float);
+    emit_box_float(res, ctx)
+}
+
+/// Emits the code to convert VAL, a whole F64 value, to a fixnum.
+/// Values that don't fit in a fixnum, infinities, and NaN are an
+/// integer overflow error.
+fn emit_float_to_fixnum(val: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    // Fixnums are in [-2^61, 2^61) and both ends are exact as floats.
+    // Comparisons with NaN are false so it fails the check too.
+    let limit = (1u64 << (ctx.word.bits() as i64 - 1 - FIXNUM_SHIFT)) as f64;
+    let min = ctx.builder.ins().f64const(-limit);
+    let max = ctx.builder.ins().f64const(limit);
+    let above_min = ctx
+        .builder
+        .ins()
+        .fcmp(FloatCC::GreaterThanOrEqual, val, min);
+    let below_max = ctx.builder.ins().fcmp(FloatCC::LessThan, val, max);
+    let in_range = ctx.builder.ins().band(above_min, below_max);
+    fatal::emit_check_no_overflow(in_range, ctx)?;
+    let int = ctx.builder.ins().fcvt_to_sint(ctx.word, val);
+    Ok(ctx.builder.ins().ishl_imm(int, FIXNUM_SHIFT))
+}
+
+/// Emits the code for rounding VAL with OP. Integers are already
+/// whole so they are returned as is. Floats are rounded to a float
+/// unless TO_INTEGER is set in which case the result is converted to
+/// a fixnum.
+pub(crate) fn emit_round(
+    op: RoundOp,
+    to_integer: bool,
+    val: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    emit_unary_numeric_dispatch(
+        val,
+        ctx,
+        |val, _| Ok(val),
+        |val, ctx| {
+            let rounded = op.emit(val, ctx);
+            if to_integer {
+                emit_float_to_fixnum(rounded, ctx)
+            } else {
+                emit_box_float(rounded, ctx)
+            }
+        },
+    )
+}
+
+/// Emits the code for (exact->inexact VAL). Integers are converted to
+/// floats and floats give an equal float.
+pub(crate) fn emit_exact_to_inexact(val: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    fatal::emit_check_number(val, ctx)?;
+    let float = emit_to_f64(val, ctx);
+    emit_box_float(float, ctx)
+}
+
+/// Emits the code for (inexact->exact VAL). Integers are returned as
+/// is. There are no exact fractions so a float with a fractional part
+/// is an error rather than being truncated, use truncate->integer or
+/// one of the other rounding primitives for that.
+pub(crate) fn emit_inexact_to_exact(val: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    emit_unary_numeric_dispatch(
+        val,
+        ctx,
+        |val, _| Ok(val),
+        |val, ctx| {
+            // Infinities are their own truncation so they get past
+            // this and are caught by the range check.
+            let truncated = ctx.builder.ins().trunc(val);
+            let is_whole = ctx.builder.ins().fcmp(FloatCC::Equal, truncated, val);
+            fatal::emit_check_whole(is_whole, ctx)?;
+            emit_float_to_fixnum(val, ctx)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::roundtrip_string;
+    use crate::testing::{expect_fatal, expect_fatal_with};
+    use crate::Expr;
+
+    #[test]
+    fn float_literal() {
+        let res = roundtrip_string("1.5").unwrap();
+        assert_eq!(res, Expr::Float(1.5))
+    }
+
+    #[test]
+    fn float_arithmetic() {
+        assert_eq!(
+            roundtrip_string("(add 1.5 2.25)").unwrap(),
+            Expr::Float(3.75)
+        );
+        assert_eq!(
+            roundtrip_string("(sub 1.5 2.25)").unwrap(),
+            Expr::Float(-0.75)
+        );
+        assert_eq!(roundtrip_string("(mul 1.5 2.0)").unwrap(), Expr::Float(3.0));
+    }
+
+    #[test]
+    fn mixed_arithmetic() {
+        assert_eq!(roundtrip_string("(add 1 0.5)").unwrap(), Expr::Float(1.5));
+        assert_eq!(roundtrip_string("(sub 0.5 1)").unwrap(), Expr::Float(-0.5));
+        assert_eq!(roundtrip_string("(mul 3 0.5)").unwrap(), Expr::Float(1.5));
+        // Integer arithmetic stays integer arithmetic.
+        assert_eq!(roundtrip_string("(add 1 2)").unwrap(), Expr::Integer(3));
+    }
+
+    #[test]
+    fn float_comparison() {
+        assert_eq!(roundtrip_string("(lt 1 1.5)").unwrap(), Expr::Bool(true));
+        assert_eq!(roundtrip_string("(gt 1 1.5)").unwrap(), Expr::Bool(false));
+        assert_eq!(roundtrip_string("(gt 2.5 2)").unwrap(), Expr::Bool(true));
+    }
+
+    #[test]
+    fn higher_order_float_arithmetic() {
+        let source = r#"
+(let plus add)
+(plus 0.25 (plus 1 1))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Float(2.25))
+    }
+
+    #[test]
+    fn abs_min_max() {
+        let run =
+            |source: &str, args: &[i64]| eval_arithmetic(source, false, &["a", "b"], args).unwrap();
+        assert_eq!(run("(abs a)", &[-5, 0]), Expr::Integer(5));
+        assert_eq!(run("(abs a)", &[5, 0]), Expr::Integer(5));
+        assert_eq!(run("(max a b)", &[3, 7]), Expr::Integer(7));
+        assert_eq!(run("(min a b)", &[3, 7]), Expr::Integer(3));
+        assert_eq!(run("(max a b)", &[-3, -7]), Expr::Integer(-3));
+        assert_eq!(roundtrip_string("(abs -1.5)").unwrap(), Expr::Float(1.5));
+        assert_eq!(roundtrip_string("(max 1 2.5)").unwrap(), Expr::Float(2.5));
+        assert_eq!(roundtrip_string("(min 1 2.5)").unwrap(), Expr::Float(1.0));
+    }
+
+    #[test]
+    fn sqrt() {
+        assert_eq!(roundtrip_string("(sqrt 16)").unwrap(), Expr::Float(4.0));
+        assert_eq!(roundtrip_string("(sqrt 2.25)").unwrap(), Expr::Float(1.5));
+        assert_eq!(
+            roundtrip_string("(sqrt 2)").unwrap(),
+            Expr::Float(2f64.sqrt())
+        );
+    }
+
+    #[test]
+    fn rounding() {
+        let cases = [
+            ("(round 2.5)", Expr::Float(2.0)),
+            ("(round 3.5)", Expr::Float(4.0)),
+            ("(round -2.5)", Expr::Float(-2.0)),
+            ("(round 2.6)", Expr::Float(3.0)),
+            ("(floor -1.5)", Expr::Float(-2.0)),
+            ("(floor 1.5)", Expr::Float(1.0)),
+            ("(ceiling -1.5)", Expr::Float(-1.0)),
+            ("(ceiling 1.2)", Expr::Float(2.0)),
+            ("(truncate -1.9)", Expr::Float(-1.0)),
+            ("(truncate 1.9)", Expr::Float(1.0)),
+            ("(floor 7)", Expr::Integer(7)),
+            ("(round -3)", Expr::Integer(-3)),
+            ("(round->integer 2.5)", Expr::Integer(2)),
+            ("(floor->integer -1.5)", Expr::Integer(-2)),
+            ("(ceiling->integer 1.2)", Expr::Integer(2)),
+            ("(truncate->integer -1.9)", Expr::Integer(-1)),
+            ("(truncate->integer 5)", Expr::Integer(5)),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(roundtrip_string(source).unwrap(), expected, "{}", source);
+        }
+        // Negative zero stays negative.
+        let res = roundtrip_string("(ceiling -0.5)").unwrap();
+        assert!(matches!(res, Expr::Float(f) if f == 0.0 && f.is_sign_negative()));
+
+        let source = "(map round->integer (list 0.5 1.5 -0.5 4))";
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(0 2 0 4)").unwrap()
+        );
+        let source = "(let f floor) (list (f -0.25) (f 3))";
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("(list -1.0 3)").unwrap()
+        );
+    }
+
+    #[test]
+    fn rounding_out_of_range() {
+        expect_fatal(
+            "(round->integer 1000000000000000000000.5)",
+            "integer overflow",
+        );
+        expect_fatal("(floor->integer (sqrt -1))", "integer overflow");
+    }
+
+    #[test]
+    fn exactness_conversions() {
+        for i in [-3, 0, 1, 42] {
+            // Passing the integer through a function keeps the
+            // conversions from being folded.
+            let source = format!(
+                "(let id (fn (x) x)) (inexact->exact (exact->inexact (id {})))",
+                i
+            );
+            assert_eq!(roundtrip_string(&source).unwrap(), Expr::Integer(i));
+        }
+        assert_eq!(
+            roundtrip_string("(exact->inexact 2)").unwrap(),
+            Expr::Float(2.0)
+        );
+        assert_eq!(
+            roundtrip_string("(exact->inexact 2.5)").unwrap(),
+            Expr::Float(2.5)
+        );
+        assert_eq!(
+            roundtrip_string("(inexact->exact 2.0)").unwrap(),
+            Expr::Integer(2)
+        );
+        assert_eq!(
+            roundtrip_string("(inexact->exact -0.0)").unwrap(),
+            Expr::Integer(0)
+        );
+        let source = "(let f exact->inexact) (let g inexact->exact) (list (f 3) (g 4.0) (g 5))";
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("(list 3.0 4 5)").unwrap()
+        );
+    }
+
+    #[test]
+    fn inexact_to_exact_errors() {
+        // Floats with a fractional part aren't truncated.
+        expect_fatal("(inexact->exact 2.5)", "float has no exact integer value");
+        expect_fatal(
+            "(inexact->exact (sqrt -1))",
+            "float has no exact integer value",
+        );
+        expect_fatal(
+            "(inexact->exact 1000000000000000000000.0)",
+            "integer overflow",
+        );
+        expect_fatal("(exact->inexact #t)", "runtime type missmatch");
+    }
+
+    #[test]
+    fn higher_order_abs_min_max() {
+        let source = r#"
+(let apply (fn (op a b) (op a b)))
+(let apply1 (fn (op a) (op a)))
+(cons (apply max 3 7) (cons (apply1 abs -5) (apply1 sqrt 9)))
+"#;
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(Expr::Integer(7), cons(Expr::Integer(5), Expr::Float(3.0)))
+        )
+    }
+
+    #[test]
+    fn checked_abs_in_range() {
+        let max = (1i64 << 61) - 1;
+        assert_eq!(
+            eval_arithmetic("(abs a)", true, &["a"], &[-max]).unwrap(),
+            Expr::Integer(max)
+        );
+    }
+
+    /// Runs SOURCE with checked arithmetic turned on if CHECKED is
+    /// set. PARAMS are bound to ARGS.
+    fn eval_arithmetic(
+        source: &str,
+        checked: bool,
+        params: &[&str],
+        args: &[i64],
+    ) -> Result<Expr, crate::errors::LustError> {
+        let mut jit = crate::compiler::JIT::default();
+        jit.options.checked_arithmetic = checked;
+        let program = crate::parse_string(source).unwrap();
+        crate::compiler::eval_program_in(jit, &program, params, args)
+    }
+
+    /// Passing the arguments in at runtime keeps the product from
+    /// being folded.
+    fn overflowing_product(checked: bool) -> Result<Expr, crate::errors::LustError> {
+        eval_arithmetic("(mul a b)", checked, &["a", "b"], &[1 << 40, 1 << 40])
+    }
+
+    #[test]
+    fn unchecked_overflow_wraps() {
+        // The product is 2^80 which wraps around to zero.
+        assert_eq!(overflowing_product(false).unwrap(), Expr::Integer(0));
+    }
+
+    #[test]
+    fn checked_overflow_traps() {
+        expect_fatal_with("checked product", "integer overflow", || {
+            let _ = overflowing_product(true);
+        });
+    }
+
+    #[test]
+    fn checked_arithmetic_in_range() {
+        let source = r#"
+(let f (fn (a b) (sub (mul a b) (add a b))))
+(cons (f 3 4) (cons (mul -3 4) (cons (f 1.5 2) ())))
+"#;
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            eval_arithmetic(source, true, &[], &[]).unwrap(),
+            cons(
+                Expr::Integer(5),
+                cons(Expr::Integer(-12), cons(Expr::Float(-0.5), Expr::Nil))
+            )
+        );
+
+        // Sums right at the edge of the fixnum range still fit.
+        let max = (1 << 61) - 1;
+        assert_eq!(
+            eval_arithmetic("(add a b)", true, &["a", "b"], &[max - 1, 1]).unwrap(),
+            Expr::Integer(max)
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! (fluid-let ((g value)...) body...) gives variables new values for
+//! as long as BODY is running and puts the old ones back afterwards,
+//! including when BODY is left by a throw or an error. The renamer
+//! lowers it into
+//!
+//! (let ((new value)... (old g)...)
+//!   (set g new)...
+//!   (__unwind-protect (begin body...) (set g old)...))
+//!
+//! (__unwind-protect BODY CLEANUP...) evaluates BODY and then CLEANUP
+//! and evaluates to the value of BODY. If something is raised out of
+//! BODY then CLEANUP is evaluated before the raise carries on.
+
+use cranelift::prelude::*;
+
+use crate::compiler::{emit_expr, Context};
+use crate::conversions::RAISED_VALUE;
+use crate::errors::LustError;
+use crate::fatal;
+use crate::location::Span;
+use crate::Expr;
+
+impl Expr {
+    /// Determines if an expression is an unwind protect expression
+    /// and returns its body and cleanup expressions.
+    pub(crate) fn is_unwind_protect(&self) -> Option<(&Expr, &[Expr])> {
+        match self {
+            Expr::List(v, _) => match &v[..] {
+                [Expr::Symbol(s), body, cleanup @ ..] if s == "__unwind-protect" => {
+                    Some((body, cleanup))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Lowers a fluid-let expression with BINDINGS and BODY into lets,
+/// sets, and an unwind protect. The new values are all evaluated
+/// before any of the variables are changed.
+pub(crate) fn lower_fluid_let(bindings: &[(&String, &Expr)], body: &[Expr], span: Span) -> Expr {
+    let symbol = |s: &str| Expr::Symbol(s.to_string());
+    let new = |i: usize| format!("__fluid-new-{}", i);
+    let old = |i: usize| format!("__fluid-old-{}", i);
+    let sets = |temp: &dyn Fn(usize) -> String| {
+        bindings
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| Expr::list(vec![symbol("set"), symbol(name), symbol(&temp(i))]))
+            .collect::<Vec<_>>()
+    };
+
+    let temps = bindings
+        .iter()
+        .enumerate()
+        .map(|(i, (_, value))| Expr::list(vec![symbol(&new(i)), (*value).clone()]))
+        .chain(
+            bindings
+                .iter()
+                .enumerate()
+                .map(|(i, (name, _))| Expr::list(vec![symbol(&old(i)), symbol(name)])),
+        )
+        .collect::<Vec<_>>();
+    let temps = if temps.is_empty() {
+        Expr::Nil
+    } else {
+        Expr::list(temps)
+    };
+
+    let mut begin = vec![symbol("begin")];
+    begin.extend(body.iter().cloned());
+    let mut protect = vec![symbol("__unwind-protect"), Expr::list(begin)];
+    protect.extend(sets(&old));
+
+    let mut res = vec![symbol("let"), temps];
+    res.extend(sets(&new));
+    res.push(Expr::list(protect));
+    Expr::List(res, span)
+}
+
+/// Emits the code for (__unwind-protect BODY CLEANUP...). BODY is
+/// evaluated with a handler installed the same way a catch's body is.
+/// The handler evaluates CLEANUP and then sends the raise on to the
+/// next handler out.
+pub(crate) fn emit_unwind_protect(
+    body: &Expr,
+    cleanup: &[Expr],
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let handler_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(exit_block, ctx.word);
+
+    ctx.handlers.push(handler_block);
+    let res = emit_expr(body, ctx);
+    ctx.handlers.pop();
+    let res = res?;
+    for e in cleanup {
+        emit_expr(e, ctx)?;
+    }
+    ctx.builder.ins().jump(exit_block, &[res]);
+
+    ctx.builder.switch_to_block(handler_block);
+    ctx.builder.seal_block(handler_block);
+    for e in cleanup {
+        emit_expr(e, ctx)?;
+    }
+    let raised = ctx.builder.ins().iconst(ctx.word, RAISED_VALUE);
+    fatal::emit_raise_onwards(raised, ctx);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+    Ok(ctx.builder.block_params(exit_block)[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{roundtrip_string, Expr};
+
+    fn run(source: &str) -> Expr {
+        roundtrip_string(source).unwrap()
+    }
+
+    #[test]
+    fn restored_after_body() {
+        let source = r#"
+(let g 1)
+(let get (fn () g))
+(let during (fluid-let ((g 2)) (get)))
+(list during (get) g)
+"#;
+        assert_eq!(run(source), run("'(2 1 1)"));
+    }
+
+    #[test]
+    fn restored_after_throw() {
+        let source = r#"
+(let g 'outer)
+(let get (fn () g))
+(let deep (fn (n) (if (eq? n 0) (throw 'out (get)) (deep (sub1 n)))))
+(let thrown (catch 'out (fluid-let ((g 'inner)) (deep 10))))
+(list thrown (get))
+"#;
+        assert_eq!(run(source), run("'(inner outer)"));
+
+        // Every fluid-let between the throw and the catch puts its
+        // variables back.
+        let source = r#"
+(let a 1)
+(let b 2)
+(let thrown
+  (catch 'out
+    (fluid-let ((a 10) (b 20))
+      (fluid-let ((a 100))
+        (throw 'out (list a b))))))
+(list thrown a b)
+"#;
+        assert_eq!(run(source), run("'((100 20) 1 2)"));
+    }
+
+    #[test]
+    fn values_evaluated_first() {
+        let source = r#"
+(let a 1)
+(let b 2)
+(let swapped (fluid-let ((a b) (b a)) (list a b)))
+(list swapped a b)
+"#;
+        assert_eq!(run(source), run("'((2 1) 1 2)"));
+    }
+
+    #[test]
+    fn fluid_let_in_functions() {
+        // The body's value is kept and the old value comes back each
+        // time around a loop.
+        let source = r#"
+(let depth 0)
+(let nest (fn (n)
+  (if (eq? n 0)
+      depth
+      (fluid-let ((depth (add1 depth))) (nest (sub1 n))))))
+(list (nest 5) depth)
+"#;
+        assert_eq!(run(source), run("'(5 0)"));
+    }
+}
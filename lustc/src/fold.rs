@@ -0,0 +1,240 @@
+//! Primitive calls whose arguments are all literals can be worked out
+//! at compile time. This replaces them with the literal they would
+//! evaluate to.
+
+use std::convert::TryFrom;
+
+use crate::conversions;
+use crate::floats::RoundOp;
+use crate::procedures::LustFn;
+use crate::Expr;
+
+/// Folds the constant primitive calls in PROGRAM and the bodies of
+/// FUNCTIONS. This runs after functions have been lifted out of the
+/// program so that it can't change what variables they capture. If
+/// CHECKED is set arithmetic that overflows is left for runtime to
+/// report.
+pub(crate) fn fold_constants(program: &mut [Expr], functions: &mut [LustFn], checked: bool) {
+    let _t = crate::timer::timeit("constant folding");
+    for e in program.iter_mut() {
+        fold_expr(e, checked);
+    }
+    for f in functions.iter_mut() {
+        for e in f.body.iter_mut() {
+            fold_expr(e, checked);
+        }
+    }
+}
+
+/// Folds EXPR from the bottom up so that nested calls like (add (add
+/// 1 2) 3) fold all the way down.
+fn fold_expr(expr: &mut Expr, checked: bool) {
+    expr.postorder_traverse_mut(&mut |e: &mut Expr| {
+        if let Some((name, args)) = e.is_primcall() {
+            if let Some(folded) = fold_primcall(name, args, checked) {
+                *e = folded;
+            }
+        }
+    })
+}
+
+/// Wraps I the same way fixnum arithmetic does at runtime.
+fn fixnum(i: i64) -> Expr {
+    Expr::Integer(i.wrapping_shl(conversions::FIXNUM_SHIFT as u32) >> conversions::FIXNUM_SHIFT)
+}
+
+/// Gets the fixnum an arithmetic operation evaluates to. EXACT is the
+/// result if it fits in a word and WRAPPED is the result wrapped
+/// around. If CHECKED is set results that don't fit in a fixnum
+/// aren't folded.
+fn arithmetic(exact: Option<i64>, wrapped: i64, checked: bool) -> Option<Expr> {
+    match exact {
+        Some(i) if fixnum(i) == Expr::Integer(i) => Some(Expr::Integer(i)),
+        _ if checked => None,
+        _ => Some(fixnum(wrapped)),
+    }
+}
+
+/// Gets the literal that calling the primitive NAME on ARGS evaluates
+/// to. Returns None if any of the arguments aren't literals of the
+/// right type or if the call would fail at runtime. Those are left
+/// alone so that the error happens when the program runs.
+fn fold_primcall(name: &str, args: &[Expr], checked: bool) -> Option<Expr> {
+    Some(match (name, args) {
+        ("add1", [Expr::Integer(a)]) => fixnum(a.wrapping_add(1)),
+        ("sub1", [Expr::Integer(a)]) => fixnum(a.wrapping_sub(1)),
+        ("negate", [Expr::Integer(a)]) => arithmetic(a.checked_neg(), a.wrapping_neg(), checked)?,
+        ("abs", [Expr::Integer(a)]) => arithmetic(a.checked_abs(), a.wrapping_abs(), checked)?,
+        ("min", [Expr::Integer(a), Expr::Integer(b)]) => Expr::Integer(*a.min(b)),
+        // Integers are already whole.
+        (name, [Expr::Integer(a)]) if RoundOp::from_primitive(name).is_some() => Expr::Integer(*a),
+        ("inexact->exact", [Expr::Integer(a)]) => Expr::Integer(*a),
+        ("exact->inexact", [Expr::Integer(a)]) => Expr::Float(*a as f64),
+        ("max", [Expr::Integer(a), Expr::Integer(b)]) => Expr::Integer(*a.max(b)),
+        ("add", [Expr::Integer(a), Expr::Integer(b)]) => {
+            arithmetic(a.checked_add(*b), a.wrapping_add(*b), checked)?
+        }
+        ("sub", [Expr::Integer(a), Expr::Integer(b)]) => {
+            arithmetic(a.checked_sub(*b), a.wrapping_sub(*b), checked)?
+        }
+        ("mul", [Expr::Integer(a), Expr::Integer(b)]) => {
+            // Unchecked multiplication multiplies both tagged values
+            // and then shifts out the extra tag so it wraps sooner.
+            let shift = conversions::FIXNUM_SHIFT as u32;
+            let wrapped = a.wrapping_shl(shift).wrapping_mul(b.wrapping_shl(shift)) >> (2 * shift);
+            arithmetic(a.checked_mul(*b), wrapped, checked)?
+        }
+        ("quotient", [Expr::Integer(a), Expr::Integer(b)]) if *b != 0 => fixnum(a.wrapping_div(*b)),
+        ("remainder", [Expr::Integer(a), Expr::Integer(b)]) if *b != 0 => {
+            fixnum(a.wrapping_rem(*b))
+        }
+        ("modulo", [Expr::Integer(a), Expr::Integer(b)]) if *b != 0 => {
+            let rem = a.wrapping_rem(*b);
+            if rem != 0 && (rem < 0) != (*b < 0) {
+                fixnum(rem + b)
+            } else {
+                fixnum(rem)
+            }
+        }
+        ("bitwise-and", [Expr::Integer(a), Expr::Integer(b)]) => fixnum(a & b),
+        ("bitwise-or", [Expr::Integer(a), Expr::Integer(b)]) => fixnum(a | b),
+        ("bitwise-xor", [Expr::Integer(a), Expr::Integer(b)]) => fixnum(a ^ b),
+        ("arithmetic-shift", [Expr::Integer(a), Expr::Integer(count)]) => {
+            // Like the emitted code shifts past the width of a word
+            // are clamped.
+            let places = count.unsigned_abs().min(63) as u32;
+            if *count >= 0 {
+                fixnum(a.wrapping_shl(places))
+            } else {
+                fixnum(a >> places)
+            }
+        }
+        ("lt", [Expr::Integer(a), Expr::Integer(b)])
+        | ("<", [Expr::Integer(a), Expr::Integer(b)]) => Expr::Bool(a < b),
+        ("gt", [Expr::Integer(a), Expr::Integer(b)])
+        | (">", [Expr::Integer(a), Expr::Integer(b)]) => Expr::Bool(a > b),
+        ("<=", [Expr::Integer(a), Expr::Integer(b)]) => Expr::Bool(a <= b),
+        (">=", [Expr::Integer(a), Expr::Integer(b)]) => Expr::Bool(a >= b),
+        ("=", [Expr::Integer(a), Expr::Integer(b)]) => Expr::Bool(a == b),
+        ("eq", [a, b]) | ("eq?", [a, b]) if is_literal(a) && is_literal(b) => {
+            Expr::Bool(a.immediate_rep() == b.immediate_rep())
+        }
+        ("not", [a]) if is_literal(a) => Expr::Bool(*a == Expr::Bool(false)),
+        ("zero?", [Expr::Integer(a)]) => Expr::Bool(*a == 0),
+        ("positive?", [Expr::Integer(a)]) => Expr::Bool(*a > 0),
+        ("negative?", [Expr::Integer(a)]) => Expr::Bool(*a < 0),
+        ("boolean=?", [Expr::Bool(a), Expr::Bool(b)]) => Expr::Bool(a == b),
+        ("char->integer", [Expr::Char(c)]) => Expr::Integer(*c as i64),
+        ("char-upcase", [Expr::Char(c)]) => Expr::Char(c.to_ascii_uppercase()),
+        ("char-downcase", [Expr::Char(c)]) => Expr::Char(c.to_ascii_lowercase()),
+        (name, [Expr::Char(c)]) if crate::chars::char_in_class(name, *c).is_some() => {
+            Expr::Bool(crate::chars::char_in_class(name, *c)?)
+        }
+        ("integer->char", [Expr::Integer(a)]) => {
+            Expr::Char(std::char::from_u32(u32::try_from(*a).ok()?)?)
+        }
+        _ => return None,
+    })
+}
+
+/// Determines if EXPR is a literal that lives in a single word.
+fn is_literal(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Integer(_) | Expr::Bool(_) | Expr::Char(_) | Expr::Nil | Expr::Keyword(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::compile_to_ir;
+    use crate::{parse_string, roundtrip_string, Expr};
+
+    /// Gets the IR of the lust_entry function from compiling SOURCE.
+    fn entry_ir(source: &str) -> String {
+        let ir = compile_to_ir(&parse_string(source).unwrap()).unwrap();
+        let start = ir.find("; lust_entry\n").unwrap();
+        let rest = &ir[start + 1..];
+        match rest.find("\n; ") {
+            Some(end) => rest[..end].to_string(),
+            None => rest.to_string(),
+        }
+    }
+
+    #[test]
+    fn nested_arithmetic() {
+        let ir = entry_ir("(add (add 1 2) 3)");
+        assert_eq!(ir.matches("iconst").count(), 1, "{}", ir);
+        assert!(ir.contains(&format!("iconst.i64 {}", Expr::Integer(6).immediate_rep())));
+        assert_eq!(
+            roundtrip_string("(add (add 1 2) 3)").unwrap(),
+            Expr::Integer(6)
+        );
+    }
+
+    #[test]
+    fn folds_match_runtime() {
+        // Each call is compared against the same call made through a
+        // function so that its arguments can't be folded.
+        let calls: &[(&str, &[&str])] = &[
+            ("sub", &["-3", "7"]),
+            ("mul", &["6", "-7"]),
+            ("quotient", &["-7", "2"]),
+            ("remainder", &["-7", "2"]),
+            ("modulo", &["-7", "2"]),
+            ("modulo", &["7", "-2"]),
+            ("bitwise-xor", &["12", "10"]),
+            ("arithmetic-shift", &["-7", "-1"]),
+            ("arithmetic-shift", &["3", "70"]),
+            ("lt", &["1", "2"]),
+            (">=", &["1", "2"]),
+            ("eq", &["#\\a", "#\\a"]),
+            ("eq", &["1", "#t"]),
+            ("not", &["()"]),
+            ("zero?", &["0"]),
+            ("positive?", &["0"]),
+            ("negative?", &["-3"]),
+            ("boolean=?", &["#f", "#f"]),
+            ("abs", &["-5"]),
+            ("abs", &["-2305843009213693952"]),
+            ("max", &["3", "7"]),
+            ("min", &["-3", "7"]),
+            ("round", &["-3"]),
+            ("floor->integer", &["7"]),
+            ("exact->inexact", &["-7"]),
+            ("inexact->exact", &["7"]),
+            ("integer->char", &["65"]),
+            ("char->integer", &["#\\A"]),
+            ("add", &["2305843009213693951", "1"]),
+            ("mul", &["2305843009213693951", "3"]),
+        ];
+        for (name, args) in calls {
+            let folded = format!("({} {})", name, args.join(" "));
+            let unfolded = format!(
+                "(let id (fn (x) x)) ({} {})",
+                name,
+                args.iter()
+                    .map(|a| format!("(id {})", a))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            );
+            assert_eq!(
+                roundtrip_string(&folded).unwrap(),
+                roundtrip_string(&unfolded).unwrap(),
+                "{}",
+                folded
+            );
+        }
+    }
+
+    #[test]
+    fn unfoldable() {
+        // Calls on variables are left for runtime.
+        let ir = entry_ir("(let a 1) (add a 2)");
+        assert!(ir.matches("iconst").count() > 1, "{}", ir);
+
+        // Dividing by zero still fails when the program runs.
+        let ir = entry_ir("(quotient 1 0)");
+        assert!(ir.contains("call"), "{}", ir);
+    }
+}
@@ -2,6 +2,7 @@
 
 use crate::compiler::{emit_expr, Context};
 use crate::conversions::*;
+use crate::errors::LustError;
 use crate::{Expr, Word};
 use cranelift::prelude::*;
 use cranelift_module::Module;
@@ -10,7 +11,7 @@ impl Expr {
     /// Determines if the expression is a foreign call and if it is
     /// returns its name and arguments.
     pub fn is_foreign_call(&self) -> Option<(String, &[Expr])> {
-        if let Expr::List(v) = self {
+        if let Expr::List(v, _) = self {
             if let Some(Expr::Symbol(s)) = v.first() {
                 if s == "foreign-call" && v.len() >= 2 {
                     if let Expr::String(name) = &v[1] {
@@ -23,7 +24,7 @@ impl Expr {
     }
 
     pub fn is_foreign_call_mut(&mut self) -> Option<(String, &mut [Expr])> {
-        if let Expr::List(v) = self {
+        if let Expr::List(v, _) = self {
             if let Some(Expr::Symbol(s)) = v.first() {
                 if s == "foreign-call" && v.len() >= 2 {
                     if let Expr::String(name) = &v[1] {
@@ -41,7 +42,7 @@ pub(crate) fn emit_foreign_call(
     name: &str,
     args: &[Expr],
     ctx: &mut Context,
-) -> Result<Value, String> {
+) -> Result<Value, LustError> {
     let mut sig = ctx.module.make_signature();
 
     for _ in args {
@@ -52,7 +53,7 @@ pub(crate) fn emit_foreign_call(
     let callee = ctx
         .module
         .declare_function(name, cranelift_module::Linkage::Import, &sig)
-        .map_err(|e| e.to_string())?;
+        .map_err(LustError::from)?;
 
     let local_callee = ctx
         .module
@@ -61,7 +62,7 @@ pub(crate) fn emit_foreign_call(
     let args = args
         .iter()
         .map(|e| emit_untag(e, ctx))
-        .collect::<Result<Vec<_>, String>>()?;
+        .collect::<Result<Vec<_>, LustError>>()?;
 
     let call = ctx.builder.ins().call(local_callee, &args);
     let res = ctx.builder.inst_results(call)[0];
@@ -90,7 +91,7 @@ pub(crate) fn emit_is_heap(val: Value, ctx: &mut Context) -> Value {
 
 /// Takes EXPR and emits the code to remove it's tag making it
 /// elegible for use as an argument for a foreign function.
-pub(crate) fn emit_untag(expr: &Expr, ctx: &mut Context) -> Result<Value, String> {
+pub(crate) fn emit_untag(expr: &Expr, ctx: &mut Context) -> Result<Value, LustError> {
     let the_val = emit_expr(expr, ctx)?;
 
     let fixnum_block = ctx.builder.create_block();
@@ -154,7 +155,15 @@ pub(crate) fn emit_untag(expr: &Expr, ctx: &mut Context) -> Result<Value, String
     ctx.builder.switch_to_block(heap_convert);
     ctx.builder.seal_block(heap_convert);
 
+    // Strings are passed to C as a pointer to their null terminated
+    // bytes which come after the length prefix.
     let naked_ptr = ctx.builder.ins().band_imm(the_val, HEAP_PTR_MASK);
+    let payload_ptr = ctx
+        .builder
+        .ins()
+        .iadd_imm(naked_ptr, ctx.word.bytes() as i64);
+    let is_string = emit_is(the_val, STRING_TAG, HEAP_TAG_MASK, ctx);
+    let naked_ptr = ctx.builder.ins().select(is_string, payload_ptr, naked_ptr);
     ctx.builder.ins().jump(return_block, &[naked_ptr]);
 
     ctx.builder.switch_to_block(shift_block);
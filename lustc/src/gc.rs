@@ -0,0 +1,804 @@
+//! A mark and sweep garbage collector for the heap. The compiler
+//! doesn't keep track of where heap pointers live so the collector is
+//! conservative: any word on the stack or in a live object that points
+//! into an object keeps that object alive. Objects never move so a
+//! word that only looks like a pointer costs some memory but can never
+//! be corrupted. See docs/gc.md for more.
+
+use std::alloc::Layout;
+use std::cell::{RefCell, UnsafeCell};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::Word;
+
+extern "C" {
+    fn malloc(size: usize) -> *mut u8;
+    fn free(ptr: *mut u8);
+}
+
+/// Collections don't happen until at least this many bytes have been
+/// allocated unless a program asks for a different initial size.
+pub(crate) const MIN_THRESHOLD: usize = 1 << 20;
+
+const WORD_SIZE: usize = std::mem::size_of::<usize>();
+
+/// Small objects are allocated out of blocks this many bytes long.
+const BLOCK_SIZE: usize = 1 << 16;
+
+/// Objects with more words than this are too big for a block and get
+/// their own allocation.
+pub(crate) const MAX_SMALL_WORDS: usize = 32;
+
+/// The most cells that compiled code is given to bump allocate out of
+/// at once.
+const BUFFER_CELLS: usize = 64;
+
+/// The fewest free cells in a row that a buffer is refilled from.
+/// Shorter runs are left alone until the next collection.
+const MIN_RUN_CELLS: usize = 8;
+
+/// A run of cells that compiled code allocates objects of one size out
+/// of by moving NEXT up until it reaches LIMIT. The cells are marked as
+/// allocated in their block when the run is handed out so the
+/// collector treats them as objects.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct AllocBuffer {
+    next: usize,
+    limit: usize,
+}
+
+const EMPTY_BUFFER: AllocBuffer = AllocBuffer { next: 0, limit: 0 };
+
+/// A chunk of memory divided into cells that all have the same size.
+/// Each cell holds one object.
+struct Block {
+    start: usize,
+    cell_size: usize,
+    cells: usize,
+    /// One bit for each cell that is set if the cell holds an object.
+    allocated: Vec<u64>,
+    /// One bit for each cell that is set if the object in the cell was
+    /// found during the current collection.
+    marked: Vec<u64>,
+}
+
+/// Where an object lives on the heap.
+#[derive(Clone, Copy)]
+enum Object {
+    /// Cell CELL of block BLOCK.
+    Small { block: usize, cell: usize },
+    /// An object allocated on its own starting at the address.
+    Large(usize),
+}
+
+/// All of the blocks that hold objects of one size.
+#[derive(Default)]
+struct SizeClass {
+    blocks: Vec<usize>,
+    /// The index into BLOCKS and the cell in that block where the
+    /// search for a free cell starts.
+    cursor: (usize, usize),
+}
+
+/// How big the heap that a program runs on starts out and whether it
+/// is allowed to grow past that.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct HeapConfig {
+    /// The number of bytes that can be allocated before the first
+    /// collection.
+    pub initial_bytes: usize,
+    /// If set the heap grows when live objects fill it. Otherwise
+    /// running out of room after a collection is a fatal error.
+    pub growable: bool,
+}
+
+impl HeapConfig {
+    /// Gets the size the heap can grow to before the next collection
+    /// if SIZE bytes survived the last one.
+    fn threshold(&self, size: usize) -> usize {
+        if self.growable {
+            self.initial_bytes.max(2 * size)
+        } else {
+            self.initial_bytes
+        }
+    }
+}
+
+impl Default for HeapConfig {
+    fn default() -> Self {
+        Self {
+            initial_bytes: MIN_THRESHOLD,
+            growable: true,
+        }
+    }
+}
+
+struct Heap {
+    blocks: Vec<Block>,
+    /// Maps the start address of every block to its index in BLOCKS.
+    /// Being ordered lets us find the block that an interior or
+    /// tagged pointer points into.
+    block_starts: BTreeMap<usize, usize>,
+    /// The size classes for objects with 1 to MAX_SMALL_WORDS words.
+    classes: Vec<SizeClass>,
+    /// Maps the address of every large object to its size in bytes
+    /// and whether or not it has been marked.
+    large: BTreeMap<usize, (usize, bool)>,
+    /// The lowest and highest addresses that objects can be found
+    /// between. Words outside of this range are ignored without a
+    /// lookup.
+    bounds: (usize, usize),
+    /// The number of bytes that objects take up.
+    size: usize,
+    /// A collection happens when SIZE grows past this.
+    threshold: usize,
+    /// The configuration of the program that is running.
+    config: HeapConfig,
+    /// The address of the bottom of the stack that programs run on.
+    /// Zero when no program is running in which case collections
+    /// can't happen as we don't know where to look for roots.
+    stack_base: usize,
+    /// The number of collections that have happened so far.
+    collections: usize,
+    /// The addresses of words outside of the stack and the heap that
+    /// may point to objects, like the slots that globals live in.
+    /// Whatever they hold is a root.
+    roots: Vec<usize>,
+}
+
+thread_local! {
+    // Programs allocate and run on the thread that they were compiled
+    // on so every thread gets its own heap and its own stack to scan.
+    static HEAP: RefCell<Heap> = const {
+        RefCell::new(Heap {
+            blocks: Vec::new(),
+            block_starts: BTreeMap::new(),
+            classes: Vec::new(),
+            large: BTreeMap::new(),
+            bounds: (usize::MAX, 0),
+            size: 0,
+            threshold: MIN_THRESHOLD,
+            config: HeapConfig {
+                initial_bytes: MIN_THRESHOLD,
+                growable: true,
+            },
+            stack_base: 0,
+            collections: 0,
+            roots: Vec::new(),
+        })
+    };
+}
+
+thread_local! {
+    // One buffer for each small size class. Compiled code loads and
+    // stores these directly so they have to stay put for as long as
+    // the thread is around.
+    static BUFFERS: UnsafeCell<[AllocBuffer; MAX_SMALL_WORDS]> =
+        const { UnsafeCell::new([EMPTY_BUFFER; MAX_SMALL_WORDS]) };
+}
+
+impl Block {
+    fn new(cell_size: usize) -> Self {
+        let layout = Layout::from_size_align(BLOCK_SIZE, WORD_SIZE).unwrap();
+        let start = unsafe { std::alloc::alloc(layout) };
+        if start.is_null() {
+            std::alloc::handle_alloc_error(layout)
+        }
+        let cells = BLOCK_SIZE / cell_size;
+        let bitmap_words = cells.div_ceil(64);
+        Self {
+            start: start as usize,
+            cell_size,
+            cells,
+            allocated: vec![0; bitmap_words],
+            marked: vec![0; bitmap_words],
+        }
+    }
+
+    /// Finds the first free cell at or after FROM and allocates it.
+    fn take_free_cell(&mut self, from: usize) -> Option<usize> {
+        let mut index = from / 64;
+        // Cells before FROM in the first bitmap word are treated as
+        // allocated so they aren't found.
+        let mut skip = (1u64 << (from % 64)) - 1;
+        while index < self.allocated.len() {
+            let free = !(self.allocated[index] | skip);
+            if free != 0 {
+                let cell = index * 64 + free.trailing_zeros() as usize;
+                if cell >= self.cells {
+                    return None;
+                }
+                self.allocated[index] |= 1 << (cell % 64);
+                return Some(cell);
+            }
+            skip = 0;
+            index += 1;
+        }
+        None
+    }
+
+    fn is_set(bitmap: &[u64], cell: usize) -> bool {
+        bitmap[cell / 64] & (1 << (cell % 64)) != 0
+    }
+}
+
+impl Heap {
+    /// Gets the object that ADDRESS points into if there is one.
+    fn find(&self, address: usize) -> Option<Object> {
+        if address < self.bounds.0 || address >= self.bounds.1 {
+            return None;
+        }
+        if let Some((&start, &index)) = self.block_starts.range(..=address).next_back() {
+            let block = &self.blocks[index];
+            let cell = (address - start) / block.cell_size;
+            if address < start + BLOCK_SIZE {
+                return if cell < block.cells && Block::is_set(&block.allocated, cell) {
+                    Some(Object::Small { block: index, cell })
+                } else {
+                    None
+                };
+            }
+        }
+        self.large
+            .range(..=address)
+            .next_back()
+            .filter(|(&start, &(size, _))| address < start + size)
+            .map(|(&start, _)| Object::Large(start))
+    }
+
+    /// Gets the start address and size of OBJECT.
+    fn extent(&self, object: Object) -> (usize, usize) {
+        match object {
+            Object::Small { block, cell } => {
+                let block = &self.blocks[block];
+                (block.start + cell * block.cell_size, block.cell_size)
+            }
+            Object::Large(start) => (start, self.large[&start].0),
+        }
+    }
+
+    /// Marks OBJECT returning false if it was already marked.
+    fn mark(&mut self, object: Object) -> bool {
+        match object {
+            Object::Small { block, cell } => {
+                let bitmap = &mut self.blocks[block].marked;
+                let was_marked = Block::is_set(bitmap, cell);
+                bitmap[cell / 64] |= 1 << (cell % 64);
+                !was_marked
+            }
+            Object::Large(start) => {
+                let entry = self.large.get_mut(&start).unwrap();
+                !std::mem::replace(&mut entry.1, true)
+            }
+        }
+    }
+
+    fn grow_bounds(&mut self, start: usize, size: usize) {
+        self.bounds.0 = self.bounds.0.min(start);
+        self.bounds.1 = self.bounds.1.max(start + size);
+    }
+
+    fn alloc_small(&mut self, words: usize) -> *mut u8 {
+        let class_index = words - 1;
+        if self.classes.len() <= class_index {
+            self.classes.resize_with(MAX_SMALL_WORDS, Default::default);
+        }
+        let class = &mut self.classes[class_index];
+        let (mut position, mut from) = class.cursor;
+        while position < class.blocks.len() {
+            let block = &mut self.blocks[class.blocks[position]];
+            if let Some(cell) = block.take_free_cell(from) {
+                class.cursor = (position, cell + 1);
+                return (block.start + cell * block.cell_size) as *mut u8;
+            }
+            position += 1;
+            from = 0;
+        }
+
+        // Every block in the class is full.
+        let mut block = Block::new(words * WORD_SIZE);
+        let cell = block.take_free_cell(0).unwrap();
+        let start = block.start;
+        let index = self.blocks.len();
+        self.block_starts.insert(start, index);
+        self.blocks.push(block);
+        self.grow_bounds(start, BLOCK_SIZE);
+        let class = &mut self.classes[class_index];
+        class.blocks.push(index);
+        class.cursor = (class.blocks.len() - 1, cell + 1);
+        start as *mut u8
+    }
+
+    /// Finds the first run of at least MIN_RUN_CELLS free cells after
+    /// the cursor of the size class for objects of WORDS words and
+    /// allocates up to BUFFER_CELLS of it without growing the heap past
+    /// its threshold. The free cells that are passed over are skipped
+    /// by the cursor. Returns the start and end of the run.
+    fn take_run(&mut self, words: usize) -> Option<(usize, usize)> {
+        let wanted =
+            BUFFER_CELLS.min(self.threshold.saturating_sub(self.size) / (words * WORD_SIZE));
+        if wanted == 0 || self.classes.len() < words {
+            return None;
+        }
+        let class = &mut self.classes[words - 1];
+        let (mut position, mut cell) = class.cursor;
+        while position < class.blocks.len() {
+            let block = &mut self.blocks[class.blocks[position]];
+            let mut run = 0;
+            while cell + run < block.cells && run < wanted {
+                if !Block::is_set(&block.allocated, cell + run) {
+                    run += 1;
+                } else if run >= MIN_RUN_CELLS {
+                    break;
+                } else {
+                    cell += run + 1;
+                    run = 0;
+                }
+            }
+            if run >= MIN_RUN_CELLS.min(wanted) && run > 0 {
+                for taken in cell..cell + run {
+                    block.allocated[taken / 64] |= 1 << (taken % 64);
+                }
+                class.cursor = (position, cell + run);
+                self.size += run * block.cell_size;
+                let start = block.start + cell * block.cell_size;
+                return Some((start, start + run * block.cell_size));
+            }
+            position += 1;
+            cell = 0;
+        }
+        class.cursor = (position, 0);
+        None
+    }
+
+    /// Reserves the free cells that directly follow the cursor of the
+    /// size class for objects of WORDS words. Stops at the first cell
+    /// that is taken, after BUFFER_CELLS cells, or once the heap would
+    /// grow past its threshold. Returns the start and end of the run.
+    fn reserve(&mut self, words: usize) -> (usize, usize) {
+        let room = self.threshold.saturating_sub(self.size) / (words * WORD_SIZE);
+        let class = &mut self.classes[words - 1];
+        let (position, from) = class.cursor;
+        let block = match class.blocks.get(position) {
+            Some(&index) => &mut self.blocks[index],
+            None => return (0, 0),
+        };
+        let mut cells = 0;
+        while cells < BUFFER_CELLS.min(room)
+            && from + cells < block.cells
+            && !Block::is_set(&block.allocated, from + cells)
+        {
+            let cell = from + cells;
+            block.allocated[cell / 64] |= 1 << (cell % 64);
+            cells += 1;
+        }
+        class.cursor = (position, from + cells);
+        self.size += cells * block.cell_size;
+        let start = block.start + from * block.cell_size;
+        (start, start + cells * block.cell_size)
+    }
+
+    fn alloc_large(&mut self, size: usize) -> *mut u8 {
+        let ptr = unsafe { malloc(size) };
+        self.large.insert(ptr as usize, (size, false));
+        self.grow_bounds(ptr as usize, size);
+        ptr
+    }
+
+    /// Frees every object that isn't reachable from ROOTS.
+    fn collect(&mut self, roots: &[usize]) {
+        // The cells left in the buffers are freed along with the rest
+        // of the garbage so compiled code has to ask for new ones.
+        BUFFERS.with(|buffers| unsafe { *buffers.get() = [EMPTY_BUFFER; MAX_SMALL_WORDS] });
+
+        let mut pending: Vec<Object> = roots.iter().filter_map(|&w| self.find(w)).collect();
+
+        while let Some(object) = pending.pop() {
+            if !self.mark(object) {
+                continue;
+            }
+            // We don't know what kind of object this is so every word
+            // in it is treated as a potential pointer. For strings and
+            // floats this might keep some garbage around.
+            let (start, size) = self.extent(object);
+            let contents =
+                unsafe { std::slice::from_raw_parts(start as *const usize, size / WORD_SIZE) };
+            pending.extend(contents.iter().filter_map(|&w| self.find(w)));
+        }
+
+        // Every cell that wasn't marked is free for the next round of
+        // allocations.
+        let mut size = 0;
+        for block in &mut self.blocks {
+            let live: u32 = block.marked.iter().map(|w| w.count_ones()).sum();
+            size += live as usize * block.cell_size;
+            block.allocated.copy_from_slice(&block.marked);
+            block.marked.iter_mut().for_each(|w| *w = 0);
+        }
+        for class in &mut self.classes {
+            class.cursor = (0, 0);
+        }
+        self.large.retain(|&object, (object_size, marked)| {
+            if std::mem::replace(marked, false) {
+                size += *object_size;
+                true
+            } else {
+                unsafe { free(object as *mut u8) };
+                false
+            }
+        });
+
+        self.size = size;
+        self.threshold = self.config.threshold(size);
+        self.collections += 1;
+    }
+}
+
+/// Allocates SIZE bytes on the heap. If the heap has grown past its
+/// threshold a collection happens first. If the heap isn't allowed to
+/// grow and there still isn't room after the collection the program
+/// exits with an out of memory error.
+pub(crate) fn alloc(size: usize) -> *mut u8 {
+    // Everything on the heap is at least a word so that a tagged
+    // pointer to it still points inside of it.
+    let words = size.max(1).div_ceil(WORD_SIZE);
+    let size = words * WORD_SIZE;
+    let should_collect = HEAP.with(|heap| {
+        let heap = heap.borrow();
+        heap.stack_base != 0 && heap.size + size > heap.threshold
+    });
+    if should_collect {
+        collect_garbage();
+    }
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        if should_collect && !heap.config.growable && heap.size + size > heap.threshold {
+            // Like the errors checked for by compiled code this ends
+            // the program.
+            let _ = writeln!(std::io::stdout(), "fatal error: out of memory");
+            std::process::exit(-1);
+        }
+        heap.size += size;
+        if words <= MAX_SMALL_WORDS {
+            heap.alloc_small(words)
+        } else {
+            heap.alloc_large(size)
+        }
+    })
+}
+
+/// The allocator that compiled code calls.
+#[no_mangle]
+pub extern "C" fn gc_alloc_lustc(size: usize) -> *mut u8 {
+    alloc(size)
+}
+
+/// Gets the address of the buffer that compiled code allocates objects
+/// of WORDS words out of on this thread. Programs run on the thread
+/// that they were compiled on so the address can be compiled in.
+pub(crate) fn alloc_buffer(words: usize) -> usize {
+    BUFFERS
+        .with(|buffers| buffers.get() as usize + (words - 1) * std::mem::size_of::<AllocBuffer>())
+}
+
+/// The allocator that compiled code calls once the buffer for objects
+/// of SIZE bytes has run out. Refills the buffer from the next long
+/// enough run of free cells and allocates the object out of it. If
+/// there isn't one the object is allocated normally, which may
+/// collect or add a block, and the buffer gets the cells after it.
+#[no_mangle]
+pub extern "C" fn gc_refill_lustc(size: usize) -> *mut u8 {
+    let words = size.max(1).div_ceil(WORD_SIZE);
+    let (ptr, next, limit) = match HEAP.with(|heap| heap.borrow_mut().take_run(words)) {
+        Some((start, end)) => (start as *mut u8, start + words * WORD_SIZE, end),
+        None => {
+            let ptr = alloc(size);
+            let (next, limit) = HEAP.with(|heap| heap.borrow_mut().reserve(words));
+            (ptr, next, limit)
+        }
+    };
+    BUFFERS.with(|buffers| unsafe { (*buffers.get())[words - 1] = AllocBuffer { next, limit } });
+    ptr
+}
+
+/// Runs a collection using the stack and registers of the current
+/// thread as roots.
+#[inline(never)]
+pub(crate) fn collect_garbage() {
+    let registers = callee_saved_registers();
+    let stack_top = &registers as *const _ as usize;
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        if heap.stack_base == 0 {
+            return;
+        }
+        let mut roots = registers.to_vec();
+        roots.extend(
+            heap.roots
+                .iter()
+                .map(|&slot| unsafe { std::ptr::read_volatile(slot as *const usize) }),
+        );
+        let mut address = stack_top & !(WORD_SIZE - 1);
+        while address < heap.stack_base {
+            roots.push(unsafe { std::ptr::read_volatile(address as *const usize) });
+            address += WORD_SIZE;
+        }
+        heap.collect(&roots);
+    })
+}
+
+/// Makes the word at SLOT a root until it is removed with
+/// remove_root. SLOT has to stay valid until then.
+pub(crate) fn add_root(slot: *const Word) {
+    HEAP.with(|heap| heap.borrow_mut().roots.push(slot as usize))
+}
+
+/// Stops treating the word at SLOT as a root.
+pub(crate) fn remove_root(slot: *const Word) {
+    // The heap may already be gone if this thread is exiting.
+    let _ = HEAP.try_with(|heap| heap.borrow_mut().roots.retain(|&r| r != slot as usize));
+}
+
+/// Runs F with the stack base set to the current frame so that any
+/// program F runs can have its stack scanned for roots. The heap
+/// follows CONFIG while F runs.
+#[inline(never)]
+pub(crate) fn with_stack_base<F, T>(config: HeapConfig, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let marker = 0usize;
+    let base = std::hint::black_box(&marker) as *const usize as usize;
+    let previous = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        let previous = (heap.stack_base, heap.config);
+        heap.stack_base = base;
+        heap.config = config;
+        heap.threshold = config.threshold(heap.size);
+        previous
+    });
+    let res = f();
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.stack_base = previous.0;
+        heap.config = previous.1;
+    });
+    res
+}
+
+/// Gets the values of the callee saved registers. Compiled code may
+/// be keeping heap pointers in them instead of on the stack. Anything
+/// in a caller saved register has already been spilled to the stack
+/// by the time we get here.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+fn callee_saved_registers() -> [usize; 6] {
+    let (rbx, rbp, r12, r13, r14, r15): (usize, usize, usize, usize, usize, usize);
+    unsafe {
+        std::arch::asm!("mov {}, rbx", out(reg) rbx, options(nomem, nostack));
+        std::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack));
+        std::arch::asm!("mov {}, r12", out(reg) r12, options(nomem, nostack));
+        std::arch::asm!("mov {}, r13", out(reg) r13, options(nomem, nostack));
+        std::arch::asm!("mov {}, r14", out(reg) r14, options(nomem, nostack));
+        std::arch::asm!("mov {}, r15", out(reg) r15, options(nomem, nostack));
+    }
+    [rbx, rbp, r12, r13, r14, r15]
+}
+
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+fn callee_saved_registers() -> [usize; 11] {
+    let mut registers = [0usize; 11];
+    unsafe {
+        std::arch::asm!("mov {}, x19", out(reg) registers[0], options(nomem, nostack));
+        std::arch::asm!("mov {}, x20", out(reg) registers[1], options(nomem, nostack));
+        std::arch::asm!("mov {}, x21", out(reg) registers[2], options(nomem, nostack));
+        std::arch::asm!("mov {}, x22", out(reg) registers[3], options(nomem, nostack));
+        std::arch::asm!("mov {}, x23", out(reg) registers[4], options(nomem, nostack));
+        std::arch::asm!("mov {}, x24", out(reg) registers[5], options(nomem, nostack));
+        std::arch::asm!("mov {}, x25", out(reg) registers[6], options(nomem, nostack));
+        std::arch::asm!("mov {}, x26", out(reg) registers[7], options(nomem, nostack));
+        std::arch::asm!("mov {}, x27", out(reg) registers[8], options(nomem, nostack));
+        std::arch::asm!("mov {}, x28", out(reg) registers[9], options(nomem, nostack));
+        std::arch::asm!("mov {}, x29", out(reg) registers[10], options(nomem, nostack));
+    }
+    registers
+}
+
+// Reading registers needs a little assembly for each architecture and
+// Cranelift, with the features that lustc uses, only generates code
+// for these two anyway.
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+compile_error!(
+    "the garbage collector can only find roots in registers on x86_64 and aarch64, see docs/gc.md"
+);
+
+/// The number of bytes that objects on the current thread's heap take
+/// up.
+pub fn heap_size() -> usize {
+    HEAP.with(|heap| heap.borrow().size)
+}
+
+/// The number of collections that have happened on the current
+/// thread.
+pub fn collections() -> usize {
+    HEAP.with(|heap| heap.borrow().collections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::roundtrip_string;
+    use crate::testing::expect_fatal_with;
+    use crate::Expr;
+
+    #[test]
+    fn cons_in_loop() {
+        let source = r#"
+(letrec ((loop (fn (n)
+                 (if (eq n 0)
+                     #t
+                     (begin (cons n n) (loop (sub n 1)))))))
+  (loop 1000000))
+"#;
+        let before = collections();
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Bool(true));
+        // A million pairs take up at least 16MB. Collections keep the
+        // heap from ever getting that big.
+        assert!(collections() > before);
+        assert!(heap_size() < 4 * MIN_THRESHOLD);
+    }
+
+    #[test]
+    fn live_objects_survive() {
+        let source = r#"
+(let chars (string->list "abc"))
+(let v (make-vector 40 chars))
+(let f (fn () v))
+(letrec ((build (fn (n acc)
+                  (if (eq n 0)
+                      acc
+                      (begin (make-vector 10) (build (sub n 1) (cons n acc))))))
+         (sum (fn (l acc)
+                (if (null? l) acc (sum (cdr l) (add acc (car l)))))))
+  (cons (sum (build 100000 ()) 0)
+        (car (cdr (vector-ref (f) 39)))))
+"#;
+        let before = collections();
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::Pair(
+                Box::new(Expr::Integer(5000050000)),
+                Box::new(Expr::Char('b'))
+            )
+        );
+        assert!(collections() > before);
+    }
+
+    /// Builds a list of N pairs and then walks it. Every pair is live
+    /// until the walk is done.
+    fn live_list_program(n: usize) -> Vec<Expr> {
+        let source = format!(
+            r#"
+(letrec ((build (fn (n acc) (if (eq n 0) acc (build (sub n 1) (cons n acc)))))
+         (count (fn (l acc) (if (null? l) acc (count (cdr l) (add1 acc))))))
+  (count (build {} ()) 0))
+"#,
+            n
+        );
+        crate::parse_string(&source).unwrap()
+    }
+
+    fn eval_with_heap(program: &[Expr], initial_bytes: usize, growable: bool) -> Expr {
+        let jit = crate::compiler::JIT {
+            heap_initial_bytes: initial_bytes,
+            heap_growable: growable,
+            ..Default::default()
+        };
+        crate::compiler::eval_program_in(jit, program, &[], &[]).unwrap()
+    }
+
+    #[test]
+    fn heap_grows() {
+        // 200000 pairs take up at least 3.2MB which is more than the
+        // default heap and a lot more than a 64KB one.
+        let program = live_list_program(200000);
+        assert_eq!(
+            eval_with_heap(&program, MIN_THRESHOLD, true),
+            Expr::Integer(200000)
+        );
+        let before = collections();
+        assert_eq!(
+            eval_with_heap(&program, 1 << 16, true),
+            Expr::Integer(200000)
+        );
+        assert!(collections() > before);
+    }
+
+    #[test]
+    fn fixed_heap_collects() {
+        // Garbage is collected to make room on a heap that can't
+        // grow.
+        let source = r#"
+(letrec ((loop (fn (n) (if (eq n 0) #t (begin (cons n n) (loop (sub n 1)))))))
+  (loop 100000))
+"#;
+        let program = crate::parse_string(source).unwrap();
+        assert_eq!(eval_with_heap(&program, 1 << 18, false), Expr::Bool(true));
+    }
+
+    #[test]
+    fn fixed_heap_out_of_memory() {
+        expect_fatal_with("live list", "out of memory", || {
+            eval_with_heap(&live_list_program(100000), 1 << 18, false);
+        });
+    }
+
+    #[test]
+    fn collect_without_program() {
+        // Outside of a program there is no stack to scan so nothing
+        // gets collected.
+        let small = alloc(16) as usize;
+        let large = alloc(8 * (MAX_SMALL_WORDS + 1)) as usize;
+        let before = heap_size();
+        collect_garbage();
+        assert_eq!(heap_size(), before);
+        HEAP.with(|heap| {
+            let heap = heap.borrow();
+            let found = |address| heap.find(address).map(|object| heap.extent(object).0);
+            assert_eq!(found(small + 3), Some(small));
+            assert_eq!(found(large + 8 * MAX_SMALL_WORDS), Some(large));
+        });
+    }
+
+    #[test]
+    fn bump_allocation() {
+        let buffer = |words| unsafe { *(alloc_buffer(words) as *const AllocBuffer) };
+        let first = gc_refill_lustc(16) as usize;
+        let AllocBuffer { next, limit } = buffer(2);
+        // The buffer starts at the cell after the one that was asked
+        // for and its cells are objects as far as the heap knows.
+        assert_eq!(next, first + 16);
+        assert!(limit > next && limit - next <= 16 * BUFFER_CELLS);
+        HEAP.with(|heap| {
+            let heap = heap.borrow();
+            assert_eq!(
+                heap.find(limit - 1).map(|o| heap.extent(o).0),
+                Some(limit - 16)
+            );
+        });
+        assert_eq!(buffer(3).next, buffer(3).limit);
+
+        // Collecting empties the buffers and frees the cells that
+        // were left in them.
+        HEAP.with(|heap| heap.borrow_mut().collect(&[]));
+        assert_eq!(buffer(2).next, 0);
+        assert_eq!(buffer(2).limit, 0);
+        assert_eq!(heap_size(), 0);
+
+        // Compiled code allocates out of the buffers.
+        let source = "(let l (list 1 2 3)) (cons (car (cdr l)) l)";
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(2 1 2 3)").unwrap()
+        );
+        assert!(buffer(2).next != 0);
+    }
+
+    #[test]
+    fn short_runs_skipped() {
+        // Every other object survives so the cells that are freed
+        // are too far apart to bump allocate from.
+        let objects: Vec<usize> = (0..40).map(|_| alloc(16) as usize).collect();
+        let roots: Vec<usize> = objects.iter().step_by(2).copied().collect();
+        HEAP.with(|heap| heap.borrow_mut().collect(&roots));
+        let refilled = gc_refill_lustc(16) as usize;
+        // The last object was garbage so its cell starts the run of
+        // free cells after the others.
+        assert_eq!(refilled, objects[39]);
+    }
+}
@@ -0,0 +1,185 @@
+//! Variables that outlive the program that defined them. When a JIT
+//! has globals the top level definitions of every program compiled
+//! into it define globals instead of variables and later programs can
+//! use and set! them. The REPL compiles each of its entries into one
+//! such JIT so that definitions, and changes made to them, carry over
+//! from one entry to the next.
+//!
+//! The renamer spells the global NAME as __global_NAME and
+//! replace_globals then lowers uses of it into
+//!
+//! (__global-ref SLOT)
+//! (__global-set! SLOT VALUE)
+//!
+//! where SLOT is the address of the word that holds the global's
+//! value. Slots are roots for the garbage collector so anything a
+//! global holds lives on between programs.
+
+use std::cell::Cell;
+
+use cranelift::prelude::*;
+
+use crate::compiler::{emit_expr, Context};
+use crate::conversions::NIL_VALUE;
+use crate::errors::LustError;
+use crate::{Expr, PreorderStatus, Word};
+
+/// The globals of a JIT in the order that they were defined.
+#[derive(Default)]
+pub(crate) struct Globals {
+    // Each slot is boxed so its address stays put as more are added.
+    slots: Vec<(String, Box<Cell<Word>>)>,
+}
+
+impl Globals {
+    /// Gets the address of the slot of the global NAME if there is one.
+    pub(crate) fn slot(&self, name: &str) -> Option<Word> {
+        self.slots
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, slot)| slot.as_ptr() as Word)
+    }
+
+    /// Makes a global called NAME that holds nil unless there is one
+    /// already.
+    pub(crate) fn define(&mut self, name: &str) {
+        if self.slot(name).is_none() {
+            let slot = Box::new(Cell::new(NIL_VALUE));
+            crate::gc::add_root(slot.as_ptr());
+            self.slots.push((name.to_string(), slot));
+        }
+    }
+
+    /// The names of every global.
+    pub(crate) fn names(&self) -> impl Iterator<Item = &str> {
+        self.slots.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// The number of globals.
+    pub(crate) fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Forgets every global after the first LEN. Nothing can refer to
+    /// them anymore once they are gone so this is only for undoing
+    /// the definitions of a program that failed to compile.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        for (_, slot) in self.slots.drain(len.min(self.slots.len())..) {
+            crate::gc::remove_root(slot.as_ptr());
+        }
+    }
+}
+
+impl Drop for Globals {
+    fn drop(&mut self) {
+        self.truncate(0)
+    }
+}
+
+/// How the renamer spells the global NAME.
+pub(crate) fn spelling(name: &str) -> String {
+    format!("__global_{}", name)
+}
+
+/// Replaces the uses of GLOBALS in PROGRAM, spelled as the renamer
+/// leaves them, with the forms that read and write their slots.
+pub(crate) fn replace_globals(program: &mut [Expr], globals: &Globals) {
+    let slot = |spelled: &str| {
+        spelled
+            .strip_prefix("__global_")
+            .and_then(|name| globals.slot(name))
+    };
+    for e in program {
+        e.preorder_traverse_mut(&mut |e| {
+            if e.is_quote().is_some() {
+                return PreorderStatus::Skip;
+            }
+            let span = e.span();
+            let form = |head: &str, mut rest: Vec<Expr>, slot: Word| {
+                let mut v = vec![Expr::Symbol(head.to_string()), Expr::Integer(slot)];
+                v.append(&mut rest);
+                Expr::List(v, span)
+            };
+            if let Some((target, value)) = e.is_set() {
+                if let Some(slot) = slot(target) {
+                    *e = form("__global-set!", vec![value.clone()], slot);
+                }
+            } else if let Expr::Symbol(s) = e {
+                if let Some(slot) = slot(s) {
+                    *e = form("__global-ref", vec![], slot);
+                }
+            }
+            PreorderStatus::Continue
+        });
+    }
+}
+
+impl Expr {
+    /// Determines if the expression reads a global and returns the
+    /// address of its slot.
+    pub(crate) fn is_global_ref(&self) -> Option<Word> {
+        match self {
+            Expr::List(v, _) => match &v[..] {
+                [Expr::Symbol(s), Expr::Integer(slot)] if s == "__global-ref" => Some(*slot),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Determines if the expression sets a global and returns the
+    /// address of its slot and the expression for its new value.
+    pub(crate) fn is_global_set(&self) -> Option<(Word, &Expr)> {
+        match self {
+            Expr::List(v, _) => match &v[..] {
+                [Expr::Symbol(s), Expr::Integer(slot), value] if s == "__global-set!" => {
+                    Some((*slot, value))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Emits a read of the global whose slot is at SLOT.
+pub(crate) fn emit_global_ref(slot: Word, ctx: &mut Context) -> Value {
+    let slot = ctx.builder.ins().iconst(ctx.word, slot);
+    ctx.builder
+        .ins()
+        .load(ctx.word, MemFlags::trusted(), slot, 0)
+}
+
+/// Emits the code to store VALUE in the global whose slot is at SLOT.
+/// Like set! evaluates to the new value.
+pub(crate) fn emit_global_set(
+    slot: Word,
+    value: &Expr,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let value = emit_expr(value, ctx)?;
+    let slot = ctx.builder.ins().iconst(ctx.word, slot);
+    ctx.builder.ins().store(MemFlags::trusted(), value, slot, 0);
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reader::parse;
+    use crate::repl::Repl;
+    use crate::Expr;
+
+    #[test]
+    fn locals_shadow_globals() {
+        let mut repl = Repl::new();
+        repl.eval(&parse("(define x 1)").unwrap()).unwrap();
+        let res = repl
+            .eval(&parse("(list (let ((x 2)) x) ((fn (x) x) 3) 'x x)").unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.to_string(), "(2 3 x 1)");
+        // Setting a local that shadows a global leaves the global be.
+        let res = repl.eval(&parse("(let ((x 4)) (set! x 5)) x").unwrap());
+        assert_eq!(res, Ok(Some(Expr::Integer(1))));
+    }
+}
@@ -0,0 +1,380 @@
+//! Hash tables map keys to values in constant time. A hash table is a
+//! two word object holding the number of entries in it as a fixnum
+//! and a vector of buckets. Each bucket is a list of (key . value)
+//! pairs whose keys hash to it.
+//!
+//! Keys are hashed by their immediate representation so only values
+//! that are compared by it with eq? can be keys: fixnums, characters,
+//! booleans, nil and symbols. When the table gets more than three
+//! quarters full it doubles its number of buckets and moves its
+//! entries over.
+
+use cranelift::prelude::*;
+
+use crate::compiler::Context;
+use crate::conversions::{self, HASH_TABLE_MASK, HASH_TABLE_TAG, NIL_VALUE, PAIR_TAG};
+use crate::errors::LustError;
+use crate::fatal;
+use crate::foreign;
+use crate::heap::{runtime_alloc, runtime_alloc_aligned, runtime_cons};
+use crate::primitives::emit_runtime_call;
+use crate::{Expr, Word};
+
+/// The number of buckets that a new hash table starts with.
+const INITIAL_BUCKETS: usize = 8;
+
+const WORD_SIZE: usize = std::mem::size_of::<Word>();
+
+/// Emits the code for a new empty hash table.
+pub(crate) fn emit_make_hash_table(ctx: &mut Context) -> Result<Value, LustError> {
+    emit_runtime_call("make_hash_table_lustc_word", &[], ctx)
+}
+
+/// Emits the code to get the value of KEY in TABLE or DEFAULT if it
+/// has none.
+pub(crate) fn emit_hash_ref(
+    table: Value,
+    key: Value,
+    default: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    emit_check_hash_table(table, ctx)?;
+    emit_check_key(key, ctx)?;
+    emit_runtime_call("hash_ref_lustc_word", &[table, key, default], ctx)
+}
+
+/// Emits the code to set the value of KEY in TABLE to VALUE. Like
+/// vector-set! this evaluates to VALUE.
+pub(crate) fn emit_hash_set(
+    table: Value,
+    key: Value,
+    value: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    emit_check_hash_table(table, ctx)?;
+    emit_check_key(key, ctx)?;
+    emit_runtime_call("hash_set_lustc_word", &[table, key, value], ctx)
+}
+
+fn emit_check_hash_table(query: Value, ctx: &mut Context) -> Result<(), LustError> {
+    fatal::emit_check_tag(query, HASH_TABLE_TAG, HASH_TABLE_MASK, ctx)
+}
+
+/// Emits a runtime type error unless KEY can be used as a key. Every
+/// immediate other than a fixnum has its low four bits set.
+fn emit_check_key(key: Value, ctx: &mut Context) -> Result<(), LustError> {
+    let is_fixnum = foreign::emit_is(key, conversions::FIXNUM_TAG, conversions::FIXNUM_MASK, ctx);
+    let is_immediate = foreign::emit_is(key, 0b1111, 0b1111, ctx);
+    let is_key = ctx.builder.ins().bor(is_fixnum, is_immediate);
+    fatal::emit_check_type(is_key, ctx)
+}
+
+/// Gets the bucket in a table with BUCKETS buckets that KEY goes in.
+fn bucket_index(key: Word, buckets: usize) -> usize {
+    // Fibonacci hashing spreads out keys that only differ in their
+    // high bits, like symbols and characters, over the low ones.
+    let hash = (key as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    ((hash ^ (hash >> 32)) as usize) % buckets
+}
+
+/// Gets a pointer to the two words of the hash table TABLE.
+fn table_words(table: Word) -> *mut Word {
+    debug_assert!(conversions::word_is_hash_table(table));
+    (table & !HASH_TABLE_MASK) as *mut Word
+}
+
+fn pair_words(pair: Word) -> *mut Word {
+    debug_assert_eq!(pair & conversions::HEAP_TAG_MASK, PAIR_TAG);
+    (pair & conversions::HEAP_PTR_MASK) as *mut Word
+}
+
+/// Allocates a vector of COUNT empty buckets.
+fn alloc_buckets(count: usize) -> Word {
+    let vector = runtime_alloc((count + 1) * WORD_SIZE);
+    unsafe {
+        *vector = Expr::Integer(count as i64).immediate_rep();
+        for i in 0..count {
+            *vector.add(i + 1) = NIL_VALUE;
+        }
+    }
+    vector as Word | conversions::VECTOR_TAG
+}
+
+/// Gets the entry for KEY in TABLE if there is one.
+fn find_entry(table: Word, key: Word) -> Option<Word> {
+    let buckets = unsafe { *table_words(table).add(1) };
+    let buckets = conversions::vector_words(buckets);
+    let mut node = buckets[bucket_index(key, buckets.len())];
+    while node != NIL_VALUE {
+        let [entry, next] = unsafe { *(pair_words(node) as *const [Word; 2]) };
+        if unsafe { *pair_words(entry) } == key {
+            return Some(entry);
+        }
+        node = next;
+    }
+    None
+}
+
+/// Moves the entries of TABLE into twice as many buckets. The nodes
+/// of the bucket lists are relinked rather than copied so the only
+/// allocation is the new vector.
+fn grow(table: Word) {
+    let words = table_words(table);
+    let old = unsafe { *words.add(1) };
+    let old = conversions::vector_words(old);
+    let new = alloc_buckets(old.len() * 2);
+    let new_words = (new & conversions::HEAP_PTR_MASK) as *mut Word;
+    for &bucket in old {
+        let mut node = bucket;
+        while node != NIL_VALUE {
+            let pair = pair_words(node);
+            let next = unsafe { *pair.add(1) };
+            let key = unsafe { *pair_words(*pair) };
+            let slot = unsafe { new_words.add(1 + bucket_index(key, old.len() * 2)) };
+            unsafe {
+                *pair.add(1) = *slot;
+                *slot = node;
+            }
+            node = next;
+        }
+    }
+    unsafe { *words.add(1) = new };
+}
+
+/// Tags the hash table whose words start at WORDS. WORDS must be
+/// thirty two byte aligned so that the tag doesn't overlap the
+/// address.
+fn tag_table(words: *mut Word) -> Word {
+    debug_assert_eq!(words as Word & HASH_TABLE_MASK, 0);
+    words as Word | HASH_TABLE_TAG
+}
+
+/// Makes a new hash table with no entries.
+#[no_mangle]
+pub extern "C" fn make_hash_table_lustc_word() -> Word {
+    let buckets = alloc_buckets(INITIAL_BUCKETS);
+    let words = runtime_alloc_aligned(2 * WORD_SIZE);
+    unsafe {
+        *words = Expr::Integer(0).immediate_rep();
+        *words.add(1) = buckets;
+    }
+    tag_table(words)
+}
+
+/// Gets the value of KEY in TABLE or DEFAULT if there isn't one.
+#[no_mangle]
+pub extern "C" fn hash_ref_lustc_word(table: Word, key: Word, default: Word) -> Word {
+    match find_entry(table, key) {
+        Some(entry) => unsafe { *pair_words(entry).add(1) },
+        None => default,
+    }
+}
+
+/// Sets the value of KEY in TABLE to VALUE and returns VALUE.
+#[no_mangle]
+pub extern "C" fn hash_set_lustc_word(table: Word, key: Word, value: Word) -> Word {
+    if let Some(entry) = find_entry(table, key) {
+        unsafe { *pair_words(entry).add(1) = value };
+        return value;
+    }
+
+    let words = table_words(table);
+    let entry = runtime_cons(key, value);
+    let buckets = unsafe { *words.add(1) };
+    let slot = {
+        let buckets_words = (buckets & conversions::HEAP_PTR_MASK) as *mut Word;
+        let len = conversions::vector_words(buckets).len();
+        unsafe { buckets_words.add(1 + bucket_index(key, len)) }
+    };
+    let node = runtime_cons(entry, unsafe { *slot });
+    unsafe { *slot = node };
+
+    let count = unsafe { *words >> conversions::FIXNUM_SHIFT } as usize + 1;
+    unsafe { *words = Expr::Integer(count as i64).immediate_rep() };
+    if count * 4 > conversions::vector_words(buckets).len() * 3 {
+        grow(table);
+    }
+    value
+}
+
+/// Gets the entries of the hash table TABLE in the order that they
+/// are stored.
+pub(crate) fn hash_table_from_immediate(table: Word) -> Expr {
+    let buckets = unsafe { *table_words(table).add(1) };
+    let mut entries = Vec::new();
+    for &bucket in conversions::vector_words(buckets) {
+        let mut node = bucket;
+        while node != NIL_VALUE {
+            let [entry, next] = unsafe { *(pair_words(node) as *const [Word; 2]) };
+            let [key, value] = unsafe { *(pair_words(entry) as *const [Word; 2]) };
+            entries.push((Expr::from_immediate(key), Expr::from_immediate(value)));
+            node = next;
+        }
+    }
+    Expr::HashTable(entries)
+}
+
+/// Makes a pair outside of the heap like Expr::immediate_rep does.
+fn leak_pair(car: Word, cdr: Word) -> Word {
+    let pair = Box::new([car, cdr]);
+    Box::into_raw(pair) as Word | PAIR_TAG
+}
+
+/// Builds a hash table holding ENTRIES outside of the heap for
+/// compiling into a program.
+pub(crate) fn hash_table_to_immediate(entries: &[(Expr, Expr)]) -> Word {
+    #[repr(C, align(32))]
+    struct Table([Word; 2]);
+
+    let buckets = (INITIAL_BUCKETS..)
+        .step_by(INITIAL_BUCKETS)
+        .find(|&b| entries.len() * 4 <= b * 3)
+        .unwrap();
+    let mut lists = vec![NIL_VALUE; buckets];
+    for (key, value) in entries {
+        let key = key.immediate_rep();
+        let entry = leak_pair(key, value.immediate_rep());
+        let bucket = &mut lists[bucket_index(key, buckets)];
+        *bucket = leak_pair(entry, *bucket);
+    }
+    let mut vector = Vec::with_capacity(buckets + 1);
+    vector.push(Expr::Integer(buckets as i64).immediate_rep());
+    vector.extend(lists);
+    let vector_word = vector.as_mut_ptr() as Word | conversions::VECTOR_TAG;
+    std::mem::forget(vector);
+
+    let table = Box::new(Table([
+        Expr::Integer(entries.len() as i64).immediate_rep(),
+        vector_word,
+    ]));
+    tag_table(Box::into_raw(table) as *mut Word)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::expect_fatal;
+    use crate::{roundtrip_string, Expr};
+
+    #[test]
+    fn set_and_ref() {
+        let source = r#"
+(let h (make-hash-table))
+(hash-set! h 1 'one)
+(hash-set! h #\a "a")
+(hash-set! h 'b #t)
+(hash-set! h #f 0)
+(list (hash-ref h 1 #f) (hash-ref h #\a #f) (hash-ref h 'b #f)
+      (hash-ref h #f #f) (hash-ref h 2 'missing))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string(r#"(list 'one "a" #t 0 'missing)"#).unwrap()
+        );
+    }
+
+    #[test]
+    fn overwrite() {
+        let source = r#"
+(let h (make-hash-table))
+(hash-set! h 'x 1)
+(hash-set! h 'x 2)
+(hash-ref h 'x 0)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(2));
+
+        let source = r#"
+(let h (make-hash-table))
+(hash-set! h 'x 1)
+(hash-set! h 'x 2)
+h
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::HashTable(vec![(Expr::Symbol("x".to_string()), Expr::Integer(2))])
+        );
+    }
+
+    #[test]
+    fn grows() {
+        // Enough keys to grow the table several times over.
+        let source = r#"
+(let h (make-hash-table))
+(let i 0)
+(while (lt i 1000)
+  (hash-set! h i (mul i i))
+  (set i (add i 1)))
+(let sum 0)
+(set i 0)
+(while (lt i 1000)
+  (set sum (add sum (sub (hash-ref h i 0) (mul i i))))
+  (set i (add i 1)))
+(list sum (hash-ref h 1000 'missing) (hash-ref h 999 0))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("(list 0 'missing 998001)").unwrap()
+        );
+    }
+
+    #[test]
+    fn survives_collections() {
+        // The table outgrows a 64KB heap many times over so entries
+        // are added and looked up across collections.
+        let source = r#"
+(let h (make-hash-table))
+(let i 0)
+(while (lt i 20000)
+  (hash-set! h i (cons i i))
+  (set i (add i 1)))
+(let ok #t)
+(set i 0)
+(while (lt i 20000)
+  (set ok (and ok (eq (car (hash-ref h i (cons -1 -1))) i)))
+  (set i (add i 1)))
+ok
+"#;
+        let program = crate::parse_string(source).unwrap();
+        let jit = crate::compiler::JIT {
+            heap_initial_bytes: 1 << 16,
+            ..Default::default()
+        };
+        assert_eq!(
+            crate::compiler::eval_program_in(jit, &program, &[], &[]),
+            Ok(Expr::Bool(true))
+        );
+    }
+
+    #[test]
+    fn higher_order_hash_tables() {
+        let source = r#"
+(let make make-hash-table)
+(let set hash-set!)
+(let ref hash-ref)
+(let h (make))
+(set h 'a 1)
+(ref h 'a 0)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(1));
+    }
+
+    #[test]
+    fn roundtrip_hash_table() {
+        let table = Expr::HashTable(vec![(Expr::Char('a'), Expr::Integer(1))]);
+        assert_eq!(
+            crate::compiler::roundtrip_expr(table.clone()).unwrap(),
+            table
+        );
+        assert_eq!(table.to_string(), "#hash((#\\a . 1))");
+    }
+
+    #[test]
+    fn bad_keys() {
+        for source in &[
+            r#"(hash-set! (make-hash-table) "a" 1)"#,
+            "(hash-ref (make-hash-table) (list 1) 0)",
+            "(hash-ref (make-vector 1) 1 0)",
+        ] {
+            expect_fatal(source, "runtime type missmatch");
+        }
+    }
+}
@@ -1,18 +1,25 @@
-//! A heap without a garbage collector
-//! A heap without manual dealocation
-//! Some things truly never die
+//! A heap with a garbage collector
+//! When it fills up the trash goes out
+//! Some things still never die
 
 use cranelift::frontend::FunctionBuilder;
 use cranelift::prelude::AbiParam;
 use cranelift::prelude::InstBuilder;
+use cranelift::prelude::IntCC;
+use cranelift::prelude::MemFlags;
 use cranelift::prelude::Value;
-use cranelift_codegen::binemit::NullTrapSink;
 use cranelift_module::Module;
 
+use crate::backend::LustModule;
 use crate::compiler::JIT;
-
-// Emits an 'alloc' function which when called makes a call to malloc.
-pub fn define_alloc(jit: &mut JIT) -> Result<(), String> {
+use crate::conversions::PAIR_TAG;
+use crate::errors::LustError;
+use crate::Word;
+
+// Emits an 'alloc' function which when called makes a call to the
+// garbage collector's allocator. Object files don't have a collector
+// around so they call malloc instead.
+pub fn define_alloc(jit: &mut JIT) -> Result<(), LustError> {
     let _t = crate::timer::timeit("emit alloc");
     let word = jit.module.target_config().pointer_type();
 
@@ -28,7 +35,11 @@ pub fn define_alloc(jit: &mut JIT) -> Result<(), String> {
     builder.append_block_params_for_function_params(entry_block);
     builder.switch_to_block(entry_block);
 
-    // Make a call to malloc:
+    let allocator = match jit.module {
+        LustModule::JIT(_) => "gc_alloc_lustc",
+        LustModule::Object(_) => "malloc",
+    };
+
     let mut sig = jit.module.make_signature();
 
     sig.params.push(AbiParam::new(word));
@@ -36,8 +47,8 @@ pub fn define_alloc(jit: &mut JIT) -> Result<(), String> {
 
     let callee = jit
         .module
-        .declare_function("malloc", cranelift_module::Linkage::Import, &sig)
-        .map_err(|e| e.to_string())?;
+        .declare_function(allocator, cranelift_module::Linkage::Import, &sig)
+        .map_err(LustError::from)?;
 
     let local_callee = jit.module.declare_func_in_func(callee, &mut builder.func);
 
@@ -52,25 +63,88 @@ pub fn define_alloc(jit: &mut JIT) -> Result<(), String> {
     builder.seal_all_blocks();
     builder.finalize();
 
-    let id = jit
-        .module
-        .declare_function(
-            "alloc",
-            cranelift_module::Linkage::Export,
-            &jit.context.func.signature,
-        )
-        .map_err(|e| e.to_string())?;
+    jit.define_function("alloc")?;
 
-    jit.module
-        .define_function(id, &mut jit.context, &mut NullTrapSink {})
-        .map_err(|e| e.to_string())?;
+    Ok(())
+}
 
-    jit.module.clear_context(&mut jit.context);
+/// Emits an allocation of SIZE bytes. Small objects are bump
+/// allocated out of the buffer the collector keeps for their size and
+/// only call into the collector once the buffer runs out. Object
+/// files don't have a collector so they always call alloc.
+pub(crate) fn emit_alloc(
+    size: i64,
+    ctx: &mut crate::compiler::Context,
+) -> Result<Value, LustError> {
+    let word_bytes = ctx.word.bytes() as i64;
+    let words = (size.max(1) + word_bytes - 1) / word_bytes;
+    if !matches!(ctx.module, LustModule::JIT(_)) || words > crate::gc::MAX_SMALL_WORDS as i64 {
+        let size = ctx.builder.ins().iconst(ctx.word, size);
+        return emit_dynamic_alloc(size, ctx);
+    }
+
+    let buffer = crate::gc::alloc_buffer(words as usize) as i64;
+    let buffer = ctx.builder.ins().iconst(ctx.word, buffer);
+    let next = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::trusted(), buffer, 0);
+    let limit = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::trusted(), buffer, word_bytes as i32);
+    let bumped = ctx.builder.ins().iadd_imm(next, words * word_bytes);
+    let fits = ctx
+        .builder
+        .ins()
+        .icmp(IntCC::UnsignedLessThanOrEqual, bumped, limit);
+
+    let fast_block = ctx.builder.create_block();
+    let slow_block = ctx.builder.create_block();
+    let done_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(done_block, ctx.word);
+    ctx.builder.ins().brz(fits, slow_block, &[]);
+    ctx.builder.ins().jump(fast_block, &[]);
+
+    ctx.builder.switch_to_block(fast_block);
+    ctx.builder.seal_block(fast_block);
+    ctx.builder
+        .ins()
+        .store(MemFlags::trusted(), bumped, buffer, 0);
+    ctx.builder.ins().jump(done_block, &[next]);
+
+    ctx.builder.switch_to_block(slow_block);
+    ctx.builder.seal_block(slow_block);
+    let size = ctx.builder.ins().iconst(ctx.word, size);
+    let res = emit_refill(size, ctx)?;
+    ctx.builder.ins().jump(done_block, &[res]);
+
+    ctx.builder.switch_to_block(done_block);
+    ctx.builder.seal_block(done_block);
+    Ok(ctx.builder.block_params(done_block)[0])
+}
 
-    Ok(())
+/// Emits a call to the collector's refill function to allocate SIZE
+/// bytes once the buffer for objects of that size is empty.
+fn emit_refill(size: Value, ctx: &mut crate::compiler::Context) -> Result<Value, LustError> {
+    let mut sig = ctx.module.make_signature();
+    sig.params.push(AbiParam::new(ctx.word));
+    sig.returns.push(AbiParam::new(ctx.word));
+    let callee = ctx
+        .module
+        .declare_function("gc_refill_lustc", cranelift_module::Linkage::Import, &sig)
+        .map_err(LustError::from)?;
+    let local_callee = ctx.module.declare_func_in_func(callee, ctx.builder.func);
+    let call = ctx.builder.ins().call(local_callee, &[size]);
+    Ok(ctx.builder.inst_results(call)[0])
 }
 
-pub(crate) fn emit_alloc(size: i64, ctx: &mut crate::compiler::Context) -> Result<Value, String> {
+/// Emits a call to alloc for SIZE bytes where SIZE is only known at
+/// runtime.
+pub(crate) fn emit_dynamic_alloc(
+    size: Value,
+    ctx: &mut crate::compiler::Context,
+) -> Result<Value, LustError> {
     let word = ctx.module.target_config().pointer_type();
 
     let mut sig = ctx.module.make_signature();
@@ -81,13 +155,12 @@ pub(crate) fn emit_alloc(size: i64, ctx: &mut crate::compiler::Context) -> Resul
     let callee = ctx
         .module
         .declare_function("alloc", cranelift_module::Linkage::Import, &sig)
-        .map_err(|e| e.to_string())?;
+        .map_err(LustError::from)?;
 
     let local_callee = ctx
         .module
         .declare_func_in_func(callee, &mut ctx.builder.func);
 
-    let size = ctx.builder.ins().iconst(word, size);
     let args = vec![size];
 
     let call = ctx.builder.ins().call(local_callee, &args);
@@ -95,3 +168,43 @@ pub(crate) fn emit_alloc(size: i64, ctx: &mut crate::compiler::Context) -> Resul
 
     Ok(res)
 }
+
+/// Allocates SIZE bytes on the heap for runtime functions implemented
+/// in Rust. This uses the same allocator as code emitted by the JIT.
+pub(crate) fn runtime_alloc(size: usize) -> *mut Word {
+    crate::gc::alloc(size) as *mut Word
+}
+
+/// Objects with five bit tags, hash tables and records, are lined up
+/// on this many bytes so that their tags don't overlap their
+/// addresses.
+pub(crate) const TAG_ALIGN: usize = 32;
+
+/// Allocates SIZE bytes starting on a TAG_ALIGN byte boundary. The
+/// heap only lines objects up on word boundaries so extra words are
+/// allocated to leave room for skipping to the next boundary.
+/// Pointers into the middle of an object keep it alive so the skipped
+/// words don't need to be tracked. At least TAG_ALIGN bytes are kept
+/// after the boundary so that a tagged pointer still points inside of
+/// the object.
+pub(crate) fn runtime_alloc_aligned(size: usize) -> *mut Word {
+    let size = aligned_alloc_size(size);
+    let storage = runtime_alloc(size) as usize;
+    storage.next_multiple_of(TAG_ALIGN) as *mut Word
+}
+
+/// The number of bytes to allocate for an object of SIZE bytes that
+/// needs to start on a TAG_ALIGN byte boundary.
+pub(crate) fn aligned_alloc_size(size: usize) -> usize {
+    size.max(TAG_ALIGN) + TAG_ALIGN - std::mem::size_of::<Word>()
+}
+
+/// Allocates a new pair on the heap from inside a runtime function.
+pub(crate) fn runtime_cons(car: Word, cdr: Word) -> Word {
+    let pair = runtime_alloc(2 * std::mem::size_of::<Word>());
+    unsafe {
+        *pair = car;
+        *pair.add(1) = cdr;
+    }
+    pair as Word | PAIR_TAG
+}
@@ -0,0 +1,295 @@
+//! Calls to small functions cost more than the functions themselves
+//! do. This replaces calls to small leaf functions, ones that don't
+//! call anything but primitives, with their bodies:
+//!
+//! (let f (fn (x) (add1 x)))
+//! (f 1) => (begin (let x' 1) (add1 x'))
+//!
+//! Each copy of the body gets fresh names for its parameters and the
+//! variables that it binds so that copies can't collide with each
+//! other or with the variables around the call.
+
+use std::collections::HashMap;
+
+use crate::primitives::string_is_builtin;
+use crate::procedures::LustFn;
+use crate::renamer::original_name;
+use crate::Expr;
+use crate::PreorderStatus;
+
+/// The inlining threshold that JITs start out with.
+pub const DEFAULT_THRESHOLD: usize = 12;
+
+/// Determines if BODY calls anything other than a primitive or makes
+/// a closure. Lifted functions are replaced by their anonymous names
+/// so any mention of one is a closure being made.
+fn makes_calls(body: &[Expr]) -> bool {
+    let mut calls = false;
+    for e in body {
+        e.preorder_traverse(&mut |e: &Expr| {
+            match e {
+                Expr::Symbol(s) if s.starts_with("__anon_fn_") => calls = true,
+                Expr::List(v, _) => match v.first() {
+                    Some(Expr::Symbol(s)) if string_is_builtin(s) => (),
+                    Some(_) => calls = true,
+                    None => (),
+                },
+                _ => (),
+            }
+            if calls {
+                PreorderStatus::Skip
+            } else {
+                PreorderStatus::Continue
+            }
+        });
+    }
+    calls
+}
+
+/// The number of expressions in BODY counting every subexpression.
+fn size(body: &[Expr]) -> usize {
+    let mut size = 0;
+    for e in body {
+        e.preorder_traverse(&mut |_: &Expr| {
+            size += 1;
+            PreorderStatus::Continue
+        });
+    }
+    size
+}
+
+/// Determines if F can be inlined. It must be small, not varadic,
+/// and a leaf. Leaves can't be recursive. Functions with free
+/// variables are skipped as those variables may not be in scope
+/// where they are called.
+fn is_inlinable(f: &LustFn, threshold: usize) -> bool {
+    f.varadic_symbol.is_none()
+        && f.free_variables.is_empty()
+        && size(&f.body) <= threshold
+        && !makes_calls(&f.body)
+}
+
+/// Builds a map from every name that always refers to one of
+/// FUNCTIONS to that function. Anonymous names always do. Variables do
+/// if they are bound once, never set, and bound to a function.
+fn known_functions<'a>(program: &[Expr], functions: &'a [LustFn]) -> HashMap<String, &'a LustFn> {
+    let by_name: HashMap<&String, &LustFn> = functions.iter().map(|f| (&f.name, f)).collect();
+
+    let mut assignments: HashMap<String, Vec<Option<&LustFn>>> = HashMap::new();
+    for e in program
+        .iter()
+        .chain(functions.iter().flat_map(|f| f.body.iter()))
+    {
+        e.preorder_traverse(&mut |e: &Expr| {
+            if let Some((name, value)) = e.is_let() {
+                let f = match value {
+                    Expr::Symbol(s) => by_name.get(s).copied(),
+                    _ => None,
+                };
+                assignments.entry(name.clone()).or_default().push(f);
+            } else if let Some((name, _)) = e.is_set() {
+                assignments.entry(name.clone()).or_default().push(None);
+            }
+            PreorderStatus::Continue
+        });
+    }
+
+    let mut known: HashMap<String, &LustFn> =
+        by_name.into_iter().map(|(n, f)| (n.clone(), f)).collect();
+    known.extend(
+        assignments
+            .into_iter()
+            .filter_map(|(name, fs)| match fs[..] {
+                [Some(f)] => Some((name, f)),
+                _ => None,
+            }),
+    );
+    known
+}
+
+/// Gets one more than the largest count the renamer has given out in
+/// PROGRAM and FUNCTIONS so that new names can carry on from there.
+fn next_count(program: &[Expr], functions: &[LustFn]) -> usize {
+    let mut next = 0;
+    for e in program
+        .iter()
+        .chain(functions.iter().flat_map(|f| f.body.iter()))
+    {
+        e.preorder_traverse(&mut |e: &Expr| {
+            if let Expr::Symbol(s) = e {
+                if let Some(Ok(count)) = s.split_once('_').map(|(c, _)| c.parse::<usize>()) {
+                    next = next.max(count + 1);
+                }
+            }
+            PreorderStatus::Continue
+        });
+    }
+    next
+}
+
+/// Makes a copy of F's body bound to ARGS. The parameters and every
+/// variable bound in the body are given new names made from COUNT.
+fn instantiate(f: &LustFn, args: &[Expr], call: &Expr, count: &mut usize) -> Expr {
+    let mut bound: Vec<String> = f.params.clone();
+    for e in &f.body {
+        e.preorder_traverse(&mut |e: &Expr| {
+            if let Some((name, _)) = e.is_let() {
+                bound.push(name.clone());
+            }
+            PreorderStatus::Continue
+        });
+    }
+    let mut renames = HashMap::new();
+    for name in bound {
+        let new = format!("{}_{}", *count, original_name(&name));
+        renames.insert(name, new);
+        *count += 1;
+    }
+
+    let mut res = vec![Expr::Symbol("begin".to_string())];
+    for (param, arg) in f.params.iter().zip(args) {
+        res.push(Expr::list(vec![
+            Expr::Symbol("let".to_string()),
+            Expr::Symbol(renames[param].clone()),
+            arg.clone(),
+        ]));
+    }
+    for e in &f.body {
+        let mut e = e.clone();
+        e.postorder_traverse_mut(&mut |e: &mut Expr| {
+            if let Expr::Symbol(s) = e {
+                if let Some(new) = renames.get(s) {
+                    *s = new.clone();
+                }
+            }
+        });
+        res.push(e);
+    }
+    Expr::List(res, call.span())
+}
+
+/// Replaces calls in PROGRAM and the bodies of FUNCTIONS to functions
+/// whose bodies have at most THRESHOLD expressions in them with those
+/// bodies. A THRESHOLD of zero turns inlining off. Needs to run after
+/// `replace_functions` and before escape analysis.
+pub(crate) fn inline_functions(program: &mut [Expr], functions: &mut [LustFn], threshold: usize) {
+    let _t = crate::timer::timeit("inlining pass");
+    if threshold == 0 {
+        return;
+    }
+    let mut count = next_count(program, functions);
+    let inlinable: HashMap<String, LustFn> = known_functions(program, functions)
+        .into_iter()
+        .filter(|(_, f)| is_inlinable(f, threshold))
+        .map(|(name, f)| (name, f.clone()))
+        .collect();
+    if inlinable.is_empty() {
+        return;
+    }
+
+    for e in program
+        .iter_mut()
+        .chain(functions.iter_mut().flat_map(|f| f.body.iter_mut()))
+    {
+        e.postorder_traverse_mut(&mut |e: &mut Expr| {
+            if let Some((Expr::Symbol(name), args)) = e.is_fncall() {
+                if let Some(f) = inlinable.get(name) {
+                    if f.params.len() == args.len() {
+                        *e = instantiate(f, args, e, &mut count);
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::{compile_entry, JIT};
+    use crate::{parse_string, roundtrip_string, Expr};
+
+    /// Compiles SOURCE with an inlining threshold of THRESHOLD and
+    /// returns its IR.
+    fn ir_with_threshold(source: &str, threshold: usize) -> String {
+        let mut jit = JIT::new(Some(String::new()));
+        jit.options.inline_threshold = threshold;
+        compile_entry(&mut jit, &parse_string(source).unwrap(), &[]).unwrap();
+        jit.ir.take().unwrap()
+    }
+
+    #[test]
+    fn tiny_function_inlined() {
+        let source = r#"
+(let f (fn (x) (add x 100)))
+(let y (f 1))
+(f y)
+"#;
+        let ir = ir_with_threshold(source, 16);
+        assert!(!ir.contains("; __anon_fn_0\n"), "{}", ir);
+        assert!(!ir.contains("call_indirect"), "{}", ir);
+
+        let ir = ir_with_threshold(source, 0);
+        assert!(ir.contains("; __anon_fn_0\n"), "{}", ir);
+        assert!(ir.contains("call_indirect"), "{}", ir);
+
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(201));
+    }
+
+    #[test]
+    fn not_inlined() {
+        // Too big.
+        let source = "(let f (fn (x) (add x (add x (add x 1))))) (f 1)";
+        assert!(ir_with_threshold(source, 4).contains("; __anon_fn_0\n"));
+        // Recursive.
+        let source = "(let f (fn (x) (if (eq? x 0) 0 (f (sub1 x))))) (f 3)";
+        assert!(ir_with_threshold(source, 100).contains("; __anon_fn_0\n"));
+        // Calls another function.
+        let source = "(let g (fn () 1)) (let f (fn () (g))) (f)";
+        assert!(ir_with_threshold(source, 100).contains("; __anon_fn_1\n"));
+        // Captures a variable.
+        let source = "(let a 1) (let f (fn () a)) (f)";
+        assert!(ir_with_threshold(source, 100).contains("; __anon_fn_0\n"));
+        // Bound to something else as well.
+        let source = "(let f (fn () 1)) (set f (fn () 2)) (f)";
+        assert!(ir_with_threshold(source, 100).contains("call_indirect"));
+    }
+
+    #[test]
+    fn inlined_names_dont_collide() {
+        // The argument mentions a variable with the same name as the
+        // parameter and the body binds a name that is used around
+        // the call.
+        let source = r#"
+(let f (fn (x) (let y (add1 x)) (mul y 2)))
+(let x 10)
+(let y 1)
+(list (f x) (f (f y)) x y)
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("(list 22 10 10 1)").unwrap()
+        );
+    }
+
+    #[test]
+    fn inlined_in_functions() {
+        let source = r#"
+(let square (fn (x) (mul x x)))
+(let sum-squares (fn (n)
+  (let loop ((i 0) (acc 0))
+    (if (eq? i n) acc (loop (add1 i) (add acc (square i)))))))
+(sum-squares 4)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(14));
+        // Arguments are evaluated once, in order, before the body.
+        let source = r#"
+(let n 0)
+(let second (fn (a b) b))
+(second (set n 1) (set n (mul n 10)))
+n
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(10));
+        let source = "((fn (a b) (sub a b)) 5 3)";
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(2));
+    }
+}
@@ -0,0 +1,274 @@
+//! (read) reads the next expression from stdin while a program is
+//! running. Input is parsed by the same parser that reads programs
+//! and the expression is built on the heap the way the program would
+//! have built it with cons and friends. Once stdin runs out (read)
+//! returns the end of file object.
+
+use std::cell::RefCell;
+use std::io::BufRead;
+
+use crate::conversions::FLOAT_TAG;
+use crate::errors::LustError;
+use crate::heap::{runtime_alloc, runtime_cons};
+use crate::parser::Parser;
+use crate::reader::Reader;
+use crate::strings::runtime_string;
+use crate::{fatal, Expr, Word};
+
+#[derive(Default)]
+struct Input {
+    /// Text that has been read but not parsed yet. Lines are read one
+    /// at a time so an expression may be waiting on the rest of
+    /// itself.
+    pending: String,
+    /// Set once there is nothing left to read.
+    eof: bool,
+    /// Read from instead of stdin when it is set.
+    provided: Option<String>,
+}
+
+thread_local! {
+    static INPUT: RefCell<Input> = RefCell::new(Input::default());
+}
+
+/// Errors the parser gives for input that stops partway through an
+/// expression. More input may finish it.
+const INCOMPLETE: &[&str] = &[
+    "unbalanced parenthesis",
+    "unexpected end of input parsing expression",
+    "unterminated block comment",
+];
+
+/// The result of parsing the start of some input.
+enum Parsed {
+    /// An expression and the number of bytes that it took up.
+    Expr(Expr, usize),
+    /// The input is an expression that hasn't been finished yet.
+    Incomplete,
+    /// There is nothing but whitespace and comments.
+    Empty,
+    Error(String),
+}
+
+/// Parses the first expression in SOURCE.
+fn parse_first(source: &str) -> Parsed {
+    let mut parser = Parser::new(source);
+    if !parser.has_more() {
+        return Parsed::Empty;
+    }
+    let res = parser.parse_expr();
+    let at_end = |loc| Reader::byte_offset(source, loc) == source.len();
+    if res.errors.iter().any(|e| {
+        INCOMPLETE.contains(&e.what.as_str())
+            || (e.what.starts_with("malformed token: \"") && at_end(e.loc.end))
+    }) {
+        return Parsed::Incomplete;
+    }
+    if let Some(e) = res.errors.first() {
+        return Parsed::Error(e.what.clone());
+    }
+    match res.expr {
+        Some(e) => {
+            let used = Reader::byte_offset(source, e.loc.end);
+            match e.into_expr() {
+                Ok(e) => Parsed::Expr(e, used),
+                Err(e) => Parsed::Error(e),
+            }
+        }
+        // Only a datum comment was left.
+        None => Parsed::Empty,
+    }
+}
+
+impl Input {
+    /// Reads another line of input into PENDING. Sets EOF if there
+    /// isn't one.
+    fn read_line(&mut self) {
+        let read = match &mut self.provided {
+            Some(provided) => {
+                let end = provided.find('\n').map_or(provided.len(), |i| i + 1);
+                let line: String = provided.drain(..end).collect();
+                self.pending.push_str(&line);
+                line.len()
+            }
+            None => std::io::stdin()
+                .lock()
+                .read_line(&mut self.pending)
+                .unwrap_or(0),
+        };
+        if read == 0 {
+            self.eof = true;
+        }
+    }
+
+    /// Reads the next expression. Returns None at the end of the
+    /// input.
+    fn read(&mut self) -> Result<Option<Expr>, LustError> {
+        loop {
+            match parse_first(&self.pending) {
+                Parsed::Expr(e, used) => {
+                    self.pending.drain(..used);
+                    return Ok(Some(e));
+                }
+                Parsed::Error(message) => {
+                    self.pending.clear();
+                    return Err(LustError::Read(message));
+                }
+                Parsed::Empty if self.eof => {
+                    self.pending.clear();
+                    return Ok(None);
+                }
+                Parsed::Incomplete if self.eof => {
+                    self.pending.clear();
+                    return Err(LustError::Read("unexpected end of input".to_string()));
+                }
+                Parsed::Empty | Parsed::Incomplete => self.read_line(),
+            }
+        }
+    }
+}
+
+/// Builds E on the heap. Lists are made of pairs with a dot before
+/// the last element making it the final cdr, like quoted lists.
+fn runtime_from_expr(e: &Expr) -> Word {
+    match e {
+        Expr::List(v, _) => {
+            let (elements, tail) = match &v[..] {
+                [init @ .., Expr::Symbol(dot), tail] if dot == "." && !init.is_empty() => {
+                    (init, runtime_from_expr(tail))
+                }
+                _ => (&v[..], Expr::Nil.immediate_rep()),
+            };
+            elements
+                .iter()
+                .rev()
+                .fold(tail, |cdr, car| runtime_cons(runtime_from_expr(car), cdr))
+        }
+        Expr::String(s) => runtime_string(s.as_bytes()),
+        Expr::Float(f) => {
+            let storage = runtime_alloc(std::mem::size_of::<f64>()) as *mut f64;
+            unsafe { *storage = *f };
+            storage as Word | FLOAT_TAG
+        }
+        // Everything else the parser makes is an immediate.
+        e => e.immediate_rep(),
+    }
+}
+
+/// Reads the next expression from stdin. Returns the end of file
+/// object if there are none left and raises an error if the input
+/// isn't a valid expression.
+#[no_mangle]
+pub extern "C" fn read_lustc_word() -> Word {
+    match INPUT.with(|input| input.borrow_mut().read()) {
+        Ok(Some(e)) => runtime_from_expr(&e),
+        Ok(None) => Expr::Eof.immediate_rep(),
+        Err(e) => fatal::raise(e),
+    }
+}
+
+/// Calls F with INPUT standing in for stdin.
+#[cfg(test)]
+pub(crate) fn provide_input<F, R>(input: &str, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    INPUT.with(|i| {
+        *i.borrow_mut() = Input {
+            provided: Some(input.to_string()),
+            ..Input::default()
+        }
+    });
+    let res = f();
+    INPUT.with(|i| *i.borrow_mut() = Input::default());
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::roundtrip_string;
+    use crate::testing::run_in_child;
+
+    fn read_all(input: &str, source: &str) -> Result<Expr, String> {
+        provide_input(input, || roundtrip_string(source))
+    }
+
+    #[test]
+    fn read_expressions() {
+        let source = "(list (read) (read) (read) (read))";
+        let input = "42 (1 \"two\" #\\3)\n'sym\n(a . 1.5)";
+        assert_eq!(
+            read_all(input, source).unwrap().to_string(),
+            r#"(42 (1 "two" #\3) (quote sym) (a . 1.5))"#
+        );
+    }
+
+    #[test]
+    fn read_across_lines() {
+        // Expressions can span lines and more than one can share a
+        // line.
+        let source = "(cons (read) (read))";
+        let input = "(1\n 2 ; comment\n #| 3 |# 4) 5\n";
+        assert_eq!(
+            read_all(input, source).unwrap(),
+            roundtrip_string("(cons '(1 2 4) 5)").unwrap()
+        );
+    }
+
+    #[test]
+    fn read_eof() {
+        let source = "(list (read) (eof-object? (read)) (eof-object? 1))";
+        assert_eq!(
+            read_all("x ; the end\n", source).unwrap(),
+            roundtrip_string("'(x #t #f)").unwrap()
+        );
+        assert_eq!(read_all("", "(read)").unwrap(), Expr::Eof);
+        assert_eq!(
+            read_all("", "(eq? (read) (eof-object))").unwrap(),
+            Expr::Bool(true)
+        );
+    }
+
+    #[test]
+    fn read_errors() {
+        assert_eq!(
+            read_all("(1 2", "(read)").unwrap_err(),
+            "read: unexpected end of input"
+        );
+        assert_eq!(
+            read_all(")", "(read)").unwrap_err(),
+            "read: unexpected closing paren"
+        );
+    }
+
+    #[test]
+    fn higher_order_read() {
+        let source = r#"
+(let r read)
+(let eof? eof-object?)
+(let loop (fn (acc)
+  (let x (r))
+  (if (eof? x) acc (loop (add acc x)))))
+(loop 0)
+"#;
+        assert_eq!(read_all("1 2 3\n4\n", source).unwrap(), Expr::Integer(10));
+    }
+
+    #[test]
+    fn read_from_stdin() {
+        let output = run_in_child("read", b"(hello \"world\")\n12\n", || {
+            let res = roundtrip_string("(list (read) (read) (eof-object? (read)))").unwrap();
+            println!("read: {}", res);
+        });
+        if let Some(output) = output {
+            assert!(output.status.success());
+            assert!(
+                String::from_utf8_lossy(&output.stdout)
+                    .contains(r#"read: ((hello "world") 12 #t)"#),
+                "{}",
+                String::from_utf8_lossy(&output.stdout)
+            );
+        }
+    }
+}
@@ -1,24 +1,54 @@
+pub mod backend;
+pub mod build;
+pub mod chars;
 pub mod compiler;
 pub mod conditional;
 pub mod conversions;
 pub mod data;
+pub mod depth;
+pub mod equality;
 pub mod errors;
 pub mod escape;
+pub mod evaluator;
 pub mod fatal;
+pub mod floats;
+pub mod fluid;
+pub mod fold;
 pub mod foreign;
+pub mod gc;
+pub mod globals;
+pub mod hashtables;
 pub mod heap;
+pub mod inline;
+pub mod input;
+pub mod lint;
+pub mod lists;
 pub mod locals;
 pub mod location;
+pub mod options;
 pub mod parser;
+pub mod peephole;
 pub mod primitives;
 pub mod procedures;
 pub mod reader;
+pub mod records;
 pub mod renamer;
+pub mod repl;
+pub mod sequence;
+pub mod strings;
+pub mod symbols;
+pub mod tail;
+#[cfg(test)]
+mod testing;
 pub mod timer;
 pub mod tokenbuffer;
 pub mod tokenizer;
+pub mod trampoline;
+pub mod values;
+pub mod vectors;
 
 use crate::errors::Printable;
+use crate::location::Span;
 use crate::parser::ExprVal;
 use crate::parser::Parser;
 
@@ -29,12 +59,66 @@ pub(crate) type UWord = u64;
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expr {
     Integer(i64),
+    Float(f64),
     Char(char),
     Bool(bool),
     Nil,
-    List(Vec<Expr>),
+    /// A list and where in the source it was read from.
+    List(Vec<Expr>, Span),
+    /// A cons cell. Lists read back from the heap are made of these.
+    Pair(Box<Expr>, Box<Expr>),
     Symbol(String),
+    /// A keyword, read from :name. Keywords evaluate to themselves
+    /// and are only equal to keywords with the same name.
+    Keyword(String),
     String(String),
+    /// A vector. These only come from the heap as there is no syntax
+    /// for them.
+    Vector(Vec<Expr>),
+    /// A hash table's (key . value) entries. Like vectors these only
+    /// come from the heap.
+    HashTable(Vec<(Expr, Expr)>),
+    /// A record's type number and fields. Like vectors these only
+    /// come from the heap.
+    Record(usize, Vec<Expr>),
+    /// What (read) returns once there is no more input.
+    Eof,
+    /// A closure read back from the heap. There is no way to see
+    /// inside of one so it is kept as the tagged pointer to it and two
+    /// are only equal if they are the same closure.
+    Procedure(Word),
+}
+
+// Hashing agrees with equality so spans are skipped. Floats are
+// hashed by their bits with both zeros hashed the same as they are
+// equal.
+impl std::hash::Hash for Expr {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Expr::Integer(i) => i.hash(state),
+            Expr::Float(f) => {
+                let f = if *f == 0.0 { 0.0 } else { *f };
+                f.to_bits().hash(state)
+            }
+            Expr::Char(c) => c.hash(state),
+            Expr::Bool(b) => b.hash(state),
+            Expr::Nil | Expr::Eof => (),
+            Expr::List(v, _) => v.hash(state),
+            Expr::Pair(car, cdr) => {
+                car.hash(state);
+                cdr.hash(state);
+            }
+            Expr::Symbol(s) | Expr::Keyword(s) | Expr::String(s) => s.hash(state),
+            Expr::Vector(v) => v.hash(state),
+            Expr::HashTable(entries) => entries.hash(state),
+            Expr::Record(id, fields) => {
+                id.hash(state);
+                fields.hash(state);
+            }
+            Expr::Procedure(word) => word.hash(state),
+        }
+    }
 }
 
 impl crate::parser::Expr {
@@ -43,7 +127,13 @@ impl crate::parser::Expr {
     pub(crate) fn into_expr(self) -> Result<Expr, String> {
         Ok(match self.val {
             ExprVal::Number(i) => Expr::Integer(i),
-            ExprVal::Id(s) => Expr::Symbol(s),
+            ExprVal::Float(f) => Expr::Float(f),
+            ExprVal::Bool(b) => Expr::Bool(b),
+            ExprVal::Char(c) => Expr::Char(c),
+            ExprVal::Id(s) => match s.strip_prefix(':') {
+                Some(name) if !name.is_empty() => Expr::Keyword(name.to_string()),
+                _ => Expr::Symbol(s),
+            },
             ExprVal::List(v) => {
                 if v.is_empty() {
                     Expr::Nil
@@ -52,9 +142,27 @@ impl crate::parser::Expr {
                         v.into_iter()
                             .map(|e| e.into_expr())
                             .collect::<Result<Vec<Expr>, String>>()?,
+                        Span(Some(self.loc)),
                     )
                 }
             }
+            ExprVal::DottedList(v, tail) => {
+                // The dot stays in the list as a symbol. In a
+                // function's parameters it marks the rest parameter
+                // and in quoted data it makes the list improper.
+                // Elements in front of a list are just more elements
+                // of it.
+                let mut v = v
+                    .into_iter()
+                    .map(|e| e.into_expr())
+                    .collect::<Result<Vec<Expr>, String>>()?;
+                match tail.into_expr()? {
+                    Expr::Nil => (),
+                    Expr::List(rest, _) => v.extend(rest),
+                    tail => v.extend(vec![Expr::Symbol(".".to_string()), tail]),
+                }
+                Expr::List(v, Span(Some(self.loc)))
+            }
             ExprVal::String(s) => Expr::String(s),
         })
     }
@@ -71,13 +179,27 @@ pub(crate) enum PreorderStatus {
 }
 
 impl Expr {
+    /// Makes a list that wasn't read from source.
+    pub fn list(v: Vec<Expr>) -> Self {
+        Expr::List(v, Span::default())
+    }
+
+    /// Gets the span of the expression if it is a list that was read
+    /// from source.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::List(_, span) => *span,
+            _ => Span::default(),
+        }
+    }
+
     /// Performs a postorder traversal of the expr calling F on each
     /// item it encounters.
     pub(crate) fn postorder_traverse_res<F, E>(&self, f: &mut F) -> Result<(), E>
     where
         F: FnMut(&Expr) -> Result<(), E>,
     {
-        if let Expr::List(v) = self {
+        if let Expr::List(v, _) = self {
             for e in v {
                 e.postorder_traverse_res(f)?;
             }
@@ -91,7 +213,7 @@ impl Expr {
     where
         F: FnMut(&mut Expr),
     {
-        if let Expr::List(v) = self {
+        if let Expr::List(v, _) = self {
             for e in v {
                 e.postorder_traverse_mut(f);
             }
@@ -107,7 +229,7 @@ impl Expr {
         if let PreorderStatus::Skip = status {
             return Ok(status);
         }
-        if let Expr::List(v) = self {
+        if let Expr::List(v, _) = self {
             for e in v {
                 e.preorder_traverse_mut_res(f)?;
             }
@@ -123,7 +245,7 @@ impl Expr {
         if let PreorderStatus::Skip = status {
             return Ok(status);
         }
-        if let Expr::List(v) = self {
+        if let Expr::List(v, _) = self {
             for e in v {
                 e.preorder_traverse_res(f)?;
             }
@@ -139,7 +261,7 @@ impl Expr {
         if let PreorderStatus::Skip = status {
             return status;
         }
-        if let Expr::List(v) = self {
+        if let Expr::List(v, _) = self {
             for e in v {
                 e.preorder_traverse(f);
             }
@@ -155,7 +277,7 @@ impl Expr {
         if let PreorderStatus::Skip = status {
             return status;
         }
-        if let Expr::List(v) = self {
+        if let Expr::List(v, _) = self {
             for e in v {
                 e.preorder_traverse_mut(f);
             }
@@ -177,8 +299,11 @@ pub fn parse_string(input: &str) -> Result<Vec<Expr>, String> {
                 e.show(input, "anonymous");
             }
             if res.errors.is_empty() {
-                let expr = res.expr.unwrap();
-                exprs.push(expr.into_expr()?);
+                // A program can end with a comment that leaves
+                // nothing behind.
+                if let Some(expr) = res.expr {
+                    exprs.push(expr.into_expr()?);
+                }
             } else {
                 return Err("parse error!".to_string());
             }
@@ -190,8 +315,8 @@ pub fn parse_string(input: &str) -> Result<Vec<Expr>, String> {
 /// Roundtrips a string by spinning up a JIT and executing it. Returns
 /// the result.
 pub fn roundtrip_string(input: &str) -> Result<Expr, String> {
-    let mut exprs = parse_string(input)?;
-    crate::compiler::roundtrip_program(&mut exprs)
+    let exprs = parse_string(input)?;
+    crate::compiler::roundtrip_program(&exprs)
 }
 
 /// Roundtrips a file by spinning up a JIT and executing it.
@@ -263,7 +388,86 @@ mod tests {
 (let cons cons)
 (cons 1 2)
 "#;
-        let expected = Expr::List(vec![Expr::Integer(1), Expr::Integer(2)]);
+        let expected = Expr::Pair(Box::new(Expr::Integer(1)), Box::new(Expr::Integer(2)));
         test_string_evaluation(input, expected);
     }
+
+    #[test]
+    fn compile_to_ir() {
+        // f is passed to apply so that it isn't inlined.
+        let program = parse_string("(let f (fn (n) (add1 n))) (apply f (list 41))").unwrap();
+        let ir = crate::compiler::compile_to_ir(&program).unwrap();
+        assert!(ir.contains("; lust_entry\nfunction "));
+        assert!(ir.contains("; __anon_fn_0\nfunction "));
+        // Runtime functions are defined by every JIT.
+        assert!(ir.contains("; alloc\n"));
+
+        // Compiling only for the IR doesn't change what running the
+        // program does.
+        assert_eq!(
+            crate::compiler::roundtrip_program(&program).unwrap(),
+            Expr::Integer(42)
+        );
+
+        let program = parse_string("(undefined)").unwrap();
+        assert_eq!(
+            crate::compiler::compile_to_ir(&program),
+            Err("undefined function (undefined) at line 1, column 1".to_string())
+        );
+    }
+
+    #[test]
+    fn disassemble() {
+        let small = crate::compiler::disassemble(&parse_string("42").unwrap()).unwrap();
+        assert!(!small.is_empty());
+        // The tagged 42 is in the code as an immediate.
+        assert!(small.contains(&(42 << 2)));
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(small.last(), Some(&0xc3), "{:x?}", small);
+
+        let large =
+            crate::compiler::disassemble(&parse_string("(let x (cons 1 2)) (car x)").unwrap())
+                .unwrap();
+        assert!(large.len() > small.len());
+
+        assert_eq!(
+            crate::compiler::disassemble(&parse_string("(car)").unwrap()),
+            Err("car expected 1 args and got 0 at line 1, column 1".to_string())
+        );
+    }
+
+    #[test]
+    fn program_with_args() {
+        use crate::compiler::roundtrip_program_with_args;
+
+        let program = parse_string("(add (mul a a) b)").unwrap();
+        assert_eq!(
+            roundtrip_program_with_args(&program, &["a", "b"], &[3, 4]).unwrap(),
+            Expr::Integer(13)
+        );
+        assert_eq!(
+            roundtrip_program_with_args(&program, &["a", "b"], &[-2, 1]).unwrap(),
+            Expr::Integer(5)
+        );
+        assert_eq!(
+            roundtrip_program_with_args(&program, &["a", "b"], &[1]),
+            Err("lust_entry expected 2 args and got 1".to_string())
+        );
+
+        // Arguments can be captured and shadowed like any other
+        // variable.
+        let program = parse_string(
+            r#"
+(let add-n (fn (x) (add x n)))
+(let n-before n)
+(let n 100)
+(cons (add-n 1) n-before)
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            roundtrip_program_with_args(&program, &["n"], &[5]).unwrap(),
+            Expr::Pair(Box::new(Expr::Integer(6)), Box::new(Expr::Integer(5)))
+        );
+    }
 }
@@ -0,0 +1,302 @@
+//! Warnings about programs that compile fine but probably don't do
+//! what was meant. At the moment that is bindings which are never
+//! used.
+
+use std::fmt;
+
+use crate::location::Span;
+use crate::Expr;
+
+/// A name that is bound by a let expression and never referenced.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Warning {
+    pub name: String,
+    /// Where the binding is. This is the binding's own list if it has
+    /// one and the let expression otherwise.
+    pub span: Span,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unused variable ({})", self.name)
+    }
+}
+
+struct Binding {
+    name: String,
+    span: Span,
+    used: bool,
+    /// Set for bindings that are warned about if they aren't used.
+    /// Function parameters and top level definitions aren't as they
+    /// are often unused on purpose.
+    reported: bool,
+    /// The order the binding was made in so that warnings come out in
+    /// the order of the program.
+    order: usize,
+}
+
+#[derive(Default)]
+struct Linter {
+    scopes: Vec<Vec<Binding>>,
+    made: usize,
+    unused: Vec<(usize, Warning)>,
+}
+
+impl Linter {
+    fn bind(&mut self, name: &str, span: Span, reported: bool) {
+        self.made += 1;
+        let binding = Binding {
+            name: name.to_string(),
+            span,
+            used: false,
+            reported,
+            order: self.made,
+        };
+        self.scopes.last_mut().unwrap().push(binding);
+    }
+
+    fn is_bound(&self, name: &str) -> bool {
+        self.scopes.iter().flatten().any(|b| b.name == name)
+    }
+
+    /// Marks the innermost binding of NAME as used. Later bindings in
+    /// a scope shadow earlier ones.
+    fn reference(&mut self, name: &str) {
+        if let Some(b) = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .flat_map(|s| s.iter_mut().rev())
+            .find(|b| b.name == name)
+        {
+            b.used = true;
+        }
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(vec![]);
+    }
+
+    fn pop_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        self.unused.extend(
+            scope
+                .into_iter()
+                .filter(|b| b.reported && !b.used)
+                .map(|b| {
+                    (
+                        b.order,
+                        Warning {
+                            name: b.name,
+                            span: b.span,
+                        },
+                    )
+                }),
+        );
+    }
+
+    fn lint_body(&mut self, body: &[Expr]) {
+        for e in body {
+            self.lint_expr(e);
+        }
+    }
+
+    fn lint_expr(&mut self, expr: &Expr) {
+        let at_top = self.scopes.len() == 1;
+        if let Expr::Symbol(s) = expr {
+            self.reference(s);
+        } else if expr.is_quote().is_some() {
+            // Quoted symbols are data and not references to
+            // variables.
+        } else if let Some((bindings, body)) = expr.is_scoped_let() {
+            let spans = binding_spans(expr, 1, bindings.len());
+            for (_, val) in &bindings {
+                self.lint_expr(val);
+            }
+            self.push_scope();
+            for ((name, _), span) in bindings.iter().zip(spans) {
+                self.bind(name, span, true);
+            }
+            self.lint_body(body);
+            self.pop_scope();
+        } else if let Some((bindings, body)) = expr.is_let_star() {
+            // Each binding is in a scope of its own nested inside the
+            // ones before it.
+            let spans = binding_spans(expr, 1, bindings.len());
+            for ((name, val), span) in bindings.iter().zip(spans) {
+                self.lint_expr(val);
+                self.push_scope();
+                self.bind(name, span, true);
+            }
+            self.lint_body(body);
+            for _ in &bindings {
+                self.pop_scope();
+            }
+        } else if let Some((bindings, body)) = expr.is_letrec() {
+            let spans = binding_spans(expr, 1, bindings.len());
+            self.push_scope();
+            for ((name, _), span) in bindings.iter().zip(spans) {
+                self.bind(name, span, true);
+            }
+            for (_, val) in &bindings {
+                self.lint_expr(val);
+            }
+            self.lint_body(body);
+            self.pop_scope();
+        } else if let Some((name, bindings, body)) = expr.is_named_let() {
+            let spans = binding_spans(expr, 2, bindings.len());
+            for (_, val) in &bindings {
+                self.lint_expr(val);
+            }
+            self.push_scope();
+            self.bind(name, expr.span(), false);
+            self.push_scope();
+            for ((name, _), span) in bindings.iter().zip(spans) {
+                self.bind(name, span, true);
+            }
+            self.lint_body(body);
+            self.pop_scope();
+            self.pop_scope();
+        } else if let Some((name, val)) = expr.is_let() {
+            // Like the renamer the value can only see the new binding
+            // if the name isn't bound already, which lets functions
+            // refer to themselves.
+            if self.is_bound(name) {
+                self.lint_expr(val);
+                self.bind(name, expr.span(), !at_top);
+            } else {
+                self.bind(name, expr.span(), !at_top);
+                self.lint_expr(val);
+            }
+        } else if let Some((target, rest)) = expr.is_define() {
+            match target {
+                Expr::List(v, _) => {
+                    if let Some(Expr::Symbol(name)) = v.first() {
+                        self.bind(name, expr.span(), false);
+                    }
+                    self.lint_fn(&v[1..], rest);
+                }
+                _ => {
+                    if let Expr::Symbol(name) = target {
+                        self.bind(name, expr.span(), false);
+                    }
+                    self.lint_body(rest);
+                }
+            }
+        } else if let Some((params, body)) = expr.is_fndef() {
+            let params: Vec<Expr> = params.into_iter().cloned().map(Expr::Symbol).collect();
+            self.lint_fn(&params, body);
+        } else if let Expr::List(v, _) = expr {
+            self.lint_body(v);
+        }
+    }
+
+    fn lint_fn(&mut self, params: &[Expr], body: &[Expr]) {
+        self.push_scope();
+        for param in params {
+            if let Expr::Symbol(name) = param {
+                self.bind(name, Span::default(), false);
+            }
+        }
+        self.lint_body(body);
+        self.pop_scope();
+    }
+}
+
+/// Gets the span of each of the COUNT bindings in the binding list at
+/// INDEX in EXPR. Bindings written without their own parens don't
+/// have one so they get the span of EXPR.
+fn binding_spans(expr: &Expr, index: usize, count: usize) -> Vec<Span> {
+    if let Expr::List(v, _) = expr {
+        if let Some(Expr::List(b, _)) = v.get(index) {
+            if b.len() == count && b.iter().all(|b| matches!(b, Expr::List(..))) {
+                return b.iter().map(Expr::span).collect();
+            }
+        }
+    }
+    vec![expr.span(); count]
+}
+
+/// Finds the names bound by let, let*, letrec, and named let
+/// expressions in PROGRAM that are never referenced. Names bound at
+/// the top level of the program aren't included as they may be used
+/// by whatever runs after it.
+pub fn lint(program: &[Expr]) -> Vec<Warning> {
+    let mut linter = Linter::default();
+    linter.push_scope();
+    linter.lint_body(program);
+    linter.pop_scope();
+    linter.unused.sort_by_key(|(order, _)| *order);
+    linter.unused.into_iter().map(|(_, w)| w).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_string;
+
+    fn unused(source: &str) -> Vec<String> {
+        lint(&parse_string(source).unwrap())
+            .into_iter()
+            .map(|w| w.name)
+            .collect()
+    }
+
+    #[test]
+    fn unused_binding() {
+        let source = "(let ((used 1) (unused 2)) (add1 used))";
+        let warnings = lint(&parse_string(source).unwrap());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].name, "unused");
+        assert_eq!(warnings[0].to_string(), "unused variable (unused)");
+
+        // The warning points at the binding.
+        let loc = warnings[0].span.0.unwrap();
+        assert_eq!((loc.start.line, loc.start.col), (0, 15));
+        assert_eq!((loc.end.line, loc.end.col), (0, 25));
+    }
+
+    #[test]
+    fn binding_forms() {
+        assert_eq!(unused("(let* ((a 1) (b a)) 3)"), vec!["b"]);
+        assert_eq!(
+            unused("(letrec ((f (fn () (g))) (g (fn () (f)))) 1)"),
+            Vec::<String>::new()
+        );
+        assert_eq!(unused("(letrec ((f (fn () 1)) (g 2)) (f))"), vec!["g"]);
+        assert_eq!(
+            unused("(let loop ((i 0) (j 0)) (loop i j))"),
+            Vec::<String>::new()
+        );
+        assert_eq!(unused("(let loop ((i 0) (j 0)) i)"), vec!["j"]);
+        assert_eq!(unused("(let (a 1 b 2) a)"), vec!["b"]);
+        assert_eq!(unused("(fn (x) (let y 1) x)"), vec!["y"]);
+    }
+
+    #[test]
+    fn shadowing() {
+        // The first a is used to make the second.
+        assert_eq!(
+            unused("(let* ((a 1) (a (add1 a))) a)"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            unused("(let ((a 1)) (let ((a a)) a))"),
+            Vec::<String>::new()
+        );
+        // Only the innermost a is referenced.
+        assert_eq!(unused("(let ((a 1)) (let ((a 2)) a))"), vec!["a"]);
+        assert_eq!(unused("(let ((a 1)) (fn (a) a))"), vec!["a"]);
+    }
+
+    #[test]
+    fn not_reported() {
+        // Top level definitions, parameters, and quoted symbols.
+        assert_eq!(
+            unused("(let a 1) (define b 2) (define (f x) 1)"),
+            Vec::<String>::new()
+        );
+        assert_eq!(unused("(let ((a 1)) 'a)"), vec!["a"]);
+        assert_eq!(unused("(let ((a 1)) (set a 2))"), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,538 @@
+//! Lists are pairs strung along in a line
+//! Finding the end means walking every one
+
+use cranelift::prelude::*;
+
+use crate::compiler::Context;
+use crate::conversions;
+use crate::errors::LustError;
+use crate::fatal;
+use crate::heap::emit_alloc;
+use crate::primitives::emit_cons;
+use crate::Expr;
+
+/// Emits the code for the number of elements in LIST as a fixnum.
+/// Anything other than nil or a pair along the way is a type error.
+pub(crate) fn emit_length(list: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    let header_block = ctx.builder.create_block();
+    let body_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(exit_block, ctx.word);
+
+    let zero = ctx
+        .builder
+        .ins()
+        .iconst(ctx.word, Expr::Integer(0).immediate_rep());
+    ctx.builder.ins().jump(header_block, &[list, zero]);
+
+    ctx.builder.switch_to_block(header_block);
+    let node = ctx.builder.block_params(header_block)[0];
+    let count = ctx.builder.block_params(header_block)[1];
+    let done = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::Equal, node, conversions::NIL_VALUE);
+    ctx.builder.ins().brnz(done, exit_block, &[count]);
+    ctx.builder.ins().jump(body_block, &[]);
+
+    ctx.builder.switch_to_block(body_block);
+    ctx.builder.seal_block(body_block);
+    fatal::emit_check_pair(node, ctx)?;
+    let next = emit_cdr(node, ctx);
+    let count = ctx
+        .builder
+        .ins()
+        .iadd_imm(count, Expr::Integer(1).immediate_rep());
+    ctx.builder.ins().jump(header_block, &[next, count]);
+    ctx.builder.seal_block(header_block);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+    Ok(ctx.builder.block_params(exit_block)[0])
+}
+
+/// Emits the code for getting the element at INDEX in LIST. INDEX is
+/// a fixnum. Running out of list before reaching it, or a negative
+/// INDEX, is an out of bounds error.
+pub(crate) fn emit_list_ref(
+    list: Value,
+    index: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    fatal::emit_check_int(index, ctx)?;
+    let nonnegative = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::SignedGreaterThanOrEqual, index, 0);
+    fatal::emit_check_list_bounds(nonnegative, ctx)?;
+
+    let header_block = ctx.builder.create_block();
+    let body_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(exit_block, ctx.word);
+
+    ctx.builder.ins().jump(header_block, &[list, index]);
+
+    ctx.builder.switch_to_block(header_block);
+    let node = ctx.builder.block_params(header_block)[0];
+    let remaining = ctx.builder.block_params(header_block)[1];
+    let in_bounds = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::NotEqual, node, conversions::NIL_VALUE);
+    fatal::emit_check_list_bounds(in_bounds, ctx)?;
+    fatal::emit_check_pair(node, ctx)?;
+    let found = ctx.builder.ins().icmp_imm(IntCC::Equal, remaining, 0);
+    ctx.builder.ins().brnz(found, exit_block, &[node]);
+    ctx.builder.ins().jump(body_block, &[]);
+
+    ctx.builder.switch_to_block(body_block);
+    ctx.builder.seal_block(body_block);
+    let next = emit_cdr(node, ctx);
+    let remaining = ctx
+        .builder
+        .ins()
+        .iadd_imm(remaining, -Expr::Integer(1).immediate_rep());
+    ctx.builder.ins().jump(header_block, &[next, remaining]);
+    ctx.builder.seal_block(header_block);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+    let node = ctx.builder.block_params(exit_block)[0];
+    let address = ctx.builder.ins().band_imm(node, conversions::HEAP_PTR_MASK);
+    Ok(ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, 0))
+}
+
+/// Emits the code for finding the first pair in ALIST, a list of
+/// pairs, whose car is eq? to KEY. Evaluates to #f if there is no such
+/// pair. An element of ALIST that is not a pair is a type error.
+pub(crate) fn emit_assoc(key: Value, alist: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    let header_block = ctx.builder.create_block();
+    let body_block = ctx.builder.create_block();
+    let next_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(exit_block, ctx.word);
+
+    ctx.builder.ins().jump(header_block, &[alist]);
+
+    ctx.builder.switch_to_block(header_block);
+    let node = ctx.builder.block_params(header_block)[0];
+    let not_found = ctx
+        .builder
+        .ins()
+        .iconst(ctx.word, Expr::Bool(false).immediate_rep());
+    let done = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::Equal, node, conversions::NIL_VALUE);
+    ctx.builder.ins().brnz(done, exit_block, &[not_found]);
+    ctx.builder.ins().jump(body_block, &[]);
+
+    ctx.builder.switch_to_block(body_block);
+    ctx.builder.seal_block(body_block);
+    fatal::emit_check_pair(node, ctx)?;
+    let entry = emit_car(node, ctx);
+    fatal::emit_check_pair(entry, ctx)?;
+    let entry_key = emit_car(entry, ctx);
+    let found = ctx.builder.ins().icmp(IntCC::Equal, entry_key, key);
+    ctx.builder.ins().brnz(found, exit_block, &[entry]);
+    ctx.builder.ins().jump(next_block, &[]);
+
+    ctx.builder.switch_to_block(next_block);
+    ctx.builder.seal_block(next_block);
+    let next = emit_cdr(node, ctx);
+    ctx.builder.ins().jump(header_block, &[next]);
+    ctx.builder.seal_block(header_block);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+    Ok(ctx.builder.block_params(exit_block)[0])
+}
+
+/// Emits the code for a new list with the elements of LIST in the
+/// opposite order. Anything other than nil or a pair along the way is
+/// a type error.
+pub(crate) fn emit_reverse(list: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    let header_block = ctx.builder.create_block();
+    let body_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(exit_block, ctx.word);
+
+    let nil = ctx.builder.ins().iconst(ctx.word, conversions::NIL_VALUE);
+    ctx.builder.ins().jump(header_block, &[list, nil]);
+
+    // Consing each element onto the front of the result as the list
+    // is walked leaves them back to front.
+    ctx.builder.switch_to_block(header_block);
+    let node = ctx.builder.block_params(header_block)[0];
+    let accum = ctx.builder.block_params(header_block)[1];
+    let done = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::Equal, node, conversions::NIL_VALUE);
+    ctx.builder.ins().brnz(done, exit_block, &[accum]);
+    ctx.builder.ins().jump(body_block, &[]);
+
+    ctx.builder.switch_to_block(body_block);
+    ctx.builder.seal_block(body_block);
+    fatal::emit_check_pair(node, ctx)?;
+    let item = emit_car(node, ctx);
+    let next = emit_cdr(node, ctx);
+    let accum = emit_cons(item, accum, ctx)?;
+    ctx.builder.ins().jump(header_block, &[next, accum]);
+    ctx.builder.seal_block(header_block);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+    Ok(ctx.builder.block_params(exit_block)[0])
+}
+
+/// Emits the code for a list of the elements of FIRST followed by
+/// those of SECOND. FIRST is copied and the last cdr of the copy is
+/// SECOND itself, so if FIRST is nil the result is SECOND. Anything
+/// other than nil or a pair along FIRST is a type error.
+pub(crate) fn emit_append(
+    first: Value,
+    second: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let word_bytes = ctx.word.bytes() as i64;
+
+    // Like map the copy starts from a placeholder pair so there is
+    // always a last pair to add to. The result is its cdr.
+    let start = emit_alloc(2 * word_bytes, ctx)?;
+
+    let header_block = ctx.builder.create_block();
+    let body_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(exit_block, ctx.word);
+
+    ctx.builder.ins().jump(header_block, &[first, start]);
+
+    ctx.builder.switch_to_block(header_block);
+    let node = ctx.builder.block_params(header_block)[0];
+    let last = ctx.builder.block_params(header_block)[1];
+    let done = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::Equal, node, conversions::NIL_VALUE);
+    ctx.builder.ins().brnz(done, exit_block, &[last]);
+    ctx.builder.ins().jump(body_block, &[]);
+
+    ctx.builder.switch_to_block(body_block);
+    ctx.builder.seal_block(body_block);
+    fatal::emit_check_pair(node, ctx)?;
+    let item = emit_car(node, ctx);
+    let next = emit_cdr(node, ctx);
+    let pair = emit_alloc(2 * word_bytes, ctx)?;
+    ctx.builder.ins().store(MemFlags::new(), item, pair, 0);
+    let tagged = ctx.builder.ins().bor_imm(pair, conversions::PAIR_TAG);
+    ctx.builder
+        .ins()
+        .store(MemFlags::new(), tagged, last, word_bytes as i32);
+    ctx.builder.ins().jump(header_block, &[next, pair]);
+    ctx.builder.seal_block(header_block);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+    let last = ctx.builder.block_params(exit_block)[0];
+    ctx.builder
+        .ins()
+        .store(MemFlags::new(), second, last, word_bytes as i32);
+    Ok(ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), start, word_bytes as i32))
+}
+
+/// Emits the code to cons together the COUNT values stored one after
+/// the other starting at ARGLOC. The last value is the final cdr so
+/// with one value that value is the result. COUNT is only known at
+/// runtime and is at least one.
+pub(crate) fn emit_dynamic_cons_star(
+    argloc: Value,
+    count: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let word_bytes = ctx.word.bytes() as i64;
+
+    let header_block = ctx.builder.create_block();
+    let body_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(exit_block, ctx.word);
+
+    let last = ctx.builder.ins().iadd_imm(count, -1);
+    let offset = ctx.builder.ins().imul_imm(last, word_bytes);
+    let address = ctx.builder.ins().iadd(argloc, offset);
+    let tail = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, 0);
+    ctx.builder.ins().jump(header_block, &[last, tail]);
+
+    // Cons on the values in front of the tail from back to front.
+    ctx.builder.switch_to_block(header_block);
+    let index = ctx.builder.block_params(header_block)[0];
+    let accum = ctx.builder.block_params(header_block)[1];
+    ctx.builder.ins().brz(index, exit_block, &[accum]);
+    ctx.builder.ins().jump(body_block, &[]);
+
+    ctx.builder.switch_to_block(body_block);
+    ctx.builder.seal_block(body_block);
+    let index = ctx.builder.ins().iadd_imm(index, -1);
+    let offset = ctx.builder.ins().imul_imm(index, word_bytes);
+    let address = ctx.builder.ins().iadd(argloc, offset);
+    let item = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, 0);
+    let accum = emit_cons(item, accum, ctx)?;
+    ctx.builder.ins().jump(header_block, &[index, accum]);
+    ctx.builder.seal_block(header_block);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+    Ok(ctx.builder.block_params(exit_block)[0])
+}
+
+/// Emits a load of the car of PAIR without checking that it is one.
+pub(crate) fn emit_car(pair: Value, ctx: &mut Context) -> Value {
+    let address = ctx.builder.ins().band_imm(pair, conversions::HEAP_PTR_MASK);
+    ctx.builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, 0)
+}
+
+/// Emits a load of the cdr of PAIR without checking that it is one.
+pub(crate) fn emit_cdr(pair: Value, ctx: &mut Context) -> Value {
+    let address = ctx.builder.ins().band_imm(pair, conversions::HEAP_PTR_MASK);
+    ctx.builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, ctx.word.bytes() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::roundtrip_string;
+    use crate::testing::expect_fatal;
+    use crate::Expr;
+
+    #[test]
+    fn cons_star() {
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string("(cons* 1 2 3)").unwrap(),
+            cons(Expr::Integer(1), cons(Expr::Integer(2), Expr::Integer(3)))
+        );
+        assert_eq!(roundtrip_string("(cons* 1)").unwrap(), Expr::Integer(1));
+        assert_eq!(
+            roundtrip_string("(cons* 1 2 '(3))").unwrap(),
+            roundtrip_string("(list 1 2 3)").unwrap()
+        );
+        assert!(roundtrip_string("(cons*)").is_err());
+
+        let source = r#"
+(let f cons*)
+(cons (f 1) (f 1 2 3))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(
+                Expr::Integer(1),
+                cons(Expr::Integer(1), cons(Expr::Integer(2), Expr::Integer(3)))
+            )
+        );
+    }
+
+    #[test]
+    fn dotted_lists() {
+        // The reader and cons* make the same thing.
+        assert_eq!(
+            roundtrip_string("'(1 2 . 3)").unwrap(),
+            roundtrip_string("(cons* 1 2 3)").unwrap()
+        );
+        assert_eq!(
+            roundtrip_string("(let l (cons* 'a 'b)) (eq? (car l) (car '(a . b)))").unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(
+            roundtrip_string("'((a . 1) (b . 2))").unwrap().to_string(),
+            "((a . 1) (b . 2))"
+        );
+        assert_eq!(
+            roundtrip_string("(cdr '(a . b))").unwrap(),
+            Expr::Symbol("b".to_string())
+        );
+        // Dotting a list onto the end is the same as writing it out.
+        assert_eq!(
+            crate::parse_string("(1 . (2 3))").unwrap(),
+            crate::parse_string("(1 2 3)").unwrap()
+        );
+        assert_eq!(
+            crate::parse_string("(1 . ())").unwrap(),
+            crate::parse_string("(1)").unwrap()
+        );
+    }
+
+    #[test]
+    fn length() {
+        assert_eq!(roundtrip_string("(length ())").unwrap(), Expr::Integer(0));
+        assert_eq!(
+            roundtrip_string("(length (list 1 2 3))").unwrap(),
+            Expr::Integer(3)
+        );
+        assert_eq!(
+            roundtrip_string("(length (cons #\\a (cons (list 1 2) ())))").unwrap(),
+            Expr::Integer(2)
+        );
+    }
+
+    #[test]
+    fn list_ref() {
+        let source = r#"
+(let l (list 10 20 30))
+(list (list-ref l 0) (list-ref l 1) (list-ref l 2))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("(list 10 20 30)").unwrap()
+        );
+    }
+
+    #[test]
+    fn higher_order_list_accessors() {
+        let source = r#"
+(let len length)
+(let ref list-ref)
+(cons (len (list 1 2)) (ref (list 1 2) 1))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::Pair(Box::new(Expr::Integer(2)), Box::new(Expr::Integer(2)))
+        );
+    }
+
+    #[test]
+    fn assoc() {
+        let source = r#"
+(let alist (list (cons 'a 1) (cons 'b 2) (cons 'a 3)))
+(list (assoc 'a alist) (assoc 'b alist) (assoc 'c alist) (assoc 'a ()))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("(list (cons 'a 1) (cons 'b 2) #f #f)").unwrap()
+        );
+        // Keys are compared with eq? so fixnums and characters work.
+        assert_eq!(
+            roundtrip_string("(cdr (assoc #\\b (list (cons #\\a 1) (cons #\\b 2))))").unwrap(),
+            Expr::Integer(2)
+        );
+        let source = r#"
+(let lookup assoc)
+(cdr (lookup 2 (list (cons 1 'one) (cons 2 'two))))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::Symbol("two".to_string())
+        );
+    }
+
+    #[test]
+    fn reverse() {
+        assert_eq!(
+            roundtrip_string("(reverse (list 1 2 3))").unwrap(),
+            roundtrip_string("(list 3 2 1)").unwrap()
+        );
+        assert_eq!(roundtrip_string("(reverse ())").unwrap(), Expr::Nil);
+        // The original list is left alone.
+        let source = r#"
+(let l (list 1 2))
+(reverse l)
+l
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("(list 1 2)").unwrap()
+        );
+    }
+
+    #[test]
+    fn append() {
+        assert_eq!(
+            roundtrip_string("(append (list 1 2) (list 3 4))").unwrap(),
+            roundtrip_string("(list 1 2 3 4)").unwrap()
+        );
+        assert_eq!(
+            roundtrip_string("(append (list 1) 2)").unwrap(),
+            Expr::Pair(Box::new(Expr::Integer(1)), Box::new(Expr::Integer(2)))
+        );
+        // The first list is copied and the second is shared.
+        let source = r#"
+(let a (list 1 2))
+(let b (list 3))
+(let c (append a b))
+(list (eq? c a) (eq? (cdr (cdr c)) b))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("(list #f #t)").unwrap()
+        );
+        // An empty first list gives back the second unchanged.
+        let source = r#"
+(let b (list 3))
+(eq? (append () b) b)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Bool(true));
+        assert_eq!(roundtrip_string("(append () ())").unwrap(), Expr::Nil);
+    }
+
+    #[test]
+    fn higher_order_reverse_append() {
+        let source = r#"
+(let rev reverse)
+(let app append)
+(app (rev (list 2 1)) (list 3))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("(list 1 2 3)").unwrap()
+        );
+    }
+
+    #[test]
+    fn malformed_lists() {
+        for source in &[
+            "(assoc 'b (list (cons 'a 1) 2))",
+            "(assoc 'a 1)",
+            "(reverse (cons 1 2))",
+            "(append 1 (list 2))",
+        ] {
+            expect_fatal(source, "runtime type missmatch");
+        }
+    }
+
+    #[test]
+    fn list_ref_past_end() {
+        for source in &[
+            "(list-ref (list 1 2 3) 3)",
+            "(list-ref () 0)",
+            "(list-ref (list 1) -1)",
+        ] {
+            expect_fatal(source, "list index out of bounds");
+        }
+    }
+}
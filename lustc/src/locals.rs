@@ -8,16 +8,20 @@ use cranelift::prelude::*;
 
 use crate::compiler::emit_expr;
 use crate::compiler::Context;
+use crate::errors::LustError;
 use crate::heap::emit_alloc;
 use crate::primitives::string_is_primitive;
 use crate::procedures::emit_make_closure;
 use crate::Expr;
 
+/// A list of names and the expressions they are bound to.
+pub type Bindings<'a> = Vec<(&'a String, &'a Expr)>;
+
 impl Expr {
     /// Determines if the expression is a let expression and if it is
     /// returns the name and expression being bound.
     pub fn is_let(&self) -> Option<(&String, &Expr)> {
-        if let Self::List(v) = self {
+        if let Self::List(v, _) = self {
             if let Some(Expr::Symbol(s)) = v.first() {
                 if s == "let" && v.len() == 3 {
                     if let Expr::Symbol(s) = &v[1] {
@@ -29,12 +33,120 @@ impl Expr {
         None
     }
 
+    /// Determines if the expression is a define expression and if it
+    /// is returns what is being defined and the rest of the
+    /// expression. Defines either bind a name, `(define a 1)`, or
+    /// define a function, `(define (f a) (add1 a))`.
+    pub fn is_define(&self) -> Option<(&Expr, &[Expr])> {
+        if let Self::List(v, _) = self {
+            if let Some(Expr::Symbol(s)) = v.first() {
+                if s == "define" && v.len() >= 2 {
+                    return Some((&v[1], &v[2..]));
+                }
+            }
+        }
+        None
+    }
+
+    /// Determines if the expression is a let expression with a list
+    /// of bindings and a body, for example `(let ((a 1) (b 2)) (add a
+    /// b))`. The bindings may also be written without the inner
+    /// parens like `(let (a 1 b 2) (add a b))`. If it is returns the
+    /// bindings and the body.
+    pub fn is_scoped_let(&self) -> Option<(Bindings<'_>, &[Expr])> {
+        self.is_binding_form("let")
+    }
+
+    /// Determines if the expression is a letrec expression. These
+    /// look like scoped let expressions but the values of the
+    /// bindings can see each other. If it is returns the bindings and
+    /// the body.
+    pub fn is_letrec(&self) -> Option<(Bindings<'_>, &[Expr])> {
+        self.is_binding_form("letrec")
+    }
+
+    /// Determines if the expression is a let* expression. These look
+    /// like scoped let expressions but each binding can see the ones
+    /// before it. If it is returns the bindings and the body.
+    pub fn is_let_star(&self) -> Option<(Bindings<'_>, &[Expr])> {
+        self.is_binding_form("let*")
+    }
+
+    /// Determines if the expression is a fluid-let expression. These
+    /// look like scoped let expressions but set existing variables
+    /// for the extent of the body instead of binding new ones. If it
+    /// is returns the bindings and the body.
+    pub fn is_fluid_let(&self) -> Option<(Bindings<'_>, &[Expr])> {
+        self.is_binding_form("fluid-let")
+    }
+
+    /// Determines if the expression is a named let expression. These
+    /// look like scoped let expressions with a name before the
+    /// bindings. The name is bound in the body to a function that
+    /// takes new values for the bindings and runs the body again. If
+    /// it is returns the name, the bindings, and the body.
+    pub fn is_named_let(&self) -> Option<(&String, Bindings<'_>, &[Expr])> {
+        if let Self::List(v, _) = self {
+            if let (Some(Expr::Symbol(s)), Some(Expr::Symbol(name))) = (v.first(), v.get(1)) {
+                if s == "let" && v.len() >= 4 {
+                    let bindings = match &v[2] {
+                        Expr::Nil => Some(vec![]),
+                        Expr::List(b, _) => Self::collect_bindings(b),
+                        _ => None,
+                    }?;
+                    return Some((name, bindings, &v[3..]));
+                }
+            }
+        }
+        None
+    }
+
+    fn is_binding_form(&self, keyword: &str) -> Option<(Bindings<'_>, &[Expr])> {
+        if let Self::List(v, _) = self {
+            if let Some(Expr::Symbol(s)) = v.first() {
+                if s == keyword && v.len() >= 3 {
+                    let bindings = match &v[1] {
+                        Expr::Nil => Some(vec![]),
+                        Expr::List(b, _) => Self::collect_bindings(b),
+                        _ => None,
+                    }?;
+                    return Some((bindings, &v[2..]));
+                }
+            }
+        }
+        None
+    }
+
+    fn collect_bindings(bindings: &[Expr]) -> Option<Bindings<'_>> {
+        let nested = bindings
+            .iter()
+            .map(|b| match b {
+                Expr::List(b, _) if b.len() == 2 => match &b[0] {
+                    Expr::Symbol(s) => Some((s, &b[1])),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>();
+        if nested.is_some() || !bindings.len().is_multiple_of(2) {
+            return nested;
+        }
+        bindings
+            .chunks(2)
+            .map(|b| match &b[0] {
+                Expr::Symbol(s) => Some((s, &b[1])),
+                _ => None,
+            })
+            .collect()
+    }
+
     /// Determines if the expression is a set expression and if it is
-    /// returns the name and expression being set.
+    /// returns the name and expression being set. Both `set` and
+    /// `set!` are accepted.
     pub fn is_set(&self) -> Option<(&String, &Expr)> {
-        if let Self::List(v) = self {
+        if let Self::List(v, _) = self {
             if let Some(Expr::Symbol(s)) = v.first() {
-                if s == "set" && v.len() == 3 {
+                if (s == "set" || s == "set!") && v.len() == 3 {
                     if let Expr::Symbol(s) = &v[1] {
                         return Some((s, &v[2]));
                     }
@@ -45,7 +157,7 @@ impl Expr {
     }
 }
 
-pub(crate) fn emit_let(name: &str, val: &Expr, ctx: &mut Context) -> Result<Value, String> {
+pub(crate) fn emit_let(name: &str, val: &Expr, ctx: &mut Context) -> Result<Value, LustError> {
     // The variable is declared but not defined yet so it is on the letstack
     ctx.letstack.push(name.to_string());
 
@@ -62,10 +174,10 @@ pub(crate) fn emit_let(name: &str, val: &Expr, ctx: &mut Context) -> Result<Valu
     };
 
     let var = if let Some(_) = ctx.env.get(name) {
-        Err(format!(
+        Err(LustError::Compile(format!(
             "internal error: let expression modifying an already declared variable: {}",
             name
-        ))
+        )))
     } else {
         Ok(emit_declare_var(
             name,
@@ -83,12 +195,12 @@ pub(crate) fn emit_let(name: &str, val: &Expr, ctx: &mut Context) -> Result<Valu
     Ok(ctx.builder.use_var(var))
 }
 
-pub(crate) fn emit_set(target: &str, val: &Expr, ctx: &mut Context) -> Result<Value, String> {
+pub(crate) fn emit_set(target: &str, val: &Expr, ctx: &mut Context) -> Result<Value, LustError> {
     let val = emit_expr(val, ctx)?;
-    let var = ctx.env.get(target).ok_or(format!(
-        "use of undeclared variable ({}) in set expression",
-        target
-    ))?;
+    let var = ctx
+        .env
+        .get(target)
+        .ok_or_else(|| LustError::UnboundSymbol(target.to_string()))?;
 
     if target.starts_with("e_") {
         let location = ctx.builder.use_var(*var);
@@ -99,7 +211,7 @@ pub(crate) fn emit_set(target: &str, val: &Expr, ctx: &mut Context) -> Result<Va
     Ok(val)
 }
 
-pub(crate) fn emit_var_access(name: &str, ctx: &mut Context) -> Result<Value, String> {
+pub(crate) fn emit_var_access(name: &str, ctx: &mut Context) -> Result<Value, LustError> {
     if name.starts_with("__anon_fn_") || string_is_primitive(name) {
         // We're dealing with a closure so we'll need to make one.
         let free_variables = ctx
@@ -127,10 +239,10 @@ pub(crate) fn emit_var_access(name: &str, ctx: &mut Context) -> Result<Value, St
     } else {
         match ctx.env.get(name) {
             Some(v) => Ok(ctx.builder.use_var(*v)),
-            None => Err(format!(
+            None => Err(LustError::Compile(format!(
                 "internal error: (regular lookup) use of undeclared variable ({})",
                 name
-            )),
+            ))),
         }
     }
 }
@@ -140,9 +252,12 @@ pub(crate) fn emit_declare_var(
     env: &mut HashMap<String, Variable>,
     builder: &mut FunctionBuilder,
     word: Type,
-) -> Result<Variable, String> {
+) -> Result<Variable, LustError> {
     if env.contains_key(name) {
-        return Err(format!("variable ({}) is declared more than once", name));
+        return Err(LustError::Compile(format!(
+            "variable ({}) is declared more than once",
+            name
+        )));
     }
     let index = env.len();
     let var = Variable::new(index);
@@ -155,7 +270,7 @@ pub(crate) fn emit_var_decl_and_assign(
     name: &str,
     val: Value,
     ctx: &mut Context,
-) -> Result<Variable, String> {
+) -> Result<Variable, LustError> {
     let var = emit_declare_var(name, &mut ctx.env, &mut ctx.builder, ctx.word)?;
 
     ctx.builder.def_var(var, val);
@@ -173,7 +288,7 @@ mod tests {
 
     #[test]
     fn let_return() {
-        let mut ast = [Expr::List(vec![
+        let mut ast = [Expr::list(vec![
             Expr::Symbol("let".to_string()),
             Expr::Symbol("tel".to_string()),
             Expr::Integer(10),
@@ -181,10 +296,10 @@ mod tests {
         let expected = Expr::Integer(10);
         test_evaluation(&mut ast, expected);
 
-        let mut ast = [Expr::List(vec![
+        let mut ast = [Expr::list(vec![
             Expr::Symbol("let".to_string()),
             Expr::Symbol("tel".to_string()),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("ttel".to_string()),
                 Expr::Char('🥺'),
@@ -197,7 +312,7 @@ mod tests {
     #[test]
     fn naked_var() {
         let mut ast = [
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("tel".to_string()),
                 Expr::Integer(10),
@@ -208,10 +323,10 @@ mod tests {
         test_evaluation(&mut ast, expected);
 
         let mut ast = [
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("tel".to_string()),
-                Expr::List(vec![
+                Expr::list(vec![
                     Expr::Symbol("let".to_string()),
                     Expr::Symbol("🚨".to_string()),
                     Expr::Char('🥺'),
@@ -236,12 +351,12 @@ mod tests {
     #[test]
     fn var_double_use() {
         let mut ast = [
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("tel".to_string()),
                 Expr::Integer(10),
             ]),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("add".to_string()),
                 Expr::Symbol("tel".to_string()),
                 Expr::Symbol("tel".to_string()),
@@ -254,57 +369,57 @@ mod tests {
     #[test]
     fn lots_of_vars() {
         let mut ast = [
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("tel".to_string()),
                 Expr::Integer(10),
             ]),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("tel1".to_string()),
                 Expr::Integer(10),
             ]),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("tel2".to_string()),
                 Expr::Integer(10),
             ]),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("tel3".to_string()),
                 Expr::Integer(10),
             ]),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("tel4".to_string()),
                 Expr::Integer(10),
             ]),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("tel5".to_string()),
                 Expr::Integer(10),
             ]),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("tel6".to_string()),
                 Expr::Integer(10),
             ]),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("tel7".to_string()),
                 Expr::Integer(10),
             ]),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("tel8".to_string()),
                 Expr::Integer(10),
             ]),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("tel9".to_string()),
                 Expr::Integer(10),
             ]),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("add".to_string()),
                 Expr::Symbol("tel8".to_string()),
                 Expr::Symbol("tel9".to_string()),
@@ -314,6 +429,358 @@ mod tests {
         test_evaluation(&mut ast, expected);
     }
 
+    #[test]
+    fn set_bang() {
+        let source = r#"
+(let x 1)
+(set! x 2)
+x
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(2));
+
+        // The value of a set expression is the new value.
+        let source = r#"
+(let x 1)
+(set! x (add1 x))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(2));
+    }
+
+    #[test]
+    fn scoped_let() {
+        let source = r#"
+(let ((a 1) (b 2))
+  (let c (add a b))
+  (add c c))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(6));
+
+        let source = r#"
+(let (x 1)
+  (set! x 2)
+  x)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(2));
+
+        let source = "(let () 1 2)";
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(2));
+    }
+
+    #[test]
+    fn scoped_let_scope() {
+        // Bindings shadow outer variables inside the body only and
+        // their values are evaluated in the outer scope.
+        let source = r#"
+(let x 1)
+(let y (let ((x 10) (z x)) (add x z)))
+(cons x y)
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::Pair(Box::new(Expr::Integer(1)), Box::new(Expr::Integer(11)))
+        );
+
+        let program = crate::reader::parse("(let ((x 1)) x) x").unwrap();
+        assert_eq!(
+            crate::compiler::eval_program(&program),
+            Err(LustError::UnboundSymbol("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn primitive_names_shadowed() {
+        // Variables named after primitives are called instead of the
+        // primitive in every kind of binding.
+        let source = r#"
+(let list (fn (& args) 'mine))
+(let add1-twice (fn (add1 x) (add1 (add1 x))))
+(let length 5)
+(let ((car cdr)) (cons (list 1 2) (cons (add1-twice sub1 3) (cons length (car '(1 2))))))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(mine 1 5 2)").unwrap()
+        );
+
+        // Recursive functions named after primitives call themselves.
+        let source = r#"
+(define (list n) (if (eq? n 0) 'done (list (sub1 n))))
+(list 3)
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::Symbol("done".to_string())
+        );
+        let source = "(letrec ((map (fn (n) (if (eq? n 0) 0 (map (sub1 n)))))) (map 4))";
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(0));
+
+        // Other values are still evaluated with the primitive in scope.
+        let source = "(let list (list 1 2)) list";
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(1 2)").unwrap()
+        );
+    }
+
+    #[test]
+    fn scoped_let_closure() {
+        let source = r#"
+(let make-counter (fn ()
+  (let ((n 0))
+    (fn () (set n (add1 n)) n))))
+(let counter (make-counter))
+(counter)
+(counter)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(2));
+    }
+
+    #[test]
+    fn let_star() {
+        assert_eq!(
+            roundtrip_string("(let* ((x 1) (y (add1 x))) y)").unwrap(),
+            Expr::Integer(2)
+        );
+        assert_eq!(
+            roundtrip_string("(let* (x 1 y (add1 x) x (mul y 10)) (add x y))").unwrap(),
+            Expr::Integer(22)
+        );
+        assert_eq!(roundtrip_string("(let* () 1)").unwrap(), Expr::Integer(1));
+    }
+
+    #[test]
+    fn let_star_scope() {
+        // Each binding sees the ones before it and the outer scope is
+        // back once the body is done.
+        let source = r#"
+(let x 1)
+(let y (let* ((z x) (x 10) (w (add x z))) w))
+(cons x y)
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::Pair(Box::new(Expr::Integer(1)), Box::new(Expr::Integer(11)))
+        );
+
+        let program = crate::reader::parse("(let* ((x 1) (y x)) y) y").unwrap();
+        assert_eq!(
+            crate::compiler::eval_program(&program),
+            Err(LustError::UnboundSymbol("y".to_string()))
+        );
+    }
+
+    #[test]
+    fn letrec() {
+        let source = r#"
+(letrec ((even? (fn (n) (if (eq n 0) #t (odd? (sub n 1)))))
+         (odd? (fn (n) (if (eq n 0) #f (even? (sub n 1))))))
+  (cons (even? 10) (odd? 7)))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::Pair(Box::new(Expr::Bool(true)), Box::new(Expr::Bool(true)))
+        );
+    }
+
+    #[test]
+    fn letrec_scope() {
+        let program = crate::reader::parse("(letrec ((x 1)) x) x").unwrap();
+        assert_eq!(
+            crate::compiler::eval_program(&program),
+            Err(LustError::UnboundSymbol("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn named_let() {
+        let source = r#"
+(let loop ((i 1) (sum 0))
+  (if (gt i 100)
+      sum
+      (loop (add1 i) (add sum i))))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(5050));
+        assert_eq!(
+            roundtrip_string("(let loop () 1)").unwrap(),
+            Expr::Integer(1)
+        );
+    }
+
+    #[test]
+    fn named_let_loops() {
+        // Self calls in tail position jump back to the start of the
+        // loop so a long loop doesn't grow the stack. The body can
+        // see variables from outside of it.
+        let source = r#"
+(let step 2)
+(let loop (i 0 count 0)
+  (if (eq i 2000000)
+      count
+      (loop (add i step) (add1 count))))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(1000000));
+    }
+
+    #[test]
+    fn named_let_scope() {
+        // The values of the bindings are evaluated outside of the
+        // loop so they see the outer loop.
+        let source = r#"
+(let loop (fn (x) (mul x 10)))
+(let loop ((i (loop 1))) (if (gt i 12) i (loop (add1 i))))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(13));
+
+        let program = crate::reader::parse("(let loop ((i 0)) i) (loop 1)").unwrap();
+        assert_eq!(
+            crate::compiler::eval_program(&program).map_err(LustError::without_location),
+            Err(LustError::UnboundFunction("loop".to_string()))
+        );
+    }
+
+    #[test]
+    fn define() {
+        let source = r#"
+(define x 10)
+(define (add-x n) (add n x))
+(define (fact n) (if (eq n 0) 1 (mul n (fact (sub n 1)))))
+(define (ten) 10)
+(cons (add-x (ten)) (fact 5))
+"#;
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(Expr::Integer(20), Expr::Integer(120))
+        )
+    }
+
+    #[test]
+    fn define_forward_references() {
+        let source = r#"
+(define (even? n) (if (eq? n 0) #t (odd? (sub1 n))))
+(define (odd? n) (if (eq? n 0) #f (even? (sub1 n))))
+(list (even? 10) (odd? 7) (even? 3))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(#t #t #f)").unwrap()
+        );
+
+        // Values can be defined after the functions that use them
+        // so long as the functions aren't called before then.
+        let source = r#"
+(define (area r) (mul pi (mul r r)))
+(define pi 3)
+(define (show) (list (area 2) (lookup)))
+(define (lookup) 'found)
+(show)
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(12 found)").unwrap()
+        );
+    }
+
+    #[test]
+    fn define_set() {
+        let source = r#"
+(define count 0)
+(define (bump) (set! count (add1 count)))
+(bump)
+(bump)
+count
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(2))
+    }
+
+    #[test]
+    fn internal_defines() {
+        let source = r#"
+(define (sum-squares a b)
+  (define (square x) (mul x x))
+  (add (square a) (square b)))
+(sum-squares 3 4)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(25));
+
+        // Definitions can refer to each other and are local to the
+        // body they are in.
+        let source = r#"
+(define (parity n)
+  (define (even? n) (if (eq? n 0) #t (odd? (sub1 n))))
+  (define (odd? n) (if (eq? n 0) #f (even? (sub1 n))))
+  (define answer (if (even? n) 'even 'odd))
+  answer)
+(define answer 'outer)
+(list (parity 10) (parity 7) answer)
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(even odd outer)").unwrap()
+        );
+
+        // They work at the start of let bodies and in lambdas that
+        // close over them too.
+        let source = r#"
+(let ((base 10))
+  (define (offset n) (add n base))
+  (define shifted (map offset '(1 2)))
+  (cons base shifted))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(10 11 12)").unwrap()
+        );
+        let source = r#"
+(define (counter)
+  (define count 0)
+  (fn () (set count (add1 count)) count))
+(let c (counter))
+(c)
+(c)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(2));
+    }
+
+    #[test]
+    fn define_errors() {
+        let program = crate::reader::parse("(fn () (define x 1))").unwrap();
+        assert_eq!(
+            crate::compiler::eval_program(&program).map_err(LustError::without_location),
+            Err(LustError::Compile(
+                "expected an expression after the definitions in a body".to_string()
+            ))
+        );
+        let program = crate::reader::parse("(fn () (add1 1) (define x 1) x)").unwrap();
+        assert_eq!(
+            crate::compiler::eval_program(&program).map_err(LustError::without_location),
+            Err(LustError::Compile(
+                "define is only allowed at the top level and at the start of a body".to_string()
+            ))
+        );
+        let program = crate::reader::parse("(define x 1 2)").unwrap();
+        assert_eq!(
+            crate::compiler::eval_program(&program).map_err(LustError::without_location),
+            Err(LustError::Compile(
+                "malformed define expression: (define x ...)".to_string()
+            ))
+        );
+        let program = crate::reader::parse("(y) (define (y) 1)").unwrap();
+        assert_eq!(
+            crate::compiler::eval_program(&program).map_err(LustError::without_location),
+            Err(LustError::UnboundFunction("y".to_string()))
+        );
+    }
+
+    #[test]
+    fn set_unbound() {
+        let program = crate::reader::parse("(set! x 2)").unwrap();
+        assert_eq!(
+            crate::compiler::eval_program(&program).map_err(LustError::without_location),
+            Err(LustError::UnboundSymbol("x".to_string()))
+        );
+    }
+
     /// Let expressions shouldn't redefine variables. There are
     /// variable uniquifying passes that should happen earlier in the
     /// compiler that make let expressions shadow variables instead of
@@ -322,12 +789,12 @@ mod tests {
     #[should_panic]
     fn var_redef() {
         let mut ast = [
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("twice".to_string()),
                 Expr::Integer(10),
             ]),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("let".to_string()),
                 Expr::Symbol("twice".to_string()),
                 Expr::Integer(10),
@@ -3,7 +3,7 @@
 use crate::reader;
 
 /// A location in source code. Stores in the form [start, end)
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Location {
     /// The (line, column) index of the first character in the token.
     pub start: reader::Location,
@@ -12,6 +12,13 @@ pub struct Location {
     pub end: reader::Location,
 }
 
+/// Where in the source a list was read from. Lists that the compiler
+/// makes up along the way have no span. Spans are ignored when
+/// comparing expressions so that a list read from source is equal to
+/// the same list built by hand.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Span(pub Option<Location>);
+
 impl Location {
     pub(crate) fn union(start: &Self, end: &Self) -> Self {
         Self {
@@ -20,3 +27,9 @@ impl Location {
         }
     }
 }
+
+impl PartialEq for Span {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
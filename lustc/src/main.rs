@@ -1,4 +1,7 @@
+use std::io;
+
 use clap::{App, Arg};
+use lustc::repl;
 use lustc::timer;
 
 fn main() {
@@ -11,9 +14,9 @@ fn main() {
             .about("Compiles and runs lust programs.")
             .arg(
                 Arg::with_name("file")
-                    .required(true)
+                    .required(false)
                     .index(1)
-                    .help("the file to run"),
+                    .help("the file to run, starts a repl if not provided"),
             )
             .arg(
                 Arg::with_name("timeit")
@@ -26,11 +29,18 @@ fn main() {
             .get_matches()
     };
 
-    let file = cli_opts.value_of("file").unwrap();
-
     timer::init(cli_opts.is_present("timeit"));
 
-    if let Err(s) = lustc::roundtrip_file(file) {
-        eprintln!("error: {}", s)
+    match cli_opts.value_of("file") {
+        Some(file) => {
+            if let Err(s) = lustc::roundtrip_file(file) {
+                eprintln!("error: {}", s)
+            }
+        }
+        None => {
+            if let Err(e) = repl::repl(io::stdin().lock(), io::stdout()) {
+                eprintln!("error: {}", e)
+            }
+        }
     }
 }
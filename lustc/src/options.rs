@@ -0,0 +1,147 @@
+//! Which optimizations the compiler runs and how the code it emits
+//! behaves. Every JIT has a set of options that every program
+//! compiled into it uses. Turning passes off one at a time makes it
+//! possible to see what each of them does to a program.
+
+/// Controls the passes that run when a program is compiled and the
+/// checks that the compiled code makes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompileOptions {
+    /// Replace primitive calls on literals with their results. See
+    /// fold.rs.
+    pub fold_constants: bool,
+
+    /// Calls to functions that don't call anything but primitives and
+    /// whose bodies have at most this many expressions in them are
+    /// replaced with the body. Zero turns inlining off.
+    pub inline_threshold: usize,
+
+    /// Don't compile functions that can never be called.
+    pub remove_unused_functions: bool,
+
+    /// Compile self tail calls into jumps back to the top of the
+    /// function so that tail recursion doesn't grow the stack.
+    pub self_tail_calls: bool,
+
+    /// If set tail calls to other procedures don't grow the stack
+    /// either. Calls are slower. See trampoline.rs.
+    pub trampoline: bool,
+
+    /// If set integer arithmetic that overflows is a runtime error
+    /// instead of wrapping around.
+    pub checked_arithmetic: bool,
+
+    /// Calling a procedure with this many calls already nested raises
+    /// a stack overflow error instead of running out of stack. Zero
+    /// turns the limit off.
+    pub stack_limit: usize,
+
+    /// If set runtime errors that would otherwise print a message and
+    /// exit the process, like a type mismatch, are raised like (error
+    /// ...) so that the program returns a LustError::Fatal instead.
+    pub raise_fatal_errors: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            fold_constants: true,
+            inline_threshold: crate::inline::DEFAULT_THRESHOLD,
+            remove_unused_functions: true,
+            self_tail_calls: true,
+            trampoline: false,
+            checked_arithmetic: false,
+            stack_limit: crate::depth::DEFAULT_STACK_LIMIT,
+            raise_fatal_errors: false,
+        }
+    }
+}
+
+impl CompileOptions {
+    /// Options with every optimization pass turned off. The checks
+    /// that compiled code makes are the same as the default.
+    pub fn unoptimized() -> Self {
+        Self {
+            fold_constants: false,
+            inline_threshold: 0,
+            remove_unused_functions: false,
+            self_tail_calls: false,
+            ..Self::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{compile_to_ir_with_options, roundtrip_program_with_options};
+    use crate::{parse_string, Expr};
+
+    /// Gets the IR of the lust_entry function from compiling SOURCE
+    /// with OPTIONS.
+    fn entry_ir(source: &str, options: CompileOptions) -> String {
+        let ir = compile_to_ir_with_options(&parse_string(source).unwrap(), options).unwrap();
+        let start = ir.find("; lust_entry\n").unwrap();
+        ir[start..].to_string()
+    }
+
+    #[test]
+    fn fold_constants_off() {
+        let source = "(add 1 2)";
+        let folded = entry_ir(source, CompileOptions::default());
+        assert!(!folded.contains("iadd"), "{}", folded);
+        let options = CompileOptions {
+            fold_constants: false,
+            ..Default::default()
+        };
+        let unfolded = entry_ir(source, options);
+        assert!(unfolded.contains("iadd"), "{}", unfolded);
+        assert_eq!(
+            roundtrip_program_with_options(&parse_string(source).unwrap(), options),
+            Ok(Expr::Integer(3))
+        );
+    }
+
+    #[test]
+    fn passes_off() {
+        let source = r#"
+(let unused (fn (x) (add x 1)))
+(let small (fn (x) (add x 1)))
+(let count (fn (n) (if (eq? n 0) 'done (count (sub1 n)))))
+(list (small 1) (count 10) (mul 2 3))
+"#;
+        let program = parse_string(source).unwrap();
+        let expected = roundtrip_program_with_options(&program, CompileOptions::default());
+        assert_eq!(
+            roundtrip_program_with_options(&program, CompileOptions::unoptimized()),
+            expected
+        );
+
+        let ir = compile_to_ir_with_options(&program, CompileOptions::default()).unwrap();
+        let unoptimized =
+            compile_to_ir_with_options(&program, CompileOptions::unoptimized()).unwrap();
+        // Only the recursive function is left once the small one is
+        // inlined and the unused ones are removed.
+        let functions = |ir: &str| ir.lines().filter(|l| l.starts_with("; __anon_fn_")).count();
+        assert_eq!(functions(&ir), 1, "{}", ir);
+        assert_eq!(functions(&unoptimized), 3, "{}", unoptimized);
+    }
+
+    #[test]
+    fn self_tail_calls_off() {
+        let source = "(let count (fn (n) (if (eq? n 0) 'done (count (sub1 n))))) (count 100000)";
+        let program = parse_string(source).unwrap();
+        assert_eq!(
+            roundtrip_program_with_options(&program, CompileOptions::default()),
+            Ok(Expr::Symbol("done".to_string()))
+        );
+        let options = CompileOptions {
+            self_tail_calls: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            roundtrip_program_with_options(&program, options),
+            Err("stack overflow: more than 10000 nested calls".to_string())
+        );
+    }
+}
@@ -31,8 +31,14 @@ pub(crate) struct Parser<'a> {
 #[derive(Debug, PartialEq, Clone)]
 pub enum ExprVal {
     Number(i64),
+    Float(f64),
+    Bool(bool),
+    Char(char),
     String(String),
     List(Vec<Expr>),
+    /// A list whose last cdr is the second element instead of nil,
+    /// written (a b . c).
+    DottedList(Vec<Expr>, Box<Expr>),
     Id(String),
 }
 
@@ -115,13 +121,22 @@ impl<'a> Parser<'a> {
     fn parse_list(&mut self, oparen: Token) -> ParseResult {
         let mut res = ParseResult::new();
         let mut v = Vec::new();
+        let mut tail = None;
+        let mut end = oparen.loc;
         loop {
             match self.tokbuffer.peek_token() {
                 Some((tok, buffer)) => match tok.ttype {
                     TokenType::Cparen => {
-                        buffer.advance();
+                        end = buffer.advance().loc;
                         break;
                     }
+                    TokenType::Dot => {
+                        let dot = buffer.advance();
+                        let mut pr = self.parse_dotted_tail(&dot, v.is_empty());
+                        res.errors.append(&mut pr.errors);
+                        tail = pr.expr;
+                        continue;
+                    }
                     _ => (),
                 },
                 None => {
@@ -136,15 +151,44 @@ impl<'a> Parser<'a> {
             res.errors.append(&mut pr.errors);
         }
 
-        let start = &oparen.loc;
-        let end = match &v.first() {
-            Some(ref e) => &e.loc,
-            None => start,
+        let val = match tail {
+            Some(tail) => ExprVal::DottedList(v, Box::new(tail)),
+            None => ExprVal::List(v),
         };
-        res.expr = Some(Expr::at_loc(Location::union(start, end), ExprVal::List(v)));
+        res.expr = Some(Expr::at_loc(Location::union(&oparen.loc, &end), val));
+        res
+    }
+
+    /// Parses what comes after the . in a dotted list. Exactly one
+    /// expression has to follow it before the list is closed and at
+    /// least one has to come before it, which EMPTY says there
+    /// wasn't.
+    fn parse_dotted_tail(&mut self, dot: &Token, empty: bool) -> ParseResult {
+        let mut res = if self.closes_list() {
+            ParseResult::new()
+        } else {
+            self.parse_expr()
+        };
+        if empty || res.expr.is_none() || !self.closes_list() {
+            res.merge_err(Error::on_tok("malformed dotted list", dot));
+        }
         res
     }
 
+    /// Determines if the next token is a closing paren.
+    fn closes_list(&mut self) -> bool {
+        matches!(
+            self.tokbuffer.peek_token(),
+            Some((
+                Token {
+                    ttype: TokenType::Cparen,
+                    ..
+                },
+                _
+            ))
+        )
+    }
+
     pub(crate) fn has_more(&self) -> bool {
         self.tokbuffer.has_next()
     }
@@ -156,7 +200,7 @@ impl<'a> Parser<'a> {
             Some(e) => ExprVal::List(vec![
                 Expr {
                     val: id,
-                    loc: startloc.clone(),
+                    loc: startloc,
                 },
                 e,
             ]),
@@ -190,6 +234,21 @@ impl<'a> Parser<'a> {
                     loc: buffer.advance().loc,
                 }),
 
+                TokenType::Float(f) => ParseResult::from_expr(Expr {
+                    val: ExprVal::Float(f),
+                    loc: buffer.advance().loc,
+                }),
+
+                TokenType::Bool(b) => ParseResult::from_expr(Expr {
+                    val: ExprVal::Bool(b),
+                    loc: buffer.advance().loc,
+                }),
+
+                TokenType::Char(c) => ParseResult::from_expr(Expr {
+                    val: ExprVal::Char(c),
+                    loc: buffer.advance().loc,
+                }),
+
                 TokenType::Id(s) => ParseResult::from_expr(Expr {
                     val: ExprVal::Id(s),
                     loc: buffer.advance().loc,
@@ -204,10 +263,6 @@ impl<'a> Parser<'a> {
                     let loc = buffer.advance().loc;
                     self.expand("quote", loc)
                 }
-                TokenType::Negate => {
-                    let loc = buffer.advance().loc;
-                    self.expand("negate", loc)
-                }
                 TokenType::Quaziquote => {
                     let loc = buffer.advance().loc;
                     self.expand("quaziquote", loc)
@@ -216,10 +271,48 @@ impl<'a> Parser<'a> {
                     let loc = buffer.advance().loc;
                     self.expand("comma", loc)
                 }
-                TokenType::Unrecognized(s, _) => ParseResult::from_err(Error::on_tok(
-                    &format!("malformed token: {}", s),
+                TokenType::Dot => ParseResult::from_err(Error::on_tok(
+                    "unexpected . outside of a list",
                     &buffer.advance(),
                 )),
+                TokenType::DatumComment => {
+                    buffer.advance();
+                    // The commented out expression is parsed so that
+                    // we know where it ends and then thrown away.
+                    let mut res = self.parse_expr();
+                    res.expr = None;
+                    match self.tokbuffer.peek_token() {
+                        // Nothing follows the comment in the list or
+                        // program that it is in.
+                        Some((
+                            Token {
+                                ttype: TokenType::Cparen,
+                                ..
+                            },
+                            _,
+                        ))
+                        | None => res,
+                        Some(_) => {
+                            let mut next = self.parse_expr();
+                            res.errors.append(&mut next.errors);
+                            res.expr = next.expr;
+                            res
+                        }
+                    }
+                }
+                TokenType::UnterminatedComment(_) => ParseResult::from_err(Error::on_tok(
+                    "unterminated block comment",
+                    &buffer.advance(),
+                )),
+                TokenType::Unrecognized(s, expected) => {
+                    let what = match *expected {
+                        TokenType::Number(_) => crate::tokenizer::parse_integer(&s)
+                            .err()
+                            .unwrap_or_else(|| format!("malformed token: {}", s)),
+                        _ => format!("malformed token: {}", s),
+                    };
+                    ParseResult::from_err(Error::on_tok(&what, &buffer.advance()))
+                }
             },
             None => {
                 let mut res = ParseResult::new();
@@ -255,7 +348,7 @@ mod tests {
         let mut parser = Parser::new(&src);
         let res = parser.parse_expr();
         if let Some(e) = res.expr {
-            assert_eq!(ExprVal::Number(1), e.val);
+            assert_eq!(ExprVal::Float(1.5), e.val);
         } else {
             assert!(false);
         }
@@ -304,4 +397,99 @@ mod tests {
         // tracks a better way to handle this.
         assert_eq!(res.errors[0].what, "unbalanced parenthesis".to_string());
     }
+
+    fn parse_all(src: &str) -> (Vec<ExprVal>, Vec<Error>) {
+        let mut parser = Parser::new(src);
+        let mut program = Program::new();
+        while parser.has_more() {
+            program.merge_result(parser.parse_expr());
+        }
+        (
+            program.program.into_iter().map(|e| e.val).collect(),
+            program.errors,
+        )
+    }
+
+    #[test]
+    fn comments() {
+        let (exprs, errors) = parse_all("; a\n1 #| 2 #| 3 |# |# 4 ; 5");
+        assert!(errors.is_empty());
+        assert_eq!(exprs, vec![ExprVal::Number(1), ExprVal::Number(4)]);
+
+        let (_, errors) = parse_all("1 #| 2");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].what, "unterminated block comment");
+    }
+
+    #[test]
+    fn dotted_lists() {
+        let (exprs, errors) = parse_all("(a b . c)");
+        assert!(errors.is_empty());
+        match &exprs[..] {
+            [ExprVal::DottedList(v, tail)] => {
+                assert_eq!(v.len(), 2);
+                assert_eq!(tail.val, ExprVal::Id("c".to_string()));
+            }
+            _ => panic!("expected a dotted list, got {:?}", exprs),
+        }
+
+        for src in &["(a . b c)", "(. a)", "(a .)", "(a . . b)"] {
+            let (_, errors) = parse_all(src);
+            assert!(
+                errors.iter().any(|e| e.what == "malformed dotted list"),
+                "{}",
+                src
+            );
+        }
+        let (_, errors) = parse_all("a . b");
+        assert_eq!(errors[0].what, "unexpected . outside of a list");
+
+        // Only a lone . is a dot.
+        let (exprs, errors) = parse_all("(a .b 1.5)");
+        assert!(errors.is_empty());
+        assert!(matches!(&exprs[..], [ExprVal::List(v)] if v.len() == 3));
+    }
+
+    #[test]
+    fn datum_comments() {
+        let id = |s: &str| ExprVal::Id(s.to_string());
+        let (exprs, errors) = parse_all("#;(a b) c #;d");
+        assert!(errors.is_empty());
+        assert_eq!(exprs, vec![id("c")]);
+
+        // Inside of a list, including at its end.
+        let (exprs, _) = parse_all("(a #;(b c) d #;e)");
+        match &exprs[..] {
+            [ExprVal::List(v)] => {
+                assert_eq!(
+                    v.iter().map(|e| e.val.clone()).collect::<Vec<_>>(),
+                    vec![id("a"), id("d")]
+                );
+            }
+            _ => panic!("expected a list, got {:?}", exprs),
+        }
+
+        // Each #; comments out its own expression.
+        let (exprs, _) = parse_all("#; #; a b c");
+        assert_eq!(exprs, vec![id("c")]);
+
+        let (exprs, _) = parse_all("'#;a b");
+        match &exprs[..] {
+            [ExprVal::List(v)] => assert_eq!(v[1].val, id("b")),
+            _ => panic!("expected a quote, got {:?}", exprs),
+        }
+    }
+
+    #[test]
+    fn integer_literal_errors() {
+        let res = Parser::new("(add 0xG 1)").parse_expr();
+        assert_eq!(res.errors.len(), 1);
+        assert_eq!(res.errors[0].what, "malformed integer literal: 0xG");
+
+        let res = Parser::new("2305843009213693952").parse_expr();
+        assert_eq!(
+            res.errors[0].what,
+            "integer literal out of range: 2305843009213693952"
+        );
+    }
 }
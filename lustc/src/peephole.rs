@@ -0,0 +1,117 @@
+//! Fixnums have a tag of zero so adding two of them doesn't need any
+//! untagging or retagging. What does repeat in a run of arithmetic is
+//! the tag checking. Every primitive checks that its arguments are
+//! numbers and then branches on whether they are fixnums or floats,
+//! even when an argument is the result of the arithmetic right before
+//! it. Comparisons make a tagged boolean which if then compares with
+//! #t to get back the condition that it started with.
+//!
+//! The context remembers what it knows about the values that it has
+//! emitted so those checks can be skipped. Only facts that hold for a
+//! value wherever it can be used are recorded, like the sum of two
+//! fixnums being a fixnum. Facts learned from a check aren't as the
+//! same value can also be used on paths where the check never ran.
+
+use cranelift::prelude::*;
+
+use crate::compiler::Context;
+
+/// What is known about the type of a value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Known {
+    Fixnum,
+    /// Either a fixnum or a float.
+    Number,
+}
+
+/// Records that VAL is always a value of type KNOWN. Returns VAL.
+pub(crate) fn record(val: Value, known: Known, ctx: &mut Context) -> Value {
+    ctx.known.insert(val, known);
+    val
+}
+
+pub(crate) fn is_fixnum(val: Value, ctx: &Context) -> bool {
+    ctx.known.get(&val) == Some(&Known::Fixnum)
+}
+
+pub(crate) fn is_number(val: Value, ctx: &Context) -> bool {
+    ctx.known.contains_key(&val)
+}
+
+/// Records that the boolean BOOL was made from CONDITION, a word that
+/// is one when it is true and zero otherwise.
+pub(crate) fn record_condition(bool: Value, condition: Value, ctx: &mut Context) {
+    ctx.conditions.insert(bool, condition);
+}
+
+/// Gets the condition that the boolean BOOL was made from if there is
+/// one.
+pub(crate) fn condition(bool: Value, ctx: &Context) -> Option<Value> {
+    ctx.conditions.get(&bool).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::compile_to_ir;
+    use crate::{parse_string, roundtrip_string, Expr};
+
+    /// Gets the IR of a function of X with BODY.
+    fn function_ir(body: &str) -> String {
+        // f is passed to apply so that it isn't inlined.
+        let source = format!("(let f (fn (x) {})) (apply f (list 1))", body);
+        let ir = compile_to_ir(&parse_string(&source).unwrap()).unwrap();
+        let start = ir.find("; __anon_fn_0\n").unwrap();
+        let f = &ir[start..];
+        f[..f.find("\n}").unwrap()].to_string()
+    }
+
+    /// The number of instructions in the function of X with BODY.
+    fn instructions(body: &str) -> usize {
+        function_ir(body)
+            .lines()
+            .filter(|l| l.starts_with('['))
+            .count()
+    }
+
+    #[test]
+    fn nested_arithmetic() {
+        // Only x needs checking. Every other argument is a literal or
+        // the result of the addition before it. Without this each
+        // addition checks both of its arguments and the two extra
+        // additions take about twice as many instructions as the
+        // first.
+        let single = instructions("(add x 1)");
+        let nested = instructions("(add (add (add x 1) 2) 3)");
+        assert!(nested - single < single / 2, "{} {}", single, nested);
+
+        let source = r#"
+(let f (fn (x) (add (mul (add x 1) (sub x 2)) (add (mul x 3) 1))))
+(list (f 5) (f 0.5))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(34 0.25)").unwrap()
+        );
+    }
+
+    #[test]
+    fn comparison_conditions() {
+        // The if branches on the comparison instead of comparing the
+        // boolean it makes with #t.
+        let t = Expr::Bool(true).immediate_rep();
+        let ir = function_ir("(if (lt x 1) 1 2)");
+        assert!(!ir.contains(&format!(", {}\n", t)), "{}", ir);
+
+        let source = r#"
+(let i 0)
+(let n 0)
+(while (< i 10) (set n (add n i)) (set i (add1 i)))
+(let b (gt n 40))
+(list n (if (lt 1.5 n) 'big 'small) (if b 1 2))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(45 big 1)").unwrap()
+        );
+    }
+}
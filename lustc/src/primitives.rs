@@ -10,23 +10,35 @@ use std::collections::HashSet;
 
 use cranelift::frontend::FunctionBuilder;
 use cranelift::prelude::*;
-use cranelift_codegen::binemit::NullTrapSink;
 use cranelift_module::Module;
 
+use crate::chars;
 use crate::compiler::emit_expr;
 use crate::compiler::Context;
 use crate::compiler::JIT;
 use crate::conversions;
+use crate::errors::LustError;
 use crate::fatal;
 use crate::fatal::emit_check_arg_count;
+use crate::floats::{
+    emit_abs, emit_arithmetic, emit_comparison, emit_exact_to_inexact, emit_inexact_to_exact,
+    emit_min_max, emit_round, emit_sqrt, ArithOp, CmpOp, RoundOp,
+};
+use crate::hashtables;
 use crate::heap::emit_alloc;
+use crate::lists;
+use crate::peephole::{self, Known};
+use crate::procedures;
 use crate::procedures::LustFn;
+use crate::strings;
+use crate::values;
+use crate::vectors;
 use crate::Expr;
 use crate::PreorderStatus;
 
 impl Expr {
     pub fn is_primcall(&self) -> Option<(&str, &[Expr])> {
-        if let Self::List(v) = self {
+        if let Self::List(v, _) = self {
             if let Some(Expr::Symbol(s)) = v.first() {
                 if string_is_primitive(s) {
                     return Some((s, &v[1..]));
@@ -42,9 +54,9 @@ pub(crate) fn emit_primitive<F>(
     arity: usize,
     jit: &mut JIT,
     mut body_builder: F,
-) -> Result<LustFn, String>
+) -> Result<LustFn, LustError>
 where
-    F: FnMut(&mut Context) -> Result<Value, String>,
+    F: FnMut(&mut Context) -> Result<Value, LustError>,
 {
     let word = jit.module.target_config().pointer_type();
 
@@ -60,6 +72,7 @@ where
 
     let builder = FunctionBuilder::new(&mut jit.context.func, &mut jit.builder_context);
 
+    let options = jit.options;
     let mut ctx = Context::new(
         builder,
         &mut jit.module,
@@ -68,6 +81,7 @@ where
         HashMap::new(),
         Vec::new(),
     );
+    ctx.options = options;
 
     let entry_block = ctx.builder.create_block();
 
@@ -82,20 +96,7 @@ where
     ctx.builder.seal_all_blocks();
     ctx.builder.finalize();
 
-    let id = jit
-        .module
-        .declare_function(
-            name,
-            cranelift_module::Linkage::Export,
-            &jit.context.func.signature,
-        )
-        .map_err(|e| e.to_string())?;
-
-    jit.module
-        .define_function(id, &mut jit.context, &mut NullTrapSink {})
-        .map_err(|e| e.to_string())?;
-
-    jit.module.clear_context(&mut jit.context);
+    jit.define_function(name)?;
 
     Ok(LustFn {
         name: name.to_string(),
@@ -108,22 +109,27 @@ where
 
 /// Collects all of the primitive functions that are used in higher
 /// order contexts.
-pub(crate) fn collect_higher_order_primitives(program: &[Expr]) -> Result<HashSet<String>, String> {
+pub(crate) fn collect_higher_order_primitives(
+    program: &[Expr],
+) -> Result<HashSet<String>, LustError> {
     let mut res = HashSet::new();
 
     for e in program {
         e.preorder_traverse_res(&mut |e| {
-            if let Expr::List(v) = e {
+            if e.is_quote().is_some() {
+                return Ok(PreorderStatus::Skip);
+            }
+            if let Expr::List(v, _) = e {
                 for ex in &v[1..] {
                     if let Expr::Symbol(s) = ex {
                         if string_is_primitive(s) {
                             res.insert(s.clone());
                         } else if string_is_builtin(s) {
                             // Functions like let and set and if can't be used in higher order contexts.
-                            return Err(format!(
+                            return Err(LustError::Compile(format!(
                                 "builtin function ({}) can not be used in a higher order context",
                                 s
-                            ));
+                            )));
                         }
                     }
                 }
@@ -155,59 +161,138 @@ fn get_primitive_args(ctx: &mut Context, block: Block, arity: usize) -> Vec<Valu
 pub(crate) fn emit_primitives(
     jit: &mut JIT,
     higher_order_primitives: HashSet<String>,
-) -> Result<Vec<LustFn>, String> {
+) -> Result<Vec<LustFn>, LustError> {
     let _t = crate::timer::timeit("emit primitives");
 
     let mut res = Vec::new();
 
     let word = jit.module.target_config().pointer_type();
 
-    if higher_order_primitives.contains("add1") {
-        res.push(emit_primitive("add1", 1, jit, |ctx| {
+    for &(name, delta) in &[("add1", 1), ("sub1", -1)] {
+        if higher_order_primitives.contains(name) {
+            res.push(emit_primitive(name, 1, jit, |ctx| {
+                let block = ctx.builder.current_block().unwrap();
+                let args = ctx.builder.block_params(block);
+                emit_check_arg_count(1, args[1], ctx, false)?;
+                // Need to reassign args because we mutably borrowed
+                // context above.
+                let args = get_primitive_args(ctx, block, 1);
+                emit_add_fixnum(args[0], delta, ctx)
+            })?);
+        }
+    }
+
+    for &name in &["print", "display"] {
+        if higher_order_primitives.contains(name) {
+            res.push(emit_primitive(name, 1, jit, |ctx| {
+                let block = ctx.builder.current_block().unwrap();
+                let args = ctx.builder.block_params(block);
+                emit_check_arg_count(1, args[1], ctx, false)?;
+                let args = get_primitive_args(ctx, block, 1);
+                emit_runtime_call("print_lustc_word", &args, ctx)
+            })?);
+        }
+    }
+
+    if higher_order_primitives.contains("write") {
+        res.push(emit_primitive("write", 1, jit, |ctx| {
             let block = ctx.builder.current_block().unwrap();
             let args = ctx.builder.block_params(block);
             emit_check_arg_count(1, args[1], ctx, false)?;
-            // Need to reassign args because we mutably borrowed context
-            // above.
             let args = get_primitive_args(ctx, block, 1);
-            let accum = args[0];
+            emit_runtime_call("write_lustc_word", &args, ctx)
+        })?);
+    }
 
-            fatal::emit_check_int(accum, ctx)?;
+    if higher_order_primitives.contains("newline") {
+        res.push(emit_primitive("newline", 0, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(0, args[1], ctx, false)?;
+            emit_newline(ctx)
+        })?);
+    }
 
-            Ok(ctx
+    if higher_order_primitives.contains("gensym") {
+        res.push(emit_primitive("gensym", 0, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(0, args[1], ctx, false)?;
+            emit_runtime_call("gensym_lustc_word", &[], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("read") {
+        res.push(emit_primitive("read", 0, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(0, args[1], ctx, false)?;
+            let res = emit_runtime_call("read_lustc_word", &[], ctx)?;
+            fatal::emit_propagate_raise(res, ctx);
+            Ok(res)
+        })?);
+    }
+
+    if higher_order_primitives.contains("throw") {
+        res.push(emit_primitive("throw", 2, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(2, args[1], ctx, false)?;
+            let args = get_primitive_args(ctx, block, 2);
+            let res = emit_runtime_call("throw_lustc_word", &args, ctx)?;
+            fatal::emit_propagate_raise(res, ctx);
+            Ok(res)
+        })?);
+    }
+
+    if higher_order_primitives.contains("eof-object") {
+        res.push(emit_primitive("eof-object", 0, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(0, args[1], ctx, false)?;
+            Ok(ctx.builder.ins().iconst(ctx.word, conversions::EOF_VALUE))
+        })?);
+    }
+
+    if higher_order_primitives.contains("eof-object?") {
+        res.push(emit_primitive("eof-object?", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(1, args[1], ctx, false)?;
+            let args = get_primitive_args(ctx, block, 1);
+
+            let accum = ctx
                 .builder
                 .ins()
-                .iadd_imm(accum, Expr::Integer(1).immediate_rep()))
+                .icmp_imm(IntCC::Equal, args[0], conversions::EOF_VALUE);
+            let accum = ctx.builder.ins().bint(ctx.word, accum);
+            Ok(emit_word_to_bool(accum, &mut ctx.builder))
         })?);
     }
 
-    if higher_order_primitives.contains("print") {
-        res.push(emit_primitive("print", 1, jit, |ctx| {
+    if higher_order_primitives.contains("symbol->string") {
+        res.push(emit_primitive("symbol->string", 1, jit, |ctx| {
             let block = ctx.builder.current_block().unwrap();
             let args = ctx.builder.block_params(block);
             emit_check_arg_count(1, args[1], ctx, false)?;
             let args = get_primitive_args(ctx, block, 1);
-            let accum = args[0];
 
-            let mut sig = ctx.module.make_signature();
-            sig.params.push(AbiParam::new(ctx.word));
-            sig.returns.push(AbiParam::new(ctx.word));
+            fatal::emit_check_symbol(args[0], ctx)?;
 
-            let callee = ctx
-                .module
-                .declare_function("print_lustc_word", cranelift_module::Linkage::Import, &sig)
-                .map_err(|e| e.to_string())?;
-
-            let local_callee = ctx
-                .module
-                .declare_func_in_func(callee, &mut ctx.builder.func);
+            emit_runtime_call("symbol_to_string_lustc_word", &args, ctx)
+        })?);
+    }
 
-            let args = vec![accum];
+    if higher_order_primitives.contains("string->symbol") {
+        res.push(emit_primitive("string->symbol", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(1, args[1], ctx, false)?;
+            let args = get_primitive_args(ctx, block, 1);
 
-            let call = ctx.builder.ins().call(local_callee, &args);
-            let res = ctx.builder.inst_results(call)[0];
+            fatal::emit_check_string(args[0], ctx)?;
 
-            Ok(res)
+            emit_runtime_call("string_to_symbol_lustc_word", &args, ctx)
         })?);
     }
 
@@ -217,31 +302,7 @@ pub(crate) fn emit_primitives(
             let args = ctx.builder.block_params(block);
             emit_check_arg_count(1, args[1], ctx, false)?;
             let args = get_primitive_args(ctx, block, 1);
-            let accum = args[0];
-
-            let mut sig = ctx.module.make_signature();
-            sig.params.push(AbiParam::new(ctx.word));
-            sig.returns.push(AbiParam::new(ctx.word));
-
-            let callee = ctx
-                .module
-                .declare_function(
-                    "println_lustc_word",
-                    cranelift_module::Linkage::Import,
-                    &sig,
-                )
-                .map_err(|e| e.to_string())?;
-
-            let local_callee = ctx
-                .module
-                .declare_func_in_func(callee, &mut ctx.builder.func);
-
-            let args = vec![accum];
-
-            let call = ctx.builder.ins().call(local_callee, &args);
-            let res = ctx.builder.inst_results(call)[0];
-
-            Ok(res)
+            emit_runtime_call("println_lustc_word", &args, ctx)
         })?);
     }
 
@@ -254,6 +315,7 @@ pub(crate) fn emit_primitives(
             let accum = args[0];
 
             fatal::emit_check_int(accum, ctx)?;
+            fatal::emit_check_char_range(accum, ctx)?;
 
             let accum = ctx.builder.ins().ishl_imm(accum, 6);
             let accum = ctx.builder.ins().bor_imm(accum, conversions::CHAR_TAG);
@@ -312,6 +374,32 @@ pub(crate) fn emit_primitives(
         })?);
     }
 
+    for &(name, op) in &[("positive?", CmpOp::Gt), ("negative?", CmpOp::Lt)] {
+        if higher_order_primitives.contains(name) {
+            res.push(emit_primitive(name, 1, jit, |ctx| {
+                let block = ctx.builder.current_block().unwrap();
+                let args = ctx.builder.block_params(block);
+                emit_check_arg_count(1, args[1], ctx, false)?;
+                let args = get_primitive_args(ctx, block, 1);
+
+                let accum = emit_sign_comparison(op, args[0], ctx)?;
+                Ok(emit_word_to_bool(accum, &mut ctx.builder))
+            })?);
+        }
+    }
+
+    if higher_order_primitives.contains("boolean=?") {
+        res.push(emit_primitive("boolean=?", 2, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(2, args[1], ctx, false)?;
+            let args = get_primitive_args(ctx, block, 2);
+
+            let accum = emit_boolean_eq(args[0], args[1], ctx)?;
+            Ok(emit_word_to_bool(accum, &mut ctx.builder))
+        })?);
+    }
+
     if higher_order_primitives.contains("not") {
         res.push(emit_primitive("not", 1, jit, |ctx| {
             let block = ctx.builder.current_block().unwrap();
@@ -320,31 +408,90 @@ pub(crate) fn emit_primitives(
             let args = get_primitive_args(ctx, block, 1);
             let accum = args[0];
 
-            fatal::emit_check_bool(accum, ctx)?;
+            Ok(emit_not(accum, ctx))
+        })?);
+    }
 
-            // To get the not of a boolean, subtract one from it and
-            // then take the absolute value.
-            let accum = ctx.builder.ins().sshr_imm(accum, conversions::BOOL_SHIFT);
-            let accum = ctx.builder.ins().icmp_imm(IntCC::Equal, accum, 0);
-            let accum = ctx.builder.ins().bint(ctx.word, accum);
+    // Every integer is a fixnum so fixnum? is another name for
+    // integer?.
+    for &name in &["integer?", "fixnum?"] {
+        if higher_order_primitives.contains(name) {
+            res.push(emit_primitive(name, 1, jit, |ctx| {
+                let block = ctx.builder.current_block().unwrap();
+                let args = ctx.builder.block_params(block);
+                emit_check_arg_count(1, args[1], ctx, false)?;
+                let args = get_primitive_args(ctx, block, 1);
+                let accum = args[0];
+
+                let accum = ctx.builder.ins().band_imm(accum, conversions::FIXNUM_MASK);
+                let accum =
+                    ctx.builder
+                        .ins()
+                        .icmp_imm(IntCC::Equal, accum, conversions::FIXNUM_TAG);
+                let accum = ctx.builder.ins().bint(word, accum);
+                Ok(emit_word_to_bool(accum, &mut ctx.builder))
+            })?);
+        }
+    }
+
+    for &name in &["char-upcase", "char-downcase"] {
+        if higher_order_primitives.contains(name) {
+            res.push(emit_primitive(name, 1, jit, |ctx| {
+                let block = ctx.builder.current_block().unwrap();
+                let args = ctx.builder.block_params(block);
+                emit_check_arg_count(1, args[1], ctx, false)?;
+                let args = get_primitive_args(ctx, block, 1);
+
+                chars::emit_char_case(name, args[0], ctx)
+            })?);
+        }
+    }
+
+    for &name in &["char-alphabetic?", "char-numeric?", "char-whitespace?"] {
+        if higher_order_primitives.contains(name) {
+            res.push(emit_primitive(name, 1, jit, |ctx| {
+                let block = ctx.builder.current_block().unwrap();
+                let args = ctx.builder.block_params(block);
+                emit_check_arg_count(1, args[1], ctx, false)?;
+                let args = get_primitive_args(ctx, block, 1);
+
+                let accum = chars::emit_char_class(name, args[0], ctx)?;
+                Ok(emit_word_to_bool(accum, &mut ctx.builder))
+            })?);
+        }
+    }
+
+    if higher_order_primitives.contains("char?") {
+        res.push(emit_primitive("char?", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(1, args[1], ctx, false)?;
+            let args = get_primitive_args(ctx, block, 1);
+            let accum = args[0];
 
+            let accum = ctx.builder.ins().band_imm(accum, conversions::CHAR_MASK);
+            let accum = ctx
+                .builder
+                .ins()
+                .icmp_imm(IntCC::Equal, accum, conversions::CHAR_TAG);
+            let accum = ctx.builder.ins().bint(word, accum);
             Ok(emit_word_to_bool(accum, &mut ctx.builder))
         })?);
     }
 
-    if higher_order_primitives.contains("integer?") {
-        res.push(emit_primitive("integer?", 1, jit, |ctx| {
+    if higher_order_primitives.contains("symbol?") {
+        res.push(emit_primitive("symbol?", 1, jit, |ctx| {
             let block = ctx.builder.current_block().unwrap();
             let args = ctx.builder.block_params(block);
             emit_check_arg_count(1, args[1], ctx, false)?;
             let args = get_primitive_args(ctx, block, 1);
             let accum = args[0];
 
-            let accum = ctx.builder.ins().band_imm(accum, conversions::FIXNUM_MASK);
+            let accum = ctx.builder.ins().band_imm(accum, conversions::SYMBOL_MASK);
             let accum = ctx
                 .builder
                 .ins()
-                .icmp_imm(IntCC::Equal, accum, conversions::FIXNUM_TAG);
+                .icmp_imm(IntCC::Equal, accum, conversions::SYMBOL_TAG);
             let accum = ctx.builder.ins().bint(word, accum);
             Ok(emit_word_to_bool(accum, &mut ctx.builder))
         })?);
@@ -410,44 +557,76 @@ pub(crate) fn emit_primitives(
         })?);
     }
 
-    if higher_order_primitives.contains("add") {
-        res.push(emit_primitive("add", 2, jit, |ctx| {
+    if higher_order_primitives.contains("string?") {
+        res.push(emit_primitive("string?", 1, jit, |ctx| {
             let block = ctx.builder.current_block().unwrap();
             let args = ctx.builder.block_params(block);
-            emit_check_arg_count(2, args[1], ctx, false)?;
+            emit_check_arg_count(1, args[1], ctx, false)?;
+            let args = get_primitive_args(ctx, block, 1);
+            let accum = args[0];
 
-            let args = get_primitive_args(ctx, block, 2);
-            let left = args[0];
-            let right = args[1];
+            let accum = ctx
+                .builder
+                .ins()
+                .band_imm(accum, conversions::HEAP_TAG_MASK);
+            let accum = ctx
+                .builder
+                .ins()
+                .icmp_imm(IntCC::Equal, accum, conversions::STRING_TAG);
+            let accum = ctx.builder.ins().bint(word, accum);
+            Ok(emit_word_to_bool(accum, &mut ctx.builder))
+        })?);
+    }
+
+    if higher_order_primitives.contains("string->list") {
+        res.push(emit_primitive("string->list", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(1, args[1], ctx, false)?;
+            let args = get_primitive_args(ctx, block, 1);
 
-            fatal::emit_check_int(left, ctx)?;
-            fatal::emit_check_int(right, ctx)?;
+            fatal::emit_check_string(args[0], ctx)?;
 
-            Ok(ctx.builder.ins().iadd(left, right))
+            emit_runtime_call("string_to_list_lustc_word", &args, ctx)
         })?);
     }
 
-    if higher_order_primitives.contains("sub") {
-        res.push(emit_primitive("sub", 2, jit, |ctx| {
+    if higher_order_primitives.contains("string-length") {
+        res.push(emit_primitive("string-length", 1, jit, |ctx| {
             let block = ctx.builder.current_block().unwrap();
             let args = ctx.builder.block_params(block);
-            emit_check_arg_count(2, args[1], ctx, false)?;
+            emit_check_arg_count(1, args[1], ctx, false)?;
+            let args = get_primitive_args(ctx, block, 1);
+            strings::emit_string_length(args[0], ctx)
+        })?);
+    }
 
+    if higher_order_primitives.contains("string-ref") {
+        res.push(emit_primitive("string-ref", 2, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(2, args[1], ctx, false)?;
             let args = get_primitive_args(ctx, block, 2);
-            let left = args[0];
-            let right = args[1];
+            strings::emit_string_ref(args[0], args[1], ctx)
+        })?);
+    }
 
-            fatal::emit_check_int(left, ctx)?;
-            fatal::emit_check_int(right, ctx)?;
+    if higher_order_primitives.contains("string-append") {
+        res.push(emit_primitive("string-append", 2, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(2, args[1], ctx, false)?;
+            let args = get_primitive_args(ctx, block, 2);
 
-            let right = ctx.builder.ins().ineg(right);
+            fatal::emit_check_string(args[0], ctx)?;
+            fatal::emit_check_string(args[1], ctx)?;
 
-            Ok(ctx.builder.ins().iadd(left, right))
+            emit_runtime_call("string_append_lustc_word", &args, ctx)
         })?);
     }
 
-    if higher_order_primitives.contains("mul") {
-        res.push(emit_primitive("mul", 2, jit, |ctx| {
+    if higher_order_primitives.contains("add") {
+        res.push(emit_primitive("add", 2, jit, |ctx| {
             let block = ctx.builder.current_block().unwrap();
             let args = ctx.builder.block_params(block);
             emit_check_arg_count(2, args[1], ctx, false)?;
@@ -456,24 +635,12 @@ pub(crate) fn emit_primitives(
             let left = args[0];
             let right = args[1];
 
-            fatal::emit_check_int(left, ctx)?;
-            fatal::emit_check_int(right, ctx)?;
-
-            let accum = ctx.builder.ins().imul(left, right);
-
-            // At this point we've picked up an extra 2^2 so we need
-            // to right shift it out.
-            //
-            // NOTE: It is possible that it would be more reasonable
-            // to shift things out first. I'm worried here that this
-            // will cause integer overflows where we wouldn't normally
-            // expect them.
-            Ok(ctx.builder.ins().sshr_imm(accum, 2))
+            emit_arithmetic(ArithOp::Add, left, right, ctx)
         })?);
     }
 
-    if higher_order_primitives.contains("eq") {
-        res.push(emit_primitive("eq", 2, jit, |ctx| {
+    if higher_order_primitives.contains("sub") {
+        res.push(emit_primitive("sub", 2, jit, |ctx| {
             let block = ctx.builder.current_block().unwrap();
             let args = ctx.builder.block_params(block);
             emit_check_arg_count(2, args[1], ctx, false)?;
@@ -482,14 +649,12 @@ pub(crate) fn emit_primitives(
             let left = args[0];
             let right = args[1];
 
-            let accum = ctx.builder.ins().icmp(IntCC::Equal, left, right);
-            let accum = ctx.builder.ins().bint(word, accum);
-            Ok(emit_word_to_bool(accum, &mut ctx.builder))
+            emit_arithmetic(ArithOp::Sub, left, right, ctx)
         })?);
     }
 
-    if higher_order_primitives.contains("lt") {
-        res.push(emit_primitive("lt", 2, jit, |ctx| {
+    if higher_order_primitives.contains("mul") {
+        res.push(emit_primitive("mul", 2, jit, |ctx| {
             let block = ctx.builder.current_block().unwrap();
             let args = ctx.builder.block_params(block);
             emit_check_arg_count(2, args[1], ctx, false)?;
@@ -498,122 +663,634 @@ pub(crate) fn emit_primitives(
             let left = args[0];
             let right = args[1];
 
-            fatal::emit_check_int(left, ctx)?;
-            fatal::emit_check_int(right, ctx)?;
-
-            let accum = ctx.builder.ins().icmp(IntCC::SignedLessThan, left, right);
-            let accum = ctx.builder.ins().bint(word, accum);
-            Ok(emit_word_to_bool(accum, &mut ctx.builder))
+            emit_arithmetic(ArithOp::Mul, left, right, ctx)
         })?);
     }
 
-    if higher_order_primitives.contains("gt") {
-        res.push(emit_primitive("gt", 2, jit, |ctx| {
+    for &name in &["quotient", "remainder", "modulo"] {
+        if higher_order_primitives.contains(name) {
+            res.push(emit_primitive(name, 2, jit, |ctx| {
+                let block = ctx.builder.current_block().unwrap();
+                let args = ctx.builder.block_params(block);
+                emit_check_arg_count(2, args[1], ctx, false)?;
+
+                let args = get_primitive_args(ctx, block, 2);
+                let left = args[0];
+                let right = args[1];
+
+                emit_division(name, left, right, ctx)
+            })?);
+        }
+    }
+
+    for &name in &[
+        "bitwise-and",
+        "bitwise-or",
+        "bitwise-xor",
+        "arithmetic-shift",
+    ] {
+        if higher_order_primitives.contains(name) {
+            res.push(emit_primitive(name, 2, jit, |ctx| {
+                let block = ctx.builder.current_block().unwrap();
+                let args = ctx.builder.block_params(block);
+                emit_check_arg_count(2, args[1], ctx, false)?;
+
+                let args = get_primitive_args(ctx, block, 2);
+                let left = args[0];
+                let right = args[1];
+
+                emit_bitwise(name, left, right, ctx)
+            })?);
+        }
+    }
+
+    if higher_order_primitives.contains("apply") {
+        res.push(emit_primitive("apply", 2, jit, |ctx| {
             let block = ctx.builder.current_block().unwrap();
             let args = ctx.builder.block_params(block);
             emit_check_arg_count(2, args[1], ctx, false)?;
 
             let args = get_primitive_args(ctx, block, 2);
-            let left = args[0];
-            let right = args[1];
+            procedures::emit_apply(args[0], args[1], ctx)
+        })?);
+    }
 
-            fatal::emit_check_int(left, ctx)?;
-            fatal::emit_check_int(right, ctx)?;
+    if higher_order_primitives.contains("fold") {
+        res.push(emit_primitive("fold", 3, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(3, args[1], ctx, false)?;
 
-            let accum = ctx
-                .builder
-                .ins()
-                .icmp(IntCC::SignedGreaterThan, left, right);
-            let accum = ctx.builder.ins().bint(word, accum);
-            Ok(emit_word_to_bool(accum, &mut ctx.builder))
+            let args = get_primitive_args(ctx, block, 3);
+            procedures::emit_fold(args[0], args[1], args[2], ctx)
         })?);
     }
 
-    if higher_order_primitives.contains("cons") {
-        res.push(emit_primitive("cons", 2, jit, |ctx| {
+    if higher_order_primitives.contains("map") {
+        res.push(emit_primitive("map", 2, jit, |ctx| {
             let block = ctx.builder.current_block().unwrap();
             let args = ctx.builder.block_params(block);
             emit_check_arg_count(2, args[1], ctx, false)?;
 
             let args = get_primitive_args(ctx, block, 2);
-            let data = args[0];
-            let next = args[1];
+            procedures::emit_map(args[0], args[1], ctx)
+        })?);
+    }
 
-            let storage = emit_alloc((ctx.word.bytes() * 2).into(), ctx)?;
+    if higher_order_primitives.contains("negate") {
+        res.push(emit_primitive("negate", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(1, args[1], ctx, false)?;
 
-            ctx.builder.ins().store(MemFlags::new(), data, storage, 0);
-            ctx.builder
+            let args = get_primitive_args(ctx, block, 1);
+            let zero = ctx
+                .builder
                 .ins()
-                .store(MemFlags::new(), next, storage, word.bytes() as i32);
+                .iconst(ctx.word, Expr::Integer(0).immediate_rep());
 
-            Ok(ctx.builder.ins().bor_imm(storage, conversions::PAIR_TAG))
+            emit_arithmetic(ArithOp::Sub, zero, args[0], ctx)
         })?);
     }
 
-    if higher_order_primitives.contains("car") {
-        res.push(emit_primitive("car", 1, jit, |ctx| {
+    if higher_order_primitives.contains("abs") {
+        res.push(emit_primitive("abs", 1, jit, |ctx| {
             let block = ctx.builder.current_block().unwrap();
             let args = ctx.builder.block_params(block);
-            emit_check_arg_count(2, args[1], ctx, false)?;
+            emit_check_arg_count(1, args[1], ctx, false)?;
 
             let args = get_primitive_args(ctx, block, 1);
-            let pair = args[0];
-
-            fatal::emit_check_pair(pair, ctx)?;
+            emit_abs(args[0], ctx)
+        })?);
+    }
 
-            let address = ctx.builder.ins().band_imm(pair, conversions::HEAP_PTR_MASK);
+    if higher_order_primitives.contains("sqrt") {
+        res.push(emit_primitive("sqrt", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(1, args[1], ctx, false)?;
 
-            Ok(ctx.builder.ins().load(word, MemFlags::new(), address, 0))
+            let args = get_primitive_args(ctx, block, 1);
+            emit_sqrt(args[0], ctx)
         })?);
     }
 
-    if higher_order_primitives.contains("cdr") {
-        res.push(emit_primitive("cdr", 1, jit, |ctx| {
+    if higher_order_primitives.contains("exact->inexact") {
+        res.push(emit_primitive("exact->inexact", 1, jit, |ctx| {
             let block = ctx.builder.current_block().unwrap();
             let args = ctx.builder.block_params(block);
-            emit_check_arg_count(2, args[1], ctx, false)?;
+            emit_check_arg_count(1, args[1], ctx, false)?;
 
             let args = get_primitive_args(ctx, block, 1);
-            let pair = args[0];
-
-            fatal::emit_check_pair(pair, ctx)?;
+            emit_exact_to_inexact(args[0], ctx)
+        })?);
+    }
 
-            let address = ctx.builder.ins().band_imm(pair, conversions::HEAP_PTR_MASK);
+    if higher_order_primitives.contains("inexact->exact") {
+        res.push(emit_primitive("inexact->exact", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(1, args[1], ctx, false)?;
 
-            Ok(ctx
-                .builder
-                .ins()
-                .load(word, MemFlags::new(), address, word.bytes() as i32))
+            let args = get_primitive_args(ctx, block, 1);
+            emit_inexact_to_exact(args[0], ctx)
         })?);
     }
 
-    Ok(res)
-}
+    for &name in &[
+        "floor",
+        "ceiling",
+        "round",
+        "truncate",
+        "floor->integer",
+        "ceiling->integer",
+        "round->integer",
+        "truncate->integer",
+    ] {
+        if higher_order_primitives.contains(name) {
+            let (op, to_integer) = RoundOp::from_primitive(name).unwrap();
+            res.push(emit_primitive(name, 1, jit, |ctx| {
+                let block = ctx.builder.current_block().unwrap();
+                let args = ctx.builder.block_params(block);
+                emit_check_arg_count(1, args[1], ctx, false)?;
+
+                let args = get_primitive_args(ctx, block, 1);
+                emit_round(op, to_integer, args[0], ctx)
+            })?);
+        }
+    }
 
-pub(crate) fn emit_primcall(name: &str, args: &[Expr], ctx: &mut Context) -> Result<Value, String> {
-    debug_assert!(string_is_primitive(name));
-    Ok(match name {
-        "add1" => {
-            check_arg_len("add1", args, 1)?;
-            let accum = emit_expr(&args[0], ctx)?;
+    for &name in &["min", "max"] {
+        if higher_order_primitives.contains(name) {
+            res.push(emit_primitive(name, 2, jit, |ctx| {
+                let block = ctx.builder.current_block().unwrap();
+                let args = ctx.builder.block_params(block);
+                emit_check_arg_count(2, args[1], ctx, false)?;
 
-            fatal::emit_check_int(accum, ctx)?;
+                let args = get_primitive_args(ctx, block, 2);
+                let left = args[0];
+                let right = args[1];
 
-            ctx.builder
-                .ins()
-                .iadd_imm(accum, Expr::Integer(1).immediate_rep())
+                emit_min_max(name, left, right, ctx)
+            })?);
         }
-        "integer->char" => {
-            check_arg_len("integer->char", args, 1)?;
+    }
 
-            // To convert an integer to a character we left shift by 6
-            // and then tag it with the character tag.
-            let accum = emit_expr(&args[0], ctx)?;
+    for &name in &["eq", "eq?"] {
+        if higher_order_primitives.contains(name) {
+            res.push(emit_primitive(name, 2, jit, |ctx| {
+                let block = ctx.builder.current_block().unwrap();
+                let args = ctx.builder.block_params(block);
+                emit_check_arg_count(2, args[1], ctx, false)?;
+
+                let args = get_primitive_args(ctx, block, 2);
+                let left = args[0];
+                let right = args[1];
+
+                let accum = ctx.builder.ins().icmp(IntCC::Equal, left, right);
+                let accum = ctx.builder.ins().bint(word, accum);
+                Ok(emit_word_to_bool(accum, &mut ctx.builder))
+            })?);
+        }
+    }
 
-            fatal::emit_check_int(accum, ctx)?;
+    for &name in &["equal", "equal?"] {
+        if higher_order_primitives.contains(name) {
+            res.push(emit_primitive(name, 2, jit, |ctx| {
+                let block = ctx.builder.current_block().unwrap();
+                let args = ctx.builder.block_params(block);
+                emit_check_arg_count(2, args[1], ctx, false)?;
 
-            let accum = ctx.builder.ins().ishl_imm(accum, 6);
-            let accum = ctx.builder.ins().bor_imm(accum, conversions::CHAR_TAG);
+                let args = get_primitive_args(ctx, block, 2);
+                emit_runtime_call("equal_lustc_word", &args, ctx)
+            })?);
+        }
+    }
+
+    if higher_order_primitives.contains("lt") {
+        res.push(emit_primitive("lt", 2, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(2, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 2);
+            let left = args[0];
+            let right = args[1];
+
+            let accum = emit_comparison(CmpOp::Lt, left, right, ctx)?;
+            Ok(emit_word_to_bool(accum, &mut ctx.builder))
+        })?);
+    }
+
+    if higher_order_primitives.contains("gt") {
+        res.push(emit_primitive("gt", 2, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(2, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 2);
+            let left = args[0];
+            let right = args[1];
+
+            let accum = emit_comparison(CmpOp::Gt, left, right, ctx)?;
+            Ok(emit_word_to_bool(accum, &mut ctx.builder))
+        })?);
+    }
+
+    for &name in &["<", ">", "<=", ">=", "="] {
+        if higher_order_primitives.contains(name) {
+            let op = CmpOp::from_primitive(name).unwrap();
+            res.push(emit_primitive(name, 2, jit, |ctx| {
+                let block = ctx.builder.current_block().unwrap();
+                let args = ctx.builder.block_params(block);
+                emit_check_arg_count(2, args[1], ctx, false)?;
+
+                let args = get_primitive_args(ctx, block, 2);
+                let left = args[0];
+                let right = args[1];
+
+                let accum = emit_comparison(op, left, right, ctx)?;
+                Ok(emit_word_to_bool(accum, &mut ctx.builder))
+            })?);
+        }
+    }
+
+    if higher_order_primitives.contains("cons") {
+        res.push(emit_primitive("cons", 2, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(2, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 2);
+            emit_cons(args[0], args[1], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("list") {
+        res.push(emit_primitive("list", 0, jit, |ctx| {
+            // Every argument goes in the list so there is no count
+            // to check.
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            let count = args[1];
+            let argloc = args[2];
+
+            emit_contigous_to_list(ctx, argloc, count)
+        })?);
+    }
+
+    if higher_order_primitives.contains("cons*") {
+        res.push(emit_primitive("cons*", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            let count = args[1];
+            let argloc = args[2];
+            emit_check_arg_count(1, count, ctx, true)?;
+
+            lists::emit_dynamic_cons_star(argloc, count, ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("values") {
+        res.push(emit_primitive("values", 0, jit, |ctx| {
+            // Any number of values can be packed so there is no count
+            // to check.
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            let count = args[1];
+            let argloc = args[2];
+
+            values::emit_dynamic_values(argloc, count, ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("call-with-values") {
+        res.push(emit_primitive("call-with-values", 2, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(2, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 2);
+            values::emit_call_with_values(args[0], args[1], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("car") {
+        res.push(emit_primitive("car", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(1, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 1);
+            let pair = args[0];
+
+            fatal::emit_check_pair(pair, ctx)?;
+
+            let address = ctx.builder.ins().band_imm(pair, conversions::HEAP_PTR_MASK);
+
+            Ok(ctx.builder.ins().load(word, MemFlags::new(), address, 0))
+        })?);
+    }
+
+    if higher_order_primitives.contains("cdr") {
+        res.push(emit_primitive("cdr", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(1, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 1);
+            let pair = args[0];
+
+            fatal::emit_check_pair(pair, ctx)?;
+
+            let address = ctx.builder.ins().band_imm(pair, conversions::HEAP_PTR_MASK);
+
+            Ok(ctx
+                .builder
+                .ins()
+                .load(word, MemFlags::new(), address, word.bytes() as i32))
+        })?);
+    }
+
+    if higher_order_primitives.contains("length") {
+        res.push(emit_primitive("length", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(1, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 1);
+            lists::emit_length(args[0], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("reverse") {
+        res.push(emit_primitive("reverse", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(1, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 1);
+            lists::emit_reverse(args[0], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("append") {
+        res.push(emit_primitive("append", 2, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(2, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 2);
+            lists::emit_append(args[0], args[1], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("list-ref") {
+        res.push(emit_primitive("list-ref", 2, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(2, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 2);
+            lists::emit_list_ref(args[0], args[1], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("assoc") {
+        res.push(emit_primitive("assoc", 2, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(2, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 2);
+            lists::emit_assoc(args[0], args[1], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("make-vector") {
+        res.push(emit_primitive("make-vector", 2, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            let count = args[1];
+            let argloc = args[2];
+            fatal::emit_check_arg_range(1, 2, count, ctx)?;
+
+            let length = ctx.builder.ins().load(ctx.word, MemFlags::new(), argloc, 0);
+
+            // The fill value is optional so it is only loaded if it
+            // was passed.
+            let fill_block = ctx.builder.create_block();
+            let merge_block = ctx.builder.create_block();
+            ctx.builder.append_block_param(merge_block, ctx.word);
+
+            let has_fill = ctx.builder.ins().icmp_imm(IntCC::Equal, count, 2);
+            let nil = ctx
+                .builder
+                .ins()
+                .iconst(ctx.word, Expr::Nil.immediate_rep());
+            ctx.builder.ins().brz(has_fill, merge_block, &[nil]);
+            ctx.builder.ins().jump(fill_block, &[]);
+
+            ctx.builder.switch_to_block(fill_block);
+            ctx.builder.seal_block(fill_block);
+            let fill =
+                ctx.builder
+                    .ins()
+                    .load(ctx.word, MemFlags::new(), argloc, ctx.word.bytes() as i32);
+            ctx.builder.ins().jump(merge_block, &[fill]);
+
+            ctx.builder.switch_to_block(merge_block);
+            ctx.builder.seal_block(merge_block);
+            let fill = ctx.builder.block_params(merge_block)[0];
+
+            vectors::emit_make_vector(length, fill, ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("make-hash-table") {
+        res.push(emit_primitive("make-hash-table", 0, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(0, args[1], ctx, false)?;
+
+            hashtables::emit_make_hash_table(ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("hash-ref") {
+        res.push(emit_primitive("hash-ref", 3, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(3, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 3);
+            hashtables::emit_hash_ref(args[0], args[1], args[2], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("hash-set!") {
+        res.push(emit_primitive("hash-set!", 3, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(3, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 3);
+            hashtables::emit_hash_set(args[0], args[1], args[2], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("vector-ref") {
+        res.push(emit_primitive("vector-ref", 2, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(2, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 2);
+            vectors::emit_vector_ref(args[0], args[1], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("vector-set!") {
+        res.push(emit_primitive("vector-set!", 3, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(3, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 3);
+            vectors::emit_vector_set(args[0], args[1], args[2], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("vector-length") {
+        res.push(emit_primitive("vector-length", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(1, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 1);
+            vectors::emit_vector_length(args[0], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("vector-fill!") {
+        res.push(emit_primitive("vector-fill!", 2, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(2, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 2);
+            vectors::emit_vector_fill(args[0], args[1], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("vector-copy") {
+        res.push(emit_primitive("vector-copy", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(1, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 1);
+            vectors::emit_vector_copy(args[0], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("list->vector") {
+        res.push(emit_primitive("list->vector", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(1, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 1);
+            vectors::emit_list_to_vector(args[0], ctx)
+        })?);
+    }
+
+    if higher_order_primitives.contains("vector->list") {
+        res.push(emit_primitive("vector->list", 1, jit, |ctx| {
+            let block = ctx.builder.current_block().unwrap();
+            let args = ctx.builder.block_params(block);
+            emit_check_arg_count(1, args[1], ctx, false)?;
+
+            let args = get_primitive_args(ctx, block, 1);
+            vectors::emit_vector_to_list(args[0], ctx)
+        })?);
+    }
+
+    Ok(res)
+}
+
+/// Emits the code to add the integer DELTA to the fixnum ACCUM. The
+/// tag of a fixnum is zero so adding the immediate representation of
+/// DELTA leaves it in place and the result needs no retagging. Like
+/// add1 always has, this wraps on overflow.
+fn emit_add_fixnum(accum: Value, delta: i64, ctx: &mut Context) -> Result<Value, LustError> {
+    fatal::emit_check_int(accum, ctx)?;
+    let res = ctx
+        .builder
+        .ins()
+        .iadd_imm(accum, Expr::Integer(delta).immediate_rep());
+    Ok(peephole::record(res, Known::Fixnum, ctx))
+}
+
+/// Emits the comparison OP of VAL, a number, against zero. Integers
+/// and floats both work as with the other comparisons.
+fn emit_sign_comparison(op: CmpOp, val: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    let zero = ctx
+        .builder
+        .ins()
+        .iconst(ctx.word, Expr::Integer(0).immediate_rep());
+    let zero = peephole::record(zero, Known::Fixnum, ctx);
+    emit_comparison(op, val, zero, ctx)
+}
+
+/// Emits a comparison of LEFT and RIGHT after checking that both are
+/// booleans. The result is a word that is one if they are the same
+/// and zero otherwise.
+fn emit_boolean_eq(left: Value, right: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    fatal::emit_check_tag(left, conversions::BOOL_TAG, conversions::BOOL_MASK, ctx)?;
+    fatal::emit_check_tag(right, conversions::BOOL_TAG, conversions::BOOL_MASK, ctx)?;
+    let accum = ctx.builder.ins().icmp(IntCC::Equal, left, right);
+    Ok(ctx.builder.ins().bint(ctx.word, accum))
+}
+
+/// Emits the boolean for CONDITION, the one or zero that a
+/// comparison gives.
+fn emit_condition_to_bool(condition: Value, ctx: &mut Context) -> Value {
+    let res = emit_word_to_bool(condition, &mut ctx.builder);
+    peephole::record_condition(res, condition, ctx);
+    res
+}
+
+pub(crate) fn emit_primcall(
+    name: &str,
+    args: &[Expr],
+    tail: bool,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    debug_assert!(string_is_primitive(name));
+    Ok(match name {
+        "add1" => {
+            check_arg_len("add1", args, 1)?;
+            let accum = emit_expr(&args[0], ctx)?;
+            emit_add_fixnum(accum, 1, ctx)?
+        }
+        "sub1" => {
+            check_arg_len("sub1", args, 1)?;
+            let accum = emit_expr(&args[0], ctx)?;
+            emit_add_fixnum(accum, -1, ctx)?
+        }
+        "integer->char" => {
+            check_arg_len("integer->char", args, 1)?;
+
+            // To convert an integer to a character we left shift by 6
+            // and then tag it with the character tag.
+            let accum = emit_expr(&args[0], ctx)?;
+
+            fatal::emit_check_int(accum, ctx)?;
+            fatal::emit_check_char_range(accum, ctx)?;
+
+            let accum = ctx.builder.ins().ishl_imm(accum, 6);
+            let accum = ctx.builder.ins().bor_imm(accum, conversions::CHAR_TAG);
             accum
         }
         "char->integer" => {
@@ -656,30 +1333,81 @@ pub(crate) fn emit_primcall(name: &str, args: &[Expr], ctx: &mut Context) -> Res
             let accum = ctx.builder.ins().bint(ctx.word, accum);
             emit_word_to_bool(accum, &mut ctx.builder)
         }
+        "positive?" | "negative?" => {
+            check_arg_len(name, args, 1)?;
+
+            let accum = emit_expr(&args[0], ctx)?;
+
+            let op = if name == "positive?" {
+                CmpOp::Gt
+            } else {
+                CmpOp::Lt
+            };
+            let accum = emit_sign_comparison(op, accum, ctx)?;
+            emit_condition_to_bool(accum, ctx)
+        }
+        "boolean=?" => {
+            check_arg_len("boolean=?", args, 2)?;
+            let left = emit_expr(&args[0], ctx)?;
+            let right = emit_expr(&args[1], ctx)?;
+
+            let accum = emit_boolean_eq(left, right, ctx)?;
+            emit_condition_to_bool(accum, ctx)
+        }
         "not" => {
             check_arg_len("not", args, 1)?;
 
             let accum = emit_expr(&args[0], ctx)?;
 
-            fatal::emit_check_bool(accum, ctx)?;
+            emit_not(accum, ctx)
+        }
+        "integer?" | "fixnum?" => {
+            check_arg_len(name, args, 1)?;
 
-            // To get the not of a boolean, subtract one from it and
-            // then take the absolute value.
-            let accum = ctx.builder.ins().sshr_imm(accum, conversions::BOOL_SHIFT);
-            let accum = ctx.builder.ins().icmp_imm(IntCC::Equal, accum, 0);
+            let accum = emit_expr(&args[0], ctx)?;
+
+            let accum = ctx.builder.ins().band_imm(accum, conversions::FIXNUM_MASK);
+            let accum = ctx
+                .builder
+                .ins()
+                .icmp_imm(IntCC::Equal, accum, conversions::FIXNUM_TAG);
             let accum = ctx.builder.ins().bint(ctx.word, accum);
             emit_word_to_bool(accum, &mut ctx.builder)
         }
-        "integer?" => {
-            check_arg_len("integer?", args, 1)?;
+        "char-upcase" | "char-downcase" => {
+            check_arg_len(name, args, 1)?;
+            let accum = emit_expr(&args[0], ctx)?;
+            chars::emit_char_case(name, accum, ctx)?
+        }
+        "char-alphabetic?" | "char-numeric?" | "char-whitespace?" => {
+            check_arg_len(name, args, 1)?;
+            let accum = emit_expr(&args[0], ctx)?;
+            let accum = chars::emit_char_class(name, accum, ctx)?;
+            emit_condition_to_bool(accum, ctx)
+        }
+        "char?" => {
+            check_arg_len("char?", args, 1)?;
 
             let accum = emit_expr(&args[0], ctx)?;
 
-            let accum = ctx.builder.ins().band_imm(accum, conversions::FIXNUM_MASK);
+            let accum = ctx.builder.ins().band_imm(accum, conversions::CHAR_MASK);
             let accum = ctx
                 .builder
                 .ins()
-                .icmp_imm(IntCC::Equal, accum, conversions::FIXNUM_TAG);
+                .icmp_imm(IntCC::Equal, accum, conversions::CHAR_TAG);
+            let accum = ctx.builder.ins().bint(ctx.word, accum);
+            emit_word_to_bool(accum, &mut ctx.builder)
+        }
+        "symbol?" => {
+            check_arg_len("symbol?", args, 1)?;
+
+            let accum = emit_expr(&args[0], ctx)?;
+
+            let accum = ctx.builder.ins().band_imm(accum, conversions::SYMBOL_MASK);
+            let accum = ctx
+                .builder
+                .ins()
+                .icmp_imm(IntCC::Equal, accum, conversions::SYMBOL_TAG);
             let accum = ctx.builder.ins().bint(ctx.word, accum);
             emit_word_to_bool(accum, &mut ctx.builder)
         }
@@ -734,10 +1462,7 @@ pub(crate) fn emit_primcall(name: &str, args: &[Expr], ctx: &mut Context) -> Res
             let left = emit_expr(&args[0], ctx)?;
             let right = emit_expr(&args[1], ctx)?;
 
-            fatal::emit_check_int(left, ctx)?;
-            fatal::emit_check_int(right, ctx)?;
-
-            ctx.builder.ins().iadd(left, right)
+            emit_arithmetic(ArithOp::Add, left, right, ctx)?
         }
         "sub" => {
             check_arg_len("sub", args, 2)?;
@@ -745,12 +1470,7 @@ pub(crate) fn emit_primcall(name: &str, args: &[Expr], ctx: &mut Context) -> Res
             let left = emit_expr(&args[0], ctx)?;
             let right = emit_expr(&args[1], ctx)?;
 
-            fatal::emit_check_int(left, ctx)?;
-            fatal::emit_check_int(right, ctx)?;
-
-            let right = ctx.builder.ins().ineg(right);
-
-            ctx.builder.ins().iadd(left, right)
+            emit_arithmetic(ArithOp::Sub, left, right, ctx)?
         }
         "mul" => {
             check_arg_len("mul", args, 2)?;
@@ -758,22 +1478,119 @@ pub(crate) fn emit_primcall(name: &str, args: &[Expr], ctx: &mut Context) -> Res
             let left = emit_expr(&args[0], ctx)?;
             let right = emit_expr(&args[1], ctx)?;
 
-            fatal::emit_check_int(left, ctx)?;
-            fatal::emit_check_int(right, ctx)?;
+            emit_arithmetic(ArithOp::Mul, left, right, ctx)?
+        }
+        "quotient" | "remainder" | "modulo" => {
+            check_arg_len(name, args, 2)?;
 
-            let accum = ctx.builder.ins().imul(left, right);
+            let left = emit_expr(&args[0], ctx)?;
+            let right = emit_expr(&args[1], ctx)?;
 
-            // At this point we've picked up an extra 2^2 so we need
-            // to right shift it out.
-            //
-            // NOTE: It is possible that it would be more reasonable
-            // to shift things out first. I'm worried here that this
-            // will cause integer overflows where we wouldn't normally
-            // expect them.
-            ctx.builder.ins().sshr_imm(accum, 2)
+            emit_division(name, left, right, ctx)?
+        }
+        "bitwise-and" | "bitwise-or" | "bitwise-xor" | "arithmetic-shift" => {
+            check_arg_len(name, args, 2)?;
+
+            let left = emit_expr(&args[0], ctx)?;
+            let right = emit_expr(&args[1], ctx)?;
+
+            emit_bitwise(name, left, right, ctx)?
+        }
+        "apply" => {
+            check_arg_len("apply", args, 2)?;
+
+            let f = emit_expr(&args[0], ctx)?;
+            let list = emit_expr(&args[1], ctx)?;
+
+            let res = procedures::emit_apply(f, list, ctx)?;
+            values::emit_call_result(res, tail, ctx)
+        }
+        "fold" => {
+            check_arg_len("fold", args, 3)?;
+
+            let f = emit_expr(&args[0], ctx)?;
+            let init = emit_expr(&args[1], ctx)?;
+            let list = emit_expr(&args[2], ctx)?;
+
+            procedures::emit_fold(f, init, list, ctx)?
+        }
+        "values" => {
+            let vals = args
+                .iter()
+                .map(|a| emit_expr(a, ctx))
+                .collect::<Result<Vec<_>, _>>()?;
+            values::emit_values(&vals, tail, ctx)?
+        }
+        "call-with-values" => {
+            check_arg_len("call-with-values", args, 2)?;
+
+            let producer = emit_expr(&args[0], ctx)?;
+            let consumer = emit_expr(&args[1], ctx)?;
+
+            let res = values::emit_call_with_values(producer, consumer, ctx)?;
+            values::emit_call_result(res, tail, ctx)
+        }
+        "map" => {
+            check_arg_len("map", args, 2)?;
+
+            let f = emit_expr(&args[0], ctx)?;
+            let list = emit_expr(&args[1], ctx)?;
+
+            procedures::emit_map(f, list, ctx)?
+        }
+        "negate" => {
+            check_arg_len("negate", args, 1)?;
+
+            let accum = emit_expr(&args[0], ctx)?;
+            let zero = ctx
+                .builder
+                .ins()
+                .iconst(ctx.word, Expr::Integer(0).immediate_rep());
+
+            emit_arithmetic(ArithOp::Sub, zero, accum, ctx)?
+        }
+        "abs" => {
+            check_arg_len("abs", args, 1)?;
+
+            let val = emit_expr(&args[0], ctx)?;
+            emit_abs(val, ctx)?
+        }
+        "sqrt" => {
+            check_arg_len("sqrt", args, 1)?;
+
+            let val = emit_expr(&args[0], ctx)?;
+            emit_sqrt(val, ctx)?
+        }
+        "exact->inexact" => {
+            check_arg_len("exact->inexact", args, 1)?;
+
+            let val = emit_expr(&args[0], ctx)?;
+            emit_exact_to_inexact(val, ctx)?
+        }
+        "inexact->exact" => {
+            check_arg_len("inexact->exact", args, 1)?;
+
+            let val = emit_expr(&args[0], ctx)?;
+            emit_inexact_to_exact(val, ctx)?
         }
-        "eq" => {
-            check_arg_len("eq", args, 2)?;
+        "floor" | "ceiling" | "round" | "truncate" | "floor->integer" | "ceiling->integer"
+        | "round->integer" | "truncate->integer" => {
+            check_arg_len(name, args, 1)?;
+
+            let val = emit_expr(&args[0], ctx)?;
+            let (op, to_integer) = RoundOp::from_primitive(name).unwrap();
+            emit_round(op, to_integer, val, ctx)?
+        }
+        "min" | "max" => {
+            check_arg_len(name, args, 2)?;
+
+            let left = emit_expr(&args[0], ctx)?;
+            let right = emit_expr(&args[1], ctx)?;
+
+            emit_min_max(name, left, right, ctx)?
+        }
+        "eq" | "eq?" => {
+            check_arg_len(name, args, 2)?;
 
             let left = emit_expr(&args[0], ctx)?;
             let right = emit_expr(&args[1], ctx)?;
@@ -782,32 +1599,37 @@ pub(crate) fn emit_primcall(name: &str, args: &[Expr], ctx: &mut Context) -> Res
             let accum = ctx.builder.ins().bint(ctx.word, accum);
             emit_word_to_bool(accum, &mut ctx.builder)
         }
+        "equal" | "equal?" => {
+            check_arg_len(name, args, 2)?;
+
+            let left = emit_expr(&args[0], ctx)?;
+            let right = emit_expr(&args[1], ctx)?;
+            emit_runtime_call("equal_lustc_word", &[left, right], ctx)?
+        }
         "lt" => {
             check_arg_len("lt", args, 2)?;
             let left = emit_expr(&args[0], ctx)?;
             let right = emit_expr(&args[1], ctx)?;
 
-            fatal::emit_check_int(left, ctx)?;
-            fatal::emit_check_int(right, ctx)?;
-
-            let accum = ctx.builder.ins().icmp(IntCC::SignedLessThan, left, right);
-            let accum = ctx.builder.ins().bint(ctx.word, accum);
-            emit_word_to_bool(accum, &mut ctx.builder)
+            let accum = emit_comparison(CmpOp::Lt, left, right, ctx)?;
+            emit_condition_to_bool(accum, ctx)
         }
         "gt" => {
             check_arg_len("gt", args, 2)?;
             let left = emit_expr(&args[0], ctx)?;
             let right = emit_expr(&args[1], ctx)?;
 
-            fatal::emit_check_int(left, ctx)?;
-            fatal::emit_check_int(right, ctx)?;
+            let accum = emit_comparison(CmpOp::Gt, left, right, ctx)?;
+            emit_condition_to_bool(accum, ctx)
+        }
+        "<" | ">" | "<=" | ">=" | "=" => {
+            check_arg_len(name, args, 2)?;
+            let left = emit_expr(&args[0], ctx)?;
+            let right = emit_expr(&args[1], ctx)?;
 
-            let accum = ctx
-                .builder
-                .ins()
-                .icmp(IntCC::SignedGreaterThan, left, right);
-            let accum = ctx.builder.ins().bint(ctx.word, accum);
-            emit_word_to_bool(accum, &mut ctx.builder)
+            let op = CmpOp::from_primitive(name).unwrap();
+            let accum = emit_comparison(op, left, right, ctx)?;
+            emit_condition_to_bool(accum, ctx)
         }
 
         "cons" => {
@@ -816,14 +1638,40 @@ pub(crate) fn emit_primcall(name: &str, args: &[Expr], ctx: &mut Context) -> Res
             let data = emit_expr(&args[0], ctx)?;
             let next = emit_expr(&args[1], ctx)?;
 
-            let storage = emit_alloc((ctx.word.bytes() * 2).into(), ctx)?;
-
-            ctx.builder.ins().store(MemFlags::new(), data, storage, 0);
-            ctx.builder
+            emit_cons(data, next, ctx)?
+        }
+        "list" => {
+            // Arguments are evaluated left to right and then consed
+            // together from the back.
+            let items = args
+                .iter()
+                .map(|e| emit_expr(e, ctx))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut accum = ctx
+                .builder
                 .ins()
-                .store(MemFlags::new(), next, storage, ctx.word.bytes() as i32);
-
-            ctx.builder.ins().bor_imm(storage, conversions::PAIR_TAG)
+                .iconst(ctx.word, Expr::Nil.immediate_rep());
+            for item in items.into_iter().rev() {
+                accum = emit_cons(item, accum, ctx)?;
+            }
+            accum
+        }
+        "cons*" => {
+            // The last argument is the final cdr so there has to be
+            // at least one.
+            check_arg_len("cons*", args, args.len().max(1))?;
+            let items = args
+                .iter()
+                .map(|e| emit_expr(e, ctx))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let (tail, items) = items.split_last().unwrap();
+            let mut accum = *tail;
+            for item in items.iter().rev() {
+                accum = emit_cons(*item, accum, ctx)?;
+            }
+            accum
         }
         "car" => {
             check_arg_len("car", args, 1)?;
@@ -852,59 +1700,341 @@ pub(crate) fn emit_primcall(name: &str, args: &[Expr], ctx: &mut Context) -> Res
                 .load(ctx.word, MemFlags::new(), address, ctx.word.bytes() as i32)
         }
 
-        "print" => {
-            check_arg_len("print", args, 1)?;
+        "print" | "display" => {
+            check_arg_len(name, args, 1)?;
             let arg = emit_expr(&args[0], ctx)?;
-            let args = vec![arg];
+            emit_runtime_call("print_lustc_word", &[arg], ctx)?
+        }
 
-            let mut sig = ctx.module.make_signature();
-            sig.params.push(AbiParam::new(ctx.word));
-            sig.returns.push(AbiParam::new(ctx.word));
+        "write" => {
+            check_arg_len("write", args, 1)?;
+            let arg = emit_expr(&args[0], ctx)?;
+            emit_runtime_call("write_lustc_word", &[arg], ctx)?
+        }
 
-            let callee = ctx
-                .module
-                .declare_function("print_lustc_word", cranelift_module::Linkage::Import, &sig)
-                .map_err(|e| e.to_string())?;
+        "newline" => {
+            check_arg_len("newline", args, 0)?;
+            emit_newline(ctx)?
+        }
 
-            let local_callee = ctx
-                .module
-                .declare_func_in_func(callee, &mut ctx.builder.func);
+        "gensym" => {
+            check_arg_len("gensym", args, 0)?;
+            emit_runtime_call("gensym_lustc_word", &[], ctx)?
+        }
 
-            let call = ctx.builder.ins().call(local_callee, &args);
-            ctx.builder.inst_results(call)[0]
+        "read" => {
+            check_arg_len("read", args, 0)?;
+            let res = emit_runtime_call("read_lustc_word", &[], ctx)?;
+            fatal::emit_propagate_raise(res, ctx);
+            res
+        }
+        "throw" => {
+            check_arg_len("throw", args, 2)?;
+            let tag = emit_expr(&args[0], ctx)?;
+            let value = emit_expr(&args[1], ctx)?;
+            let res = emit_runtime_call("throw_lustc_word", &[tag, value], ctx)?;
+            fatal::emit_propagate_raise(res, ctx);
+            res
+        }
+        "eof-object" => {
+            check_arg_len("eof-object", args, 0)?;
+            ctx.builder.ins().iconst(ctx.word, conversions::EOF_VALUE)
+        }
+        "eof-object?" => {
+            check_arg_len("eof-object?", args, 1)?;
+            let accum = emit_expr(&args[0], ctx)?;
+            let accum = ctx
+                .builder
+                .ins()
+                .icmp_imm(IntCC::Equal, accum, conversions::EOF_VALUE);
+            let accum = ctx.builder.ins().bint(ctx.word, accum);
+            emit_word_to_bool(accum, &mut ctx.builder)
+        }
+
+        "symbol->string" => {
+            check_arg_len("symbol->string", args, 1)?;
+            let arg = emit_expr(&args[0], ctx)?;
+            fatal::emit_check_symbol(arg, ctx)?;
+            emit_runtime_call("symbol_to_string_lustc_word", &[arg], ctx)?
+        }
+
+        "string->symbol" => {
+            check_arg_len("string->symbol", args, 1)?;
+            let arg = emit_expr(&args[0], ctx)?;
+            fatal::emit_check_string(arg, ctx)?;
+            emit_runtime_call("string_to_symbol_lustc_word", &[arg], ctx)?
         }
 
         "println" => {
             check_arg_len("println", args, 1)?;
             let arg = emit_expr(&args[0], ctx)?;
-            let args = vec![arg];
-
-            let mut sig = ctx.module.make_signature();
-            sig.params.push(AbiParam::new(ctx.word));
-            sig.returns.push(AbiParam::new(ctx.word));
-
-            let callee = ctx
-                .module
-                .declare_function(
-                    "println_lustc_word",
-                    cranelift_module::Linkage::Import,
-                    &sig,
-                )
-                .map_err(|e| e.to_string())?;
+            emit_runtime_call("println_lustc_word", &[arg], ctx)?
+        }
 
-            let local_callee = ctx
-                .module
-                .declare_func_in_func(callee, &mut ctx.builder.func);
+        "string?" => {
+            check_arg_len("string?", args, 1)?;
+
+            let accum = emit_expr(&args[0], ctx)?;
 
-            let call = ctx.builder.ins().call(local_callee, &args);
-            ctx.builder.inst_results(call)[0]
+            let accum = ctx
+                .builder
+                .ins()
+                .band_imm(accum, conversions::HEAP_TAG_MASK);
+            let accum = ctx
+                .builder
+                .ins()
+                .icmp_imm(IntCC::Equal, accum, conversions::STRING_TAG);
+            let accum = ctx.builder.ins().bint(ctx.word, accum);
+            emit_word_to_bool(accum, &mut ctx.builder)
+        }
+
+        "string->list" => {
+            check_arg_len("string->list", args, 1)?;
+            let arg = emit_expr(&args[0], ctx)?;
+            fatal::emit_check_string(arg, ctx)?;
+            emit_runtime_call("string_to_list_lustc_word", &[arg], ctx)?
+        }
+
+        "string-length" => {
+            check_arg_len("string-length", args, 1)?;
+            let string = emit_expr(&args[0], ctx)?;
+            strings::emit_string_length(string, ctx)?
+        }
+
+        "string-ref" => {
+            check_arg_len("string-ref", args, 2)?;
+            let string = emit_expr(&args[0], ctx)?;
+            let index = emit_expr(&args[1], ctx)?;
+            strings::emit_string_ref(string, index, ctx)?
+        }
+
+        "string-append" => {
+            check_arg_len("string-append", args, 2)?;
+            let first = emit_expr(&args[0], ctx)?;
+            let second = emit_expr(&args[1], ctx)?;
+            fatal::emit_check_string(first, ctx)?;
+            fatal::emit_check_string(second, ctx)?;
+            emit_runtime_call("string_append_lustc_word", &[first, second], ctx)?
+        }
+
+        "length" => {
+            check_arg_len("length", args, 1)?;
+            let list = emit_expr(&args[0], ctx)?;
+            lists::emit_length(list, ctx)?
+        }
+        "reverse" => {
+            check_arg_len("reverse", args, 1)?;
+            let list = emit_expr(&args[0], ctx)?;
+            lists::emit_reverse(list, ctx)?
+        }
+        "append" => {
+            check_arg_len("append", args, 2)?;
+            let first = emit_expr(&args[0], ctx)?;
+            let second = emit_expr(&args[1], ctx)?;
+            lists::emit_append(first, second, ctx)?
+        }
+        "list-ref" => {
+            check_arg_len("list-ref", args, 2)?;
+            let list = emit_expr(&args[0], ctx)?;
+            let index = emit_expr(&args[1], ctx)?;
+            lists::emit_list_ref(list, index, ctx)?
+        }
+        "assoc" => {
+            check_arg_len("assoc", args, 2)?;
+            let key = emit_expr(&args[0], ctx)?;
+            let alist = emit_expr(&args[1], ctx)?;
+            lists::emit_assoc(key, alist, ctx)?
+        }
+        "make-vector" => {
+            // The fill value is optional and defaults to nil.
+            check_arg_len("make-vector", args, args.len().clamp(1, 2))?;
+            let length = emit_expr(&args[0], ctx)?;
+            let fill = match args.get(1) {
+                Some(fill) => emit_expr(fill, ctx)?,
+                None => ctx
+                    .builder
+                    .ins()
+                    .iconst(ctx.word, Expr::Nil.immediate_rep()),
+            };
+            vectors::emit_make_vector(length, fill, ctx)?
+        }
+        "make-hash-table" => {
+            check_arg_len("make-hash-table", args, 0)?;
+            hashtables::emit_make_hash_table(ctx)?
+        }
+        "hash-ref" => {
+            check_arg_len("hash-ref", args, 3)?;
+            let table = emit_expr(&args[0], ctx)?;
+            let key = emit_expr(&args[1], ctx)?;
+            let default = emit_expr(&args[2], ctx)?;
+            hashtables::emit_hash_ref(table, key, default, ctx)?
+        }
+        "hash-set!" => {
+            check_arg_len("hash-set!", args, 3)?;
+            let table = emit_expr(&args[0], ctx)?;
+            let key = emit_expr(&args[1], ctx)?;
+            let value = emit_expr(&args[2], ctx)?;
+            hashtables::emit_hash_set(table, key, value, ctx)?
+        }
+        "vector-ref" => {
+            check_arg_len("vector-ref", args, 2)?;
+            let vector = emit_expr(&args[0], ctx)?;
+            let index = emit_expr(&args[1], ctx)?;
+            vectors::emit_vector_ref(vector, index, ctx)?
+        }
+        "vector-set!" => {
+            check_arg_len("vector-set!", args, 3)?;
+            let vector = emit_expr(&args[0], ctx)?;
+            let index = emit_expr(&args[1], ctx)?;
+            let value = emit_expr(&args[2], ctx)?;
+            vectors::emit_vector_set(vector, index, value, ctx)?
+        }
+        "vector-length" => {
+            check_arg_len("vector-length", args, 1)?;
+            let vector = emit_expr(&args[0], ctx)?;
+            vectors::emit_vector_length(vector, ctx)?
+        }
+        "vector-fill!" => {
+            check_arg_len("vector-fill!", args, 2)?;
+            let vector = emit_expr(&args[0], ctx)?;
+            let fill = emit_expr(&args[1], ctx)?;
+            vectors::emit_vector_fill(vector, fill, ctx)?
+        }
+        "vector-copy" => {
+            check_arg_len("vector-copy", args, 1)?;
+            let vector = emit_expr(&args[0], ctx)?;
+            vectors::emit_vector_copy(vector, ctx)?
+        }
+        "list->vector" => {
+            check_arg_len("list->vector", args, 1)?;
+            let list = emit_expr(&args[0], ctx)?;
+            vectors::emit_list_to_vector(list, ctx)?
+        }
+        "vector->list" => {
+            check_arg_len("vector->list", args, 1)?;
+            let vector = emit_expr(&args[0], ctx)?;
+            vectors::emit_vector_to_list(vector, ctx)?
         }
 
         _ => panic!("non primitive in emit_primcall: {}", name),
     })
 }
 
-fn emit_word_to_bool(accum: Value, builder: &mut FunctionBuilder) -> Value {
+/// Emits the code for the integer division primitive NAME on LEFT
+/// and RIGHT. Quotient truncates towards zero, the result of remainder
+/// has the sign of the dividend, and the result of modulo has the sign
+/// of the divisor.
+fn emit_division(
+    name: &str,
+    left: Value,
+    right: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    fatal::emit_check_int(left, ctx)?;
+    fatal::emit_check_int(right, ctx)?;
+    fatal::emit_check_nonzero(right, ctx)?;
+
+    // Both arguments carry the same 2^2 factor. For quotient it
+    // cancels out and needs to be shifted back in. For remainder it
+    // stays along for the ride and the result is already tagged.
+    Ok(match name {
+        "quotient" => {
+            let accum = ctx.builder.ins().sdiv(left, right);
+            ctx.builder.ins().ishl_imm(accum, conversions::FIXNUM_SHIFT)
+        }
+        "remainder" => ctx.builder.ins().srem(left, right),
+        "modulo" => {
+            // srem gives the remainder the sign of the dividend. If
+            // that disagrees with the sign of the divisor adding the
+            // divisor gets us the modulo.
+            let rem = ctx.builder.ins().srem(left, right);
+            let signs = ctx.builder.ins().bxor(rem, right);
+            let signs_differ = ctx.builder.ins().icmp_imm(IntCC::SignedLessThan, signs, 0);
+            let nonzero = ctx.builder.ins().icmp_imm(IntCC::NotEqual, rem, 0);
+            let adjust = ctx.builder.ins().band(signs_differ, nonzero);
+            let adjusted = ctx.builder.ins().iadd(rem, right);
+            ctx.builder.ins().select(adjust, adjusted, rem)
+        }
+        _ => panic!("non division primitive in emit_division: {}", name),
+    })
+}
+
+/// Emits the code for the bitwise primitive NAME on LEFT and RIGHT.
+/// For arithmetic-shift RIGHT is the number of places to shift LEFT
+/// by. Positive counts shift left and negative ones shift right.
+fn emit_bitwise(
+    name: &str,
+    left: Value,
+    right: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    fatal::emit_check_int(left, ctx)?;
+    fatal::emit_check_int(right, ctx)?;
+
+    // The fixnum tag is all zeros so and, or, and xor of two fixnums
+    // leave the tag bits zero and the result is already tagged.
+    Ok(match name {
+        "bitwise-and" => ctx.builder.ins().band(left, right),
+        "bitwise-or" => ctx.builder.ins().bor(left, right),
+        "bitwise-xor" => ctx.builder.ins().bxor(left, right),
+        "arithmetic-shift" => {
+            let count = ctx.builder.ins().sshr_imm(right, conversions::FIXNUM_SHIFT);
+            let is_left = ctx
+                .builder
+                .ins()
+                .icmp_imm(IntCC::SignedGreaterThanOrEqual, count, 0);
+            let negated = ctx.builder.ins().ineg(count);
+            let count = ctx.builder.ins().select(is_left, count, negated);
+
+            // Cranelift takes shift amounts modulo the word size. Past
+            // the width of a word every bit has been shifted out so
+            // clamping gets the same result.
+            let max = (ctx.word.bits() - 1) as i64;
+            let too_far = ctx
+                .builder
+                .ins()
+                .icmp_imm(IntCC::UnsignedGreaterThan, count, max);
+            let max = ctx.builder.ins().iconst(ctx.word, max);
+            let count = ctx.builder.ins().select(too_far, max, count);
+
+            // Shifting left fills the tag with zeros. Shifting right
+            // drags value bits into the tag which need to be cleared.
+            let shifted_left = ctx.builder.ins().ishl(left, count);
+            let shifted_right = ctx.builder.ins().sshr(left, count);
+            let shifted_right = ctx
+                .builder
+                .ins()
+                .band_imm(shifted_right, !conversions::FIXNUM_MASK);
+            ctx.builder
+                .ins()
+                .select(is_left, shifted_left, shifted_right)
+        }
+        _ => panic!("non bitwise primitive in emit_bitwise: {}", name),
+    })
+}
+
+/// Emits the code to print a newline. Evaluates to nil like the
+/// other printing primitives.
+fn emit_newline(ctx: &mut Context) -> Result<Value, LustError> {
+    let newline = ctx
+        .builder
+        .ins()
+        .iconst(ctx.word, Expr::Char('\n').immediate_rep());
+    emit_runtime_call("print_lustc_word", &[newline], ctx)
+}
+
+/// Emits the code for the not of VAL. Only #f is false so this is #t
+/// if VAL is #f and #f for everything else, nil included.
+fn emit_not(val: Value, ctx: &mut Context) -> Value {
+    let accum = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::Equal, val, Expr::Bool(false).immediate_rep());
+    let accum = ctx.builder.ins().bint(ctx.word, accum);
+    emit_word_to_bool(accum, &mut ctx.builder)
+}
+
+pub(crate) fn emit_word_to_bool(accum: Value, builder: &mut FunctionBuilder) -> Value {
     let accum = builder.ins().ishl_imm(accum, conversions::BOOL_SHIFT);
     let accum = builder.ins().bor_imm(accum, conversions::BOOL_TAG);
     accum
@@ -913,46 +2043,190 @@ fn emit_word_to_bool(accum: Value, builder: &mut FunctionBuilder) -> Value {
 pub(crate) fn string_is_builtin(s: &str) -> bool {
     string_is_primitive(s)
         || s == "if"
+        || s == "cond"
+        || s == "case"
+        || s == "else"
+        || s == "when"
+        || s == "unless"
+        || s == "and"
+        || s == "begin"
+        || s == "while"
+        || s == "or"
         || s == "quote"
         || s == "let"
+        || s == "define"
+        || s == "letrec"
+        || s == "let*"
         || s == "fn"
         || s == "set"
+        || s == "set!"
         || s == "foreign-call"
         || s == "error"
+        || s == "catch"
+        || s == "assert"
+        || s == "__assert"
+        || s == "fluid-let"
+        || s == "__unwind-protect"
+        || s == "time"
+        || s == "__entry-arg"
+        || s == "define-record-type"
+        || s == "__record-make"
+        || s == "__record?"
+        || s == "__record-ref"
+        || s == "__record-set!"
+        || s == "__global-ref"
+        || s == "__global-set!"
 }
 
 pub(crate) fn string_is_primitive(s: &str) -> bool {
     s == "add1"
+        || s == "sub1"
         || s == "print"
+        || s == "display"
+        || s == "write"
+        || s == "newline"
         || s == "println"
         || s == "integer->char"
         || s == "char->integer"
         || s == "null?"
         || s == "zero?"
+        || s == "positive?"
+        || s == "negative?"
+        || s == "boolean=?"
         || s == "not"
+        || s == "char?"
+        || s == "char-upcase"
+        || s == "char-downcase"
+        || s == "char-alphabetic?"
+        || s == "char-numeric?"
+        || s == "char-whitespace?"
+        || s == "symbol?"
+        || s == "gensym"
+        || s == "symbol->string"
+        || s == "read"
+        || s == "eof-object"
+        || s == "eof-object?"
+        || s == "throw"
+        || s == "string->symbol"
         || s == "boolean?"
         || s == "integer?"
+        || s == "fixnum?"
         || s == "pair?"
         || s == "closure?"
+        || s == "string?"
+        || s == "string->list"
+        || s == "string-length"
+        || s == "string-ref"
+        || s == "string-append"
         || s == "add"
         || s == "sub"
         || s == "mul"
+        || s == "negate"
+        || s == "abs"
+        || s == "min"
+        || s == "max"
+        || s == "sqrt"
+        || s == "floor"
+        || s == "ceiling"
+        || s == "round"
+        || s == "truncate"
+        || s == "floor->integer"
+        || s == "ceiling->integer"
+        || s == "round->integer"
+        || s == "truncate->integer"
+        || s == "exact->inexact"
+        || s == "inexact->exact"
+        || s == "quotient"
+        || s == "remainder"
+        || s == "modulo"
+        || s == "bitwise-and"
+        || s == "bitwise-or"
+        || s == "bitwise-xor"
+        || s == "arithmetic-shift"
         || s == "eq"
+        || s == "eq?"
+        || s == "equal"
+        || s == "equal?"
         || s == "lt"
         || s == "gt"
+        || s == "<"
+        || s == ">"
+        || s == "<="
+        || s == ">="
+        || s == "="
         || s == "cons"
+        || s == "list"
+        || s == "cons*"
+        || s == "length"
+        || s == "reverse"
+        || s == "append"
+        || s == "list-ref"
+        || s == "assoc"
         || s == "car"
         || s == "cdr"
+        || s == "make-vector"
+        || s == "make-hash-table"
+        || s == "hash-ref"
+        || s == "hash-set!"
+        || s == "vector-ref"
+        || s == "vector-set!"
+        || s == "vector-length"
+        || s == "vector-fill!"
+        || s == "vector-copy"
+        || s == "list->vector"
+        || s == "vector->list"
+        || s == "apply"
+        || s == "fold"
+        || s == "map"
+        || s == "values"
+        || s == "call-with-values"
+}
+
+/// Emits a call to a runtime function implemented in Rust. Runtime
+/// functions take words as arguments and return a single word.
+pub(crate) fn emit_runtime_call(
+    name: &str,
+    args: &[Value],
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let mut sig = ctx.module.make_signature();
+    for _ in args {
+        sig.params.push(AbiParam::new(ctx.word));
+    }
+    sig.returns.push(AbiParam::new(ctx.word));
+
+    let callee = ctx
+        .module
+        .declare_function(name, cranelift_module::Linkage::Import, &sig)
+        .map_err(LustError::from)?;
+
+    let local_callee = ctx.module.declare_func_in_func(callee, ctx.builder.func);
+
+    let call = ctx.builder.ins().call(local_callee, args);
+    Ok(ctx.builder.inst_results(call)[0])
+}
+
+/// Emits code to allocate a pair holding DATA and NEXT and returns
+/// the tagged pointer to it.
+pub(crate) fn emit_cons(data: Value, next: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    let storage = emit_alloc((ctx.word.bytes() * 2).into(), ctx)?;
+
+    ctx.builder.ins().store(MemFlags::new(), data, storage, 0);
+    ctx.builder
+        .ins()
+        .store(MemFlags::new(), next, storage, ctx.word.bytes() as i32);
+
+    Ok(ctx.builder.ins().bor_imm(storage, conversions::PAIR_TAG))
 }
 
-fn check_arg_len(name: &str, args: &[Expr], expected: usize) -> Result<(), String> {
+fn check_arg_len(name: &str, args: &[Expr], expected: usize) -> Result<(), LustError> {
     if args.len() != expected {
-        Err(format!(
-            "{} expected {} args and got {}",
-            name,
+        Err(LustError::ArityMismatch {
+            name: name.to_string(),
             expected,
-            args.len()
-        ))
+            got: args.len(),
+            varadic: false,
+        })
     } else {
         Ok(())
     }
@@ -962,7 +2236,7 @@ pub(crate) fn emit_contigous_to_list(
     ctx: &mut crate::compiler::Context,
     ptr: Value,
     len: Value,
-) -> Result<Value, String> {
+) -> Result<Value, LustError> {
     let word = ctx.module.target_config().pointer_type();
 
     let mut sig = ctx.module.make_signature();
@@ -979,7 +2253,7 @@ pub(crate) fn emit_contigous_to_list(
             cranelift_module::Linkage::Import,
             &sig,
         )
-        .map_err(|e| e.to_string())?;
+        .map_err(LustError::from)?;
 
     let local_callee = ctx
         .module
@@ -995,7 +2269,7 @@ pub(crate) fn emit_contigous_to_list(
 
 // Defines the function contiguous-to-list which converts a contiguous
 // vec of values into a list.
-pub(crate) fn define_contiguous_to_list(jit: &mut JIT) -> Result<(), String> {
+pub(crate) fn define_contiguous_to_list(jit: &mut JIT) -> Result<(), LustError> {
     let word = jit.module.target_config().pointer_type();
 
     // A pointer to the beginning of the contiguous memory
@@ -1040,10 +2314,10 @@ pub(crate) fn define_contiguous_to_list(jit: &mut JIT) -> Result<(), String> {
     let callee = jit
         .module
         .declare_function("alloc", cranelift_module::Linkage::Import, &sig)
-        .map_err(|e| e.to_string())?;
+        .map_err(LustError::from)?;
 
     let local_callee = jit.module.declare_func_in_func(callee, &mut builder.func);
-    let size = builder.ins().iconst(word, 2);
+    let size = builder.ins().iconst(word, (word.bytes() * 2) as i64);
     let args = vec![size];
     let call = builder.ins().call(local_callee, &args);
 
@@ -1063,7 +2337,7 @@ pub(crate) fn define_contiguous_to_list(jit: &mut JIT) -> Result<(), String> {
             cranelift_module::Linkage::Import,
             &sig,
         )
-        .map_err(|e| e.to_string())?;
+        .map_err(LustError::from)?;
 
     let local_callee = jit.module.declare_func_in_func(callee, &mut builder.func);
 
@@ -1086,20 +2360,7 @@ pub(crate) fn define_contiguous_to_list(jit: &mut JIT) -> Result<(), String> {
     builder.seal_all_blocks();
     builder.finalize();
 
-    let id = jit
-        .module
-        .declare_function(
-            "contiguous-to-list",
-            cranelift_module::Linkage::Export,
-            &jit.context.func.signature,
-        )
-        .map_err(|e| e.to_string())?;
-
-    jit.module
-        .define_function(id, &mut jit.context, &mut NullTrapSink {})
-        .map_err(|e| e.to_string())?;
-
-    jit.module.clear_context(&mut jit.context);
+    jit.define_function("contiguous-to-list")?;
 
     Ok(())
 }
@@ -1112,14 +2373,14 @@ mod tests {
 
     fn test_evaluation(expr: Expr, expected: Expr) {
         assert_eq!(
-            crate::compiler::roundtrip_program(&mut [expr]).unwrap(),
+            crate::compiler::roundtrip_program(&[expr]).unwrap(),
             expected
         )
     }
 
     #[test]
     fn add1() {
-        let ast = Expr::List(vec![Expr::Symbol("add1".to_string()), Expr::Integer(1)]);
+        let ast = Expr::list(vec![Expr::Symbol("add1".to_string()), Expr::Integer(1)]);
         let expected = Expr::Integer(2);
         test_evaluation(ast, expected);
     }
@@ -1127,11 +2388,11 @@ mod tests {
     #[test]
     fn nested_add1() {
         // (add1 (add1 (add1 1)))
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("add1".to_string()),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("add1".to_string()),
-                Expr::List(vec![Expr::Symbol("add1".to_string()), Expr::Integer(1)]),
+                Expr::list(vec![Expr::Symbol("add1".to_string()), Expr::Integer(1)]),
             ]),
         ]);
         let expected = Expr::Integer(4);
@@ -1141,22 +2402,55 @@ mod tests {
     #[test]
     fn add1_comprehensive() {
         for i in -10..10 {
-            let ast = Expr::List(vec![Expr::Symbol("add1".to_string()), Expr::Integer(i)]);
+            let ast = Expr::list(vec![Expr::Symbol("add1".to_string()), Expr::Integer(i)]);
             let expected = Expr::Integer(i + 1);
             test_evaluation(ast, expected);
         }
     }
 
+    #[test]
+    fn add1_and_sub1() {
+        assert_eq!(roundtrip_string("(add1 -1)").unwrap(), Expr::Integer(0));
+        assert_eq!(roundtrip_string("(sub1 0)").unwrap(), Expr::Integer(-1));
+        assert_eq!(
+            roundtrip_string("(sub1 (add1 41))").unwrap(),
+            Expr::Integer(41)
+        );
+        // Keep the arguments from being folded away.
+        let program = crate::parse_string("(cons (add1 n) (sub1 n))").unwrap();
+        for n in -10..10 {
+            assert_eq!(
+                crate::compiler::eval_program_with_args(&program, &["n"], &[n]),
+                Ok(Expr::Pair(
+                    Box::new(Expr::Integer(n + 1)),
+                    Box::new(Expr::Integer(n - 1))
+                ))
+            );
+        }
+        // The result is still tagged as a fixnum.
+        let program =
+            crate::parse_string("(cons (integer? (sub1 n)) (integer? (add1 n)))").unwrap();
+        assert_eq!(
+            crate::compiler::eval_program_with_args(&program, &["n"], &[-1]),
+            Ok(Expr::Pair(
+                Box::new(Expr::Bool(true)),
+                Box::new(Expr::Bool(true))
+            ))
+        );
+        let source = "(let f sub1) (let g add1) (f (g (f 5)))";
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(4));
+    }
+
     #[test]
     fn integer_to_char() {
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("integer->char".to_string()),
             Expr::Integer(0x2764),
         ]);
         let expected = Expr::Char('❤');
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("integer->char".to_string()),
             Expr::Integer(128175),
         ]);
@@ -1167,9 +2461,9 @@ mod tests {
     #[test]
     fn char_to_integer() {
         for c in 'm'..'q' {
-            let ast = Expr::List(vec![
+            let ast = Expr::list(vec![
                 Expr::Symbol("integer->char".to_string()),
-                Expr::List(vec![
+                Expr::list(vec![
                     Expr::Symbol("char->integer".to_string()),
                     Expr::Char(c),
                 ]),
@@ -1179,96 +2473,221 @@ mod tests {
         }
     }
 
+    #[test]
+    fn char_integer_roundtrip() {
+        assert_eq!(
+            roundtrip_string("(char->integer #\\A)").unwrap(),
+            Expr::Integer(65)
+        );
+        assert_eq!(
+            roundtrip_string("(integer->char 65)").unwrap(),
+            Expr::Char('A')
+        );
+        assert_eq!(
+            roundtrip_string("(integer->char (char->integer #\\A))").unwrap(),
+            Expr::Char('A')
+        );
+        assert_eq!(
+            roundtrip_string("(let c integer->char) (let i char->integer) (c (i #\\A))").unwrap(),
+            Expr::Char('A')
+        );
+    }
+
+    #[test]
+    fn integer_to_char_bounds() {
+        for i in [0, 0xD7FF, 0xE000, 0x10FFFF].iter() {
+            assert_eq!(
+                roundtrip_string(&format!("(integer->char {})", i)).unwrap(),
+                Expr::Char(std::char::from_u32(*i).unwrap())
+            );
+        }
+    }
+
     #[test]
     fn is_null() {
-        let ast = Expr::List(vec![Expr::Symbol("null?".to_string()), Expr::Nil]);
+        let ast = Expr::list(vec![Expr::Symbol("null?".to_string()), Expr::Nil]);
         let expected = Expr::Bool(true);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![Expr::Symbol("null?".to_string()), Expr::Integer(0)]);
+        let ast = Expr::list(vec![Expr::Symbol("null?".to_string()), Expr::Integer(0)]);
         let expected = Expr::Bool(false);
         test_evaluation(ast, expected);
     }
 
     #[test]
     fn is_zero() {
-        let ast = Expr::List(vec![Expr::Symbol("zero?".to_string()), Expr::Nil]);
+        let ast = Expr::list(vec![Expr::Symbol("zero?".to_string()), Expr::Nil]);
         let expected = Expr::Bool(false);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![Expr::Symbol("zero?".to_string()), Expr::Integer(0)]);
+        let ast = Expr::list(vec![Expr::Symbol("zero?".to_string()), Expr::Integer(0)]);
         let expected = Expr::Bool(true);
         test_evaluation(ast, expected);
     }
 
+    #[test]
+    fn sign_predicates() {
+        let cases = [
+            ("(zero? 0)", true),
+            ("(zero? 7)", false),
+            ("(positive? 5)", true),
+            ("(positive? 0)", false),
+            ("(positive? -5)", false),
+            ("(negative? 5)", false),
+            ("(negative? 0)", false),
+            ("(negative? -5)", true),
+            ("(positive? 0.5)", true),
+            ("(negative? -0.5)", true),
+            ("(negative? most-negative-fixnum)", true),
+            ("(positive? most-positive-fixnum)", true),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(
+                roundtrip_string(source).unwrap(),
+                Expr::Bool(expected),
+                "{}",
+                source
+            );
+        }
+        let source = r#"
+(let id (fn (x) x))
+(list (map positive? (list 3 -1 0)) (map negative? (list 3 -1 0)) (negative? (id -4)))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'((#t #f #f) (#f #t #f) #t)").unwrap()
+        );
+    }
+
+    #[test]
+    fn boolean_eq() {
+        let cases = [
+            ("(boolean=? #t #t)", true),
+            ("(boolean=? #f #f)", true),
+            ("(boolean=? #t #f)", false),
+            ("(boolean=? #f (eq? 1 1))", false),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(
+                roundtrip_string(source).unwrap(),
+                Expr::Bool(expected),
+                "{}",
+                source
+            );
+        }
+        let source = "(let b boolean=?) (list (b #t #t) (b #t #f))";
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(#t #f)").unwrap()
+        );
+    }
+
     #[test]
     fn not() {
-        let ast = Expr::List(vec![Expr::Symbol("not".to_string()), Expr::Bool(false)]);
+        let ast = Expr::list(vec![Expr::Symbol("not".to_string()), Expr::Bool(false)]);
         let expected = Expr::Bool(true);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![Expr::Symbol("not".to_string()), Expr::Bool(true)]);
+        let ast = Expr::list(vec![Expr::Symbol("not".to_string()), Expr::Bool(true)]);
         let expected = Expr::Bool(false);
         test_evaluation(ast, expected);
+
+        // Only #f is false. Everything else, nil included, is true.
+        assert_eq!(roundtrip_string("(not 0)").unwrap(), Expr::Bool(false));
+        assert_eq!(roundtrip_string("(not ())").unwrap(), Expr::Bool(false));
+        assert_eq!(roundtrip_string("(not #\\a)").unwrap(), Expr::Bool(false));
+    }
+
+    #[test]
+    fn is_eq() {
+        assert_eq!(roundtrip_string("(eq? 3 3)").unwrap(), Expr::Bool(true));
+        assert_eq!(roundtrip_string("(eq? 3 4)").unwrap(), Expr::Bool(false));
+        assert_eq!(
+            roundtrip_string("(eq? #\\a #\\a)").unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(roundtrip_string("(eq? #t #f)").unwrap(), Expr::Bool(false));
+        // Heap objects are compared by identity.
+        let source = r#"
+(let p (cons 1 2))
+(cons (eq? p p) (eq? p (cons 1 2)))
+"#;
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(Expr::Bool(true), Expr::Bool(false))
+        )
     }
 
     #[test]
     fn is_boolean() {
-        let ast = Expr::List(vec![Expr::Symbol("boolean?".to_string()), Expr::Nil]);
+        let ast = Expr::list(vec![Expr::Symbol("boolean?".to_string()), Expr::Nil]);
         let expected = Expr::Bool(false);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![Expr::Symbol("boolean?".to_string()), Expr::Integer(1)]);
+        let ast = Expr::list(vec![Expr::Symbol("boolean?".to_string()), Expr::Integer(1)]);
         let expected = Expr::Bool(false);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![Expr::Symbol("boolean?".to_string()), Expr::Char('a')]);
+        let ast = Expr::list(vec![Expr::Symbol("boolean?".to_string()), Expr::Char('a')]);
         let expected = Expr::Bool(false);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("boolean?".to_string()),
             Expr::Bool(false),
         ]);
         let expected = Expr::Bool(true);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![Expr::Symbol("boolean?".to_string()), Expr::Bool(true)]);
+        let ast = Expr::list(vec![Expr::Symbol("boolean?".to_string()), Expr::Bool(true)]);
         let expected = Expr::Bool(true);
         test_evaluation(ast, expected);
     }
 
     #[test]
     fn is_integer() {
-        let ast = Expr::List(vec![Expr::Symbol("integer?".to_string()), Expr::Nil]);
+        let ast = Expr::list(vec![Expr::Symbol("integer?".to_string()), Expr::Nil]);
         let expected = Expr::Bool(false);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![Expr::Symbol("integer?".to_string()), Expr::Char('a')]);
+        let ast = Expr::list(vec![Expr::Symbol("integer?".to_string()), Expr::Char('a')]);
         let expected = Expr::Bool(false);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("integer?".to_string()),
             Expr::Bool(false),
         ]);
         let expected = Expr::Bool(false);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![Expr::Symbol("integer?".to_string()), Expr::Bool(true)]);
+        let ast = Expr::list(vec![Expr::Symbol("integer?".to_string()), Expr::Bool(true)]);
         let expected = Expr::Bool(false);
         test_evaluation(ast, expected);
 
         for i in -10..10 {
-            let ast = Expr::List(vec![Expr::Symbol("integer?".to_string()), Expr::Integer(i)]);
+            let ast = Expr::list(vec![Expr::Symbol("integer?".to_string()), Expr::Integer(i)]);
             let expected = Expr::Bool(true);
             test_evaluation(ast, expected);
         }
     }
 
+    #[test]
+    fn is_fixnum() {
+        assert_eq!(
+            roundtrip_string("(cons (fixnum? 1) (fixnum? 1.5))").unwrap(),
+            Expr::Pair(Box::new(Expr::Bool(true)), Box::new(Expr::Bool(false)))
+        );
+        assert_eq!(
+            roundtrip_string("(let f fixnum?) (f most-negative-fixnum)").unwrap(),
+            Expr::Bool(true)
+        );
+    }
+
     #[test]
     fn add() {
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("add".to_string()),
             Expr::Integer(1),
             Expr::Integer(1),
@@ -1276,7 +2695,7 @@ mod tests {
         let expected = Expr::Integer(2);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("add".to_string()),
             Expr::Integer(-1000),
             Expr::Integer(10),
@@ -1284,10 +2703,10 @@ mod tests {
         let expected = Expr::Integer(-990);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("add".to_string()),
             Expr::Integer(1),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("add".to_string()),
                 Expr::Integer(-1),
                 Expr::Integer(-1),
@@ -1299,7 +2718,7 @@ mod tests {
 
     #[test]
     fn mul() {
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("mul".to_string()),
             Expr::Integer(1),
             Expr::Integer(1),
@@ -1307,7 +2726,7 @@ mod tests {
         let expected = Expr::Integer(1);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("mul".to_string()),
             Expr::Integer(-1000),
             Expr::Integer(10),
@@ -1315,10 +2734,10 @@ mod tests {
         let expected = Expr::Integer(-10000);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("mul".to_string()),
             Expr::Integer(1),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("add".to_string()),
                 Expr::Integer(-1),
                 Expr::Integer(-1),
@@ -1330,7 +2749,7 @@ mod tests {
 
     #[test]
     fn sub() {
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("sub".to_string()),
             Expr::Integer(1),
             Expr::Integer(1),
@@ -1338,7 +2757,7 @@ mod tests {
         let expected = Expr::Integer(0);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("sub".to_string()),
             Expr::Integer(-1000),
             Expr::Integer(10),
@@ -1346,10 +2765,10 @@ mod tests {
         let expected = Expr::Integer(-1010);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("sub".to_string()),
             Expr::Integer(1),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("sub".to_string()),
                 Expr::Integer(-1),
                 Expr::Integer(-1),
@@ -1361,7 +2780,7 @@ mod tests {
 
     #[test]
     fn eq() {
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("eq".to_string()),
             Expr::Integer(1),
             Expr::Integer(1),
@@ -1369,7 +2788,7 @@ mod tests {
         let expected = Expr::Bool(true);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("eq".to_string()),
             Expr::Char('🚨'),
             Expr::Integer(1),
@@ -1377,10 +2796,10 @@ mod tests {
         let expected = Expr::Bool(false);
         test_evaluation(ast, expected);
 
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("eq".to_string()),
             Expr::Nil,
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("sub".to_string()),
                 Expr::Integer(-1),
                 Expr::Integer(-1),
@@ -1392,9 +2811,9 @@ mod tests {
 
     #[test]
     fn is_pair() {
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("pair?".to_string()),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("cons".to_string()),
                 Expr::Integer(-1),
                 Expr::Integer(-1),
@@ -1406,9 +2825,9 @@ mod tests {
 
     #[test]
     fn car() {
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("car".to_string()),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("cons".to_string()),
                 Expr::Integer(-1),
                 Expr::Integer(2),
@@ -1421,9 +2840,9 @@ mod tests {
 
     #[test]
     fn cdr() {
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("cdr".to_string()),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("cons".to_string()),
                 Expr::Integer(-1),
                 Expr::Integer(2),
@@ -1436,14 +2855,14 @@ mod tests {
 
     #[test]
     fn car_cdr_car() {
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("car".to_string()),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("cdr".to_string()),
-                Expr::List(vec![
+                Expr::list(vec![
                     Expr::Symbol("cons".to_string()),
                     Expr::Integer(1),
-                    Expr::List(vec![
+                    Expr::list(vec![
                         Expr::Symbol("cons".to_string()),
                         Expr::Integer(2),
                         Expr::Integer(3),
@@ -1457,14 +2876,14 @@ mod tests {
 
     #[test]
     fn car_cdr_car_mix() {
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("car".to_string()),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("cdr".to_string()),
-                Expr::List(vec![
+                Expr::list(vec![
                     Expr::Symbol("cons".to_string()),
                     Expr::Char('a'),
-                    Expr::List(vec![
+                    Expr::list(vec![
                         Expr::Symbol("cons".to_string()),
                         Expr::Bool(true),
                         Expr::Integer(2),
@@ -1478,18 +2897,18 @@ mod tests {
 
     #[test]
     fn tree() {
-        let ast = Expr::List(vec![
+        let ast = Expr::list(vec![
             Expr::Symbol("car".to_string()),
-            Expr::List(vec![
+            Expr::list(vec![
                 Expr::Symbol("car".to_string()),
-                Expr::List(vec![
+                Expr::list(vec![
                     Expr::Symbol("cons".to_string()),
-                    Expr::List(vec![
+                    Expr::list(vec![
                         Expr::Symbol("cons".to_string()),
                         Expr::Bool(false),
                         Expr::Integer(2),
                     ]),
-                    Expr::List(vec![
+                    Expr::list(vec![
                         Expr::Symbol("cons".to_string()),
                         Expr::Bool(true),
                         Expr::Integer(2),
@@ -1502,6 +2921,78 @@ mod tests {
         test_evaluation(ast, expected);
     }
 
+    #[test]
+    fn cons_list() {
+        let expected = Expr::Pair(
+            Box::new(Expr::Integer(1)),
+            Box::new(Expr::Pair(Box::new(Expr::Integer(2)), Box::new(Expr::Nil))),
+        );
+        assert_eq!(roundtrip_string("(cons 1 (cons 2 ()))").unwrap(), expected);
+
+        let source = r#"
+(let l (cons 1 (cons 2 ())))
+(cons (car l) (cons (car (cdr l)) (cdr (cdr l))))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn list() {
+        assert_eq!(roundtrip_string("(list)").unwrap(), Expr::Nil);
+
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        let expected = cons(
+            Expr::Integer(1),
+            cons(Expr::Integer(2), cons(Expr::Integer(3), Expr::Nil)),
+        );
+        assert_eq!(roundtrip_string("(list 1 2 3)").unwrap(), expected);
+        assert_eq!(
+            roundtrip_string("(cons 1 (cons 2 (cons 3 ())))").unwrap(),
+            roundtrip_string("(list 1 2 3)").unwrap()
+        );
+
+        let source = r#"
+(let l (list 1 (add 1 1) 3))
+(list (car l) (car (cdr l)) (car (cdr (cdr l))) (cdr (cdr (cdr l))))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(
+                Expr::Integer(1),
+                cons(
+                    Expr::Integer(2),
+                    cons(Expr::Integer(3), cons(Expr::Nil, Expr::Nil))
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn higher_order_list() {
+        let source = r#"
+(let make list)
+(cons (make) (make 1 2))
+"#;
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(
+                Expr::Nil,
+                cons(Expr::Integer(1), cons(Expr::Integer(2), Expr::Nil))
+            )
+        );
+    }
+
+    #[test]
+    fn higher_order_car_cdr() {
+        let source = r#"
+(let first car)
+(let rest cdr)
+(first (rest (cons 1 (cons 2 ()))))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(2));
+    }
+
     #[test]
     fn higher_order_builtin_assignment() {
         let source = r#"
@@ -1543,6 +3034,455 @@ mod tests {
         assert_eq!(Expr::Integer(2), res)
     }
 
+    #[test]
+    fn comparisons() {
+        assert_eq!(roundtrip_string("(< 1 2)").unwrap(), Expr::Bool(true));
+        assert_eq!(roundtrip_string("(< 2 1)").unwrap(), Expr::Bool(false));
+        assert_eq!(roundtrip_string("(> 2 1)").unwrap(), Expr::Bool(true));
+        assert_eq!(roundtrip_string("(> 1 2)").unwrap(), Expr::Bool(false));
+        assert_eq!(roundtrip_string("(= 3 3)").unwrap(), Expr::Bool(true));
+        assert_eq!(roundtrip_string("(= 3 4)").unwrap(), Expr::Bool(false));
+    }
+
+    #[test]
+    fn comparisons_equal_operands() {
+        assert_eq!(roundtrip_string("(< 2 2)").unwrap(), Expr::Bool(false));
+        assert_eq!(roundtrip_string("(> 2 2)").unwrap(), Expr::Bool(false));
+        assert_eq!(roundtrip_string("(<= 2 2)").unwrap(), Expr::Bool(true));
+        assert_eq!(roundtrip_string("(>= 2 2)").unwrap(), Expr::Bool(true));
+        assert_eq!(roundtrip_string("(<= 3 2)").unwrap(), Expr::Bool(false));
+        assert_eq!(roundtrip_string("(>= 2 3)").unwrap(), Expr::Bool(false));
+    }
+
+    #[test]
+    fn comparisons_negative() {
+        assert_eq!(roundtrip_string("-3").unwrap(), Expr::Integer(-3));
+        assert_eq!(roundtrip_string("(< -3 2)").unwrap(), Expr::Bool(true));
+        assert_eq!(roundtrip_string("(> -3 -4)").unwrap(), Expr::Bool(true));
+        assert_eq!(roundtrip_string("(<= -4 -3)").unwrap(), Expr::Bool(true));
+        assert_eq!(roundtrip_string("(>= -1 0)").unwrap(), Expr::Bool(false));
+        assert_eq!(
+            roundtrip_string("(= -2 (sub 0 2))").unwrap(),
+            Expr::Bool(true)
+        );
+    }
+
+    #[test]
+    fn comparison_in_if() {
+        let source = r#"
+(let max (fn (a b) (if (>= a b) a b)))
+(cons (max 1 2) (max -1 -2))
+"#;
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(Expr::Integer(2), Expr::Integer(-1))
+        )
+    }
+
+    #[test]
+    fn higher_order_comparison() {
+        let source = r#"
+(let apply (fn (op a b) (op a b)))
+(cons (apply <= 1 1) (apply = 1 2))
+"#;
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(Expr::Bool(true), Expr::Bool(false))
+        )
+    }
+
+    #[test]
+    fn quotient() {
+        assert_eq!(
+            roundtrip_string("(quotient 7 2)").unwrap(),
+            Expr::Integer(3)
+        );
+        assert_eq!(
+            roundtrip_string("(quotient -7 2)").unwrap(),
+            Expr::Integer(-3)
+        );
+        assert_eq!(
+            roundtrip_string("(quotient 7 -2)").unwrap(),
+            Expr::Integer(-3)
+        );
+        assert_eq!(
+            roundtrip_string("(quotient -7 -2)").unwrap(),
+            Expr::Integer(3)
+        );
+        assert_eq!(
+            roundtrip_string("(quotient 6 3)").unwrap(),
+            Expr::Integer(2)
+        );
+    }
+
+    #[test]
+    fn remainder_and_modulo() {
+        // Remainder follows the dividend.
+        assert_eq!(
+            roundtrip_string("(remainder 7 2)").unwrap(),
+            Expr::Integer(1)
+        );
+        assert_eq!(
+            roundtrip_string("(remainder -7 2)").unwrap(),
+            Expr::Integer(-1)
+        );
+        assert_eq!(
+            roundtrip_string("(remainder 7 -2)").unwrap(),
+            Expr::Integer(1)
+        );
+        assert_eq!(
+            roundtrip_string("(remainder -7 -2)").unwrap(),
+            Expr::Integer(-1)
+        );
+        // Modulo follows the divisor.
+        assert_eq!(roundtrip_string("(modulo 7 2)").unwrap(), Expr::Integer(1));
+        assert_eq!(roundtrip_string("(modulo -7 2)").unwrap(), Expr::Integer(1));
+        assert_eq!(
+            roundtrip_string("(modulo 7 -2)").unwrap(),
+            Expr::Integer(-1)
+        );
+        assert_eq!(
+            roundtrip_string("(modulo -7 -2)").unwrap(),
+            Expr::Integer(-1)
+        );
+        // No correction when the division is exact.
+        assert_eq!(roundtrip_string("(modulo -6 3)").unwrap(), Expr::Integer(0));
+        assert_eq!(
+            roundtrip_string("(remainder -6 3)").unwrap(),
+            Expr::Integer(0)
+        );
+    }
+
+    #[test]
+    fn higher_order_division() {
+        let source = r#"
+(let apply (fn (op a b) (op a b)))
+(cons (apply quotient 9 2) (apply modulo -9 2))
+"#;
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(Expr::Integer(4), Expr::Integer(1))
+        )
+    }
+
+    #[test]
+    fn bitwise() {
+        assert_eq!(
+            roundtrip_string("(bitwise-and 12 10)").unwrap(),
+            Expr::Integer(8)
+        );
+        assert_eq!(
+            roundtrip_string("(bitwise-or 12 10)").unwrap(),
+            Expr::Integer(14)
+        );
+        assert_eq!(
+            roundtrip_string("(bitwise-xor 12 10)").unwrap(),
+            Expr::Integer(6)
+        );
+        assert_eq!(
+            roundtrip_string("(bitwise-and -1 5)").unwrap(),
+            Expr::Integer(5)
+        );
+        assert_eq!(
+            roundtrip_string("(bitwise-xor -1 0)").unwrap(),
+            Expr::Integer(-1)
+        );
+    }
+
+    #[test]
+    fn arithmetic_shift() {
+        for (n, count, expected) in [
+            (1, 4, 16),
+            (3, 0, 3),
+            (16, -2, 4),
+            (7, -1, 3),
+            (-7, -1, -4),
+            (-1, -100, -1),
+            (5, -100, 0),
+            (5, 100, 0),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                roundtrip_string(&format!("(arithmetic-shift {} {})", n, count)).unwrap(),
+                Expr::Integer(*expected)
+            );
+        }
+
+        // The result of a shift is still an integer.
+        assert_eq!(
+            roundtrip_string("(integer? (arithmetic-shift 9 -2))").unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(
+            roundtrip_string("(add1 (arithmetic-shift 1 3))").unwrap(),
+            Expr::Integer(9)
+        );
+    }
+
+    #[test]
+    fn higher_order_bitwise() {
+        let source = r#"
+(let apply (fn (op a b) (op a b)))
+(cons (apply bitwise-or 1 2) (apply arithmetic-shift 1 2))
+"#;
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(Expr::Integer(3), Expr::Integer(4))
+        )
+    }
+
+    #[test]
+    fn apply() {
+        assert_eq!(
+            roundtrip_string("(apply add (cons 1 (cons 2 ())))").unwrap(),
+            Expr::Integer(3)
+        );
+        assert_eq!(
+            roundtrip_string("(apply (fn () 5) ())").unwrap(),
+            Expr::Integer(5)
+        );
+
+        let source = r#"
+(let rest (fn (a . rest) rest))
+(apply rest (cons 1 (cons 2 (cons 3 ()))))
+"#;
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(Expr::Integer(2), cons(Expr::Integer(3), Expr::Nil))
+        );
+    }
+
+    #[test]
+    fn higher_order_apply() {
+        let source = r#"
+(let call apply)
+(call mul (cons 6 (cons 7 ())))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(42));
+    }
+
+    #[test]
+    fn fold() {
+        assert_eq!(
+            roundtrip_string("(fold add 0 (list 1 2 3))").unwrap(),
+            Expr::Integer(6)
+        );
+        assert_eq!(
+            roundtrip_string("(fold add 42 ())").unwrap(),
+            Expr::Integer(42)
+        );
+
+        // The accumulator comes first so folding with cons reverses
+        // the list into nested pairs.
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string("(fold (fn (acc x) (cons x acc)) () (list 1 2 3))").unwrap(),
+            cons(
+                Expr::Integer(3),
+                cons(Expr::Integer(2), cons(Expr::Integer(1), Expr::Nil))
+            )
+        );
+
+        let source = r#"
+(let scale 10)
+(fold (fn (acc x) (add (mul acc scale) x)) 0 (list 1 2 3))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(123));
+    }
+
+    #[test]
+    fn fold_long_list() {
+        // Folding is a loop so long lists don't grow the stack.
+        let source = r#"
+(let range (fn (n acc) (if (eq n 0) acc (range (sub n 1) (cons n acc)))))
+(fold add 0 (range 100000 ()))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(5000050000));
+    }
+
+    #[test]
+    fn higher_order_fold() {
+        let source = r#"
+(let reduce fold)
+(reduce mul 1 (list 1 2 3 4))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(24));
+    }
+
+    #[test]
+    fn map() {
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string("(map add1 (list 1 2 3))").unwrap(),
+            cons(
+                Expr::Integer(2),
+                cons(Expr::Integer(3), cons(Expr::Integer(4), Expr::Nil))
+            )
+        );
+        assert_eq!(roundtrip_string("(map add1 ())").unwrap(), Expr::Nil);
+
+        let source = r#"
+(let l (list 1 2))
+(let m (map (fn (x) (cons x l)) l))
+(list (car (car m)) (car (car (cdr m))) (cdr (cdr m)) (eq? (cdr (car m)) l))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("(list 1 2 () #t)").unwrap()
+        );
+    }
+
+    #[test]
+    fn map_many_allocations() {
+        // Enough is allocated here that the collector runs partway
+        // through building the result.
+        let source = r#"
+(let range (fn (n acc) (if (eq n 0) acc (range (sub n 1) (cons n acc)))))
+(let l (map (fn (x) (list x x x x)) (range 100000 ())))
+(cons (length l) (fold (fn (acc x) (add acc (car x))) 0 l))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::Pair(
+                Box::new(Expr::Integer(100000)),
+                Box::new(Expr::Integer(5000050000))
+            )
+        );
+    }
+
+    #[test]
+    fn higher_order_map() {
+        let source = r#"
+(let each map)
+(each (fn (x) (mul x x)) (list 1 2 3))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("(list 1 4 9)").unwrap()
+        );
+    }
+
+    #[test]
+    fn gensym() {
+        let source = r#"
+(let a (gensym))
+(let b (gensym))
+(list (eq? a b) (eq? a a) (symbol? a) (eq? a 'g0))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("(list #f #t #t #f)").unwrap()
+        );
+
+        let source = r#"
+(let make gensym)
+(eq? (make) (make))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Bool(false));
+    }
+
+    #[test]
+    fn display() {
+        use crate::conversions::capture_output;
+
+        let source = r#"
+(display 42)
+(display #\a)
+(display #t)
+(display #f)
+(display ())
+(display (cons 1 (cons #\b (cons "c" ()))))
+(display (cons 1 2))
+"#;
+        let (res, output) = capture_output(|| roundtrip_string(source));
+        assert_eq!(res.unwrap(), Expr::Nil);
+        assert_eq!(output, "42a#t#f()(1 b c)(1 . 2)");
+
+        let source = r#"
+(let show display)
+(show -7)
+"#;
+        let (res, output) = capture_output(|| roundtrip_string(source));
+        assert_eq!(res.unwrap(), Expr::Nil);
+        assert_eq!(output, "-7");
+    }
+
+    #[test]
+    fn write_and_newline() {
+        use crate::conversions::capture_output;
+
+        let source = r#"
+(write "say \"hi\"")
+(newline)
+(write (cons #\a (cons #\space (cons "b" ()))))
+(newline)
+(display "say \"hi\"")
+"#;
+        let (res, output) = capture_output(|| roundtrip_string(source));
+        assert_eq!(res.unwrap(), Expr::Nil);
+        assert_eq!(
+            output,
+            "\"say \\\"hi\\\"\"\n(#\\a #\\space \"b\")\nsay \"hi\""
+        );
+
+        // What write prints can be read back in.
+        let written: Vec<&str> = output.lines().take(2).collect();
+        assert_eq!(
+            crate::reader::parse(written[0]).unwrap(),
+            vec![Expr::String("say \"hi\"".to_string())]
+        );
+        assert_eq!(
+            crate::reader::parse(written[1]).unwrap(),
+            crate::reader::parse("(#\\a #\\space \"b\")").unwrap()
+        );
+
+        let source = r#"
+(let w write)
+(let n newline)
+(w #\z)
+(n)
+"#;
+        let (res, output) = capture_output(|| roundtrip_string(source));
+        assert_eq!(res.unwrap(), Expr::Nil);
+        assert_eq!(output, "#\\z\n");
+    }
+
+    #[test]
+    fn type_predicates() {
+        let predicates = ["integer?", "char?", "boolean?", "null?", "pair?", "symbol?"];
+        let values = ["1", "#\\a", "#t", "()", "(cons 1 2)", "'a"];
+        for (i, predicate) in predicates.iter().enumerate() {
+            for (j, value) in values.iter().enumerate() {
+                let source = format!("({} {})", predicate, value);
+                assert_eq!(
+                    roundtrip_string(&source).unwrap(),
+                    Expr::Bool(i == j),
+                    "{}",
+                    source
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn higher_order_type_predicate() {
+        let source = r#"
+(let check (fn (p v) (p v)))
+(cons (check char? #\a) (check char? 97))
+"#;
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(Expr::Bool(true), Expr::Bool(false))
+        )
+    }
+
     #[test]
     fn is_closure() {
         let source = r#"
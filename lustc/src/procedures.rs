@@ -2,11 +2,16 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 
 use crate::compiler::{emit_expr, JIT};
-use crate::heap::emit_alloc;
+use crate::conversions;
+use crate::errors::LustError;
+use crate::fatal;
+use crate::heap::{emit_alloc, emit_dynamic_alloc};
 use crate::locals::emit_var_decl_and_assign;
 use crate::primitives::emit_contigous_to_list;
 use crate::primitives::string_is_builtin;
+use crate::renamer::original_name;
 use crate::Expr;
+use crate::PreorderStatus;
 use crate::{compiler::Context, fatal::emit_check_callable};
 use cranelift::prelude::*;
 use cranelift_module::{Linkage, Module};
@@ -18,7 +23,7 @@ impl Expr {
     /// to its arguments. Otherwise, returns None.
     pub fn is_fncall(&self) -> Option<(&Expr, &[Expr])> {
         match self {
-            Self::List(v) => {
+            Self::List(v, _) => {
                 if let Some(e) = v.first() {
                     Some((e, &v[1..]))
                 } else {
@@ -32,7 +37,7 @@ impl Expr {
     /// Determines if the expression is a function definition and if
     /// it is returns a tuple containing its paramaters and its body.
     pub fn is_fndef(&self) -> Option<(Vec<&String>, &[Expr])> {
-        if let Self::List(v) = self {
+        if let Self::List(v, _) = self {
             if let Some(Expr::Symbol(s)) = v.first() {
                 if s == "fn" && v.len() >= 3 {
                     let params = Self::collect_list_of_symbols(&v[1])?;
@@ -48,7 +53,7 @@ impl Expr {
     /// collecting arguments to a function.
     fn collect_list_of_symbols(expr: &Expr) -> Option<Vec<&String>> {
         match expr {
-            Expr::List(v) => {
+            Expr::List(v, _) => {
                 let mut res = Vec::with_capacity(v.len());
                 for e in v {
                     match e {
@@ -76,7 +81,7 @@ pub fn emit_procedure(
     body: &[Expr],
     varadic_symbol: &Option<String>,
     fnmap: &HashMap<String, LustFn>,
-) -> Result<(), String> {
+) -> Result<(), LustError> {
     let word = jit.module.target_config().pointer_type();
 
     // Closure param
@@ -99,6 +104,15 @@ pub fn emit_procedure(
     builder.switch_to_block(entry_block);
     builder.seal_block(entry_block);
 
+    // Self tail calls jump to the loop header with new arguments
+    // instead of making a call. It takes the same arguments as the
+    // function itself and isn't sealed until the body is compiled as
+    // that is when we know all of the tail calls that jump to it.
+    let loop_header = builder.create_block();
+    for _ in 0..3 {
+        builder.append_block_param(loop_header, word);
+    }
+    let options = jit.options;
     let mut ctx = Context::new(
         builder,
         &mut jit.module,
@@ -107,9 +121,16 @@ pub fn emit_procedure(
         fnmap.clone(),
         Vec::new(),
     );
+    ctx.loop_header = Some((name.to_string(), loop_header));
+    ctx.options = options;
+
+    ctx.entry_depth = crate::depth::emit_enter(options.stack_limit, &mut ctx)?;
+    let entry_params = ctx.builder.block_params(entry_block).to_vec();
+    ctx.builder.ins().jump(loop_header, &entry_params);
+    ctx.builder.switch_to_block(loop_header);
 
-    let closure_ptr = ctx.builder.block_params(entry_block)[0];
-    let arg_count = ctx.builder.block_params(entry_block)[1];
+    let closure_ptr = ctx.builder.block_params(loop_header)[0];
+    let arg_count = ctx.builder.block_params(loop_header)[1];
 
     crate::fatal::emit_check_arg_count(
         params.len(),
@@ -118,7 +139,7 @@ pub fn emit_procedure(
         varadic_symbol.is_some(),
     )?;
 
-    let argloc = ctx.builder.block_params(entry_block)[2];
+    let argloc = ctx.builder.block_params(loop_header)[2];
 
     // Assign regular arguments
     for (i, p) in params.iter().enumerate() {
@@ -176,10 +197,17 @@ pub fn emit_procedure(
 
     let vals = body
         .iter()
-        .map(|e| emit_expr(e, &mut ctx))
+        .enumerate()
+        .map(|(i, e)| {
+            // The last expression's value is what gets returned so it
+            // is in tail position.
+            ctx.tail_position = i == body.len() - 1;
+            emit_expr(e, &mut ctx)
+        })
         .collect::<Result<Vec<_>, _>>()?;
 
     // Emit a return instruction to return the result.
+    crate::depth::emit_leave(&mut ctx);
     ctx.builder.ins().return_(&[*vals
         .last()
         .ok_or("expected at least one expression".to_string())?]);
@@ -188,19 +216,7 @@ pub fn emit_procedure(
     ctx.builder.seal_all_blocks();
     ctx.builder.finalize();
 
-    let id = jit
-        .module
-        .declare_function(name, Linkage::Export, &jit.context.func.signature)
-        .map_err(|e| e.to_string())?;
-
-    jit.module
-        .define_function(id, &mut jit.context, &mut codegen::binemit::NullTrapSink {})
-        .map_err(|e| e.to_string())?;
-
-    // If you want to dump the generated IR this is the way:
-    // println!("{}", jit.context.func.display(jit.module.isa()));
-
-    jit.module.clear_context(&mut jit.context);
+    jit.define_function(name)?;
 
     Ok(())
 }
@@ -208,26 +224,22 @@ pub fn emit_procedure(
 /// Emits a call to a function. If the name is the name of an
 /// anonymous function emits a direct call. Otherwise, emits an
 /// indirect one to the function pointed to by the argument variable.
-pub(crate) fn emit_fncall(head: &Expr, args: &[Expr], ctx: &mut Context) -> Result<Value, String> {
+///
+/// If TAIL is set the call is in tail position. When a function in
+/// tail position calls itself we jump back to the top of it with the
+/// new arguments rather than making a call so that tail recursion
+/// doesn't grow the stack. With trampolining on, tail calls to other
+/// functions are left for the caller to make.
+pub(crate) fn emit_fncall(
+    head: &Expr,
+    args: &[Expr],
+    tail: bool,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
     let closure_ptr = emit_check_callable(head, ctx)?;
 
     let word = ctx.module.target_config().pointer_type();
 
-    let mut sig = ctx.module.make_signature();
-
-    // Argument which is a pointer to the closure
-    sig.params.push(AbiParam::new(word));
-
-    // Argument that is the number of args being passed in. Used for
-    // validating the number of arguments and varadic functions.
-    sig.params.push(AbiParam::new(word));
-
-    // A pointer to where the arguments are stored on the heap.
-    sig.params.push(AbiParam::new(word));
-
-    // We always return a single word
-    sig.returns.push(AbiParam::new(word));
-
     // First argumnet is a pointer to the closure
     let closure_ptr = ctx
         .builder
@@ -257,19 +269,330 @@ pub(crate) fn emit_fncall(head: &Expr, args: &[Expr], ctx: &mut Context) -> Resu
     }
     argsc.push(argloc);
 
+    if let (true, Some((name, loop_header))) = (tail, ctx.loop_header.clone()) {
+        if ctx.options.self_tail_calls {
+            // We can't always tell which function a call is to at
+            // compile time so we check at runtime if the function
+            // being called is the one we're in.
+            let self_ptr = emit_get_fn_addr(&name, ctx)?;
+            let is_self = ctx.builder.ins().icmp(IntCC::Equal, fn_ptr, self_ptr);
+            let call_block = ctx.builder.create_block();
+
+            ctx.builder.ins().brnz(is_self, loop_header, &argsc);
+            ctx.builder.ins().jump(call_block, &[]);
+
+            ctx.builder.switch_to_block(call_block);
+            ctx.builder.seal_block(call_block);
+        }
+
+        if crate::trampoline::should_bounce(ctx) {
+            crate::trampoline::emit_bounce(&argsc, ctx);
+            // Nothing comes after the return but the caller expects
+            // a value and a block to keep emitting into.
+            let after_block = ctx.builder.create_block();
+            ctx.builder.switch_to_block(after_block);
+            ctx.builder.seal_block(after_block);
+            return Ok(ctx.builder.ins().iconst(ctx.word, conversions::NIL_VALUE));
+        }
+    }
+
+    let res = emit_call_indirect(fn_ptr, &argsc, ctx);
+    Ok(crate::values::emit_call_result(res, tail, ctx))
+}
+
+/// Emits a call to the function at FN_PTR. ARGS are the untagged
+/// closure pointer, the number of arguments, and where the arguments
+/// are stored on the heap.
+pub(crate) fn emit_call_indirect(fn_ptr: Value, args: &[Value], ctx: &mut Context) -> Value {
+    let res = emit_raw_call(fn_ptr, args, ctx);
+    let res = crate::trampoline::emit_trampoline(res, ctx);
+    fatal::emit_propagate_raise(res, ctx);
+    res
+}
+
+/// Emits just the call instruction for a call to the function at
+/// FN_PTR with ARGS. The result may be RAISED_VALUE or, when
+/// trampolining, TAIL_CALL_VALUE.
+pub(crate) fn emit_raw_call(fn_ptr: Value, args: &[Value], ctx: &mut Context) -> Value {
+    let word = ctx.module.target_config().pointer_type();
+
+    let mut sig = ctx.module.make_signature();
+
+    // Argument which is a pointer to the closure
+    sig.params.push(AbiParam::new(word));
+
+    // Argument that is the number of args being passed in. Used for
+    // validating the number of arguments and varadic functions.
+    sig.params.push(AbiParam::new(word));
+
+    // A pointer to where the arguments are stored on the heap.
+    sig.params.push(AbiParam::new(word));
+
+    // We always return a single word
+    sig.returns.push(AbiParam::new(word));
+
     let sig_ref = ctx.builder.import_signature(sig);
 
-    let call = ctx.builder.ins().call_indirect(sig_ref, fn_ptr, &argsc);
-    let res = ctx.builder.inst_results(call)[0];
+    let call = ctx.builder.ins().call_indirect(sig_ref, fn_ptr, args);
+    ctx.builder.inst_results(call)[0]
+}
 
-    Ok(res)
+/// Emits a call to the closure F with the elements of the list LIST
+/// as its arguments. The number of arguments is only known once the
+/// list has been walked so the callee's own argument count check is
+/// what catches a mismatch.
+pub(crate) fn emit_apply(f: Value, list: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    fatal::emit_check_closure(f, ctx)?;
+
+    let word_bytes = ctx.word.bytes() as i64;
+
+    // Count the elements of the list checking that it is made of
+    // pairs along the way.
+    let count_header = ctx.builder.create_block();
+    let count_body = ctx.builder.create_block();
+    let count_exit = ctx.builder.create_block();
+    ctx.builder.append_block_param(count_header, ctx.word);
+    ctx.builder.append_block_param(count_header, ctx.word);
+    ctx.builder.append_block_param(count_exit, ctx.word);
+
+    let zero = ctx.builder.ins().iconst(ctx.word, 0);
+    ctx.builder.ins().jump(count_header, &[list, zero]);
+
+    ctx.builder.switch_to_block(count_header);
+    let node = ctx.builder.block_params(count_header)[0];
+    let count = ctx.builder.block_params(count_header)[1];
+    let done = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::Equal, node, conversions::NIL_VALUE);
+    ctx.builder.ins().brnz(done, count_exit, &[count]);
+    ctx.builder.ins().jump(count_body, &[]);
+
+    ctx.builder.switch_to_block(count_body);
+    ctx.builder.seal_block(count_body);
+    fatal::emit_check_pair(node, ctx)?;
+    let address = ctx.builder.ins().band_imm(node, conversions::HEAP_PTR_MASK);
+    let next = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, word_bytes as i32);
+    let count = ctx.builder.ins().iadd_imm(count, 1);
+    ctx.builder.ins().jump(count_header, &[next, count]);
+    ctx.builder.seal_block(count_header);
+
+    ctx.builder.switch_to_block(count_exit);
+    ctx.builder.seal_block(count_exit);
+    let count = ctx.builder.block_params(count_exit)[0];
+
+    // Copy the elements into the space for the arguments.
+    let size = ctx.builder.ins().imul_imm(count, word_bytes);
+    let argloc = emit_dynamic_alloc(size, ctx)?;
+
+    let copy_header = ctx.builder.create_block();
+    let copy_body = ctx.builder.create_block();
+    let copy_exit = ctx.builder.create_block();
+    ctx.builder.append_block_param(copy_header, ctx.word);
+    ctx.builder.append_block_param(copy_header, ctx.word);
+
+    ctx.builder.ins().jump(copy_header, &[list, argloc]);
+
+    ctx.builder.switch_to_block(copy_header);
+    let node = ctx.builder.block_params(copy_header)[0];
+    let slot = ctx.builder.block_params(copy_header)[1];
+    let done = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::Equal, node, conversions::NIL_VALUE);
+    ctx.builder.ins().brnz(done, copy_exit, &[]);
+    ctx.builder.ins().jump(copy_body, &[]);
+
+    ctx.builder.switch_to_block(copy_body);
+    ctx.builder.seal_block(copy_body);
+    let address = ctx.builder.ins().band_imm(node, conversions::HEAP_PTR_MASK);
+    let car = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, 0);
+    ctx.builder.ins().store(MemFlags::new(), car, slot, 0);
+    let next = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, word_bytes as i32);
+    let slot = ctx.builder.ins().iadd_imm(slot, word_bytes);
+    ctx.builder.ins().jump(copy_header, &[next, slot]);
+    ctx.builder.seal_block(copy_header);
+
+    ctx.builder.switch_to_block(copy_exit);
+    ctx.builder.seal_block(copy_exit);
+
+    let closure_ptr = ctx.builder.ins().band_imm(f, conversions::HEAP_PTR_MASK);
+    let fn_ptr = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), closure_ptr, 0);
+
+    Ok(emit_call_indirect(
+        fn_ptr,
+        &[closure_ptr, count, argloc],
+        ctx,
+    ))
+}
+
+/// Emits a loop that calls the closure F with an accumulator and
+/// each element of LIST in turn. The accumulator starts as INIT and
+/// is replaced by the result of each call. Evaluates to the final
+/// accumulator.
+pub(crate) fn emit_fold(
+    f: Value,
+    init: Value,
+    list: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    fatal::emit_check_closure(f, ctx)?;
+
+    let word_bytes = ctx.word.bytes() as i64;
+
+    let closure_ptr = ctx.builder.ins().band_imm(f, conversions::HEAP_PTR_MASK);
+    let fn_ptr = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), closure_ptr, 0);
+
+    let header_block = ctx.builder.create_block();
+    let body_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(exit_block, ctx.word);
+
+    ctx.builder.ins().jump(header_block, &[init, list]);
+
+    ctx.builder.switch_to_block(header_block);
+    let accum = ctx.builder.block_params(header_block)[0];
+    let node = ctx.builder.block_params(header_block)[1];
+    let done = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::Equal, node, conversions::NIL_VALUE);
+    ctx.builder.ins().brnz(done, exit_block, &[accum]);
+    ctx.builder.ins().jump(body_block, &[]);
+
+    ctx.builder.switch_to_block(body_block);
+    ctx.builder.seal_block(body_block);
+    fatal::emit_check_pair(node, ctx)?;
+    let address = ctx.builder.ins().band_imm(node, conversions::HEAP_PTR_MASK);
+    let car = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, 0);
+    let next = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, word_bytes as i32);
+
+    // Each call gets its own space for arguments as the callee is
+    // free to hold onto it.
+    let argloc = emit_alloc(2 * word_bytes, ctx)?;
+    ctx.builder.ins().store(MemFlags::new(), accum, argloc, 0);
+    ctx.builder
+        .ins()
+        .store(MemFlags::new(), car, argloc, word_bytes as i32);
+    let count = ctx.builder.ins().iconst(ctx.word, 2);
+    let accum = emit_call_indirect(fn_ptr, &[closure_ptr, count, argloc], ctx);
+    let accum = crate::values::emit_first_value(accum, ctx);
+    ctx.builder.ins().jump(header_block, &[accum, next]);
+    ctx.builder.seal_block(header_block);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+    Ok(ctx.builder.block_params(exit_block)[0])
+}
+
+/// Emits a loop that calls the closure F on each element of LIST and
+/// evaluates to a new list of the results in the same order.
+pub(crate) fn emit_map(f: Value, list: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    fatal::emit_check_closure(f, ctx)?;
+
+    let word_bytes = ctx.word.bytes() as i64;
+
+    let closure_ptr = ctx.builder.ins().band_imm(f, conversions::HEAP_PTR_MASK);
+    let fn_ptr = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), closure_ptr, 0);
+
+    // The results are added to the end of the list as they come in.
+    // Starting from a placeholder pair means there is always a last
+    // pair to add them to. The result is the placeholder's cdr.
+    let nil = ctx.builder.ins().iconst(ctx.word, conversions::NIL_VALUE);
+    let start = emit_alloc(2 * word_bytes, ctx)?;
+    ctx.builder
+        .ins()
+        .store(MemFlags::new(), nil, start, word_bytes as i32);
+
+    let header_block = ctx.builder.create_block();
+    let body_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(header_block, ctx.word);
+
+    ctx.builder.ins().jump(header_block, &[list, start]);
+
+    ctx.builder.switch_to_block(header_block);
+    let node = ctx.builder.block_params(header_block)[0];
+    let last = ctx.builder.block_params(header_block)[1];
+    let done = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::Equal, node, conversions::NIL_VALUE);
+    ctx.builder.ins().brnz(done, exit_block, &[]);
+    ctx.builder.ins().jump(body_block, &[]);
+
+    ctx.builder.switch_to_block(body_block);
+    ctx.builder.seal_block(body_block);
+    fatal::emit_check_pair(node, ctx)?;
+    let address = ctx.builder.ins().band_imm(node, conversions::HEAP_PTR_MASK);
+    let car = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, 0);
+    let next = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, word_bytes as i32);
+
+    let argloc = emit_alloc(word_bytes, ctx)?;
+    ctx.builder.ins().store(MemFlags::new(), car, argloc, 0);
+    let count = ctx.builder.ins().iconst(ctx.word, 1);
+    let res = emit_call_indirect(fn_ptr, &[closure_ptr, count, argloc], ctx);
+    let res = crate::values::emit_first_value(res, ctx);
+
+    let pair = emit_alloc(2 * word_bytes, ctx)?;
+    ctx.builder.ins().store(MemFlags::new(), res, pair, 0);
+    ctx.builder
+        .ins()
+        .store(MemFlags::new(), nil, pair, word_bytes as i32);
+    let tagged = ctx.builder.ins().bor_imm(pair, conversions::PAIR_TAG);
+    ctx.builder
+        .ins()
+        .store(MemFlags::new(), tagged, last, word_bytes as i32);
+    ctx.builder.ins().jump(header_block, &[next, pair]);
+    ctx.builder.seal_block(header_block);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+    Ok(ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), start, word_bytes as i32))
 }
 
 /// A descriptor of an anonymous function.
 #[derive(Debug, Clone)]
 pub struct LustFn {
     /// The functions name. This is always in the form
-    /// __anon_fn_{number}.
+    /// __anon_fn_{number}, followed by the JIT's namespace if it has
+    /// one.
     pub name: String,
     /// The param names for the function.
     pub params: Vec<String>,
@@ -281,8 +604,17 @@ pub struct LustFn {
     pub varadic_symbol: Option<String>,
 }
 
+/// Determines if P is the varadic marker. Both `&` and the more
+/// traditional `.` may be used to mark the rest parameter. The
+/// renamer will have added a COUNT_ prefix so that is stripped off
+/// first. Only the marker on its own counts, a parameter named `x.`
+/// is just a parameter.
 fn is_varadic_param(p: &str) -> bool {
-    p.chars().last() == Some('&')
+    let name = match p.split_once('_') {
+        Some((count, name)) if count.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => p,
+    };
+    name == "&" || name == "."
 }
 
 fn is_varadic_signature(sig: &[&String]) -> bool {
@@ -290,24 +622,26 @@ fn is_varadic_signature(sig: &[&String]) -> bool {
     varadic
 }
 
-fn get_and_validate_varadic(sig: &[&String]) -> Result<String, String> {
+fn get_and_validate_varadic(sig: &[&String]) -> Result<String, LustError> {
     if sig.len() < 2 {
-        return Err("a varadic signature (one with the & symbol) must have at least one additional symbol to bind the varadic arguments to.".to_string());
+        return Err(LustError::Compile("a varadic signature (one with the & or . symbol) must have at least one additional symbol to bind the varadic arguments to.".to_string()));
     }
     if !is_varadic_param(sig[sig.len() - 2]) {
-        return Err("varadic symbol (&) in non tail position".to_string());
+        return Err(LustError::Compile(
+            "varadic symbol (& or .) in non tail position".to_string(),
+        ));
     }
     Ok(sig[sig.len() - 1].clone())
 }
 
 /// Collects all of the anonymous functions in a program and returns a
 /// list of them.
-pub(crate) fn collect_functions(program: &[Expr]) -> Result<Vec<LustFn>, String> {
+pub(crate) fn collect_functions(program: &[Expr]) -> Result<Vec<LustFn>, LustError> {
     let _t = crate::timer::timeit("function collection pass");
     let mut res = Vec::new();
 
     for e in program {
-        e.postorder_traverse_res::<_, String>(&mut |e: &Expr| {
+        e.postorder_traverse_res::<_, LustError>(&mut |e: &Expr| {
             if let Some((params, body)) = e.is_fndef() {
                 let (varadic_symbol, params) = if is_varadic_signature(&params) {
                     (Some(get_and_validate_varadic(&params)?), {
@@ -343,6 +677,92 @@ pub(crate) fn build_fn_map(functions: Vec<LustFn>) -> HashMap<String, LustFn> {
     functions.into_iter().map(|f| (f.name.clone(), f)).collect()
 }
 
+/// Builds a map from the names that are known to always refer to a
+/// function to the number of arguments that the function takes and
+/// whether or not it is varadic. Anonymous functions are always
+/// known. Variables are known if every value they are bound or set to
+/// is nil or a function taking the same arguments. Nil is allowed as
+/// that is how letrec declares its names before setting them. Needs
+/// to run after `replace_functions`.
+pub(crate) fn build_arg_count_map(
+    program: &[Expr],
+    functions: &[LustFn],
+) -> HashMap<String, (usize, bool)> {
+    let mut map: HashMap<String, (usize, bool)> = functions
+        .iter()
+        .map(|f| (f.name.clone(), (f.params.len(), f.varadic_symbol.is_some())))
+        .collect();
+
+    // The arities of the values assigned to each name. None stands for
+    // a value that isn't a known function.
+    let mut assignments: HashMap<String, Vec<Option<(usize, bool)>>> = HashMap::new();
+    for e in program
+        .iter()
+        .chain(functions.iter().flat_map(|f| f.body.iter()))
+    {
+        e.preorder_traverse(&mut |e: &Expr| {
+            if let Some((name, value)) = e.is_let().or_else(|| e.is_set()) {
+                let arities = assignments.entry(name.clone()).or_default();
+                match value {
+                    Expr::Nil => (),
+                    Expr::Symbol(s) => arities.push(map.get(s).copied()),
+                    _ => arities.push(None),
+                }
+            }
+            PreorderStatus::Continue
+        });
+    }
+
+    let mut known = Vec::new();
+    for (name, arities) in assignments {
+        if let Some(&Some(arity)) = arities.first() {
+            if arities.iter().all(|&a| a == Some(arity)) {
+                known.push((name, arity));
+            }
+        }
+    }
+    map.extend(known);
+    map
+}
+
+/// Checks that every call to a function in ARG_COUNTS passes it the
+/// number of arguments that it expects. Calls to anything else are
+/// checked when the program runs.
+pub(crate) fn check_arg_counts(
+    program: &[Expr],
+    functions: &[LustFn],
+    arg_counts: &HashMap<String, (usize, bool)>,
+) -> Result<(), LustError> {
+    let _t = crate::timer::timeit("argument count pass");
+    for e in program
+        .iter()
+        .chain(functions.iter().flat_map(|f| f.body.iter()))
+    {
+        e.postorder_traverse_res(&mut |e: &Expr| {
+            if let Some((Expr::Symbol(name), args)) = e.is_fncall() {
+                if let Some(&(expected, varadic)) = arg_counts.get(name) {
+                    if args.len() < expected || (!varadic && args.len() > expected) {
+                        let name = if name.starts_with("__anon_fn_") {
+                            "fn"
+                        } else {
+                            original_name(name)
+                        };
+                        return Err(LustError::ArityMismatch {
+                            name: name.to_string(),
+                            expected,
+                            got: args.len(),
+                            varadic,
+                        }
+                        .at(e.span()));
+                    }
+                }
+            }
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
 /// Replaces functions with their anonymous names. Takes a program and
 /// a list of functions from `collect_functions` as arguments. Needs
 /// the list of functions because it needs to be sure to replace their
@@ -357,20 +777,107 @@ pub(crate) fn replace_functions(program: &mut [Expr], functions: &mut [LustFn])
         // the bodies from the `functions` list with the updated one.
         e.postorder_traverse_mut(&mut |e: &mut Expr| {
             if let Some(_) = e.is_fndef() {
-                if let Expr::List(v) = e {
+                if let Expr::List(v, _) = e {
                     functions[count].body = v[2..].to_vec();
                 } else {
                     // This should really never happen
                     panic!("fndef outside of a list")
                 }
 
-                *e = Expr::Symbol(format!("__anon_fn_{}", count));
+                *e = Expr::Symbol(functions[count].name.clone());
                 count += 1;
             }
         })
     }
 }
 
+/// Determines if E binds or sets a name to one of FUNCTIONS and if it
+/// does returns the name and the function.
+fn is_fn_definition<'a>(
+    e: &'a Expr,
+    functions: &HashSet<String>,
+) -> Option<(&'a String, &'a String)> {
+    match e.is_let().or_else(|| e.is_set()) {
+        Some((name, Expr::Symbol(f))) if functions.contains(f) => Some((name, f)),
+        _ => None,
+    }
+}
+
+/// Collects the symbols referenced by the sequence BODY into
+/// REFERENCES. Functions bound to a name by an expression whose value
+/// is thrown away aren't referenced by that binding. They are
+/// referenced if the name is.
+fn collect_references(body: &[Expr], functions: &HashSet<String>, references: &mut Vec<String>) {
+    for (i, e) in body.iter().enumerate() {
+        if i + 1 < body.len() && is_fn_definition(e, functions).is_some() {
+            continue;
+        }
+        e.preorder_traverse(&mut |e: &Expr| {
+            if let Some(body) = e.is_begin() {
+                collect_references(body, functions, references);
+                return PreorderStatus::Skip;
+            }
+            if let Expr::Symbol(s) = e {
+                references.push(s.clone());
+            }
+            PreorderStatus::Continue
+        });
+    }
+}
+
+/// Removes the functions that PROGRAM can never call from
+/// FUNCTIONS. A function is reachable if the program or a reachable
+/// function mentions it or a name that it is bound to. Bindings of
+/// names to removed functions are replaced with bindings to nil so
+/// that nothing is left that refers to them. Needs to run after
+/// `replace_functions`.
+pub(crate) fn remove_unused_functions(program: &mut [Expr], functions: &mut Vec<LustFn>) {
+    let _t = crate::timer::timeit("unused function pass");
+    let names: HashSet<String> = functions.iter().map(|f| f.name.clone()).collect();
+
+    let mut definitions: HashMap<String, Vec<String>> = HashMap::new();
+    for e in program
+        .iter()
+        .chain(functions.iter().flat_map(|f| f.body.iter()))
+    {
+        e.preorder_traverse(&mut |e: &Expr| {
+            if let Some((name, f)) = is_fn_definition(e, &names) {
+                definitions.entry(name.clone()).or_default().push(f.clone());
+            }
+            PreorderStatus::Continue
+        });
+    }
+
+    let bodies: HashMap<&String, &[Expr]> =
+        functions.iter().map(|f| (&f.name, &f.body[..])).collect();
+    let mut reachable = HashSet::new();
+    let mut references = Vec::new();
+    collect_references(program, &names, &mut references);
+    while let Some(reference) = references.pop() {
+        let reached = Some(&reference)
+            .filter(|r| names.contains(*r))
+            .into_iter()
+            .chain(definitions.get(&reference).into_iter().flatten());
+        for f in reached {
+            if reachable.insert(f.clone()) {
+                collect_references(bodies[f], &names, &mut references);
+            }
+        }
+    }
+
+    functions.retain(|f| reachable.contains(&f.name));
+    for e in program
+        .iter_mut()
+        .chain(functions.iter_mut().flat_map(|f| f.body.iter_mut()))
+    {
+        e.postorder_traverse_mut(&mut |e: &mut Expr| {
+            if matches!(e, Expr::Symbol(s) if names.contains(s) && !reachable.contains(s)) {
+                *e = Expr::Nil;
+            }
+        })
+    }
+}
+
 /// Collects the bound and unbound variables in E into two sets and
 /// returns them in a tuple (bound, unbound).
 fn analyze_variables(e: &Expr) -> (HashSet<&String>, HashSet<&String>) {
@@ -417,7 +924,7 @@ fn analyze_variables(e: &Expr) -> (HashSet<&String>, HashSet<&String>) {
         if !string_is_builtin(s) && !bound.contains(s) && !s.starts_with("__anon_") {
             free.insert(s);
         }
-    } else if let Expr::List(v) = e {
+    } else if let Expr::List(v, _) = e {
         for e in v {
             let (newbound, newfree) = analyze_variables(e);
             bound.extend(newbound);
@@ -455,8 +962,39 @@ pub(crate) fn annotate_free_variables(f: &mut LustFn) {
     f.free_variables = free.into_iter().cloned().collect();
 }
 
+/// Gets the free variables of every function in PROGRAM as the
+/// compiler sees them. Functions are named __anon_fn_N in the order
+/// that their definitions end in the source so inner functions come
+/// before the functions that they are in. Variables go by the names
+/// they have in the source and are sorted.
+///
+/// Useful for finding out what a closure captures.
+pub fn analyze_free_variables(program: &[Expr]) -> Result<HashMap<String, Vec<String>>, LustError> {
+    let mut program = program.to_vec();
+    crate::renamer::make_names_unique(&mut program)?;
+    // Quoted symbols are not variables so data is replaced first just
+    // as it would be when compiling.
+    let data = crate::data::collect_data(&program);
+    crate::data::replace_data(&mut program, &data);
+
+    let mut functions = collect_functions(&program)?;
+    Ok(functions
+        .iter_mut()
+        .map(|f| {
+            annotate_free_variables(f);
+            let mut free = f
+                .free_variables
+                .iter()
+                .map(|v| original_name(v).to_string())
+                .collect::<Vec<_>>();
+            free.sort();
+            (f.name.clone(), free)
+        })
+        .collect())
+}
+
 /// Emits code to allocate a closure and returns a pointer to it.
-fn emit_alloc_closure(var_count: usize, ctx: &mut Context) -> Result<Value, String> {
+fn emit_alloc_closure(var_count: usize, ctx: &mut Context) -> Result<Value, LustError> {
     // Free variables and the function pointer.
     let size = (var_count + 1) * (ctx.word.bytes() as usize);
     emit_alloc(size as i64, ctx)
@@ -479,7 +1017,7 @@ pub(crate) fn emit_make_closure(
     fn_name: &str,
     free_variables: &[String],
     ctx: &mut Context,
-) -> Result<Value, String> {
+) -> Result<Value, LustError> {
     let closure_ptr = emit_alloc_closure(free_variables.len(), ctx)?;
     let fn_ptr = emit_get_fn_addr(fn_name, ctx)?;
 
@@ -508,10 +1046,10 @@ pub(crate) fn emit_make_closure(
             // variable.
             match ctx.env.get(free) {
                 Some(v) => Ok(ctx.builder.use_var(*v)),
-                None => Err(format!(
+                None => Err(LustError::Compile(format!(
                     "internal error: (constructing closure) use of undeclared variable ({})",
                     free
-                )),
+                ))),
             }?
         };
         // let val = emit_var_access(free, ctx)?;
@@ -530,7 +1068,7 @@ pub(crate) fn emit_make_closure(
         .bor_imm(closure_ptr, crate::conversions::CLOSURE_TAG))
 }
 
-pub(crate) fn emit_get_fn_addr(name: &str, ctx: &mut Context) -> Result<Value, String> {
+pub(crate) fn emit_get_fn_addr(name: &str, ctx: &mut Context) -> Result<Value, LustError> {
     let mut sig = ctx.module.make_signature();
     // Clojure
     sig.params.push(AbiParam::new(ctx.word));
@@ -544,7 +1082,7 @@ pub(crate) fn emit_get_fn_addr(name: &str, ctx: &mut Context) -> Result<Value, S
     let callee = ctx
         .module
         .declare_function(name, Linkage::Import, &sig)
-        .map_err(|e| e.to_string())?;
+        .map_err(LustError::from)?;
 
     let local_callee = ctx
         .module
@@ -673,6 +1211,28 @@ mod tests {
         assert_eq!(res, Expr::Integer(4))
     }
 
+    #[test]
+    fn analyzed_free_variables() {
+        let source = r#"
+(let outer 1)
+(let unused 2)
+(let make-adder (fn (n)
+  (let offset (add n outer))
+  (fn (x) (add x offset))))
+((make-adder 1) '(unused))
+"#;
+        let free = analyze_free_variables(&parse_string(source).unwrap()).unwrap();
+        let names = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(free.len(), 2);
+        assert_eq!(free["__anon_fn_0"], names(&["offset"]));
+        assert_eq!(free["__anon_fn_1"], names(&["outer"]));
+
+        let err = analyze_free_variables(&parse_string("(fn () y)").unwrap()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with(&LustError::UnboundSymbol("y".to_string()).to_string()));
+    }
+
     /// Variables and function paramaters should not conflict.
     #[test]
     fn argument_scoping() {
@@ -736,32 +1296,57 @@ mod tests {
     #[test]
     fn list_impl() {
         let res = roundtrip_file("examples/list.lisp").unwrap();
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
         assert_eq!(
             res,
-            Expr::List(vec![
+            cons(
                 Expr::Integer(1),
-                Expr::List(vec![
+                cons(
                     Expr::Integer(2),
-                    Expr::List(vec![
+                    cons(
                         Expr::Integer(3),
-                        Expr::List(vec![
-                            Expr::List(vec![Expr::Integer(1), Expr::Integer(2)]),
-                            Expr::Nil
-                        ])
-                    ])
-                ])
-            ])
+                        cons(cons(Expr::Integer(1), Expr::Integer(2)), Expr::Nil)
+                    )
+                )
+            )
         )
     }
 
+    #[test]
+    fn tail_recursion() {
+        // Without tail calls this would overflow the stack.
+        let source = r#"
+(let count-down (fn (n)
+                    (if (eq n 0)
+                        n
+                        (count-down (sub n 1)))))
+(count-down 1000000)
+"#;
+        let res = roundtrip_string(source).unwrap();
+        assert_eq!(res, Expr::Integer(0));
+    }
+
+    #[test]
+    fn tail_call_to_other_function() {
+        // Tail calls to functions other than the current one are
+        // regular calls.
+        let source = r#"
+(let double (fn (n) (add n n)))
+(let f (fn (n) (if (eq n 0) 1 (double n))))
+(let g (fn (n acc) (if (eq n 0) acc (g (sub n 1) (add acc (f n))))))
+(g 10 0)
+"#;
+        let res = roundtrip_string(source).unwrap();
+        assert_eq!(res, Expr::Integer(110));
+    }
+
     #[test]
     fn varadic_collection() {
-        // Hack here where we prefix the varadic symbol with two
-        // characters so that functions that expect processed input
-        // handle it correcty.
+        // The marker is found with or without the prefix that the
+        // renamer adds to it.
         let source = r#"
 (let half 5)
-(let foo (fn (n aa& m)
+(let foo (fn (n & m)
              (let half (sub n half))
              (mul half 2)))
 (foo 26)
@@ -787,4 +1372,194 @@ mod tests {
         let res = roundtrip_string(source).unwrap();
         assert_eq!(Expr::Integer(4), res)
     }
+
+    fn arity_error(source: &str) -> LustError {
+        let program = crate::reader::parse(source).unwrap();
+        crate::compiler::eval_program(&program)
+            .unwrap_err()
+            .without_location()
+    }
+
+    fn arity_mismatch(name: &str, expected: usize, got: usize) -> LustError {
+        LustError::ArityMismatch {
+            name: name.to_string(),
+            expected,
+            got,
+            varadic: false,
+        }
+    }
+
+    #[test]
+    fn arg_count_mismatch() {
+        assert_eq!(
+            arity_error("(let f (fn (a b) a)) (f 1)"),
+            arity_mismatch("f", 2, 1)
+        );
+        assert_eq!(
+            arity_error("(define (f a b) a) (f 1 2 3)"),
+            arity_mismatch("f", 2, 3)
+        );
+        assert_eq!(
+            arity_error("(let v (fn (a b & c) c)) (v 1)"),
+            LustError::ArityMismatch {
+                name: "v".to_string(),
+                expected: 2,
+                got: 1,
+                varadic: true,
+            }
+        );
+        assert_eq!(arity_error("((fn (a) a))"), arity_mismatch("fn", 1, 0));
+        // Calls inside of function bodies and to functions bound by
+        // letrec are checked as well.
+        assert_eq!(
+            arity_error("(letrec ((f (fn (n) (f n n)))) (f 1))"),
+            arity_mismatch("f", 1, 2)
+        );
+        assert_eq!(
+            crate::roundtrip_string("(let f (fn () 1))\n(f 1)"),
+            Err("f expected 0 args and got 1 at line 2, column 1".to_string())
+        );
+        // Varadic functions take any number of arguments past their
+        // last named one.
+        assert_eq!(
+            crate::roundtrip_string("(define (g a . r) a) (g)"),
+            Err("g expected at least 1 args and got 0 at line 1, column 22".to_string())
+        );
+    }
+
+    #[test]
+    fn unknown_arg_counts() {
+        // A variable that is set to something else might not hold the
+        // function it was bound to so calls to it are only checked
+        // when they run.
+        let program =
+            crate::reader::parse("(let f (fn (a) a)) (set! f (fn (a b) b)) (f 1 2)").unwrap();
+        assert_eq!(
+            crate::compiler::eval_program(&program),
+            Ok(Expr::Integer(2))
+        );
+    }
+
+    #[test]
+    fn dotted_rest_param() {
+        let source = r#"
+(let v (fn (a . rest) (cons a rest)))
+(v 1 2 3)
+"#;
+        let res = roundtrip_string(source).unwrap();
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            res,
+            cons(
+                Expr::Integer(1),
+                cons(Expr::Integer(2), cons(Expr::Integer(3), Expr::Nil))
+            )
+        )
+    }
+
+    #[test]
+    fn empty_rest_param() {
+        let source = r#"
+(let v (fn (a . rest) rest))
+(v 1)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Nil);
+
+        let source = r#"
+(let v (fn (& rest) rest))
+(v)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Nil)
+    }
+
+    #[test]
+    fn params_ending_in_markers() {
+        // Only a standalone . or & marks the rest parameter.
+        let source = r#"
+(let v (fn (x. y&) (cons x. y&)))
+(v 1 2)
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::Pair(Box::new(Expr::Integer(1)), Box::new(Expr::Integer(2)))
+        );
+        let source = "(let v (fn (a. . rest) (cons a. rest))) (v 1 2)";
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(1 2)").unwrap()
+        );
+    }
+
+    #[test]
+    fn closure_passed_between_functions() {
+        // The closure made by make-counter is called from inside of
+        // call-twice after make-counter has returned.
+        let source = r#"
+(let make-counter (fn (start)
+                    (let count start)
+                    (fn () (set! count (add1 count)) count)))
+(let call-twice (fn (f) (f) (f)))
+(call-twice (make-counter 10))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(12));
+
+        // Callees don't need to be symbols.
+        let source = r#"
+(let compose (fn (f g) (fn (x) (f (g x)))))
+((compose add1 (fn (x) (mul x 2))) 5)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(11));
+    }
+
+    #[test]
+    fn unused_functions() {
+        // In order of collection the functions are unused-a, unused-b,
+        // square, the adder returned by make-adder, and make-adder.
+        // square is inlined so it isn't needed either.
+        let source = r#"
+(define (unused-a x) (add x 1))
+(define (unused-b) (unused-a 2))
+(define (square x) (mul x x))
+(define (make-adder n) (fn (x) (add x n)))
+(let add2 (make-adder 2))
+(add2 (square 3))
+"#;
+        let program = parse_string(source).unwrap();
+        let ir = crate::compiler::compile_to_ir(&program).unwrap();
+        for used in &["__anon_fn_3", "__anon_fn_4"] {
+            assert!(ir.contains(&format!("; {}\n", used)), "{}", used);
+        }
+        for unused in &["__anon_fn_0", "__anon_fn_1", "__anon_fn_2"] {
+            assert!(!ir.contains(&format!("; {}\n", unused)), "{}", unused);
+        }
+        assert_eq!(
+            crate::compiler::roundtrip_program(&program).unwrap(),
+            Expr::Integer(11)
+        );
+    }
+
+    #[test]
+    fn functions_reached_through_closures() {
+        let source = r#"
+(let helper (fn (x) (mul x 10)))
+(let apply-helper (fn (y) (helper y)))
+(let get (fn () apply-helper))
+((get) 4)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(40));
+
+        // A binding whose value is used keeps its function around.
+        let source = r#"
+(let make (fn () (let inner (fn () 7))))
+((make))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(7));
+
+        let source = r#"
+(letrec ((even? (fn (n) (if (zero? n) #t (odd? (sub n 1)))))
+         (odd? (fn (n) (if (zero? n) #f (even? (sub n 1))))))
+  (even? 10))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Bool(true));
+    }
 }
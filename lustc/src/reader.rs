@@ -1,8 +1,13 @@
 //! A small reader that reads non-whitespace tokens from an input
-//! string and keeps track of its visual location in the string.
+//! string and keeps track of its visual location in the string. Also
+//! home to `parse` which reads a whole program into expressions.
 
 use std::iter;
 
+use crate::errors::ParseError;
+use crate::parser::Parser;
+use crate::Expr;
+
 /// A location in a string.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Location {
@@ -17,6 +22,8 @@ pub(crate) struct Reader<'a> {
     // Tracks the visible location of the reader. This means that tabs
     // do not count as a single character.
     pub loc: Location,
+    // The number of bytes of the source that have been read.
+    offset: usize,
     source: &'a str,
     chars: iter::Peekable<std::str::Chars<'a>>,
 }
@@ -26,6 +33,7 @@ impl<'a> Reader<'a> {
     pub(crate) fn new(source: &'a str) -> Self {
         Self {
             loc: Location::new(),
+            offset: 0,
             source,
             chars: source.chars().peekable(),
         }
@@ -73,6 +81,7 @@ impl<'a> Reader<'a> {
         let c = self.chars.next();
         if let Some(c) = c {
             self.update_loc(c);
+            self.offset += c.len_utf8();
         }
         c
     }
@@ -129,6 +138,37 @@ impl<'a> Reader<'a> {
     pub(crate) fn loc(&self) -> Location {
         self.loc
     }
+
+    /// Gets the byte offset of LOC in SOURCE. Locations track visual
+    /// columns so we find the offset by reading up to LOC.
+    pub(crate) fn byte_offset(source: &str, loc: Location) -> usize {
+        let mut reader = Reader::new(source);
+        while reader.loc != loc && reader.next_char().is_some() {}
+        reader.offset
+    }
+}
+
+/// Parses INPUT into a list of expressions. Returns an error holding
+/// the byte offset of the first syntax error if there is one.
+pub fn parse(input: &str) -> Result<Vec<Expr>, ParseError> {
+    let mut parser = Parser::new(input);
+    let mut exprs = Vec::new();
+    while parser.has_more() {
+        let res = parser.parse_expr();
+        if let Some(e) = res.errors.first() {
+            return Err(ParseError {
+                offset: Reader::byte_offset(input, e.loc.start),
+                message: e.what.clone(),
+            });
+        }
+        if let Some(e) = res.expr {
+            exprs.push(
+                e.into_expr()
+                    .map_err(|message| ParseError { offset: 0, message })?,
+            );
+        }
+    }
+    Ok(exprs)
 }
 
 impl Location {
@@ -142,3 +182,60 @@ impl Location {
         Self { line, col }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_atoms() {
+        assert_eq!(
+            parse("1 #t #f #\\a () foo \"bar\"").unwrap(),
+            vec![
+                Expr::Integer(1),
+                Expr::Bool(true),
+                Expr::Bool(false),
+                Expr::Char('a'),
+                Expr::Nil,
+                Expr::Symbol("foo".to_string()),
+                Expr::String("bar".to_string()),
+            ]
+        )
+    }
+
+    #[test]
+    fn parse_nested() {
+        assert_eq!(
+            parse("(a (b #\\space) ())").unwrap(),
+            vec![Expr::list(vec![
+                Expr::Symbol("a".to_string()),
+                Expr::list(vec![Expr::Symbol("b".to_string()), Expr::Char(' ')]),
+                Expr::Nil,
+            ])]
+        )
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = parse("(add1 41").unwrap_err();
+        assert_eq!(err.offset, 0);
+        assert_eq!(err.message, "unbalanced parenthesis");
+
+        let err = parse("(add1 41))").unwrap_err();
+        assert_eq!(err.offset, 9);
+        assert_eq!(err.message, "unexpected closing paren");
+
+        // Offsets are in bytes and not characters.
+        let err = parse("(let 🚨 #\\bogus)").unwrap_err();
+        assert_eq!(err.offset, 10);
+    }
+
+    #[test]
+    fn parse_and_run() {
+        let program = parse("(add1 41)").unwrap();
+        assert_eq!(
+            crate::compiler::roundtrip_program(&program).unwrap(),
+            Expr::Integer(42)
+        )
+    }
+}
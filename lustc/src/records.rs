@@ -0,0 +1,531 @@
+//! Records are new data types with named fields:
+//!
+//! (define-record-type point (make-point x y) point?
+//!   (x point-x set-point-x!)
+//!   (y point-y))
+//!
+//! A definition is lowered by the renamer into let expressions that
+//! bind a constructor, a predicate, and an accessor and optional
+//! modifier for each field. Those are ordinary functions which are
+//! lifted like any other and call the record forms below:
+//!
+//! (__record-make TYPE field...)
+//! (__record? TYPE obj)
+//! (__record-ref TYPE INDEX obj)
+//! (__record-set! TYPE INDEX obj value)
+//!
+//! A record is a type word followed by one word for each field. The
+//! type word is the record type's number as a fixnum. Records are
+//! aligned to thirty two bytes and tagged with RECORD_TAG.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+use cranelift::prelude::*;
+
+use crate::compiler::{emit_expr, Context};
+use crate::conversions::{RECORD_MASK, RECORD_TAG};
+use crate::errors::LustError;
+use crate::heap::{aligned_alloc_size, emit_alloc, TAG_ALIGN};
+use crate::location::Span;
+use crate::primitives::emit_word_to_bool;
+use crate::{fatal, foreign, Expr, Word};
+
+struct RecordType {
+    name: String,
+    fields: Vec<String>,
+}
+
+thread_local! {
+    // Like symbols, record types are numbered while compiling and
+    // looked up by number when records are read back.
+    static TYPES: RefCell<Vec<RecordType>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Registers a record type named NAME with FIELDS and returns its
+/// number. Every definition makes a new type, even if one with the
+/// same name already exists.
+fn define_type(name: &str, fields: &[String]) -> usize {
+    TYPES.with(|types| {
+        let mut types = types.borrow_mut();
+        types.push(RecordType {
+            name: name.to_string(),
+            fields: fields.to_vec(),
+        });
+        types.len() - 1
+    })
+}
+
+/// Gets the name and field names of the record type numbered ID.
+pub(crate) fn type_info(id: usize) -> Option<(String, Vec<String>)> {
+    TYPES.with(|types| {
+        types
+            .borrow()
+            .get(id)
+            .map(|t| (t.name.clone(), t.fields.clone()))
+    })
+}
+
+/// The forms that definitions are lowered into.
+pub(crate) enum RecordOp<'a> {
+    Make(usize, &'a [Expr]),
+    Is(usize, &'a Expr),
+    Ref(usize, usize, &'a Expr),
+    Set(usize, usize, &'a Expr, &'a Expr),
+}
+
+impl Expr {
+    /// Determines if an expression is a define-record-type expression
+    /// and returns everything after define-record-type.
+    pub fn is_define_record_type(&self) -> Option<&[Expr]> {
+        match self {
+            Expr::List(v, _) => match &v[..] {
+                [Expr::Symbol(s), rest @ ..] if s == "define-record-type" => Some(rest),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Determines if an expression is one of the record forms that
+    /// definitions are lowered into.
+    pub(crate) fn is_record_op(&self) -> Option<RecordOp<'_>> {
+        let v = match self {
+            Expr::List(v, _) => v,
+            _ => return None,
+        };
+        let number = |e: &Expr| match e {
+            Expr::Integer(i) if *i >= 0 => Some(*i as usize),
+            _ => None,
+        };
+        match &v[..] {
+            [Expr::Symbol(s), t, fields @ ..] if s == "__record-make" => {
+                Some(RecordOp::Make(number(t)?, fields))
+            }
+            [Expr::Symbol(s), t, obj] if s == "__record?" => Some(RecordOp::Is(number(t)?, obj)),
+            [Expr::Symbol(s), t, i, obj] if s == "__record-ref" => {
+                Some(RecordOp::Ref(number(t)?, number(i)?, obj))
+            }
+            [Expr::Symbol(s), t, i, obj, value] if s == "__record-set!" => {
+                Some(RecordOp::Set(number(t)?, number(i)?, obj, value))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn malformed(what: &str) -> LustError {
+    LustError::Compile(format!("malformed define-record-type expression: {}", what))
+}
+
+fn symbol(e: &Expr) -> Option<&String> {
+    match e {
+        Expr::Symbol(s) => Some(s),
+        _ => None,
+    }
+}
+
+/// Lowers the define-record-type expression whose parts after
+/// define-record-type are REST into the let expressions that define
+/// its procedures. The expressions are given SPAN.
+///
+/// (define-record-type point (make-point x) point? (x point-x set-x!))
+/// =>
+/// (begin
+///   (let make-point (fn (x) (__record-make TYPE x)))
+///   (let point? (fn (obj) (__record? TYPE obj)))
+///   (let point-x (fn (obj) (__record-ref TYPE 0 obj)))
+///   (let set-x! (fn (obj value) (__record-set! TYPE 0 obj value))))
+pub(crate) fn lower_define_record_type(rest: &[Expr], span: Span) -> Result<Expr, LustError> {
+    let (name, constructor, predicate, field_specs) = match rest {
+        [name, Expr::List(constructor, _), predicate, fields @ ..] => {
+            (name, constructor, predicate, fields)
+        }
+        _ => return Err(malformed("expected a name, constructor, and predicate")),
+    };
+    let name = symbol(name).ok_or_else(|| malformed("type name must be a symbol"))?;
+    let predicate = symbol(predicate).ok_or_else(|| malformed("predicate must be a symbol"))?;
+
+    // Each field is (name accessor) or (name accessor modifier).
+    let mut fields = Vec::new();
+    let mut procedures = Vec::new();
+    for spec in field_specs {
+        let parts = match spec {
+            Expr::List(v, _) => v
+                .iter()
+                .map(symbol)
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| malformed("fields must be lists of symbols"))?,
+            _ => return Err(malformed("fields must be lists of symbols")),
+        };
+        match &parts[..] {
+            [_, accessor] => procedures.push((fields.len(), *accessor, None)),
+            [_, accessor, modifier] => procedures.push((fields.len(), *accessor, Some(*modifier))),
+            _ => return Err(malformed("expected (field accessor [modifier])")),
+        };
+        if fields.contains(parts[0]) {
+            return Err(malformed(&format!("duplicate field {}", parts[0])));
+        }
+        fields.push(parts[0].clone());
+    }
+
+    let (constructor, params) = match &constructor[..] {
+        [Expr::Symbol(c), params @ ..] => (c, params),
+        _ => return Err(malformed("constructor must be a symbol")),
+    };
+    let mut seen = HashSet::new();
+    for param in params {
+        match symbol(param) {
+            Some(p) if fields.contains(p) && seen.insert(p) => (),
+            _ => {
+                return Err(malformed(&format!(
+                    "constructor argument {} is not a field",
+                    param
+                )))
+            }
+        }
+    }
+
+    let id = define_type(name, &fields);
+    let sym = |s: &str| Expr::Symbol(s.to_string());
+    let list = |v: Vec<Expr>| Expr::List(v, span);
+    let id_expr = Expr::Integer(id as i64);
+    let define = |name: &str, params: Vec<Expr>, body: Expr| {
+        let params = if params.is_empty() {
+            Expr::Nil
+        } else {
+            list(params)
+        };
+        list(vec![
+            sym("let"),
+            sym(name),
+            list(vec![sym("fn"), params, body]),
+        ])
+    };
+
+    let mut res = vec![sym("begin")];
+    // Fields that the constructor doesn't take start out as nil.
+    let initial = fields
+        .iter()
+        .map(|f| if seen.contains(f) { sym(f) } else { Expr::Nil });
+    res.push(define(
+        constructor,
+        params.to_vec(),
+        list(
+            vec![sym("__record-make"), id_expr.clone()]
+                .into_iter()
+                .chain(initial)
+                .collect(),
+        ),
+    ));
+    res.push(define(
+        predicate,
+        vec![sym("obj")],
+        list(vec![sym("__record?"), id_expr.clone(), sym("obj")]),
+    ));
+    for (index, accessor, modifier) in procedures {
+        let index = Expr::Integer(index as i64);
+        res.push(define(
+            accessor,
+            vec![sym("obj")],
+            list(vec![
+                sym("__record-ref"),
+                id_expr.clone(),
+                index.clone(),
+                sym("obj"),
+            ]),
+        ));
+        if let Some(modifier) = modifier {
+            res.push(define(
+                modifier,
+                vec![sym("obj"), sym("value")],
+                list(vec![
+                    sym("__record-set!"),
+                    id_expr.clone(),
+                    index,
+                    sym("obj"),
+                    sym("value"),
+                ]),
+            ));
+        }
+    }
+    Ok(list(res))
+}
+
+/// The type word of records of the type numbered ID.
+fn type_word(id: usize) -> Word {
+    Expr::Integer(id as i64).immediate_rep()
+}
+
+pub(crate) fn emit_record_op(op: RecordOp, ctx: &mut Context) -> Result<Value, LustError> {
+    match op {
+        RecordOp::Make(id, fields) => emit_make_record(id, fields, ctx),
+        RecordOp::Is(id, obj) => {
+            let obj = emit_expr(obj, ctx)?;
+            emit_is_record(id, obj, ctx)
+        }
+        RecordOp::Ref(id, index, obj) => {
+            let obj = emit_expr(obj, ctx)?;
+            let address = emit_field_address(id, index, obj, ctx)?;
+            Ok(ctx
+                .builder
+                .ins()
+                .load(ctx.word, MemFlags::new(), address, 0))
+        }
+        RecordOp::Set(id, index, obj, value) => {
+            let obj = emit_expr(obj, ctx)?;
+            let value = emit_expr(value, ctx)?;
+            let address = emit_field_address(id, index, obj, ctx)?;
+            ctx.builder.ins().store(MemFlags::new(), value, address, 0);
+            Ok(value)
+        }
+    }
+}
+
+/// Emits the code for a new record of the type numbered ID holding
+/// FIELDS.
+fn emit_make_record(id: usize, fields: &[Expr], ctx: &mut Context) -> Result<Value, LustError> {
+    let values = fields
+        .iter()
+        .map(|f| emit_expr(f, ctx))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let word_bytes = ctx.word.bytes() as usize;
+    let storage = emit_alloc(
+        aligned_alloc_size((1 + fields.len()) * word_bytes) as i64,
+        ctx,
+    )?;
+    let storage = ctx.builder.ins().iadd_imm(storage, TAG_ALIGN as i64 - 1);
+    let storage = ctx.builder.ins().band_imm(storage, -(TAG_ALIGN as i64));
+
+    let type_word = ctx.builder.ins().iconst(ctx.word, type_word(id));
+    ctx.builder
+        .ins()
+        .store(MemFlags::new(), type_word, storage, 0);
+    for (i, value) in values.into_iter().enumerate() {
+        ctx.builder.ins().store(
+            MemFlags::new(),
+            value,
+            storage,
+            ((i + 1) * word_bytes) as i32,
+        );
+    }
+    Ok(ctx.builder.ins().bor_imm(storage, RECORD_TAG))
+}
+
+/// Emits #t if OBJ is a record of the type numbered ID and #f
+/// otherwise. The type word is only loaded once OBJ is known to be a
+/// record.
+fn emit_is_record(id: usize, obj: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    let check_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(exit_block, ctx.word);
+
+    let is_record = foreign::emit_is(obj, RECORD_TAG, RECORD_MASK, ctx);
+    let false_ = ctx
+        .builder
+        .ins()
+        .iconst(ctx.word, Expr::Bool(false).immediate_rep());
+    ctx.builder.ins().brz(is_record, exit_block, &[false_]);
+    ctx.builder.ins().jump(check_block, &[]);
+
+    ctx.builder.switch_to_block(check_block);
+    ctx.builder.seal_block(check_block);
+    let is_type = emit_is_type(id, obj, ctx);
+    let is_type = ctx.builder.ins().bint(ctx.word, is_type);
+    let is_type = emit_word_to_bool(is_type, &mut ctx.builder);
+    ctx.builder.ins().jump(exit_block, &[is_type]);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+    Ok(ctx.builder.block_params(exit_block)[0])
+}
+
+/// Emits a check that the type word of the record RECORD is the one
+/// for the type numbered ID.
+fn emit_is_type(id: usize, record: Value, ctx: &mut Context) -> Value {
+    let address = ctx.builder.ins().band_imm(record, !RECORD_MASK);
+    let found = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, 0);
+    ctx.builder
+        .ins()
+        .icmp_imm(IntCC::Equal, found, type_word(id))
+}
+
+/// Emits the address of field INDEX of OBJ. Anything other than a
+/// record of the type numbered ID is a type error.
+fn emit_field_address(
+    id: usize,
+    index: usize,
+    obj: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    fatal::emit_check_tag(obj, RECORD_TAG, RECORD_MASK, ctx)?;
+    let is_type = emit_is_type(id, obj, ctx);
+    fatal::emit_check_type(is_type, ctx)?;
+    let address = ctx.builder.ins().band_imm(obj, !RECORD_MASK);
+    Ok(ctx
+        .builder
+        .ins()
+        .iadd_imm(address, ((index + 1) * ctx.word.bytes() as usize) as i64))
+}
+
+/// Reads the record that WORD points to back off the heap.
+pub(crate) fn record_from_immediate(word: Word) -> Expr {
+    let ptr = (word & !RECORD_MASK) as *const Word;
+    let id = (unsafe { *ptr } >> crate::conversions::FIXNUM_SHIFT) as usize;
+    let (_, fields) = type_info(id).expect("record type was never defined");
+    let words = unsafe { std::slice::from_raw_parts(ptr.add(1), fields.len()) };
+    Expr::Record(id, words.iter().map(|&w| Expr::from_immediate(w)).collect())
+}
+
+/// Builds a record of the type numbered ID holding FIELDS outside of
+/// the heap for compiling into a program.
+pub(crate) fn record_to_immediate(id: usize, fields: &[Expr]) -> Word {
+    #[repr(C, align(32))]
+    struct Chunk([Word; 4]);
+
+    let mut words = vec![type_word(id)];
+    words.extend(fields.iter().map(Expr::immediate_rep));
+    let mut chunks: Vec<Chunk> = words
+        .chunks(4)
+        .map(|c| {
+            let mut chunk = [0; 4];
+            chunk[..c.len()].copy_from_slice(c);
+            Chunk(chunk)
+        })
+        .collect();
+    let ptr = chunks.as_mut_ptr() as Word;
+    std::mem::forget(chunks);
+    ptr | RECORD_TAG
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::expect_fatal_with;
+    use crate::{roundtrip_string, Expr};
+
+    const POINT: &str = r#"
+(define-record-type point (make-point x y) point?
+  (x point-x set-point-x!)
+  (y point-y set-point-y!))
+"#;
+
+    fn run(source: &str) -> Result<Expr, String> {
+        roundtrip_string(&format!("{}{}", POINT, source))
+    }
+
+    #[test]
+    fn point_record() {
+        let source = r#"
+(let p (make-point 1 2))
+(set-point-x! p 10)
+(set-point-y! p (add (point-y p) 5))
+(list (point-x p) (point-y p) (point? p) (point? (cons 1 2)) (point? 3))
+"#;
+        assert_eq!(
+            run(source).unwrap(),
+            roundtrip_string("'(10 7 #t #f #f)").unwrap()
+        );
+    }
+
+    #[test]
+    fn record_printing() {
+        assert_eq!(
+            run("(make-point 1 \"two\")").unwrap().to_string(),
+            "#<point x: 1 y: \"two\">"
+        );
+        // Records read back with their fields.
+        match run("(make-point 1 (list 2))").unwrap() {
+            Expr::Record(_, fields) => assert_eq!(
+                fields,
+                vec![Expr::Integer(1), roundtrip_string("'(2)").unwrap()]
+            ),
+            e => panic!("expected a record, got {}", e),
+        }
+    }
+
+    #[test]
+    fn record_types_are_distinct() {
+        let source = r#"
+(define-record-type other (make-other x) other? (x other-x))
+(let p (make-point 1 2))
+(list (other? p) (point? (make-other 1)) (other-x (make-other 3)))
+"#;
+        assert_eq!(
+            run(source).unwrap(),
+            roundtrip_string("'(#f #f 3)").unwrap()
+        );
+    }
+
+    #[test]
+    fn constructor_fields() {
+        // The constructor can take the fields in any order and leave
+        // some out. Those start out as nil.
+        let source = r#"
+(define-record-type node (make-node value) node?
+  (next node-next set-node-next!)
+  (value node-value))
+(let n (make-node 1))
+(let before (node-next n))
+(set-node-next! n (make-node 2))
+(list before (node-value (node-next n)))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(() 2)").unwrap()
+        );
+    }
+
+    #[test]
+    fn higher_order_accessors() {
+        let source = r#"
+(let ps (map (fn (i) (make-point i (mul i i))) (list 1 2 3)))
+(map point-y ps)
+"#;
+        assert_eq!(run(source).unwrap(), roundtrip_string("'(1 4 9)").unwrap());
+    }
+
+    #[test]
+    fn records_survive_collections() {
+        // Enough records to fill the heap a few times over. Only the
+        // tagged pointers to them are kept around.
+        let source = r#"
+(let build (fn (n)
+  (let loop ((i 0) (acc ()))
+    (if (eq? i n) acc (loop (add1 i) (cons (make-point i 1) acc))))))
+(let sum (fn (ps)
+  (let loop ((ps ps) (acc 0))
+    (if (null? ps) acc (loop (cdr ps) (add acc (add (point-x (car ps)) (point-y (car ps)))))))))
+(sum (build 100000))
+"#;
+        assert_eq!(run(source).unwrap(), Expr::Integer(5000050000));
+    }
+
+    #[test]
+    fn malformed_definitions() {
+        let error = |source: &str| roundtrip_string(source).unwrap_err();
+        assert!(error("(define-record-type point)").contains("malformed define-record-type"));
+        assert!(
+            error("(define-record-type point (make-point z) point? (x point-x))")
+                .contains("constructor argument z is not a field")
+        );
+        assert!(error("(define-record-type p (make-p) p? (x p-x) (x p-x2))")
+            .contains("duplicate field x"));
+    }
+
+    #[test]
+    fn wrong_record_type() {
+        for source in &[
+            "(define-record-type other (make-other x) other? (x other-x)) (point-x (make-other 1))",
+            "(point-y (cons 1 2))",
+            "(set-point-x! 1 2)",
+        ] {
+            expect_fatal_with(source, "runtime type missmatch", || {
+                let _ = run(source);
+            });
+        }
+    }
+}
@@ -4,7 +4,14 @@
 
 use std::collections::HashMap;
 
-use crate::primitives::string_is_builtin;
+use crate::conversions;
+use crate::errors::LustError;
+use crate::fatal;
+use crate::fluid;
+use crate::globals::{self, Globals};
+use crate::location::Span;
+use crate::primitives::{string_is_builtin, string_is_primitive};
+use crate::records;
 use crate::Expr;
 use crate::PreorderStatus;
 
@@ -12,8 +19,8 @@ impl Expr {
     /// Renames the variable being bound to by the let expression that
     /// this expression is assumed to represent. Returns an error if
     /// the expression is not indeed a let expression.
-    fn rename_let_binding(&mut self, count: usize) -> Result<(), String> {
-        if let Self::List(v) = self {
+    fn rename_let_binding(&mut self, count: usize) -> Result<(), LustError> {
+        if let Self::List(v, _) = self {
             if let Some(Expr::Symbol(s)) = v.first() {
                 if s == "let" && v.len() == 3 {
                     if let Expr::Symbol(s) = &mut v[1] {
@@ -23,43 +30,43 @@ impl Expr {
                 }
             }
         }
-        Err(format!(
+        Err(LustError::Compile(format!(
             "internal error: rename_let_binding called on non-let expression: ({:?})",
             self
-        ))
+        )))
     }
 
-    fn get_let_name(&mut self) -> Result<String, String> {
+    fn get_let_name(&mut self) -> Result<String, LustError> {
         if let Some((name, _)) = self.is_let() {
             Ok(name.clone())
         } else {
-            Err(format!(
+            Err(LustError::Compile(format!(
                 "internal error: get_let_name called on non-let expression: ({:?})",
                 self
-            ))
+            )))
         }
     }
 
-    fn get_let_value_mut(&mut self) -> Result<&mut Expr, String> {
+    fn get_let_value_mut(&mut self) -> Result<&mut Expr, LustError> {
         if let Some(_) = self.is_let() {
-            if let Self::List(v) = self {
+            if let Self::List(v, _) = self {
                 return Ok(&mut v[2]);
             }
         }
-        Err(format!(
+        Err(LustError::Compile(format!(
             "internal error: get_let_value_mut called on non-let expression: ({:?})",
             self
-        ))
+        )))
     }
 
-    fn get_symbol_name(&self) -> Result<String, String> {
+    fn get_symbol_name(&self) -> Result<String, LustError> {
         if let Self::Symbol(s) = self {
             Ok(s.clone())
         } else {
-            Err(format!(
+            Err(LustError::Compile(format!(
                 "internal error: get_symbol_name called on non-symbol expression: ({:?})",
                 self
-            ))
+            )))
         }
     }
 
@@ -67,10 +74,10 @@ impl Expr {
         &mut self,
         count: &mut usize,
         env: &mut HashMap<String, String>,
-    ) -> Result<(), String> {
+    ) -> Result<(), LustError> {
         if let Some(_) = self.is_fndef() {
-            if let Self::List(v) = self {
-                if let Self::List(v) = &mut v[1] {
+            if let Self::List(v, _) = self {
+                if let Self::List(v, _) = &mut v[1] {
                     for s in v {
                         let old_name = s.get_symbol_name()?;
                         let new_name = format!("{}_{}", *count, old_name);
@@ -84,35 +91,366 @@ impl Expr {
                 }
             }
         }
-        Err(format!(
+        Err(LustError::Compile(format!(
             "internal error: rename_fn_params called on non-fndef expression: ({:?})",
             self
-        ))
+        )))
     }
 
-    fn get_fn_body_mut(&mut self) -> Result<&mut [Expr], String> {
+    fn get_fn_body_mut(&mut self) -> Result<&mut [Expr], LustError> {
         if let Some(_) = self.is_fndef() {
-            if let Self::List(v) = self {
+            if let Self::List(v, _) = self {
                 return Ok(&mut v[2..]);
             }
         }
-        Err(format!(
+        Err(LustError::Compile(format!(
             "internal error: get_fn_body_mut called on non-fndef expression: ({:?})",
             self
-        ))
+        )))
     }
 }
 
+/// Renames the names bound by a scoped let or letrec expression and
+/// lowers it into a begin expression. Once every name is unique the
+/// scoping is taken care of so the bindings can be made with regular
+/// let expressions in the enclosing scope.
+///
+/// If RECURSIVE is set the values of the bindings are renamed in the
+/// new scope so that they can refer to each other. All of the names
+/// are declared as nil first and then set to their values:
+///
+/// (letrec ((a 1)) a) => (begin (let 0_a ()) (set 0_a 1) 0_a)
+///
+/// Otherwise the values are renamed in the enclosing scope:
+///
+/// (let ((a 1)) a) => (begin (let 0_a 1) 0_a)
+///
+/// The begin expression is given SPAN so that errors in it still
+/// point at the original expression.
+fn lower_binding_form(
+    bindings: &[(&String, &Expr)],
+    body: &[Expr],
+    recursive: bool,
+    span: Span,
+    env: &mut HashMap<String, String>,
+    count: &mut usize,
+) -> Result<Expr, LustError> {
+    let mut nenv = env.clone();
+    let mut names = Vec::with_capacity(bindings.len());
+    for (name, _) in bindings {
+        let new_name = format!("{}_{}", *count, name);
+        *count += 1;
+        nenv.insert(name.to_string(), new_name.clone());
+        names.push(new_name);
+    }
+
+    let mut vals = Vec::with_capacity(bindings.len());
+    for (_, val) in bindings {
+        let mut val = (*val).clone();
+        if recursive {
+            make_expr_names_unique(&mut val, &mut nenv, count)?;
+        } else {
+            make_expr_names_unique(&mut val, env, count)?;
+        }
+        vals.push(val);
+    }
+
+    let mut body = body.to_vec();
+    for e in body.iter_mut() {
+        make_expr_names_unique(e, &mut nenv, count)?;
+    }
+
+    let form = |keyword: &str, name: &str, val: Expr| {
+        Expr::list(vec![
+            Expr::Symbol(keyword.to_string()),
+            Expr::Symbol(name.to_string()),
+            val,
+        ])
+    };
+
+    let mut sequence = vec![Expr::Symbol("begin".to_string())];
+    if recursive {
+        sequence.extend(names.iter().map(|name| form("let", name, Expr::Nil)));
+        sequence.extend(
+            names
+                .iter()
+                .zip(vals)
+                .map(|(name, val)| form("set", name, val)),
+        );
+    } else {
+        sequence.extend(
+            names
+                .iter()
+                .zip(vals)
+                .map(|(name, val)| form("let", name, val)),
+        );
+    }
+    sequence.extend(body);
+    Ok(Expr::List(sequence, span))
+}
+
+/// Nests the bindings of a let* expression into one scoped let for
+/// each so that every binding is made in the scope of the ones before
+/// it:
+///
+/// (let* ((a 1) (b a)) b) => (let ((a 1)) (let ((b a)) b))
+///
+/// The outermost let is given SPAN.
+fn nest_let_star(bindings: &[(&String, &Expr)], body: &[Expr], span: Span) -> Expr {
+    let scope = |binding: Expr, body: Vec<Expr>| {
+        let mut v = vec![Expr::Symbol("let".to_string()), binding];
+        v.extend(body);
+        v
+    };
+    let mut body = body.to_vec();
+    for (name, val) in bindings.iter().skip(1).rev() {
+        let binding = Expr::list(vec![Expr::list(vec![
+            Expr::Symbol(name.to_string()),
+            (*val).clone(),
+        ])]);
+        body = vec![Expr::list(scope(binding, body))];
+    }
+    let first = match bindings.first() {
+        Some((name, val)) => Expr::list(vec![Expr::list(vec![
+            Expr::Symbol(name.to_string()),
+            (*val).clone(),
+        ])]),
+        None => Expr::Nil,
+    };
+    Expr::List(scope(first, body), span)
+}
+
+/// Lowers a named let expression into a call to a recursive function
+/// that takes the bindings as arguments:
+///
+/// (let loop ((i 0)) (loop i)) => ((letrec ((loop (fn (i) (loop i)))) loop) 0)
+///
+/// The values of the bindings are outside of the letrec so they can't
+/// see the function. Calls to it in tail position become loops as
+/// functions that call themselves in tail position jump back to their
+/// start. The call is given SPAN.
+fn lower_named_let(name: &str, bindings: &[(&String, &Expr)], body: &[Expr], span: Span) -> Expr {
+    let params = bindings
+        .iter()
+        .map(|(name, _)| Expr::Symbol(name.to_string()))
+        .collect::<Vec<_>>();
+    let params = if params.is_empty() {
+        Expr::Nil
+    } else {
+        Expr::list(params)
+    };
+    let mut function = vec![Expr::Symbol("fn".to_string()), params];
+    function.extend(body.iter().cloned());
+
+    let letrec = Expr::list(vec![
+        Expr::Symbol("letrec".to_string()),
+        Expr::list(vec![Expr::list(vec![
+            Expr::Symbol(name.to_string()),
+            Expr::list(function),
+        ])]),
+        Expr::Symbol(name.to_string()),
+    ]);
+    let mut call = vec![letrec];
+    call.extend(bindings.iter().map(|(_, val)| (*val).clone()));
+    Expr::List(call, span)
+}
+
+/// Checks the datums of each clause in the case expression EXPR and
+/// quotes the ones that are symbols so that they aren't mistaken for
+/// variables.
+fn quote_case_datums(expr: &mut Expr) -> Result<(), LustError> {
+    let clauses = match expr {
+        Expr::List(v, _) => &mut v[2..],
+        _ => return Ok(()),
+    };
+    for clause in clauses {
+        let datums = match clause {
+            Expr::List(v, _) => match v.first_mut() {
+                Some(Expr::Symbol(s)) if s == "else" => continue,
+                Some(Expr::List(datums, _)) => datums,
+                _ => continue,
+            },
+            _ => continue,
+        };
+        for datum in datums {
+            let literal = match &*datum {
+                Expr::Symbol(s) => Expr::list(vec![
+                    Expr::Symbol("quote".to_string()),
+                    Expr::Symbol(s.clone()),
+                ]),
+                Expr::Integer(_) | Expr::Char(_) | Expr::Bool(_) | Expr::Nil | Expr::Keyword(_) => {
+                    continue
+                }
+                _ => return Err(bad_case_datum(datum)),
+            };
+            *datum = literal;
+        }
+    }
+    Ok(())
+}
+
+fn bad_case_datum(datum: &Expr) -> LustError {
+    LustError::Compile(format!(
+        "case datums must be integers, characters, booleans, nil, symbols or keywords, got: {}",
+        datum
+    ))
+}
+
+/// Gets the value of the constant named NAME if there is one. These
+/// can be shadowed like any other variable.
+fn named_constant(name: &str) -> Option<Expr> {
+    match name {
+        "most-positive-fixnum" => Some(Expr::Integer(conversions::FIXNUM_MAX)),
+        "most-negative-fixnum" => Some(Expr::Integer(conversions::FIXNUM_MIN)),
+        _ => None,
+    }
+}
+
+/// Gets the name that NAME had before it was made unique.
+pub(crate) fn original_name(name: &str) -> &str {
+    match name.split_once('_') {
+        Some((count, rest)) if !count.is_empty() && count.chars().all(|c| c.is_ascii_digit()) => {
+            rest
+        }
+        _ => name,
+    }
+}
+
+/// Lowers a top level define expression into the let expression that
+/// it is equivalent to:
+///
+/// (define a 1) => (let a 1)
+/// (define (f a) (add1 a)) => (let f (fn (a) (add1 a)))
+///
+/// As names bound by let at the top level are visible to every
+/// expression that comes after them this makes the definition
+/// global. The let expression is given SPAN.
+fn lower_define(target: &Expr, rest: &[Expr], span: Span) -> Result<Expr, LustError> {
+    let binding = |name: &Expr, val: Expr| {
+        Expr::List(
+            vec![Expr::Symbol("let".to_string()), name.clone(), val],
+            span,
+        )
+    };
+    match (target, rest) {
+        (Expr::Symbol(_), [val]) => Ok(binding(target, val.clone())),
+        (Expr::List(v, _), body) if matches!(v.first(), Some(Expr::Symbol(_))) => {
+            let params = if v.len() == 1 {
+                Expr::Nil
+            } else {
+                Expr::list(v[1..].to_vec())
+            };
+            let mut fndef = vec![Expr::Symbol("fn".to_string()), params];
+            fndef.extend_from_slice(body);
+            Ok(binding(&v[0], Expr::list(fndef)))
+        }
+        _ => Err(LustError::Compile(format!(
+            "malformed define expression: (define {} ...)",
+            target
+        ))),
+    }
+}
+
+/// Turns the definitions at the start of the body of EXPR, if it is a
+/// function or a scoped let or letrec, into a letrec around the rest
+/// of the body:
+///
+/// (fn () (define a 1) (define (f) a) (f)) => (fn () (letrec ((a 1) (f (fn () a))) (f)))
+///
+/// Like at the top level the definitions can refer to each other.
+/// Definitions anywhere else in a body are left for the renamer to
+/// report.
+fn hoist_internal_defines(expr: &mut Expr) -> Result<(), LustError> {
+    let is_body =
+        expr.is_fndef().is_some() || expr.is_scoped_let().is_some() || expr.is_letrec().is_some();
+    let v = match expr {
+        Expr::List(v, _) if is_body => v,
+        _ => return Ok(()),
+    };
+    let defines = v[2..]
+        .iter()
+        .take_while(|e| e.is_define().is_some())
+        .count();
+    if defines == 0 {
+        return Ok(());
+    }
+    let span = v[2].span();
+    if v.len() == 2 + defines {
+        return Err(LustError::Compile(
+            "expected an expression after the definitions in a body".to_string(),
+        )
+        .at(span));
+    }
+    let mut bindings = Vec::with_capacity(defines);
+    for e in v.drain(2..2 + defines) {
+        let (target, rest) = e.is_define().unwrap();
+        let lowered = lower_define(target, rest, e.span()).map_err(|err| err.at(e.span()))?;
+        if let Expr::List(mut binding, _) = lowered {
+            binding.remove(0);
+            bindings.push(Expr::list(binding));
+        }
+    }
+    let mut letrec = vec![Expr::Symbol("letrec".to_string()), Expr::list(bindings)];
+    letrec.extend(v.drain(2..));
+    v.push(Expr::List(letrec, span));
+    Ok(())
+}
+
 fn make_expr_names_unique(
     expr: &mut Expr,
     env: &mut HashMap<String, String>,
     count: &mut usize,
-) -> Result<(), String> {
-    expr.preorder_traverse_mut_res::<_, String>(&mut |expr| {
-        if let Some(_) = expr.is_let() {
+) -> Result<(), LustError> {
+    let span = expr.span();
+    expr.preorder_traverse_mut_res::<_, LustError>(&mut |expr| {
+        hoist_internal_defines(expr)?;
+        if expr.is_define().is_some() {
+            return Err(LustError::Compile(
+                "define is only allowed at the top level and at the start of a body".to_string(),
+            ));
+        } else if expr.is_quote().is_some() {
+            // Quoted symbols are data and not references to
+            // variables.
+            return Ok(PreorderStatus::Skip);
+        } else if let Some(asserted) = expr.is_assert() {
+            *expr = fatal::lower_assert(asserted, expr.span());
+            make_expr_names_unique(expr, env, count)?;
+            return Ok(PreorderStatus::Skip);
+        } else if let Some(rest) = expr.is_define_record_type() {
+            *expr = records::lower_define_record_type(rest, expr.span())?;
+            make_expr_names_unique(expr, env, count)?;
+            return Ok(PreorderStatus::Skip);
+        } else if let Some((bindings, body)) = expr.is_scoped_let() {
+            let lowered = lower_binding_form(&bindings, body, false, expr.span(), env, count)?;
+            *expr = lowered;
+            return Ok(PreorderStatus::Skip);
+        } else if let Some((bindings, body)) = expr.is_fluid_let() {
+            *expr = fluid::lower_fluid_let(&bindings, body, expr.span());
+            make_expr_names_unique(expr, env, count)?;
+            return Ok(PreorderStatus::Skip);
+        } else if let Some((bindings, body)) = expr.is_let_star() {
+            *expr = nest_let_star(&bindings, body, expr.span());
+            make_expr_names_unique(expr, env, count)?;
+            return Ok(PreorderStatus::Skip);
+        } else if let Some((name, bindings, body)) = expr.is_named_let() {
+            *expr = lower_named_let(name, &bindings, body, expr.span());
+            make_expr_names_unique(expr, env, count)?;
+            return Ok(PreorderStatus::Skip);
+        } else if let Some((bindings, body)) = expr.is_letrec() {
+            let lowered = lower_binding_form(&bindings, body, true, expr.span(), env, count)?;
+            *expr = lowered;
+            return Ok(PreorderStatus::Skip);
+        } else if expr.is_let().is_some() {
             let old_name = expr.get_let_name()?;
 
-            let name_exists = env.contains_key(&old_name) || string_is_builtin(&old_name);
+            // Functions bound to the name of a primitive can call
+            // themselves like any other function. Otherwise a
+            // recursive function named list would quietly call the
+            // list primitive.
+            let binds_function = expr.is_let().is_some_and(|(_, v)| v.is_fndef().is_some());
+            let shadows_primitive = string_is_primitive(&old_name) && binds_function;
+            let name_exists =
+                env.contains_key(&old_name) || (string_is_builtin(&old_name) && !shadows_primitive);
 
             if name_exists {
                 make_expr_names_unique(expr.get_let_value_mut()?, env, count)?;
@@ -136,6 +474,14 @@ fn make_expr_names_unique(
             // body above we indicate to the traversal that it should
             // skip the rest of this expression.
             return Ok(PreorderStatus::Skip);
+        } else if expr.is_case().is_some() {
+            quote_case_datums(expr)?;
+            if let Expr::List(v, _) = expr {
+                for e in v {
+                    make_expr_names_unique(e, env, count)?;
+                }
+            }
+            return Ok(PreorderStatus::Skip);
         } else if let Some(_) = expr.is_fndef() {
             // Make a new enviroment to analyze this function in. It
             // has all of the items in the current enviroment and
@@ -148,27 +494,183 @@ fn make_expr_names_unique(
             // We've already traversed the body so we don't want the
             // traversal to continue on this expr.
             return Ok(PreorderStatus::Skip);
+        } else if let Expr::List(v, _) = expr {
+            // Calls to names that aren't bound are most likely typos
+            // so they get their own error.
+            if let Some(Expr::Symbol(s)) = v.first() {
+                if !env.contains_key(s) && named_constant(s).is_none() && !string_is_builtin(s) {
+                    return Err(LustError::UnboundFunction(s.clone()));
+                }
+            }
+            // Lists are traversed here instead of by the traversal so
+            // that errors are located at the innermost list that they
+            // happen in.
+            for e in v {
+                make_expr_names_unique(e, env, count)?;
+            }
+            return Ok(PreorderStatus::Skip);
         } else if let Expr::Symbol(s) = expr {
-            let newname = env
-                .get(s)
-                .or_else(|| if string_is_builtin(s) { Some(s) } else { None })
-                .ok_or(format!("undefined variable ({})", s))?;
-            *s = newname.to_string();
+            if let Some(newname) = env.get(s) {
+                *s = newname.to_string();
+            } else if let Some(constant) = named_constant(s) {
+                *expr = constant;
+            } else if !string_is_builtin(s) {
+                return Err(LustError::UnboundSymbol(s.clone()));
+            }
         }
 
         Ok(PreorderStatus::Continue)
-    })?;
+    })
+    .map_err(|e| e.at(span))?;
     Ok(())
 }
 
-pub fn make_names_unique(program: &mut [Expr]) -> Result<(), String> {
+pub fn make_names_unique(program: &mut [Expr]) -> Result<(), LustError> {
     let _t = crate::timer::timeit("symbol renaming pass");
     let mut count = 0;
     let mut env = HashMap::new();
 
-    for e in program {
+    let mut defined = Vec::with_capacity(program.len());
+    for e in program.iter_mut() {
+        if let Some((target, rest)) = e.is_define() {
+            *e = lower_define(target, rest, e.span()).map_err(|err| err.at(e.span()))?;
+            defined.push(e.is_let().map(|(name, _)| name.clone()));
+        } else {
+            defined.push(None);
+        }
+    }
+
+    // The top level is a letrec* scope. Definitions that functions
+    // earlier in the program refer to are declared at the start of
+    // the program and their definitions become sets. Other uses of a
+    // name before it is defined run before it has a value so they are
+    // left as errors.
+    let mut declared = Vec::new();
+    for i in 0..program.len() {
+        let name = match &defined[i] {
+            Some(name) if program[..i].iter().any(|e| mentions(e, name, true)) => name,
+            _ => continue,
+        };
+        let new_name = format!("{}_{}", count, name);
+        count += 1;
+        env.insert(name.clone(), new_name.clone());
+        if let Expr::List(v, _) = &mut program[i] {
+            v[0] = Expr::Symbol("set".to_string());
+        }
+        declared.push((i, name.clone(), new_name));
+    }
+
+    for (i, e) in program.iter_mut().enumerate() {
+        // Earlier lets may have shadowed the declared name.
+        if let Some((_, name, new_name)) = declared.iter().find(|(j, ..)| *j == i) {
+            env.insert(name.clone(), new_name.clone());
+        }
         make_expr_names_unique(e, &mut env, &mut count)?;
     }
 
+    if let Some(first) = program.first_mut() {
+        if !declared.is_empty() {
+            let mut sequence = vec![Expr::Symbol("begin".to_string())];
+            sequence.extend(declared.into_iter().map(|(_, _, name)| {
+                Expr::list(vec![
+                    Expr::Symbol("let".to_string()),
+                    Expr::Symbol(name),
+                    Expr::Nil,
+                ])
+            }));
+            let span = first.span();
+            sequence.push(std::mem::replace(first, Expr::Nil));
+            *first = Expr::List(sequence, span);
+        }
+    }
+
     Ok(())
 }
+
+/// Like make_names_unique but the definitions at the top level of
+/// PROGRAM, including the procedures of record types, define GLOBALS
+/// and names that GLOBALS already has refer to them. Globals are
+/// spelled by globals::spelling for replace_globals to find.
+pub(crate) fn make_names_unique_with_globals(
+    program: &mut [Expr],
+    globals: &mut Globals,
+) -> Result<(), LustError> {
+    let _t = crate::timer::timeit("symbol renaming pass");
+    let mut count = 0;
+    let mut env: HashMap<String, String> = globals
+        .names()
+        .map(|name| (name.to_string(), globals::spelling(name)))
+        .collect();
+
+    // Definitions become sets of the globals that they define.
+    let mut defined = Vec::with_capacity(program.len());
+    for e in program.iter_mut() {
+        let span = e.span();
+        let mut names = Vec::new();
+        if let Some((target, rest)) = e.is_define() {
+            *e = lower_define(target, rest, span).map_err(|err| err.at(span))?;
+            let_to_set(e, &mut names);
+        } else if let Some(rest) = e.is_define_record_type() {
+            *e = records::lower_define_record_type(rest, span).map_err(|err| err.at(span))?;
+            if let Expr::List(v, _) = e {
+                for e in &mut v[1..] {
+                    let_to_set(e, &mut names);
+                }
+            }
+        }
+        defined.push(names);
+    }
+
+    // As in make_names_unique functions can use definitions that come
+    // after them.
+    for (i, names) in defined.iter().enumerate() {
+        for name in names {
+            globals.define(name);
+            if program[..i].iter().any(|e| mentions(e, name, true)) {
+                env.insert(name.clone(), globals::spelling(name));
+            }
+        }
+    }
+
+    for (e, names) in program.iter_mut().zip(&defined) {
+        for name in names {
+            env.insert(name.clone(), globals::spelling(name));
+        }
+        make_expr_names_unique(e, &mut env, &mut count)?;
+    }
+    Ok(())
+}
+
+/// Turns E, a (let NAME VALUE) expression, into (set NAME VALUE) and
+/// adds NAME to NAMES.
+fn let_to_set(e: &mut Expr, names: &mut Vec<String>) {
+    if let Some((name, _)) = e.is_let() {
+        names.push(name.clone());
+        if let Expr::List(v, _) = e {
+            v[0] = Expr::Symbol("set".to_string());
+        }
+    }
+}
+
+/// Determines if the symbol NAME appears in E outside of a quote. If
+/// IN_FUNCTION is set only appearances inside of a function count.
+fn mentions(e: &Expr, name: &str, in_function: bool) -> bool {
+    let mut found = false;
+    e.preorder_traverse(&mut |e: &Expr| {
+        match e {
+            Expr::Symbol(s) if s == name && !in_function => found = true,
+            e if e.is_quote().is_some() => return PreorderStatus::Skip,
+            e if e.is_fndef().is_some() && in_function => {
+                found = mentions(e, name, false);
+                return PreorderStatus::Skip;
+            }
+            _ => (),
+        }
+        if found {
+            PreorderStatus::Skip
+        } else {
+            PreorderStatus::Continue
+        }
+    });
+    found
+}
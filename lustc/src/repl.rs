@@ -0,0 +1,260 @@
+//! State for an interactive session. Every entry is compiled into
+//! the same JIT where its top level definitions become globals so
+//! that functions and values defined on one line can be used and
+//! changed on the next.
+
+use std::io::{self, BufRead, Write};
+
+use crate::compiler::{compile_entry, run_entry, JIT};
+use crate::errors::LustError;
+use crate::globals::Globals;
+use crate::reader;
+use crate::Expr;
+
+/// Holds the code and globals of the entries run during a session.
+pub struct Repl {
+    jit: JIT,
+    // Each entry's code is namespaced with the number of entries
+    // before it so that it doesn't clash with theirs.
+    entries: usize,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        let mut jit = JIT {
+            globals: Some(Globals::default()),
+            ..JIT::default()
+        };
+        // A bad entry shouldn't end the session.
+        jit.options.raise_fatal_errors = true;
+        Self { jit, entries: 0 }
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs ENTRY and returns the result of its last expression, or
+    /// None if the last expression is a definition. Definitions made
+    /// by ENTRY are there for later entries unless it fails to
+    /// compile.
+    pub fn eval(&mut self, entry: &[Expr]) -> Result<Option<Expr>, LustError> {
+        // Programs that end with a definition evaluate to nil.
+        let defines_last = entry
+            .last()
+            .is_some_and(|e| e.is_define().is_some() || e.is_define_record_type().is_some());
+
+        let globals = self.globals().len();
+        self.jit.namespace = Some(format!("repl_{}", self.entries));
+        self.entries += 1;
+        let compiled = match compile_entry(&mut self.jit, entry, &[]) {
+            Ok(compiled) => compiled,
+            Err(e) => {
+                // Nothing ran so the globals that the entry would
+                // have defined can go.
+                self.globals().truncate(globals);
+                return Err(e);
+            }
+        };
+
+        // Safety: the code lives as long as self.
+        let res = unsafe { run_entry(&self.jit, compiled, &[]) }?;
+        Ok(if defines_last { None } else { Some(res) })
+    }
+
+    fn globals(&mut self) -> &mut Globals {
+        self.jit.globals.get_or_insert_with(Globals::default)
+    }
+}
+
+impl Drop for Repl {
+    fn drop(&mut self) {
+        // Safety: entries can't outlive self so none are running.
+        unsafe { self.jit.module.free_memory() }
+    }
+}
+
+/// Reads entries from INPUT and writes their results to OUTPUT until
+/// INPUT runs out. Entries with unclosed parens continue onto the next
+/// line. Errors are written to OUTPUT and the session carries on.
+/// Anything the program itself prints goes to stdout as usual.
+pub fn repl(input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut repl = Repl::new();
+    let mut entry = String::new();
+    let mut lines = input.lines();
+
+    loop {
+        write!(output, "{}", if entry.is_empty() { "> " } else { ". " })?;
+        output.flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        entry.push_str(&line);
+        entry.push('\n');
+
+        let exprs = match reader::parse(&entry) {
+            Ok(exprs) => exprs,
+            Err(e)
+                if e.message == "unbalanced parenthesis"
+                    || e.message == "unterminated block comment" =>
+            {
+                continue
+            }
+            Err(e) => {
+                writeln!(output, "error: {}", e)?;
+                entry.clear();
+                continue;
+            }
+        };
+        entry.clear();
+
+        if exprs.is_empty() {
+            continue;
+        }
+        match repl.eval(&exprs) {
+            Ok(Some(res)) => writeln!(output, "{}", res)?,
+            Ok(None) => (),
+            Err(e) => writeln!(output, "error: {}", e)?,
+        }
+    }
+    writeln!(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::parse;
+
+    fn eval(repl: &mut Repl, entry: &str) -> Result<Option<Expr>, LustError> {
+        repl.eval(&parse(entry).unwrap())
+    }
+
+    #[test]
+    fn definitions_persist() {
+        let mut repl = Repl::new();
+        assert_eq!(eval(&mut repl, "(define x 10)"), Ok(None));
+        assert_eq!(eval(&mut repl, "(define (add-x n) (add n x))"), Ok(None));
+        assert_eq!(eval(&mut repl, "(add-x 1)"), Ok(Some(Expr::Integer(11))));
+        // Expressions that aren't definitions are forgotten.
+        eval(&mut repl, "(let y 1)").unwrap();
+        assert_eq!(
+            eval(&mut repl, "y"),
+            Err(LustError::UnboundSymbol("y".to_string()))
+        );
+    }
+
+    #[test]
+    fn errors_are_forgotten() {
+        let mut repl = Repl::new();
+        assert!(eval(&mut repl, "(define x y)").is_err());
+        assert!(eval(&mut repl, "(define x 1) (car 1 2)").is_err());
+        // Neither of the failed definitions were kept.
+        assert_eq!(
+            eval(&mut repl, "x"),
+            Err(LustError::UnboundSymbol("x".to_string()))
+        );
+        eval(&mut repl, "(define x 2)").unwrap();
+        assert_eq!(eval(&mut repl, "(add1 x)"), Ok(Some(Expr::Integer(3))));
+    }
+
+    #[test]
+    fn changes_persist() {
+        let mut repl = Repl::new();
+        eval(&mut repl, "(define x 0)").unwrap();
+        eval(&mut repl, "(set! x 5)").unwrap();
+        assert_eq!(eval(&mut repl, "x"), Ok(Some(Expr::Integer(5))));
+        // Functions from earlier entries see changes made later.
+        eval(&mut repl, "(define (get) x)").unwrap();
+        eval(&mut repl, "(set! x 6)").unwrap();
+        assert_eq!(eval(&mut repl, "(get)"), Ok(Some(Expr::Integer(6))));
+        // Definitions are only run once.
+        eval(&mut repl, "(define count 0)").unwrap();
+        eval(&mut repl, "(define (tick) (set! count (add1 count)) count)").unwrap();
+        eval(&mut repl, "(tick)").unwrap();
+        assert_eq!(eval(&mut repl, "(tick)"), Ok(Some(Expr::Integer(2))));
+        // Redefining a function changes what calls to it do.
+        eval(&mut repl, "(define (f) 1) (define (g) (f))").unwrap();
+        eval(&mut repl, "(define (f) 2)").unwrap();
+        assert_eq!(eval(&mut repl, "(g)"), Ok(Some(Expr::Integer(2))));
+    }
+
+    #[test]
+    fn records_persist() {
+        let mut repl = Repl::new();
+        eval(
+            &mut repl,
+            "(define-record-type point (make-point x y) point? (x point-x) (y point-y set-y!))",
+        )
+        .unwrap();
+        eval(&mut repl, "(define p (make-point 3 4))").unwrap();
+        eval(&mut repl, "(set-y! p 5)").unwrap();
+        let res = eval(&mut repl, "(list (point? p) (point-x p) (point-y p))");
+        assert_eq!(res.unwrap().unwrap().to_string(), "(#t 3 5)");
+    }
+
+    #[test]
+    fn runtime_errors_keep_definitions() {
+        let mut repl = Repl::new();
+        assert!(eval(&mut repl, r#"(define z 1) (error "oops")"#).is_err());
+        // The definition ran before the error so it stays.
+        assert_eq!(eval(&mut repl, "z"), Ok(Some(Expr::Integer(1))));
+    }
+
+    #[test]
+    fn fatal_errors_keep_the_session() {
+        let mut repl = Repl::new();
+        eval(&mut repl, "(define v (make-vector 2 7))").unwrap();
+        assert_eq!(
+            eval(&mut repl, "(car 5)").map_err(|e| e.to_string()),
+            Err("runtime type missmatch".to_string())
+        );
+        assert!(eval(&mut repl, "(vector-ref v 99)").is_err());
+        // Both errors came from later entries so v is still around.
+        assert_eq!(
+            eval(&mut repl, "(vector-ref v 1)"),
+            Ok(Some(Expr::Integer(7)))
+        );
+    }
+
+    #[test]
+    fn globals_survive_collections() {
+        let mut repl = Repl::new();
+        eval(&mut repl, "(define l (list 1 2 3))").unwrap();
+        let before = crate::gc::collections();
+        eval(
+            &mut repl,
+            "(letrec ((loop (fn (n) (if (eq n 0) #t (begin (cons n n) (loop (sub n 1)))))))
+               (loop 1000000))",
+        )
+        .unwrap();
+        assert!(crate::gc::collections() > before);
+        let res = eval(&mut repl, "l");
+        assert_eq!(res.unwrap().unwrap().to_string(), "(1 2 3)");
+    }
+
+    /// Runs a session reading INPUT and returns what it wrote.
+    fn session(input: &str) -> String {
+        let mut output = Vec::new();
+        repl(std::io::Cursor::new(input), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn repl_over_streams() {
+        let input = "(define (sq n) (mul n n))\n(sq 12)\n";
+        assert_eq!(session(input), "> > 144\n> \n");
+        // Entries can span lines and errors don't end the session.
+        let input = "(car 1 2)\n(add 1\n 2)\n";
+        assert_eq!(
+            session(input),
+            "> error: car expected 1 args and got 2 at line 1, column 1\n> . 3\n> \n"
+        );
+        // Nor do errors that would otherwise exit the process.
+        let input = "(car 5)\n(add 1 2)\n";
+        assert_eq!(session(input), "> error: runtime type missmatch\n> 3\n> \n");
+    }
+}
@@ -0,0 +1,169 @@
+//! First this, then that, and then the other thing. Only the last
+//! one gets to say anything.
+
+use cranelift::prelude::*;
+
+use crate::compiler::emit_expr;
+use crate::compiler::Context;
+use crate::conditional::emit_is_true;
+use crate::errors::LustError;
+use crate::Expr;
+
+impl Expr {
+    /// Determines if the expression is a begin expression and if it
+    /// is returns the expressions in its body.
+    pub fn is_begin(&self) -> Option<&[Expr]> {
+        match self {
+            Self::List(v, _) => match v.first() {
+                Some(Expr::Symbol(s)) if s == "begin" => Some(&v[1..]),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Determines if the expression is a while expression and if it
+    /// is returns its test and body.
+    pub fn is_while(&self) -> Option<(&Expr, &[Expr])> {
+        match self {
+            Self::List(v, _) => match &v[..] {
+                [Expr::Symbol(s), test, body @ ..] if s == "while" => Some((test, body)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Emits each expression in EXPRS in order and returns the value of
+/// the last one. An empty sequence evaluates to nil. If TAIL is set
+/// the last expression is in tail position.
+pub(crate) fn emit_sequence(
+    exprs: &[Expr],
+    tail: bool,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let mut res = None;
+    for (i, e) in exprs.iter().enumerate() {
+        ctx.tail_position = tail && i == exprs.len() - 1;
+        res = Some(emit_expr(e, ctx)?);
+    }
+    Ok(match res {
+        Some(v) => v,
+        None => ctx
+            .builder
+            .ins()
+            .iconst(ctx.word, Expr::Nil.immediate_rep()),
+    })
+}
+
+/// Emits a loop that runs BODY for as long as TEST is true. Like if
+/// only #t counts as true. The loop evaluates to nil.
+///
+/// The header block that evaluates TEST is jumped back to from the end
+/// of the body so it is only sealed once the body has been emitted.
+pub(crate) fn emit_while(
+    test: &Expr,
+    body: &[Expr],
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let header_block = ctx.builder.create_block();
+    let body_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+
+    ctx.builder.ins().jump(header_block, &[]);
+
+    ctx.builder.switch_to_block(header_block);
+    let test = emit_expr(test, ctx)?;
+    let test = emit_is_true(test, ctx);
+    ctx.builder.ins().brz(test, exit_block, &[]);
+    ctx.builder.ins().jump(body_block, &[]);
+
+    ctx.builder.switch_to_block(body_block);
+    ctx.builder.seal_block(body_block);
+    // Nothing in the body is in tail position as the loop always
+    // goes back to the test.
+    emit_sequence(body, false, ctx)?;
+    ctx.builder.ins().jump(header_block, &[]);
+    ctx.builder.seal_block(header_block);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+
+    Ok(ctx
+        .builder
+        .ins()
+        .iconst(ctx.word, Expr::Nil.immediate_rep()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::roundtrip_string;
+    use crate::Expr;
+
+    #[test]
+    fn begin() {
+        assert_eq!(roundtrip_string("(begin 1 2 3)").unwrap(), Expr::Integer(3));
+        assert_eq!(roundtrip_string("(begin)").unwrap(), Expr::Nil);
+    }
+
+    #[test]
+    fn begin_in_order() {
+        let source = r#"
+(let x 1)
+(let y (begin (set x (add x x)) (set x (add1 x)) x))
+(cons x y)
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::Pair(Box::new(Expr::Integer(3)), Box::new(Expr::Integer(3)))
+        );
+    }
+
+    #[test]
+    fn begin_in_tail_position() {
+        let source = r#"
+(let count (fn (n) (begin 1 (if (eq n 0) n (count (sub n 1))))))
+(count 1000000)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(0));
+    }
+
+    #[test]
+    fn while_loop() {
+        let source = r#"
+(let i 1)
+(let sum 0)
+(while (<= i 10)
+  (set! sum (add sum i))
+  (set! i (add1 i)))
+sum
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(55));
+
+        assert_eq!(
+            roundtrip_string("(while (eq 1 2) (add 1 1))").unwrap(),
+            Expr::Nil
+        );
+    }
+
+    #[test]
+    fn while_in_function() {
+        // Loops in functions can use and update captured variables
+        // and nest.
+        let source = r#"
+(let table (fn (n)
+  (let total 0)
+  (let i 0)
+  (while (lt i n)
+    (let j 0)
+    (while (lt j n)
+      (set! total (add total (mul i j)))
+      (set! j (add1 j)))
+    (set! i (add1 i)))
+  total))
+(table 4)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(36));
+    }
+}
@@ -0,0 +1,229 @@
+//! Strings are bytes laid out in a row
+//! But sometimes a list of chars is the way to go
+
+use cranelift::prelude::*;
+
+use crate::compiler::Context;
+use crate::conversions::{self, string_bytes, NIL_VALUE};
+use crate::errors::LustError;
+use crate::fatal;
+use crate::heap::{runtime_alloc, runtime_cons};
+use crate::{Expr, Word};
+
+/// Emits the code for the length of STRING in bytes as a fixnum.
+pub(crate) fn emit_string_length(string: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    fatal::emit_check_string(string, ctx)?;
+    let address = ctx
+        .builder
+        .ins()
+        .band_imm(string, conversions::HEAP_PTR_MASK);
+    let length = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, 0);
+    Ok(ctx
+        .builder
+        .ins()
+        .ishl_imm(length, conversions::FIXNUM_SHIFT))
+}
+
+/// Emits the code for the byte at INDEX in STRING as a character.
+/// Checks that INDEX is in bounds first.
+pub(crate) fn emit_string_ref(
+    string: Value,
+    index: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let length = emit_string_length(string, ctx)?;
+    fatal::emit_check_int(index, ctx)?;
+    fatal::emit_check_string_bounds(index, length, ctx)?;
+
+    let address = ctx
+        .builder
+        .ins()
+        .band_imm(string, conversions::HEAP_PTR_MASK);
+    let offset = ctx.builder.ins().sshr_imm(index, conversions::FIXNUM_SHIFT);
+    let byte = ctx.builder.ins().iadd(address, offset);
+    let byte = ctx
+        .builder
+        .ins()
+        .uload8(ctx.word, MemFlags::new(), byte, ctx.word.bytes() as i32);
+    let c = ctx.builder.ins().ishl_imm(byte, conversions::CHAR_SHIFT);
+    Ok(ctx.builder.ins().bor_imm(c, conversions::CHAR_TAG))
+}
+
+/// Allocates a string holding BYTES on the heap from inside a runtime
+/// function. It is laid out the same way as string literals are.
+pub(crate) fn runtime_string(bytes: &[u8]) -> Word {
+    let word_size = std::mem::size_of::<Word>();
+    let words = 1 + (bytes.len() + 1).div_ceil(word_size);
+    let storage = runtime_alloc(words * word_size);
+    unsafe {
+        *storage = bytes.len() as Word;
+        let payload = storage.add(1) as *mut u8;
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), payload, bytes.len());
+        *payload.add(bytes.len()) = 0;
+    }
+    storage as Word | conversions::STRING_TAG
+}
+
+/// Makes a new string out of the bytes in the string FIRST followed by
+/// the bytes in the string SECOND.
+#[no_mangle]
+pub extern "C" fn string_append_lustc_word(first: Word, second: Word) -> Word {
+    let bytes = [string_bytes(first), string_bytes(second)].concat();
+    runtime_string(&bytes)
+}
+
+/// Converts the string WORD into a list of its characters.
+#[no_mangle]
+pub extern "C" fn string_to_list_lustc_word(word: Word) -> Word {
+    String::from_utf8_lossy(string_bytes(word))
+        .chars()
+        .rev()
+        .fold(NIL_VALUE, |rest, c| {
+            runtime_cons(Expr::Char(c).immediate_rep(), rest)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::roundtrip_string;
+    use crate::testing::expect_fatal;
+    use crate::Expr;
+
+    #[test]
+    fn string_literal() {
+        assert_eq!(
+            roundtrip_string(r#""hello""#).unwrap(),
+            Expr::String("hello".to_string())
+        );
+        assert_eq!(
+            roundtrip_string(r#""""#).unwrap(),
+            Expr::String("".to_string())
+        );
+    }
+
+    #[test]
+    fn string_in_list() {
+        let source = r#"
+(let s "hello")
+(car (cdr (cons 1 (cons s ()))))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::String("hello".to_string())
+        );
+    }
+
+    #[test]
+    fn is_string() {
+        assert_eq!(
+            roundtrip_string(r#"(string? "hello")"#).unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(
+            roundtrip_string("(string? (cons 1 2))").unwrap(),
+            Expr::Bool(false)
+        );
+        assert_eq!(roundtrip_string("(string? ())").unwrap(), Expr::Bool(false));
+    }
+
+    #[test]
+    fn string_to_list() {
+        assert_eq!(
+            roundtrip_string(r#"(car (cdr (string->list "hi🚨")))"#).unwrap(),
+            Expr::Char('i')
+        );
+        assert_eq!(roundtrip_string(r#"(string->list "")"#).unwrap(), Expr::Nil);
+        let source = r#"
+(let f string->list)
+(car (f "abc"))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Char('a'));
+    }
+
+    #[test]
+    fn string_length() {
+        assert_eq!(
+            roundtrip_string(r#"(string-length "hello")"#).unwrap(),
+            Expr::Integer(5)
+        );
+        assert_eq!(
+            roundtrip_string(r#"(string-length "")"#).unwrap(),
+            Expr::Integer(0)
+        );
+        // Lengths are in bytes.
+        assert_eq!(
+            roundtrip_string(r#"(string-length "🚨")"#).unwrap(),
+            Expr::Integer(4)
+        );
+    }
+
+    #[test]
+    fn string_ref() {
+        assert_eq!(
+            roundtrip_string(r#"(string-ref "hello" 0)"#).unwrap(),
+            Expr::Char('h')
+        );
+        assert_eq!(
+            roundtrip_string(r#"(string-ref "hello" 4)"#).unwrap(),
+            Expr::Char('o')
+        );
+        let source = r#"
+(let s "abc")
+(letrec ((count (fn (i n)
+                  (if (eq i (string-length s))
+                      n
+                      (count (add1 i) (if (eq (string-ref s i) #\b) (add1 n) n))))))
+  (count 0 0))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(1));
+    }
+
+    #[test]
+    fn string_append() {
+        assert_eq!(
+            roundtrip_string(r#"(string-append "hello " "world")"#).unwrap(),
+            Expr::String("hello world".to_string())
+        );
+        assert_eq!(
+            roundtrip_string(r#"(string-append "" "")"#).unwrap(),
+            Expr::String("".to_string())
+        );
+        let source = r#"
+(let s (string-append (string-append "ab" "") "cd🚨"))
+(cons (string-length s) (string-ref s 3))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::Pair(Box::new(Expr::Integer(8)), Box::new(Expr::Char('d')))
+        );
+    }
+
+    #[test]
+    fn higher_order_strings() {
+        let source = r#"
+(let len string-length)
+(let ref string-ref)
+(let app string-append)
+(let s (app "x" "yz"))
+(cons (len s) (ref s 2))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::Pair(Box::new(Expr::Integer(3)), Box::new(Expr::Char('z')))
+        );
+    }
+
+    #[test]
+    fn string_ref_out_of_bounds() {
+        for source in &[
+            r#"(string-ref "abc" 3)"#,
+            r#"(string-ref "abc" -1)"#,
+            r#"(string-ref "" 0)"#,
+        ] {
+            expect_fatal(source, "string index out of bounds");
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! A symbol is a name and nothing more. Two symbols with the same
+//! name are the same symbol so each name is given a number the first
+//! time it is seen and symbols are stored as that number.
+//!
+//! Every JIT has an interner of its own so the numbers that one JIT
+//! gives out don't change what another's programs see. Keywords are
+//! numbered by the same interner.
+//!
+//! Floats made outside of compiled code, for example in quoted data or
+//! by read, are interned too. They can't change so every float with
+//! the same bits can share one box. The boxes are owned by the
+//! interner and freed along with it.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::conversions::{string_bytes, SYMBOL_SHIFT};
+use crate::strings::runtime_string;
+use crate::{Expr, UWord, Word};
+
+#[derive(Default)]
+pub(crate) struct Interner {
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+    /// The number of symbols made by gensym so far.
+    generated: usize,
+    /// Boxed floats keyed by their bits.
+    floats: HashMap<u64, Box<f64>>,
+}
+
+/// An interner that a JIT and the thread compiling and running its
+/// programs share.
+pub(crate) type SharedInterner = Rc<RefCell<Interner>>;
+
+thread_local! {
+    // The interner of the JIT whose program is being compiled or run
+    // on this thread. Programs run on the thread that they were
+    // compiled on so the symbols made while compiling are the ones
+    // that are read back. Outside of any JIT the thread's own
+    // interner is used.
+    static INTERNER: RefCell<SharedInterner> = RefCell::new(SharedInterner::default());
+}
+
+/// Makes INTERNER the one that symbols are interned with on this
+/// thread until the returned guard is dropped, at which point the one
+/// before it is put back.
+pub(crate) fn enter(interner: &SharedInterner) -> InternerGuard {
+    InternerGuard(INTERNER.with(|current| current.replace(interner.clone())))
+}
+
+/// Puts back the interner that was in use before `enter` when it is
+/// dropped.
+pub(crate) struct InternerGuard(SharedInterner);
+
+impl Drop for InternerGuard {
+    fn drop(&mut self) {
+        INTERNER.with(|current| current.replace(self.0.clone()));
+    }
+}
+
+/// Gets the names of the symbols in INTERNER in the order that they
+/// were first interned.
+pub(crate) fn names(interner: &SharedInterner) -> Vec<String> {
+    interner.borrow().names.clone()
+}
+
+/// Runs F on the interner in use on this thread.
+fn with_interner<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut Interner) -> R,
+{
+    INTERNER.with(|current| f(&mut current.borrow().borrow_mut()))
+}
+
+/// Gets the number for the symbol named NAME.
+pub(crate) fn intern(name: &str) -> usize {
+    with_interner(|interner| {
+        if let Some(&id) = interner.ids.get(name) {
+            return id;
+        }
+        let id = interner.names.len();
+        interner.names.push(name.to_string());
+        interner.ids.insert(name.to_string(), id);
+        id
+    })
+}
+
+/// Gets a box holding F that lives as long as the interner in use.
+pub(crate) fn intern_float(f: f64) -> *const f64 {
+    with_interner(|interner| {
+        let boxed = interner
+            .floats
+            .entry(f.to_bits())
+            .or_insert_with(|| Box::new(f));
+        &**boxed as *const f64
+    })
+}
+
+/// Makes a symbol that has never been seen before and returns its
+/// number. Generated symbols are named g0, g1, and so on skipping any
+/// of those names that are already taken.
+pub(crate) fn gensym() -> usize {
+    let name = with_interner(|interner| loop {
+        let name = format!("g{}", interner.generated);
+        interner.generated += 1;
+        if !interner.ids.contains_key(&name) {
+            return name;
+        }
+    });
+    intern(&name)
+}
+
+/// Returns a new symbol that is not eq? to any other.
+#[no_mangle]
+pub extern "C" fn gensym_lustc_word() -> Word {
+    Expr::Symbol(name(gensym()).unwrap()).immediate_rep()
+}
+
+/// Makes a new string holding the name of the symbol WORD.
+#[no_mangle]
+pub extern "C" fn symbol_to_string_lustc_word(word: Word) -> Word {
+    let id = (word as UWord >> SYMBOL_SHIFT) as usize;
+    runtime_string(name(id).unwrap().as_bytes())
+}
+
+/// Gets the symbol named by the string WORD.
+#[no_mangle]
+pub extern "C" fn string_to_symbol_lustc_word(word: Word) -> Word {
+    let name = String::from_utf8_lossy(string_bytes(word));
+    Expr::Symbol(name.into_owned()).immediate_rep()
+}
+
+/// Gets the name of the symbol numbered ID.
+pub(crate) fn name(id: usize) -> Option<String> {
+    with_interner(|interner| interner.names.get(id).cloned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::roundtrip_string;
+    use crate::testing::expect_fatal;
+
+    #[test]
+    fn interned_once() {
+        let a = intern("interned-once-a");
+        let b = intern("interned-once-b");
+        assert_ne!(a, b);
+        assert_eq!(intern("interned-once-a"), a);
+        assert_eq!(name(b).unwrap(), "interned-once-b");
+    }
+
+    #[test]
+    fn gensym_is_fresh() {
+        let taken = format!("g{}", with_interner(|i| i.generated));
+        let taken = intern(&taken);
+        let a = gensym();
+        let b = gensym();
+        assert_ne!(a, b);
+        assert_ne!(a, taken);
+        assert_ne!(b, taken);
+    }
+
+    #[test]
+    fn symbol_to_string() {
+        assert_eq!(
+            roundtrip_string("(symbol->string 'foo)").unwrap(),
+            Expr::String("foo".to_string())
+        );
+        assert_eq!(
+            roundtrip_string("(string-append (symbol->string 'a) (symbol->string 'b))").unwrap(),
+            Expr::String("ab".to_string())
+        );
+    }
+
+    #[test]
+    fn string_to_symbol() {
+        assert_eq!(
+            roundtrip_string(r#"(eq? (string->symbol "foo") (string->symbol "foo"))"#).unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(
+            roundtrip_string(r#"(eq? (string->symbol "foo") 'foo)"#).unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(
+            roundtrip_string(r#"(eq? (string->symbol "foo") (string->symbol "bar"))"#).unwrap(),
+            Expr::Bool(false)
+        );
+        assert_eq!(
+            roundtrip_string(r#"(string->symbol (string-append "new-" "name"))"#).unwrap(),
+            Expr::Symbol("new-name".to_string())
+        );
+        let source = r#"
+(let s gensym)
+(let g (s))
+(eq? (string->symbol (symbol->string g)) g)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Bool(true));
+    }
+
+    #[test]
+    fn higher_order_conversions() {
+        let source = r#"
+(let to-string symbol->string)
+(let to-symbol string->symbol)
+(to-symbol (to-string 'roundtrip))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::Symbol("roundtrip".to_string())
+        );
+    }
+
+    #[test]
+    fn interned_symbols() {
+        use crate::compiler::{compile_entry, run_entry, JIT};
+        use crate::parse_string;
+
+        fn run(jit: &mut JIT, source: &str) -> Expr {
+            let entry = compile_entry(jit, &parse_string(source).unwrap(), &[]).unwrap();
+            // Safety: lust_entry was just compiled into JIT.
+            unsafe { run_entry(jit, entry, &[]) }.unwrap()
+        }
+
+        let mut jit = JIT::default();
+        assert!(jit.interned_symbols().is_empty());
+        let res = run(&mut jit, r#"(list 'apple :pear (string->symbol "plum"))"#);
+        assert_eq!(res, roundtrip_string("(list 'apple :pear 'plum)").unwrap());
+        let listed = jit.interned_symbols();
+        // Names are listed in the order that they were first seen.
+        let position = |name: &str| listed.iter().position(|n| n == name).unwrap();
+        assert!(position("apple") < position("pear"));
+        assert!(position("pear") < position("plum"));
+
+        // Another JIT has its own symbols.
+        let mut other = JIT::default();
+        run(&mut other, "'(fig apple)");
+        assert_eq!(other.interned_symbols(), ["fig", "apple"]);
+        assert!(!jit.interned_symbols().contains(&"fig".to_string()));
+
+        // Symbols interned outside of any JIT are in neither.
+        intern("interned-outside");
+        assert!(!other
+            .interned_symbols()
+            .contains(&"interned-outside".to_string()));
+    }
+
+    #[test]
+    fn keywords() {
+        let keyword = Expr::Keyword("foo".to_string());
+        assert_eq!(roundtrip_string(":foo").unwrap(), keyword);
+        assert_eq!(keyword.to_string(), ":foo");
+        let cases = [
+            ("(eq? :foo :foo)", true),
+            ("(let k (fn () :foo)) (eq? (k) :foo)", true),
+            ("(eq? :foo :bar)", false),
+            ("(eq? :foo 'foo)", false),
+            ("(eq? ':foo :foo)", true),
+            ("(symbol? :foo)", false),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(
+                roundtrip_string(source).unwrap(),
+                Expr::Bool(expected),
+                "{}",
+                source
+            );
+        }
+        assert_eq!(
+            roundtrip_string("'(:a 1 b)").unwrap(),
+            roundtrip_string("(list :a 1 'b)").unwrap()
+        );
+        let source =
+            "(let f (fn (k) (case k ((:a) 1) ((:b) 2) (else 3)))) (list (f :a) (f :b) (f 'a))";
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(1 2 3)").unwrap()
+        );
+        // A colon on its own is still a symbol.
+        assert_eq!(
+            roundtrip_string("':").unwrap(),
+            Expr::Symbol(":".to_string())
+        );
+    }
+
+    #[test]
+    fn bad_conversions() {
+        for source in &["(symbol->string \"foo\")", "(string->symbol 'foo)"] {
+            expect_fatal(source, "runtime type missmatch");
+        }
+    }
+}
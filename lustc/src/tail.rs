@@ -0,0 +1,201 @@
+//! Which expressions are in tail position. An expression is in tail
+//! position if its value is returned from the function it is in
+//! without anything else being done to it. Calls in tail position are
+//! the ones that don't need to grow the stack, see docs/tco.md.
+
+use crate::Expr;
+
+/// Gets every subexpression of EXPR that is in tail position in the
+/// function around it. Only function bodies start a tail position so
+/// nothing outside of a function in EXPR is returned. Lists returned
+/// from here have the span they were read from for pointing at them
+/// in source.
+///
+/// This follows what the compiler does: the last expression of a
+/// body, both branches of an if, the last expression of each cond
+/// and case clause and of when, unless, begin, and `and`, and the last
+/// expression in the body of a scoped let, let* or letrec are in tail
+/// position when the expression around them is. The body of a named
+/// let is the body of a function so its last expression is always in
+/// tail position. Arguments to calls and the expressions in an `or`
+/// never are.
+pub fn tail_positions(expr: &Expr) -> Vec<&Expr> {
+    let mut res = Vec::new();
+    collect_tail_positions(expr, false, &mut res);
+    res
+}
+
+/// Collects the expressions in tail position in EXPR into RES. TAIL is
+/// set if EXPR itself is in tail position.
+fn collect_tail_positions<'a>(expr: &'a Expr, tail: bool, res: &mut Vec<&'a Expr>) {
+    if tail {
+        res.push(expr);
+    }
+    if !matches!(expr, Expr::List(..)) || expr.is_quote().is_some() {
+        return;
+    }
+
+    if let Some((_, body)) = expr.is_fndef() {
+        collect_body(body, true, res);
+    } else if let Some((target, body)) = expr.is_define() {
+        match target {
+            // (define (f a) body...) makes a function.
+            Expr::List(..) => collect_body(body, true, res),
+            _ => collect_body(body, false, res),
+        }
+    } else if let Some((cond, then, else_)) = expr.is_conditional() {
+        collect_tail_positions(cond, false, res);
+        collect_tail_positions(then, tail, res);
+        collect_tail_positions(else_, tail, res);
+    } else if let Some(clauses) = expr.is_cond() {
+        for clause in clauses {
+            if let Expr::List(v, _) = clause {
+                match v.split_first() {
+                    Some((Expr::Symbol(s), body)) if s == "else" => collect_body(body, tail, res),
+                    Some((test, body)) => {
+                        collect_tail_positions(test, false, res);
+                        collect_body(body, tail, res);
+                    }
+                    None => (),
+                }
+            }
+        }
+    } else if let Some((key, clauses)) = expr.is_case() {
+        collect_tail_positions(key, false, res);
+        for clause in clauses {
+            if let Expr::List(v, _) = clause {
+                collect_body(&v[1..], tail, res);
+            }
+        }
+    } else if let Some((test, body)) = expr.is_when().or_else(|| expr.is_unless()) {
+        collect_tail_positions(test, false, res);
+        collect_body(body, tail, res);
+    } else if let Some(body) = expr.is_begin().or_else(|| expr.is_and()) {
+        collect_body(body, tail, res);
+    } else if let Some((_, bindings, body)) = expr.is_named_let() {
+        for (_, value) in bindings {
+            collect_tail_positions(value, false, res);
+        }
+        collect_body(body, true, res);
+    } else if let Some((bindings, body)) = expr
+        .is_scoped_let()
+        .or_else(|| expr.is_let_star())
+        .or_else(|| expr.is_letrec())
+    {
+        for (_, value) in bindings {
+            collect_tail_positions(value, false, res);
+        }
+        collect_body(body, tail, res);
+    } else if let Expr::List(v, _) = expr {
+        // Calls, primitives, let, set and or. None of their parts
+        // are returned as is.
+        for e in v {
+            collect_tail_positions(e, false, res);
+        }
+    }
+}
+
+/// Collects the expressions in tail position in BODY whose last
+/// expression is in tail position if TAIL is set.
+fn collect_body<'a>(body: &'a [Expr], tail: bool, res: &mut Vec<&'a Expr>) {
+    for (i, e) in body.iter().enumerate() {
+        collect_tail_positions(e, tail && i == body.len() - 1, res);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_string;
+
+    fn parse(source: &str) -> Expr {
+        parse_string(source).unwrap().remove(0)
+    }
+
+    #[test]
+    fn recursive_function() {
+        let f = Expr::list(vec![
+            Expr::Symbol("fn".to_string()),
+            Expr::list(vec![
+                Expr::Symbol("n".to_string()),
+                Expr::Symbol("acc".to_string()),
+            ]),
+            parse("(display n)"),
+            parse("(if (eq n 0) acc (begin (display acc) (f (sub n 1) (mul acc n))))"),
+        ]);
+        assert_eq!(
+            tail_positions(&f),
+            vec![
+                &parse("(if (eq n 0) acc (begin (display acc) (f (sub n 1) (mul acc n))))"),
+                &Expr::Symbol("acc".to_string()),
+                &parse("(begin (display acc) (f (sub n 1) (mul acc n)))"),
+                &parse("(f (sub n 1) (mul acc n))"),
+            ]
+        );
+    }
+
+    #[test]
+    fn forms() {
+        let tails = |source: &str| {
+            tail_positions(&parse(source))
+                .into_iter()
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            tails("(fn (a) (cond ((f a) (g a) (h a)) (else (k a))))"),
+            vec![
+                parse("(cond ((f a) (g a) (h a)) (else (k a)))"),
+                parse("(h a)"),
+                parse("(k a)")
+            ]
+        );
+        assert_eq!(
+            tails("(fn (a) (when (f a) (g a) (h a)))"),
+            vec![parse("(when (f a) (g a) (h a))"), parse("(h a)")]
+        );
+        assert_eq!(
+            tails("(fn (a) (case (f a) ((1) (g a)) (else (h a))))"),
+            vec![
+                parse("(case (f a) ((1) (g a)) (else (h a)))"),
+                parse("(g a)"),
+                parse("(h a)")
+            ]
+        );
+        assert_eq!(
+            tails("(fn (a) (and (f a) (g a)))"),
+            vec![parse("(and (f a) (g a))"), parse("(g a)")]
+        );
+        assert_eq!(
+            tails("(fn (a) (or (f a) (g a)))"),
+            vec![parse("(or (f a) (g a))")]
+        );
+        assert_eq!(
+            tails("(fn (a) (let ((b (f a))) (g b)))"),
+            vec![parse("(let ((b (f a))) (g b))"), parse("(g b)")]
+        );
+        assert_eq!(
+            tails("(define (loop n) (loop (f n)))"),
+            vec![parse("(loop (f n))")]
+        );
+        assert_eq!(
+            tails("(let loop ((i (f 0))) (g i) (loop i))"),
+            vec![parse("(loop i)")]
+        );
+
+        // Nothing is in tail position outside of a function but
+        // functions anywhere are looked at.
+        assert_eq!(tails("(if a (f a) (g a))"), vec![]);
+        assert_eq!(tails("(f (fn () (g)) '(fn () (h)))"), vec![parse("(g)")]);
+    }
+
+    #[test]
+    fn spans() {
+        let f = parse("(fn (n)\n  (f n))");
+        let outer = f.span().0.unwrap();
+        let inner = tail_positions(&f)[0].span().0.unwrap();
+        assert_eq!(inner.start.line, outer.start.line + 1);
+        assert_eq!(inner.start.col, 2);
+    }
+}
@@ -0,0 +1,62 @@
+//! Helpers for tests of programs that exit the process. Fatal errors
+//! can't be caught so the program is run by a copy of the test that
+//! wants to see it fail, started as a child process, and the test
+//! looks at what the child printed.
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+use crate::roundtrip_string;
+
+// Set in the child to the key of the call that it is there to run.
+const CHILD: &str = "LUSTC_TEST_CHILD";
+
+/// Runs RUN in a copy of the calling test with STDIN as its input and
+/// returns what the copy wrote and how it exited. The copy runs the
+/// test from the start so KEY tells this call apart from any others
+/// that the test makes. In the copy this runs RUN and exits, or
+/// returns None right away if the copy is for another call.
+pub(crate) fn run_in_child(key: &str, stdin: &[u8], run: impl FnOnce()) -> Option<Output> {
+    if let Ok(child) = std::env::var(CHILD) {
+        if child == key {
+            run();
+            std::process::exit(0);
+        }
+        return None;
+    }
+    // The test harness names the thread that runs a test after it.
+    let thread = std::thread::current();
+    let test = thread.name().expect("not running in a test");
+    let mut child = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", test, "--nocapture"])
+        .env(CHILD, key)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    Some(child.wait_with_output().unwrap())
+}
+
+/// Checks that SOURCE exits the process with a fatal error whose
+/// message contains MESSAGE.
+pub(crate) fn expect_fatal(source: &str, message: &str) {
+    expect_fatal_with(source, message, || {
+        let _ = roundtrip_string(source);
+    })
+}
+
+/// Like expect_fatal but the program is run by RUN. KEY tells the
+/// calls in a test apart.
+pub(crate) fn expect_fatal_with(key: &str, message: &str, run: impl FnOnce()) {
+    if let Some(output) = run_in_child(key, &[], run) {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(!output.status.success(), "{} didn't fail: {}", key, stdout);
+        assert!(
+            stdout.contains(&format!("fatal error: {}", message)),
+            "{}: {}",
+            key,
+            stdout
+        );
+    }
+}
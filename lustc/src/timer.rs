@@ -1,4 +1,19 @@
-use std::time::Instant;
+//! Measuring where time goes. timeit reports how long each of the
+//! compiler's passes take when lustc is run with --timeit, the JIT
+//! keeps a tally of how long it spends lifting functions and
+//! generating machine code, and (time expr) reports how long a
+//! program spends evaluating expr.
+
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use cranelift::prelude::*;
+
+use crate::compiler::{emit_expr, Context};
+use crate::conversions;
+use crate::errors::LustError;
+use crate::primitives::emit_runtime_call;
+use crate::{Expr, Word};
 
 static mut SHOW_TIMES: bool = false;
 
@@ -20,3 +35,97 @@ pub fn timeit(label: &'static str) -> impl Drop {
     }
     Timer(label, Instant::now())
 }
+
+/// How long compiling a program took.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CompileTimes {
+    /// Time spent lifting functions out of the program and working
+    /// out what they capture.
+    pub lifting: Duration,
+    /// Time spent by Cranelift turning functions into machine code.
+    pub codegen: Duration,
+}
+
+impl Expr {
+    /// Determines if an expression is a time expression and returns
+    /// the expression that it times.
+    pub fn is_time(&self) -> Option<&Expr> {
+        match self {
+            Expr::List(v, _) => match &v[..] {
+                [Expr::Symbol(s), expr] if s == "time" => Some(expr),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Emits the code for (time EXPR) which evaluates EXPR, prints how
+/// long that took, and evaluates to its value.
+pub(crate) fn emit_time(expr: &Expr, ctx: &mut Context) -> Result<Value, LustError> {
+    let start = emit_runtime_call("clock_lustc_word", &[], ctx)?;
+    let val = emit_expr(expr, ctx)?;
+    let end = emit_runtime_call("clock_lustc_word", &[], ctx)?;
+    let elapsed = ctx.builder.ins().isub(end, start);
+    emit_runtime_call("print_elapsed_lustc_word", &[elapsed], ctx)?;
+    Ok(val)
+}
+
+/// Returns the number of nanoseconds since the first time this was
+/// called as a fixnum. Only the difference between two calls means
+/// anything.
+#[no_mangle]
+pub extern "C" fn clock_lustc_word() -> Word {
+    static START: OnceLock<Instant> = OnceLock::new();
+    let nanos = START.get_or_init(Instant::now).elapsed().as_nanos();
+    Expr::Integer(nanos.min(conversions::FIXNUM_MAX as u128) as i64).immediate_rep()
+}
+
+/// Prints ELAPSED, a fixnum number of nanoseconds.
+#[no_mangle]
+pub extern "C" fn print_elapsed_lustc_word(elapsed: Word) -> Word {
+    let elapsed = elapsed >> conversions::FIXNUM_SHIFT;
+    conversions::write_output(&format!("elapsed time: {} ns\n", elapsed));
+    Expr::Nil.immediate_rep()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conversions::capture_output;
+    use crate::{parse_string, roundtrip_string, Expr};
+
+    #[test]
+    fn time() {
+        let (res, output) = capture_output(|| roundtrip_string("(time (add 1 2))"));
+        assert_eq!(res.unwrap(), Expr::Integer(3));
+        let nanos = output
+            .strip_prefix("elapsed time: ")
+            .and_then(|s| s.strip_suffix(" ns\n"))
+            .unwrap();
+        assert!(nanos.parse::<i64>().unwrap() >= 0);
+    }
+
+    #[test]
+    fn time_measures() {
+        let source = r#"
+(let spin (fn (n) (if (eq n 0) 0 (spin (sub n 1)))))
+(time (spin 100000))
+"#;
+        let (res, output) = capture_output(|| roundtrip_string(source));
+        assert_eq!(res.unwrap(), Expr::Integer(0));
+        let nanos: i64 = output
+            .trim_start_matches("elapsed time: ")
+            .trim_end_matches(" ns\n")
+            .parse()
+            .unwrap();
+        assert!(nanos > 0);
+    }
+
+    #[test]
+    fn compile_times() {
+        let program = parse_string("(let f (fn (x) (add x 1))) (f 1)").unwrap();
+        let (res, times) = crate::compiler::roundtrip_program_timed(&program).unwrap();
+        assert_eq!(res, Expr::Integer(2));
+        assert!(times.codegen > std::time::Duration::from_nanos(0));
+    }
+}
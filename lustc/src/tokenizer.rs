@@ -1,16 +1,26 @@
+use crate::conversions::{FIXNUM_MAX, FIXNUM_MIN};
 use crate::location::Location;
 use crate::reader::{self, Reader};
 
 /// A token type. When paired with a location makes a token.
 #[derive(Debug, PartialEq, Clone)]
 pub enum TokenType {
-    /// A number. Anything that matches the regex [0-9]+.[0-9]+.
+    /// A number. Either decimal, [0-9]+, hex, 0x[0-9a-fA-F]+, or
+    /// binary, 0b[01]+, and optionally starting with a -.
     Number(i64),
+    /// A floating point number. Anything that matches the regex
+    /// -?[0-9]+.[0-9]*.
+    Float(f64),
     /// A string. Strings are made up of a sequence of non-newline
     /// characters that begin and end with '"'. The enclosed string
     /// does not contain the opening and closing quotes. The \n and \t
     /// escape sequences are supported.
     String(String),
+    /// A boolean. Either #t or #f.
+    Bool(bool),
+    /// A character. Written as #\ followed by the character or one
+    /// of the names space, newline, or tab.
+    Char(char),
     /// Opening parenthesis.
     Oparen,
     /// Closing parenthesis.
@@ -21,8 +31,15 @@ pub enum TokenType {
     Quaziquote,
     // A comma
     Comma,
-    // A - sign
-    Negate,
+    /// A lone . which separates the last cdr of a dotted list from
+    /// the elements before it.
+    Dot,
+    /// A datum comment #; which comments out the expression that
+    /// follows it.
+    DatumComment,
+    /// A block comment, #| ... |#, that the input ends inside of. The
+    /// enclosed string is the text of the comment.
+    UnterminatedComment(String),
     /// An identifier. This is any sequence of characters not matched
     /// by the above rules.
     Id(String),
@@ -70,7 +87,7 @@ impl<'a> Tokenizer<'a> {
     /// otherwise.
     pub(crate) fn next_token(&mut self) -> Option<Token> {
         self.reader.skip_whitespace();
-        match self.reader.peek() {
+        match self.reader.peek().copied() {
             None => None,
             Some(c) => Some(match c {
                 // Comments
@@ -78,17 +95,35 @@ impl<'a> Tokenizer<'a> {
                     self.reader.skip_line();
                     return self.next_token();
                 }
+                '#' if self.reader.peek_2() == Some('|') => {
+                    return match self.skip_block_comment() {
+                        Ok(()) => self.next_token(),
+                        Err(token) => Some(token),
+                    };
+                }
                 '0'..='9' => self.tokenize_number(),
                 '(' => self.eat_token_at_point(TokenType::Oparen),
                 ')' => self.eat_token_at_point(TokenType::Cparen),
                 '\'' => self.eat_token_at_point(TokenType::Quote),
                 '`' => self.eat_token_at_point(TokenType::Quaziquote),
                 ',' => self.eat_token_at_point(TokenType::Comma),
+                // A - is the start of a negative number if a digit
+                // follows it and is a symbol otherwise.
                 '-' => match self.reader.peek_2() {
-                    Some('0'..='9') => self.eat_token_at_point(TokenType::Negate),
-                    _ => self.eat_token_at_point(TokenType::Id("-".to_string())),
+                    Some('0'..='9') => self.tokenize_number(),
+                    _ => self.tokenize_id(),
                 },
                 '"' => self.tokenize_string(),
+                '#' => match self.reader.peek_2() {
+                    Some('\\') => self.tokenize_char(),
+                    Some(';') => {
+                        let start = self.reader.loc();
+                        self.reader.next();
+                        self.reader.next();
+                        Token::new(start, self.reader.loc(), TokenType::DatumComment)
+                    }
+                    _ => self.tokenize_id(),
+                },
                 _ => self.tokenize_id(),
             }),
         }
@@ -108,6 +143,41 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
+    /// Skips a block comment. Assumes that the reader is positioned at
+    /// the # of the opening #|. Block comments nest so the comment
+    /// only ends once every #| inside of it has been closed. If the
+    /// input ends first an UnterminatedComment token is returned.
+    fn skip_block_comment(&mut self) -> Result<(), Token> {
+        let start = self.reader.loc();
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            match (self.reader.next(), self.reader.peek()) {
+                (Some('#'), Some('|')) => {
+                    depth += 1;
+                    text.push_str("#|");
+                    self.reader.next();
+                }
+                (Some('|'), Some('#')) => {
+                    depth -= 1;
+                    text.push_str("|#");
+                    self.reader.next();
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                (Some(c), _) => text.push(c),
+                (None, _) => {
+                    return Err(Token::new(
+                        start,
+                        self.reader.loc(),
+                        TokenType::UnterminatedComment(text),
+                    ))
+                }
+            }
+        }
+    }
+
     /// Tokenizes a number and returns a number token if sucessful.
     fn tokenize_number(&mut self) -> Token {
         let start = self.reader.loc();
@@ -117,7 +187,7 @@ impl<'a> Tokenizer<'a> {
             if let Some(c) = self.reader.peek() {
                 if match c {
                     '0'..='9' => true,
-                    '.' => false,
+                    '.' => true,
                     '(' | ')' => false,
                     c => !c.is_ascii_whitespace(),
                 } {
@@ -131,12 +201,21 @@ impl<'a> Tokenizer<'a> {
                 break;
             }
         }
-        match res.parse::<i64>() {
-            Ok(f) => Token::new(start, self.reader.loc(), TokenType::Number(f)),
-            Err(_) => Token::new(
+        let ttype = if res.contains('.') {
+            res.parse::<f64>()
+                .map(TokenType::Float)
+                .map_err(|_| TokenType::Float(0.0))
+        } else {
+            parse_integer(&res)
+                .map(TokenType::Number)
+                .map_err(|_| TokenType::Number(0))
+        };
+        match ttype {
+            Ok(ttype) => Token::new(start, self.reader.loc(), ttype),
+            Err(expected) => Token::new(
                 start,
                 self.reader.loc(),
-                TokenType::Unrecognized(res, Box::new(TokenType::Number(0))),
+                TokenType::Unrecognized(res, Box::new(expected)),
             ),
         }
     }
@@ -161,7 +240,55 @@ impl<'a> Tokenizer<'a> {
                 None => break,
             }
         }
-        Token::new(start, self.reader.loc(), TokenType::Id(res))
+        let ttype = match res.as_str() {
+            "#t" => TokenType::Bool(true),
+            "#f" => TokenType::Bool(false),
+            "." => TokenType::Dot,
+            _ => TokenType::Id(res),
+        };
+        Token::new(start, self.reader.loc(), ttype)
+    }
+
+    /// Tokenizes a character literal. Assumes that the reader is
+    /// positioned at the # of the #\ prefix.
+    fn tokenize_char(&mut self) -> Token {
+        let start = self.reader.loc();
+        // Eat the #\ prefix.
+        self.reader.next();
+        self.reader.next();
+        let mut res = String::new();
+        // The first character is always part of the literal so that
+        // things like #\( and #\  work.
+        if let Some(c) = self.reader.next() {
+            res.push(c);
+        }
+        loop {
+            match self.reader.peek() {
+                Some(c) if !(*c == '(' || *c == ')' || c.is_ascii_whitespace()) => {
+                    res.push(*c);
+                    self.reader.next();
+                }
+                _ => break,
+            }
+        }
+        let mut chars = res.chars();
+        let c = match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => match res.as_str() {
+                "space" => Some(' '),
+                "newline" => Some('\n'),
+                "tab" => Some('\t'),
+                _ => None,
+            },
+        };
+        match c {
+            Some(c) => Token::new(start, self.reader.loc(), TokenType::Char(c)),
+            None => Token::new(
+                start,
+                self.reader.loc(),
+                TokenType::Unrecognized(format!("#\\{}", res), Box::new(TokenType::Char(' '))),
+            ),
+        }
     }
 
     /// Tokenizes a string. If the string has an invalid excape or
@@ -181,6 +308,7 @@ impl<'a> Tokenizer<'a> {
                             'n' => res.push('\n'),
                             't' => res.push('\t'),
                             '"' => res.push('"'),
+                            '\\' => res.push('\\'),
                             c => {
                                 valid = false;
                                 res.push_str(&format!("\\{}", c).to_string());
@@ -237,6 +365,37 @@ impl Token {
     }
 }
 
+/// Parses the integer literal S. Literals are decimal unless they
+/// start with 0x for hex or 0b for binary and may be negative. Returns
+/// a message saying what is wrong with S if it is malformed or doesn't
+/// fit in a fixnum.
+pub(crate) fn parse_integer(s: &str) -> Result<i64, String> {
+    let (negative, unsigned) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let (radix, digits) = if let Some(hex) = unsigned.strip_prefix("0x") {
+        (16, hex)
+    } else if let Some(binary) = unsigned.strip_prefix("0b") {
+        (2, binary)
+    } else {
+        (10, unsigned)
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(format!("malformed integer literal: {}", s));
+    }
+
+    let out_of_range = || format!("integer literal out of range: {}", s);
+    // Every digit is valid so the only way for this to fail is for
+    // there to be too many of them.
+    let magnitude = i128::from_str_radix(digits, radix).map_err(|_| out_of_range())?;
+    let value = if negative { -magnitude } else { magnitude };
+    if value < FIXNUM_MIN as i128 || value > FIXNUM_MAX as i128 {
+        return Err(out_of_range());
+    }
+    Ok(value as i64)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -266,6 +425,45 @@ mod tests {
         );
     }
 
+    #[test]
+    fn floats() {
+        let input = "1.5 2.";
+        let mut tokenizer = Tokenizer::new(input);
+        let mut actual = Vec::new();
+        while let Some(token) = tokenizer.next_token() {
+            actual.push(token);
+        }
+        assert_eq!(
+            actual,
+            vec![
+                Token::from_raw(0, 0, 0, 3, TokenType::Float(1.5)),
+                Token::from_raw(0, 4, 0, 6, TokenType::Float(2.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn bools_and_chars() {
+        let input = "#t #f #\\a #\\space #\\( #\\🥺 #\\bogus";
+        let mut tokenizer = Tokenizer::new(input);
+        let mut actual = Vec::new();
+        while let Some(token) = tokenizer.next_token() {
+            actual.push(token.ttype);
+        }
+        assert_eq!(
+            actual,
+            vec![
+                TokenType::Bool(true),
+                TokenType::Bool(false),
+                TokenType::Char('a'),
+                TokenType::Char(' '),
+                TokenType::Char('('),
+                TokenType::Char('🥺'),
+                TokenType::Unrecognized("#\\bogus".to_string(), Box::new(TokenType::Char(' '))),
+            ]
+        );
+    }
+
     #[test]
     fn strings() {
         let strings = "\"hello\" \"hello\\t\"";
@@ -337,4 +535,140 @@ mod tests {
             ]
         );
     }
+
+    fn tokenize(input: &str) -> Vec<TokenType> {
+        let mut tokenizer = Tokenizer::new(input);
+        let mut actual = Vec::new();
+        while let Some(token) = tokenizer.next_token() {
+            actual.push(token.ttype);
+        }
+        actual
+    }
+
+    #[test]
+    fn integer_radixes() {
+        assert_eq!(
+            tokenize("42 -42 0xff 0xFF -0x10 0b1010 -0b1 0"),
+            vec![
+                TokenType::Number(42),
+                TokenType::Number(-42),
+                TokenType::Number(255),
+                TokenType::Number(255),
+                TokenType::Number(-16),
+                TokenType::Number(10),
+                TokenType::Number(-1),
+                TokenType::Number(0),
+            ]
+        );
+        assert_eq!(tokenize("-1.5"), vec![TokenType::Float(-1.5)]);
+    }
+
+    #[test]
+    fn minus_symbol() {
+        assert_eq!(
+            tokenize("(- -x - 1)"),
+            vec![
+                TokenType::Oparen,
+                TokenType::Id("-".to_string()),
+                TokenType::Id("-x".to_string()),
+                TokenType::Id("-".to_string()),
+                TokenType::Number(1),
+                TokenType::Cparen,
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_integers() {
+        let bad = |s: &str| TokenType::Unrecognized(s.to_string(), Box::new(TokenType::Number(0)));
+        assert_eq!(
+            tokenize("0xG 0b102 0x -1-2 12ab"),
+            vec![
+                bad("0xG"),
+                bad("0b102"),
+                bad("0x"),
+                bad("-1-2"),
+                bad("12ab")
+            ]
+        );
+        assert_eq!(
+            parse_integer("0xG"),
+            Err("malformed integer literal: 0xG".to_string())
+        );
+    }
+
+    #[test]
+    fn line_comments() {
+        assert_eq!(
+            tokenize("1 ; 2 3\n4 ;5"),
+            vec![TokenType::Number(1), TokenType::Number(4)]
+        );
+    }
+
+    #[test]
+    fn block_comments() {
+        assert_eq!(
+            tokenize("1 #| 2\n3 |# 4 #|5|#6"),
+            vec![
+                TokenType::Number(1),
+                TokenType::Number(4),
+                TokenType::Number(6)
+            ]
+        );
+        // Block comments nest.
+        assert_eq!(
+            tokenize("(a #| b #| c |# d |# e)"),
+            vec![
+                TokenType::Oparen,
+                TokenType::Id("a".to_string()),
+                TokenType::Id("e".to_string()),
+                TokenType::Cparen,
+            ]
+        );
+        assert_eq!(
+            tokenize("1 #| a #| b |# c"),
+            vec![
+                TokenType::Number(1),
+                TokenType::UnterminatedComment("#| a #| b |# c".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn datum_comments() {
+        assert_eq!(
+            tokenize("(a #;b #;(c d))"),
+            vec![
+                TokenType::Oparen,
+                TokenType::Id("a".to_string()),
+                TokenType::DatumComment,
+                TokenType::Id("b".to_string()),
+                TokenType::DatumComment,
+                TokenType::Oparen,
+                TokenType::Id("c".to_string()),
+                TokenType::Id("d".to_string()),
+                TokenType::Cparen,
+                TokenType::Cparen,
+            ]
+        );
+    }
+
+    #[test]
+    fn integer_range() {
+        // Fixnums have two bits fewer than a word.
+        let max = (1i64 << 61) - 1;
+        assert_eq!(parse_integer(&max.to_string()), Ok(max));
+        assert_eq!(parse_integer(&(-max - 1).to_string()), Ok(-max - 1));
+        for s in &[
+            "2305843009213693952",
+            "-2305843009213693953",
+            "0x7fffffffffffffff",
+            "99999999999999999999999999999999999999999",
+        ] {
+            assert_eq!(
+                parse_integer(s),
+                Err(format!("integer literal out of range: {}", s))
+            );
+        }
+    }
 }
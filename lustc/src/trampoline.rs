@@ -0,0 +1,188 @@
+//! Self tail calls jump back to the top of the procedure but tail
+//! calls to other procedures are regular calls so mutual recursion
+//! grows the stack. With trampolining on a procedure making a tail
+//! call to another procedure doesn't call it. It leaves the closure
+//! and arguments for the call here and returns TAIL_CALL_VALUE. Every
+//! call site checks for that value and makes the call that was left
+//! for it, over and over until a real result comes back. The stack
+//! never holds more than one frame for a chain of tail calls.
+//!
+//! This makes every call a little slower so it is off unless the JIT
+//! asks for it.
+
+use std::cell::UnsafeCell;
+
+use cranelift::prelude::*;
+
+use crate::backend::LustModule;
+use crate::compiler::Context;
+use crate::conversions::TAIL_CALL_VALUE;
+use crate::procedures::emit_raw_call;
+use crate::Word;
+
+thread_local! {
+    // The untagged closure pointer, argument count, and argument
+    // location of the tail call that was left for the caller to
+    // make. Compiled code loads and stores this directly so it has to
+    // stay put.
+    static PENDING: UnsafeCell<[Word; 3]> = const { UnsafeCell::new([0; 3]) };
+}
+
+/// Gets the address of the current thread's pending tail call.
+/// Programs run on the thread that they were compiled on so this can
+/// be compiled in.
+fn pending_address() -> i64 {
+    PENDING.with(|pending| pending.get() as i64)
+}
+
+/// Determines if the code being emitted ought to trampoline. Object
+/// files have no runtime to leave pending calls in so they never do.
+fn enabled(ctx: &Context) -> bool {
+    ctx.options.trampoline && matches!(ctx.module, LustModule::JIT(_))
+}
+
+/// Determines if a call in tail position ought to be left for the
+/// caller instead of made. Only procedures return to code that checks
+/// for tail calls, and inside of a catch the call has to be made for
+/// the catch to see what it raises.
+pub(crate) fn should_bounce(ctx: &Context) -> bool {
+    enabled(ctx) && ctx.loop_header.is_some() && ctx.handlers.is_empty()
+}
+
+/// Emits a return of TAIL_CALL_VALUE that leaves the call described
+/// by ARGS, the untagged closure pointer, the number of arguments,
+/// and where they are stored, for the caller to make. Leaves the
+/// current block filled.
+pub(crate) fn emit_bounce(args: &[Value], ctx: &mut Context) {
+    let address = ctx.builder.ins().iconst(ctx.word, pending_address());
+    let word_bytes = ctx.word.bytes() as i32;
+    for (i, arg) in args.iter().enumerate() {
+        ctx.builder
+            .ins()
+            .store(MemFlags::trusted(), *arg, address, i as i32 * word_bytes);
+    }
+    crate::depth::emit_leave(ctx);
+    let bounce = ctx.builder.ins().iconst(ctx.word, TAIL_CALL_VALUE);
+    ctx.builder.ins().return_(&[bounce]);
+}
+
+/// Emits the code that makes the tail calls left by a call that
+/// returned RESULT until one of them returns something else. Returns
+/// that result. Does nothing when trampolining is off.
+pub(crate) fn emit_trampoline(result: Value, ctx: &mut Context) -> Value {
+    if !enabled(ctx) {
+        return result;
+    }
+    let header_block = ctx.builder.create_block();
+    let call_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(exit_block, ctx.word);
+
+    ctx.builder.ins().jump(header_block, &[result]);
+
+    ctx.builder.switch_to_block(header_block);
+    let result = ctx.builder.block_params(header_block)[0];
+    let bounced = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::Equal, result, TAIL_CALL_VALUE);
+    ctx.builder.ins().brnz(bounced, call_block, &[]);
+    ctx.builder.ins().jump(exit_block, &[result]);
+
+    ctx.builder.switch_to_block(call_block);
+    ctx.builder.seal_block(call_block);
+    let address = ctx.builder.ins().iconst(ctx.word, pending_address());
+    let word_bytes = ctx.word.bytes() as i32;
+    let args = (0..3)
+        .map(|i| {
+            ctx.builder
+                .ins()
+                .load(ctx.word, MemFlags::trusted(), address, i * word_bytes)
+        })
+        .collect::<Vec<_>>();
+    let fn_ptr = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), args[0], 0);
+    let result = emit_raw_call(fn_ptr, &args, ctx);
+    ctx.builder.ins().jump(header_block, &[result]);
+    ctx.builder.seal_block(header_block);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+    ctx.builder.block_params(exit_block)[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::{eval_program_in, JIT};
+    use crate::errors::LustError;
+    use crate::{parse_string, roundtrip_string, Expr};
+
+    fn eval_trampolined(source: &str) -> Result<Expr, LustError> {
+        let mut jit = JIT::default();
+        jit.options.trampoline = true;
+        eval_program_in(jit, &parse_string(source).unwrap(), &[], &[])
+    }
+
+    const EVEN_ODD: &str = r#"
+(define (even? n) (if (eq? n 0) #t (odd? (sub1 n))))
+(define (odd? n) (if (eq? n 0) #f (even? (sub1 n))))
+"#;
+
+    #[test]
+    fn mutual_recursion() {
+        let source = format!(
+            "{} (list (even? 1000000) (odd? 1000001) (even? 7))",
+            EVEN_ODD
+        );
+        assert_eq!(
+            eval_trampolined(&source).unwrap(),
+            roundtrip_string("'(#t #t #f)").unwrap()
+        );
+        // Without trampolining the same program runs out of stack.
+        assert_eq!(
+            roundtrip_string(&source).unwrap_err(),
+            "stack overflow: more than 10000 nested calls"
+        );
+    }
+
+    #[test]
+    fn results_unchanged() {
+        // Calls that aren't in tail position, closures, higher order
+        // primitives, and raises all behave the same.
+        let source = r#"
+(let make-adder (fn (n) (fn (x) (add x n))))
+(let add5 (make-adder 5))
+(let twice (fn (f x) (f (f x))))
+(let sum (fn (n) (if (eq? n 0) 0 (add n (sum (sub1 n))))))
+(let call (fn (f) (f 10)))
+(let thrower (fn (x) (throw 'out x)))
+(let pass (fn (x) (thrower x)))
+(list
+  (twice add5 1)
+  (sum 100)
+  (map (fn (x) (call add5)) '(1 2))
+  (apply call (list sub1))
+  (catch 'out (pass 42)))
+"#;
+        assert_eq!(
+            eval_trampolined(source).unwrap(),
+            roundtrip_string(source).unwrap()
+        );
+    }
+
+    #[test]
+    fn tail_calls_through_closures() {
+        let source = r#"
+(define (countdown n k) (if (eq? n 0) (k 'done) (step n k)))
+(define (step n k) (countdown (sub1 n) k))
+(countdown 100000 (fn (x) x))
+"#;
+        assert_eq!(
+            eval_trampolined(source).unwrap(),
+            Expr::Symbol("done".to_string())
+        );
+    }
+}
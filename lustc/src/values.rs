@@ -0,0 +1,387 @@
+//! More than one value returned at once. (values a b) packs its
+//! arguments together and call-with-values unpacks them as the
+//! arguments to a function.
+//!
+//! A single value is just that value. Any other number of values is
+//! laid out like a vector but with the bitwise not of the count as its
+//! length. The length is always negative so call-with-values can tell
+//! multiple values apart from a vector that is returned as a single
+//! value.
+//!
+//! Multiple values are only kept together on their way back to a
+//! caller. Everywhere else only the first of them, or nil if there are
+//! none, is used. (values a b) that isn't in tail position evaluates
+//! to a, and a call that isn't in tail position takes the first of
+//! any values that it gets back. A call in tail position passes them
+//! on to its own caller.
+
+use cranelift::prelude::*;
+
+use crate::compiler::Context;
+use crate::conversions;
+use crate::errors::LustError;
+use crate::fatal;
+use crate::heap::{emit_alloc, emit_dynamic_alloc};
+use crate::procedures::emit_call_indirect;
+use crate::Expr;
+
+/// Emits the code to pack VALS together. One value is returned as
+/// is. Unless TAIL is set the values have nowhere to go but the
+/// expression around them so only the first is kept.
+pub(crate) fn emit_values(
+    vals: &[Value],
+    tail: bool,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    if let [val] = vals {
+        return Ok(*val);
+    }
+    if !tail {
+        return Ok(match vals.first() {
+            Some(val) => *val,
+            None => ctx.builder.ins().iconst(ctx.word, conversions::NIL_VALUE),
+        });
+    }
+    let word_bytes = ctx.word.bytes() as usize;
+    let storage = emit_alloc(((vals.len() + 1) * word_bytes) as i64, ctx)?;
+    let header = Expr::Integer(!(vals.len() as i64)).immediate_rep();
+    let header = ctx.builder.ins().iconst(ctx.word, header);
+    ctx.builder.ins().store(MemFlags::new(), header, storage, 0);
+    for (i, val) in vals.iter().enumerate() {
+        ctx.builder.ins().store(
+            MemFlags::new(),
+            *val,
+            storage,
+            ((i + 1) * word_bytes) as i32,
+        );
+    }
+    Ok(ctx.builder.ins().bor_imm(storage, conversions::VECTOR_TAG))
+}
+
+/// Emits the code to pack the COUNT values stored one after the other
+/// starting at ARGLOC. COUNT is only known at runtime.
+pub(crate) fn emit_dynamic_values(
+    argloc: Value,
+    count: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let word_bytes = ctx.word.bytes() as i64;
+
+    let single_block = ctx.builder.create_block();
+    let pack_block = ctx.builder.create_block();
+    let header_block = ctx.builder.create_block();
+    let body_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(exit_block, ctx.word);
+
+    let is_single = ctx.builder.ins().icmp_imm(IntCC::Equal, count, 1);
+    ctx.builder.ins().brnz(is_single, single_block, &[]);
+    ctx.builder.ins().jump(pack_block, &[]);
+
+    ctx.builder.switch_to_block(single_block);
+    ctx.builder.seal_block(single_block);
+    let val = ctx.builder.ins().load(ctx.word, MemFlags::new(), argloc, 0);
+    ctx.builder.ins().jump(exit_block, &[val]);
+
+    ctx.builder.switch_to_block(pack_block);
+    ctx.builder.seal_block(pack_block);
+    let size = ctx.builder.ins().iadd_imm(count, 1);
+    let size = ctx.builder.ins().imul_imm(size, word_bytes);
+    let storage = emit_dynamic_alloc(size, ctx)?;
+    let header = ctx.builder.ins().bnot(count);
+    let header = ctx
+        .builder
+        .ins()
+        .ishl_imm(header, conversions::FIXNUM_SHIFT);
+    ctx.builder.ins().store(MemFlags::new(), header, storage, 0);
+    let zero = ctx.builder.ins().iconst(ctx.word, 0);
+    ctx.builder.ins().jump(header_block, &[zero]);
+
+    // Copy the values over one word at a time.
+    ctx.builder.switch_to_block(header_block);
+    let index = ctx.builder.block_params(header_block)[0];
+    let done = ctx.builder.ins().icmp(IntCC::Equal, index, count);
+    let packed = ctx.builder.ins().bor_imm(storage, conversions::VECTOR_TAG);
+    ctx.builder.ins().brnz(done, exit_block, &[packed]);
+    ctx.builder.ins().jump(body_block, &[]);
+
+    ctx.builder.switch_to_block(body_block);
+    ctx.builder.seal_block(body_block);
+    let offset = ctx.builder.ins().imul_imm(index, word_bytes);
+    let from = ctx.builder.ins().iadd(argloc, offset);
+    let val = ctx.builder.ins().load(ctx.word, MemFlags::new(), from, 0);
+    let to = ctx.builder.ins().iadd(storage, offset);
+    ctx.builder
+        .ins()
+        .store(MemFlags::new(), val, to, word_bytes as i32);
+    let next = ctx.builder.ins().iadd_imm(index, 1);
+    ctx.builder.ins().jump(header_block, &[next]);
+    ctx.builder.seal_block(header_block);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+    Ok(ctx.builder.block_params(exit_block)[0])
+}
+
+/// Emits the code to get the first of the values in RES, the result
+/// of a call. Nil if there are none. Anything other than multiple
+/// values is returned as is.
+pub(crate) fn emit_first_value(res: Value, ctx: &mut Context) -> Value {
+    let check_block = ctx.builder.create_block();
+    let unpack_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(exit_block, ctx.word);
+
+    let tag = ctx.builder.ins().band_imm(res, conversions::HEAP_TAG_MASK);
+    let is_vector = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::Equal, tag, conversions::VECTOR_TAG);
+    ctx.builder.ins().brnz(is_vector, check_block, &[]);
+    ctx.builder.ins().jump(exit_block, &[res]);
+
+    ctx.builder.switch_to_block(check_block);
+    ctx.builder.seal_block(check_block);
+    let address = ctx.builder.ins().band_imm(res, conversions::HEAP_PTR_MASK);
+    let header = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, 0);
+    let is_values = ctx.builder.ins().icmp_imm(IntCC::SignedLessThan, header, 0);
+    ctx.builder.ins().brnz(is_values, unpack_block, &[]);
+    ctx.builder.ins().jump(exit_block, &[res]);
+
+    // A count of zero is a header of !0.
+    ctx.builder.switch_to_block(unpack_block);
+    ctx.builder.seal_block(unpack_block);
+    let none = Expr::Integer(!0).immediate_rep();
+    let is_empty = ctx.builder.ins().icmp_imm(IntCC::Equal, header, none);
+    let first = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, ctx.word.bytes() as i32);
+    let nil = ctx.builder.ins().iconst(ctx.word, conversions::NIL_VALUE);
+    let first = ctx.builder.ins().select(is_empty, nil, first);
+    ctx.builder.ins().jump(exit_block, &[first]);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+    ctx.builder.block_params(exit_block)[0]
+}
+
+/// Gets what the call that returned RES evaluates to. If TAIL is set
+/// the call is in tail position and any values are passed on as they
+/// are. Otherwise only the first is kept.
+pub(crate) fn emit_call_result(res: Value, tail: bool, ctx: &mut Context) -> Value {
+    if tail {
+        res
+    } else {
+        emit_first_value(res, ctx)
+    }
+}
+
+/// Emits a call to the closure PRODUCER with no arguments and then a
+/// call to the closure CONSUMER with the values that it returned as
+/// arguments.
+pub(crate) fn emit_call_with_values(
+    producer: Value,
+    consumer: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    fatal::emit_check_closure(producer, ctx)?;
+    fatal::emit_check_closure(consumer, ctx)?;
+
+    let word_bytes = ctx.word.bytes() as i64;
+
+    let closure_ptr = ctx
+        .builder
+        .ins()
+        .band_imm(producer, conversions::HEAP_PTR_MASK);
+    let fn_ptr = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), closure_ptr, 0);
+    let count = ctx.builder.ins().iconst(ctx.word, 0);
+    let argloc = emit_alloc(0, ctx)?;
+    let res = emit_call_indirect(fn_ptr, &[closure_ptr, count, argloc], ctx);
+
+    let check_block = ctx.builder.create_block();
+    let unpack_block = ctx.builder.create_block();
+    let single_block = ctx.builder.create_block();
+    let call_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(call_block, ctx.word);
+    ctx.builder.append_block_param(call_block, ctx.word);
+
+    // Multiple values are vectors with a negative length.
+    let tag = ctx.builder.ins().band_imm(res, conversions::HEAP_TAG_MASK);
+    let is_vector = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::Equal, tag, conversions::VECTOR_TAG);
+    ctx.builder.ins().brnz(is_vector, check_block, &[]);
+    ctx.builder.ins().jump(single_block, &[]);
+
+    ctx.builder.switch_to_block(check_block);
+    ctx.builder.seal_block(check_block);
+    let address = ctx.builder.ins().band_imm(res, conversions::HEAP_PTR_MASK);
+    let header = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, 0);
+    let is_values = ctx.builder.ins().icmp_imm(IntCC::SignedLessThan, header, 0);
+    ctx.builder.ins().brnz(is_values, unpack_block, &[]);
+    ctx.builder.ins().jump(single_block, &[]);
+
+    // The values are already laid out the way that arguments are so
+    // they are passed in place.
+    ctx.builder.switch_to_block(unpack_block);
+    ctx.builder.seal_block(unpack_block);
+    let count = ctx
+        .builder
+        .ins()
+        .sshr_imm(header, conversions::FIXNUM_SHIFT);
+    let count = ctx.builder.ins().bnot(count);
+    let values = ctx.builder.ins().iadd_imm(address, word_bytes);
+    ctx.builder.ins().jump(call_block, &[count, values]);
+
+    ctx.builder.switch_to_block(single_block);
+    ctx.builder.seal_block(single_block);
+    let one = ctx.builder.ins().iconst(ctx.word, 1);
+    let single = emit_alloc(word_bytes, ctx)?;
+    ctx.builder.ins().store(MemFlags::new(), res, single, 0);
+    ctx.builder.ins().jump(call_block, &[one, single]);
+
+    ctx.builder.switch_to_block(call_block);
+    ctx.builder.seal_block(call_block);
+    let count = ctx.builder.block_params(call_block)[0];
+    let argloc = ctx.builder.block_params(call_block)[1];
+
+    let closure_ptr = ctx
+        .builder
+        .ins()
+        .band_imm(consumer, conversions::HEAP_PTR_MASK);
+    let fn_ptr = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), closure_ptr, 0);
+    Ok(emit_call_indirect(
+        fn_ptr,
+        &[closure_ptr, count, argloc],
+        ctx,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{roundtrip_string, Expr};
+
+    #[test]
+    fn call_with_values() {
+        assert_eq!(
+            roundtrip_string("(call-with-values (fn () (values 1 2)) add)").unwrap(),
+            Expr::Integer(3)
+        );
+        assert_eq!(
+            roundtrip_string("(call-with-values (fn () (values 7)) add1)").unwrap(),
+            Expr::Integer(8)
+        );
+        assert_eq!(
+            roundtrip_string("(call-with-values (fn () (values)) (fn () 1))").unwrap(),
+            Expr::Integer(1)
+        );
+        let source = r#"
+(call-with-values (fn () (values 1 #\a "b")) list)
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string(r#"(list 1 #\a "b")"#).unwrap()
+        );
+    }
+
+    #[test]
+    fn single_values() {
+        // Anything that isn't multiple values is passed as a single
+        // argument, vectors included.
+        assert_eq!(
+            roundtrip_string("(call-with-values (fn () 5) add1)").unwrap(),
+            Expr::Integer(6)
+        );
+        let source = r#"
+(call-with-values (fn () (make-vector 2 1)) (fn (v) (vector-ref v 1)))
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(1));
+    }
+
+    #[test]
+    fn first_value_elsewhere() {
+        // Outside of call-with-values only the first value is seen.
+        let cases = [
+            ("(values 1 2)", "1"),
+            ("(add 1 (values 2 3))", "3"),
+            ("(list (values) (values 4 5 6))", "'(() 4)"),
+            ("(let f (fn () (values 1 2))) (add1 (f))", "2"),
+            (
+                "(let f (fn () (values 1 2))) (map (fn (x) (f)) '(a b))",
+                "'(1 1)",
+            ),
+            (
+                "(let f (fn (x) (values x x))) (list (f 1) (apply f '(2)))",
+                "'(1 2)",
+            ),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(
+                roundtrip_string(source).unwrap(),
+                roundtrip_string(expected).unwrap(),
+                "{}",
+                source
+            );
+        }
+        let (res, output) =
+            crate::conversions::capture_output(|| roundtrip_string("(println (values 1 2))"));
+        assert_eq!(res.unwrap(), Expr::Nil);
+        assert_eq!(output, "1\n");
+    }
+
+    #[test]
+    fn values_through_tail_calls() {
+        // Calls in tail position pass multiple values on to their
+        // caller.
+        let source = r#"
+(define (two) (values 1 2))
+(define (pass) (two))
+(define (pick b) (if b (pass) (apply values '(3 4))))
+(define (nested) (call-with-values pass values))
+(list (call-with-values pass list)
+      (call-with-values (fn () (pick #t)) list)
+      (call-with-values (fn () (pick #f)) list)
+      (call-with-values nested list))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'((1 2) (1 2) (3 4) (1 2))").unwrap()
+        );
+    }
+
+    #[test]
+    fn higher_order_values() {
+        let source = r#"
+(let pack values)
+(let both (fn (a b) (cons a b)))
+(cons (call-with-values (fn () (pack 1 2)) both)
+      (call-with-values (fn () (pack 3)) (fn (x) x)))
+"#;
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(cons(Expr::Integer(1), Expr::Integer(2)), Expr::Integer(3))
+        );
+
+        let source = r#"
+(let cwv call-with-values)
+(cwv (fn () (values 4 5)) mul)
+"#;
+        assert_eq!(roundtrip_string(source).unwrap(), Expr::Integer(20));
+    }
+}
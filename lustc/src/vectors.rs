@@ -0,0 +1,473 @@
+//! Vectors are words laid out in a row
+//! Reaching past the end gets you nowhere
+
+use cranelift::prelude::*;
+
+use crate::compiler::Context;
+use crate::conversions;
+use crate::errors::LustError;
+use crate::fatal;
+use crate::heap::emit_dynamic_alloc;
+use crate::lists;
+use crate::primitives::emit_contigous_to_list;
+
+/// Emits the allocation of an untagged vector with room for LENGTH
+/// elements, a nonnegative fixnum, and stores its length. The
+/// elements are left for the caller to fill in.
+fn emit_alloc_vector(length: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    let elements_size = emit_elements_size(length, ctx);
+    let size = ctx
+        .builder
+        .ins()
+        .iadd_imm(elements_size, ctx.word.bytes() as i64);
+    let storage = emit_dynamic_alloc(size, ctx)?;
+
+    // The length is stored as is which keeps it a fixnum.
+    ctx.builder.ins().store(MemFlags::new(), length, storage, 0);
+    Ok(storage)
+}
+
+/// Emits the number of bytes that LENGTH, a fixnum, elements take up.
+fn emit_elements_size(length: Value, ctx: &mut Context) -> Value {
+    // A fixnum is its value times four so shifting it left by one
+    // more gets the number of bytes.
+    ctx.builder.ins().ishl_imm(length, 1)
+}
+
+/// Emits a loop over the elements of a vector of LENGTH elements, a
+/// fixnum. BODY is called to emit the loop's body with the offset in
+/// bytes of the element from the start of the vector.
+fn emit_for_each_element<F>(length: Value, ctx: &mut Context, mut body: F)
+where
+    F: FnMut(Value, &mut Context),
+{
+    let header_block = ctx.builder.create_block();
+    let body_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+
+    ctx.builder.append_block_param(header_block, ctx.word);
+
+    // Elements come after the length.
+    let start = ctx.builder.ins().iconst(ctx.word, ctx.word.bytes() as i64);
+    let elements_size = emit_elements_size(length, ctx);
+    let end = ctx.builder.ins().iadd(start, elements_size);
+    ctx.builder.ins().jump(header_block, &[start]);
+
+    ctx.builder.switch_to_block(header_block);
+    let offset = ctx.builder.block_params(header_block)[0];
+    let done = ctx
+        .builder
+        .ins()
+        .icmp(IntCC::UnsignedGreaterThanOrEqual, offset, end);
+    ctx.builder.ins().brnz(done, exit_block, &[]);
+    ctx.builder.ins().jump(body_block, &[]);
+
+    ctx.builder.switch_to_block(body_block);
+    ctx.builder.seal_block(body_block);
+    body(offset, ctx);
+    let next = ctx.builder.ins().iadd_imm(offset, ctx.word.bytes() as i64);
+    ctx.builder.ins().jump(header_block, &[next]);
+    ctx.builder.seal_block(header_block);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+}
+
+/// Emits a store of FILL into each of the LENGTH elements of the
+/// vector at ADDRESS.
+fn emit_fill(address: Value, length: Value, fill: Value, ctx: &mut Context) {
+    emit_for_each_element(length, ctx, |offset, ctx| {
+        let element = ctx.builder.ins().iadd(address, offset);
+        ctx.builder.ins().store(MemFlags::new(), fill, element, 0);
+    });
+}
+
+/// Emits the code for a vector of LENGTH elements each of which is
+/// FILL. LENGTH is a fixnum.
+pub(crate) fn emit_make_vector(
+    length: Value,
+    fill: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    fatal::emit_check_int(length, ctx)?;
+    fatal::emit_check_nonnegative(length, ctx)?;
+    let storage = emit_alloc_vector(length, ctx)?;
+    emit_fill(storage, length, fill, ctx);
+    Ok(ctx.builder.ins().bor_imm(storage, conversions::VECTOR_TAG))
+}
+
+/// Emits the code for the number of elements in VECTOR as a fixnum.
+pub(crate) fn emit_vector_length(vector: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    fatal::emit_check_vector(vector, ctx)?;
+    let address = ctx
+        .builder
+        .ins()
+        .band_imm(vector, conversions::HEAP_PTR_MASK);
+    Ok(emit_load_length(address, ctx))
+}
+
+/// Emits the code for setting every element of VECTOR to FILL.
+/// Evaluates to nil.
+pub(crate) fn emit_vector_fill(
+    vector: Value,
+    fill: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let length = emit_vector_length(vector, ctx)?;
+    let address = ctx
+        .builder
+        .ins()
+        .band_imm(vector, conversions::HEAP_PTR_MASK);
+    emit_fill(address, length, fill, ctx);
+    Ok(ctx.builder.ins().iconst(ctx.word, conversions::NIL_VALUE))
+}
+
+/// Emits the code for a new vector with the same elements as VECTOR.
+pub(crate) fn emit_vector_copy(vector: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    let length = emit_vector_length(vector, ctx)?;
+    let address = ctx
+        .builder
+        .ins()
+        .band_imm(vector, conversions::HEAP_PTR_MASK);
+    let storage = emit_alloc_vector(length, ctx)?;
+    emit_for_each_element(length, ctx, |offset, ctx| {
+        let from = ctx.builder.ins().iadd(address, offset);
+        let element = ctx.builder.ins().load(ctx.word, MemFlags::new(), from, 0);
+        let to = ctx.builder.ins().iadd(storage, offset);
+        ctx.builder.ins().store(MemFlags::new(), element, to, 0);
+    });
+    Ok(ctx.builder.ins().bor_imm(storage, conversions::VECTOR_TAG))
+}
+
+/// Emits the code for a vector with the elements of LIST in the same
+/// order. Anything other than a proper list is a type error.
+pub(crate) fn emit_list_to_vector(list: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    // Counting the elements checks that the list is made of pairs so
+    // the copy doesn't need to.
+    let length = lists::emit_length(list, ctx)?;
+    let storage = emit_alloc_vector(length, ctx)?;
+
+    let header_block = ctx.builder.create_block();
+    let body_block = ctx.builder.create_block();
+    let exit_block = ctx.builder.create_block();
+    ctx.builder.append_block_param(header_block, ctx.word);
+    ctx.builder.append_block_param(header_block, ctx.word);
+
+    let start = ctx.builder.ins().iadd_imm(storage, ctx.word.bytes() as i64);
+    ctx.builder.ins().jump(header_block, &[list, start]);
+
+    ctx.builder.switch_to_block(header_block);
+    let node = ctx.builder.block_params(header_block)[0];
+    let address = ctx.builder.block_params(header_block)[1];
+    let done = ctx
+        .builder
+        .ins()
+        .icmp_imm(IntCC::Equal, node, conversions::NIL_VALUE);
+    ctx.builder.ins().brnz(done, exit_block, &[]);
+    ctx.builder.ins().jump(body_block, &[]);
+
+    ctx.builder.switch_to_block(body_block);
+    ctx.builder.seal_block(body_block);
+    let element = lists::emit_car(node, ctx);
+    ctx.builder
+        .ins()
+        .store(MemFlags::new(), element, address, 0);
+    let next = lists::emit_cdr(node, ctx);
+    let address = ctx.builder.ins().iadd_imm(address, ctx.word.bytes() as i64);
+    ctx.builder.ins().jump(header_block, &[next, address]);
+    ctx.builder.seal_block(header_block);
+
+    ctx.builder.switch_to_block(exit_block);
+    ctx.builder.seal_block(exit_block);
+
+    Ok(ctx.builder.ins().bor_imm(storage, conversions::VECTOR_TAG))
+}
+
+/// Emits the code for a list of the elements of VECTOR in the same
+/// order.
+pub(crate) fn emit_vector_to_list(vector: Value, ctx: &mut Context) -> Result<Value, LustError> {
+    fatal::emit_check_vector(vector, ctx)?;
+    let address = ctx
+        .builder
+        .ins()
+        .band_imm(vector, conversions::HEAP_PTR_MASK);
+    let length = emit_load_length(address, ctx);
+    let length = ctx
+        .builder
+        .ins()
+        .sshr_imm(length, conversions::FIXNUM_SHIFT);
+    let elements = ctx.builder.ins().iadd_imm(address, ctx.word.bytes() as i64);
+    emit_contigous_to_list(ctx, elements, length)
+}
+
+/// Emits a load of the length of the vector at ADDRESS as a fixnum.
+fn emit_load_length(address: Value, ctx: &mut Context) -> Value {
+    let length = ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), address, 0);
+    // Multiple values are stored with a negative length. They have no
+    // elements as far as vector operations are concerned.
+    let negative = ctx.builder.ins().icmp_imm(IntCC::SignedLessThan, length, 0);
+    let zero = ctx.builder.ins().iconst(ctx.word, 0);
+    ctx.builder.ins().select(negative, zero, length)
+}
+
+/// Emits the address of the element at INDEX in VECTOR. Checks that
+/// VECTOR is a vector and that INDEX is in bounds first.
+fn emit_element_address(
+    vector: Value,
+    index: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    fatal::emit_check_vector(vector, ctx)?;
+    fatal::emit_check_int(index, ctx)?;
+
+    let address = ctx
+        .builder
+        .ins()
+        .band_imm(vector, conversions::HEAP_PTR_MASK);
+    let length = emit_load_length(address, ctx);
+    fatal::emit_check_bounds(index, length, ctx)?;
+
+    let offset = ctx.builder.ins().ishl_imm(index, 1);
+    let element = ctx.builder.ins().iadd(address, offset);
+    Ok(ctx.builder.ins().iadd_imm(element, ctx.word.bytes() as i64))
+}
+
+/// Emits the code for getting the element at INDEX in VECTOR.
+pub(crate) fn emit_vector_ref(
+    vector: Value,
+    index: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let element = emit_element_address(vector, index, ctx)?;
+    Ok(ctx
+        .builder
+        .ins()
+        .load(ctx.word, MemFlags::new(), element, 0))
+}
+
+/// Emits the code for setting the element at INDEX in VECTOR to
+/// VALUE. Like set this evaluates to VALUE.
+pub(crate) fn emit_vector_set(
+    vector: Value,
+    index: Value,
+    value: Value,
+    ctx: &mut Context,
+) -> Result<Value, LustError> {
+    let element = emit_element_address(vector, index, ctx)?;
+    ctx.builder.ins().store(MemFlags::new(), value, element, 0);
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::roundtrip_string;
+    use crate::testing::expect_fatal;
+    use crate::Expr;
+
+    #[test]
+    fn make_vector() {
+        assert_eq!(
+            roundtrip_string("(make-vector 3)").unwrap(),
+            Expr::Vector(vec![Expr::Nil, Expr::Nil, Expr::Nil])
+        );
+        assert_eq!(
+            roundtrip_string("(make-vector 2 #\\a)").unwrap(),
+            Expr::Vector(vec![Expr::Char('a'), Expr::Char('a')])
+        );
+        assert_eq!(
+            roundtrip_string("(make-vector 0 1)").unwrap(),
+            Expr::Vector(vec![])
+        );
+    }
+
+    #[test]
+    fn vector_set_and_ref() {
+        let source = r#"
+(let v (make-vector 5 0))
+(vector-set! v 0 10)
+(vector-set! v 2 (cons 1 2))
+(vector-set! v 4 #t)
+(cons (vector-ref v 0)
+      (cons (vector-ref v 1)
+            (cons (car (vector-ref v 2))
+                  (cons (vector-ref v 4) ()))))
+"#;
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(
+                Expr::Integer(10),
+                cons(
+                    Expr::Integer(0),
+                    cons(Expr::Integer(1), cons(Expr::Bool(true), Expr::Nil))
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn vector_set_evaluates_to_value() {
+        assert_eq!(
+            roundtrip_string("(vector-set! (make-vector 1) 0 7)").unwrap(),
+            Expr::Integer(7)
+        );
+    }
+
+    #[test]
+    fn vector_fill_loop() {
+        let source = r#"
+(let v (make-vector 4))
+(letrec ((fill (fn (i)
+                 (if (lt i 4)
+                     (begin (vector-set! v i (mul i i)) (fill (add1 i)))
+                     v))))
+  (fill 0))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            Expr::Vector(vec![
+                Expr::Integer(0),
+                Expr::Integer(1),
+                Expr::Integer(4),
+                Expr::Integer(9),
+            ])
+        );
+    }
+
+    #[test]
+    fn higher_order_vectors() {
+        let source = r#"
+(let make make-vector)
+(let set vector-set!)
+(let ref vector-ref)
+(let v (make 2 5))
+(set v 1 6)
+(cons (ref v 0) (cons (ref v 1) (cons (make 1) ())))
+"#;
+        let cons = |car, cdr| Expr::Pair(Box::new(car), Box::new(cdr));
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            cons(
+                Expr::Integer(5),
+                cons(
+                    Expr::Integer(6),
+                    cons(Expr::Vector(vec![Expr::Nil]), Expr::Nil)
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn list_vector_conversions() {
+        assert_eq!(
+            roundtrip_string("(list->vector (list 1 #\\a \"b\"))").unwrap(),
+            Expr::Vector(vec![
+                Expr::Integer(1),
+                Expr::Char('a'),
+                Expr::String("b".to_string())
+            ])
+        );
+        assert_eq!(
+            roundtrip_string("(list->vector ())").unwrap(),
+            Expr::Vector(vec![])
+        );
+        assert_eq!(
+            roundtrip_string("(vector->list (make-vector 0))").unwrap(),
+            Expr::Nil
+        );
+        assert_eq!(
+            roundtrip_string("(vector->list (list->vector (list 1 2 3)))").unwrap(),
+            roundtrip_string("(list 1 2 3)").unwrap()
+        );
+        let source = r#"
+(let v (make-vector 3 0))
+(vector-set! v 1 'x)
+(let to-list vector->list)
+(let to-vector list->vector)
+(list (to-list v) (vector-ref (to-vector '(a b c)) 2) (to-list (to-vector '(1 (2)))))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'((0 x 0) c (1 (2)))").unwrap()
+        );
+        // Vectors made from lists are new vectors.
+        let source = r#"
+(let l (list 1 2))
+(let v (list->vector l))
+(vector-set! v 0 10)
+(list (car l) (vector->list v))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(1 (10 2))").unwrap()
+        );
+    }
+
+    #[test]
+    fn length_fill_and_copy() {
+        let source = r#"
+(let v (make-vector 3 0))
+(let filled (vector-fill! v 'x))
+(list filled (vector-length v) (vector->list v) (vector-length (make-vector 0)))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(() 3 (x x x) 0)").unwrap()
+        );
+
+        // Copies have the same elements and don't share storage with
+        // the original.
+        let source = r#"
+(let v (list->vector '(1 2 3)))
+(let c (vector-copy v))
+(vector-set! c 0 10)
+(vector-fill! v 7)
+(list (vector->list v) (vector->list c))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'((7 7 7) (10 2 3))").unwrap()
+        );
+        assert_eq!(
+            roundtrip_string("(vector-copy (make-vector 0))").unwrap(),
+            Expr::Vector(vec![])
+        );
+
+        let source = r#"
+(let len vector-length)
+(let fill vector-fill!)
+(let copy vector-copy)
+(let v (make-vector 2))
+(fill v 4)
+(list (len v) (vector->list (copy v)))
+"#;
+        assert_eq!(
+            roundtrip_string(source).unwrap(),
+            roundtrip_string("'(2 (4 4))").unwrap()
+        );
+    }
+
+    #[test]
+    fn bad_conversions() {
+        for source in &[
+            "(list->vector (cons 1 2))",
+            "(vector->list (list 1))",
+            "(vector-length \"abc\")",
+            "(vector-fill! (list 1) 0)",
+            "(vector-copy 5)",
+        ] {
+            expect_fatal(source, "runtime type missmatch");
+        }
+    }
+
+    #[test]
+    fn make_vector_arity() {
+        assert_eq!(
+            roundtrip_string("(make-vector 1 2 3)").unwrap_err(),
+            "make-vector expected 2 args and got 3 at line 1, column 1"
+        );
+    }
+}